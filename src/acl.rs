@@ -0,0 +1,174 @@
+use std::{
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+    sync::Arc,
+};
+
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use ipnet::IpNet;
+use tracing::{Level, event};
+
+use crate::Error;
+
+const FORWARDED_FOR_HEADER: &str = "X-Forwarded-For";
+
+/// Client addresses allowed to use a listener
+#[derive(Debug, Default)]
+pub struct Acl {
+    pub(crate) allow: Vec<IpNet>,
+    pub(crate) deny: Vec<IpNet>,
+    pub(crate) trusted_proxies: Vec<IpNet>,
+}
+
+/// Parse a network in CIDR notation, a single address being a network of its own
+pub(crate) fn parse_network(network: &str) -> Result<IpNet, String> {
+    IpNet::from_str(network)
+        .or_else(|_| IpAddr::from_str(network).map(IpNet::from))
+        .map_err(|_| format!("Invalid network '{network}'"))
+}
+
+impl Acl {
+    fn is_allowed(&self, address: &IpAddr) -> bool {
+        if self.deny.iter().any(|network| network.contains(address)) {
+            return false;
+        }
+
+        self.allow.is_empty() || self.allow.iter().any(|network| network.contains(address))
+    }
+
+    fn is_trusted(&self, address: &IpAddr) -> bool {
+        self.trusted_proxies
+            .iter()
+            .any(|network| network.contains(address))
+    }
+
+    /// Address of the client, taken from `X-Forwarded-For` only when the peer is a trusted proxy
+    fn client_address(&self, peer: IpAddr, request: &Request) -> IpAddr {
+        if !self.is_trusted(&peer) {
+            return peer;
+        }
+
+        let forwarded: Vec<IpAddr> = request
+            .headers()
+            .get_all(FORWARDED_FOR_HEADER)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .filter_map(|address| IpAddr::from_str(address.trim()).ok())
+            .collect();
+
+        // The closest untrusted hop is the client, anything before it can be spoofed
+        forwarded
+            .into_iter()
+            .rev()
+            .find(|address| !self.is_trusted(address))
+            .unwrap_or(peer)
+    }
+}
+
+pub async fn acl_middleware(
+    State(acl): State<Arc<Acl>>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let address = acl.client_address(peer.ip(), &request);
+
+    if acl.is_allowed(&address) {
+        next.run(request).await
+    } else {
+        event!(Level::WARN, "Denied request from {address}");
+
+        Error {
+            status: 403,
+            message: String::from("Forbidden"),
+        }
+        .into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::Body;
+
+    use super::*;
+
+    fn acl(trusted_proxies: &[&str]) -> Acl {
+        Acl {
+            trusted_proxies: trusted_proxies
+                .iter()
+                .map(|network| parse_network(network).unwrap())
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    fn request(forwarded: &[&str]) -> Request {
+        let mut request = Request::builder();
+
+        for value in forwarded {
+            request = request.header(FORWARDED_FOR_HEADER, *value);
+        }
+
+        request.body(Body::empty()).unwrap()
+    }
+
+    fn address(address: &str) -> IpAddr {
+        IpAddr::from_str(address).unwrap()
+    }
+
+    #[test]
+    fn ignores_forwarded_for_from_untrusted_peers() {
+        let acl = acl(&["10.0.0.0/8"]);
+
+        assert_eq!(
+            acl.client_address(address("192.0.2.1"), &request(&["198.51.100.7"])),
+            address("192.0.2.1")
+        );
+    }
+
+    #[test]
+    fn takes_closest_untrusted_hop() {
+        let acl = acl(&["10.0.0.0/8"]);
+
+        // The first address was sent by the client, it could be anything
+        assert_eq!(
+            acl.client_address(
+                address("10.0.0.2"),
+                &request(&["203.0.113.9, 198.51.100.7", "10.0.0.1"])
+            ),
+            address("198.51.100.7")
+        );
+    }
+
+    #[test]
+    fn falls_back_to_peer() {
+        let acl = acl(&["10.0.0.0/8"]);
+
+        assert_eq!(
+            acl.client_address(address("10.0.0.2"), &request(&[])),
+            address("10.0.0.2")
+        );
+        assert_eq!(
+            acl.client_address(address("10.0.0.2"), &request(&["10.0.0.1, not an address"])),
+            address("10.0.0.2")
+        );
+    }
+
+    #[test]
+    fn denies_before_allowing() {
+        let acl = Acl {
+            allow: vec![parse_network("192.0.2.0/24").unwrap()],
+            deny: vec![parse_network("192.0.2.66").unwrap()],
+            ..Default::default()
+        };
+
+        assert!(acl.is_allowed(&address("192.0.2.1")));
+        assert!(!acl.is_allowed(&address("192.0.2.66")));
+        assert!(!acl.is_allowed(&address("198.51.100.7")));
+    }
+}
@@ -0,0 +1,168 @@
+use std::sync::Arc;
+
+use axum::{
+    Extension, Json,
+    extract::{Path, Query, Request, State},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::{Level, event, instrument};
+
+use crate::{
+    Error, budgets::api_key, export::ExportFormat, logging::LogFilter, models::Message,
+    storage::Storage,
+};
+
+/// Listener of the administration API, whose requests must give its token
+#[derive(Debug)]
+pub struct Admin {
+    pub listener: String,
+    pub(crate) token: String,
+}
+
+/// Refuse the requests to the administration API without its token, given as a bearer token or
+/// in the `X-API-Key` header
+pub async fn admin_middleware(
+    State(admin): State<Arc<Admin>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if api_key(request.headers()) == Some(admin.token.as_str()) {
+        return next.run(request).await;
+    }
+
+    event!(
+        Level::WARN,
+        "Denied request to {} without the admin token",
+        request.uri().path()
+    );
+
+    Error {
+        status: 401,
+        message: String::from("Unknown admin token"),
+    }
+    .into_response()
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UsageQuery {
+    workspace: Option<String>,
+    since: Option<DateTime<Utc>>,
+    /// Split the usage of each workspace by the model that answered
+    #[serde(default)]
+    by_model: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ExportQuery {
+    #[serde(default)]
+    format: ExportFormat,
+}
+
+/// Filter of the logs, with the same syntax as `RUST_LOG`
+#[derive(Debug, Deserialize, Serialize)]
+pub struct LogLevel {
+    filter: String,
+}
+
+#[instrument(skip(storage))]
+pub async fn usage_handler(
+    Extension(storage): Extension<Option<Arc<Storage>>>,
+    Query(query): Query<UsageQuery>,
+) -> Result<impl IntoResponse, Error> {
+    let Some(storage) = storage else {
+        return Err(Error {
+            status: 404,
+            message: String::from("Storage not configured"),
+        });
+    };
+
+    let usage = storage
+        .usage(query.workspace.as_deref(), query.since, query.by_model)
+        .await
+        .map_err(|error| {
+            event!(Level::ERROR, "Couldn't query usage: {error}");
+
+            Error {
+                status: 500,
+                message: String::from("Couldn't query usage"),
+            }
+        })?;
+
+    Ok(Json(usage))
+}
+
+#[instrument(skip(storage))]
+pub async fn export_handler(
+    Extension(storage): Extension<Option<Arc<Storage>>>,
+    Path(id): Path<String>,
+    Query(query): Query<ExportQuery>,
+) -> Result<Response, Error> {
+    let Some(storage) = storage else {
+        return Err(Error {
+            status: 404,
+            message: String::from("Storage not configured"),
+        });
+    };
+
+    let conversation = storage.conversation(&id).await.map_err(|error| {
+        event!(Level::ERROR, "Couldn't query conversation: {error}");
+
+        Error {
+            status: 500,
+            message: String::from("Couldn't query conversation"),
+        }
+    })?;
+
+    let Some(conversation) = conversation else {
+        return Err(Error {
+            status: 404,
+            message: format!("Unknown conversation '{id}'"),
+        });
+    };
+
+    let messages: Vec<Message> =
+        serde_json::from_str(&conversation.transcript).map_err(|error| {
+            event!(
+                Level::ERROR,
+                "Invalid transcript of conversation {id}: {error}"
+            );
+
+            Error {
+                status: 500,
+                message: String::from("Invalid transcript"),
+            }
+        })?;
+
+    Ok(query.format.export(&id, &conversation, messages))
+}
+
+#[instrument(skip(config))]
+pub async fn config_handler(Extension(config): Extension<Arc<Value>>) -> impl IntoResponse {
+    Json(config)
+}
+
+#[instrument(skip(logs))]
+pub async fn log_level_handler(Extension(logs): Extension<Arc<LogFilter>>) -> impl IntoResponse {
+    Json(LogLevel {
+        filter: logs.current(),
+    })
+}
+
+#[instrument(skip(logs))]
+pub async fn set_log_level_handler(
+    Extension(logs): Extension<Arc<LogFilter>>,
+    Json(level): Json<LogLevel>,
+) -> Result<impl IntoResponse, Error> {
+    logs.set(&level.filter).map_err(|message| Error {
+        status: 400,
+        message,
+    })?;
+
+    Ok(Json(LogLevel {
+        filter: logs.current(),
+    }))
+}
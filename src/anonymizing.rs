@@ -0,0 +1,83 @@
+use hmac::{Hmac, Mac};
+use rmcp::model::JsonObject;
+use serde_json::Value;
+use sha2::Sha256;
+
+use crate::models::{Message, TextMessage};
+
+/// Prefix of the hashes standing for the contents
+const HASH_PREFIX: &str = "hmac-sha256:";
+
+/// Contents of the messages and tool calls stored as salted hashes instead of plaintext, so the
+/// same content can be traced across requests without being readable. The salt is kept by the
+/// deployment, the hashes of two deployments not matching
+#[derive(Debug)]
+pub struct Anonymizer {
+    salt: String,
+}
+
+impl Anonymizer {
+    pub fn new(salt: String) -> Anonymizer {
+        Anonymizer { salt }
+    }
+
+    /// HMAC-SHA256 of a text, keyed with the salt
+    pub(crate) fn hash(&self, text: &str) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.salt.as_bytes())
+            .expect("HMAC accepts keys of any size");
+        mac.update(text.as_bytes());
+
+        format!("{HASH_PREFIX}{}", hex::encode(mac.finalize().into_bytes()))
+    }
+
+    /// Arguments of a tool call with the hash of each value, the names of the parameters being kept
+    pub(crate) fn arguments(&self, arguments: &JsonObject) -> JsonObject {
+        arguments
+            .iter()
+            .map(|(name, value)| {
+                let text = match value {
+                    Value::String(text) => text.clone(),
+                    value => value.to_string(),
+                };
+
+                (name.clone(), Value::String(self.hash(&text)))
+            })
+            .collect()
+    }
+
+    /// Message with its content hashed, its role and the tools called being kept
+    pub(crate) fn message(&self, message: &Message) -> Message {
+        match message {
+            Message::TextMessage(TextMessage { role, content }) => {
+                Message::TextMessage(TextMessage {
+                    role: role.clone(),
+                    content: self.hash(content),
+                })
+            }
+            Message::ToolCalls { role, tool_calls } => Message::ToolCalls {
+                role: role.clone(),
+                tool_calls: tool_calls
+                    .iter()
+                    .map(|call| {
+                        let mut call = call.clone();
+                        call.arguments = call
+                            .arguments
+                            .as_ref()
+                            .map(|arguments| self.arguments(arguments));
+
+                        call
+                    })
+                    .collect(),
+            },
+            Message::ToolOutput {
+                r#type,
+                call_id,
+                output,
+            } => Message::ToolOutput {
+                r#type: r#type.clone(),
+                call_id: call_id.clone(),
+                output: self.hash(output),
+            },
+        }
+    }
+}
@@ -0,0 +1,185 @@
+use std::sync::Arc;
+
+use axum::{
+    Extension, Json,
+    http::{HeaderMap, header::AUTHORIZATION},
+    response::IntoResponse,
+};
+use chrono::{DateTime, Datelike, Utc};
+use serde::{Deserialize, Serialize};
+use tracing::{Level, event, instrument};
+
+use crate::{Error, models::Usage, storage::Storage};
+
+/// Header holding the API key of a client, when not given as a bearer token
+const API_KEY_HEADER: &str = "x-api-key";
+const BEARER_PREFIX: &str = "Bearer ";
+const TOKENS_PER_PRICE: f64 = 1_000_000.0;
+
+/// Price of the tokens of a model, per million tokens
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+pub struct Price {
+    pub(crate) prompt: f64,
+    pub(crate) completion: f64,
+}
+
+impl Price {
+    pub(crate) fn cost(&self, usage: &Usage) -> f64 {
+        (usage.prompt_tokens as f64 * self.prompt
+            + usage.completion_tokens as f64 * self.completion)
+            / TOKENS_PER_PRICE
+    }
+}
+
+/// Span of time after which a budget starts over
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Period {
+    Day,
+    Month,
+}
+
+impl Period {
+    fn name(&self) -> &'static str {
+        match self {
+            Period::Day => "day",
+            Period::Month => "month",
+        }
+    }
+
+    fn start(&self, now: DateTime<Utc>) -> DateTime<Utc> {
+        let day = now
+            .date_naive()
+            .and_hms_opt(0, 0, 0)
+            .expect("Midnight is a valid time")
+            .and_utc();
+
+        match self {
+            Period::Day => day,
+            Period::Month => day
+                .with_day(1)
+                .expect("The first day of the month is a valid date"),
+        }
+    }
+}
+
+/// Tokens and cost a client can spend per period
+#[derive(Debug)]
+pub struct Budget {
+    pub(crate) name: String,
+    pub(crate) key: String,
+    pub(crate) period: Period,
+    pub(crate) max_tokens: Option<u64>,
+    pub(crate) max_cost: Option<f64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BudgetReport {
+    name: String,
+    period: Period,
+    since: DateTime<Utc>,
+    tokens: i64,
+    cost: f64,
+    max_tokens: Option<u64>,
+    max_cost: Option<f64>,
+    exceeded: bool,
+}
+
+impl Budget {
+    async fn report(&self, storage: &Storage) -> Result<BudgetReport, Error> {
+        let since = self.period.start(Utc::now());
+
+        let (tokens, cost) = storage.spent(&self.name, since).await.map_err(|error| {
+            event!(
+                Level::ERROR,
+                "Couldn't query budget of {}: {error}",
+                self.name
+            );
+
+            Error {
+                status: 500,
+                message: String::from("Couldn't check budget"),
+            }
+        })?;
+
+        let exceeded = self
+            .max_tokens
+            .is_some_and(|max_tokens| tokens >= max_tokens as i64)
+            || self.max_cost.is_some_and(|max_cost| cost >= max_cost);
+
+        Ok(BudgetReport {
+            name: self.name.clone(),
+            period: self.period,
+            since,
+            tokens,
+            cost,
+            max_tokens: self.max_tokens,
+            max_cost: self.max_cost,
+            exceeded,
+        })
+    }
+
+    /// Refuse the requests of a client that spent its budget for the current period
+    #[instrument(skip_all, fields(client = self.name))]
+    pub(crate) async fn check(&self, storage: &Storage) -> Result<(), Error> {
+        if self.report(storage).await?.exceeded {
+            event!(Level::WARN, "Budget of {} exceeded", self.name);
+
+            return Err(Error {
+                status: 429,
+                message: format!("Budget exceeded for the current {}", self.period.name()),
+            });
+        }
+
+        Ok(())
+    }
+}
+
+/// Budgets of the clients, recognized by their API key
+#[derive(Debug, Default)]
+pub struct Budgets {
+    pub(crate) budgets: Vec<Budget>,
+}
+
+impl Budgets {
+    /// Budget of the client sending a request, from its bearer token or API key header
+    pub(crate) fn client(&self, headers: &HeaderMap) -> Option<&Budget> {
+        let key = api_key(headers)?;
+
+        self.budgets.iter().find(|budget| budget.key == key)
+    }
+}
+
+/// API key of the client sending a request, from its bearer token or API key header
+pub(crate) fn api_key(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix(BEARER_PREFIX))
+        .or_else(|| {
+            headers
+                .get(API_KEY_HEADER)
+                .and_then(|value| value.to_str().ok())
+        })
+}
+
+#[instrument(skip(budgets, storage))]
+pub async fn budgets_handler(
+    Extension(budgets): Extension<Arc<Budgets>>,
+    Extension(storage): Extension<Option<Arc<Storage>>>,
+) -> Result<impl IntoResponse, Error> {
+    let Some(storage) = storage else {
+        return Err(Error {
+            status: 404,
+            message: String::from("Storage not configured"),
+        });
+    };
+
+    let mut reports = Vec::new();
+
+    for budget in budgets.budgets.iter() {
+        reports.push(budget.report(&storage).await?);
+    }
+
+    Ok(Json(reports))
+}
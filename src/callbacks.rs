@@ -0,0 +1,104 @@
+use std::time::Duration;
+
+use hmac::{Hmac, Mac};
+use reqwest::{Client as HttpClient, Url, header::HeaderValue};
+use sha2::Sha256;
+use tokio::time::sleep;
+use tracing::{Level, event, instrument};
+
+use crate::{Error, jobs::Job};
+
+const SIGNATURE_HEADER: &str = "X-MCP-Manager-Signature";
+const TIMEOUT: Duration = Duration::from_secs(10);
+const ATTEMPTS: u32 = 3;
+
+/// Notification of finished requests to the URL given in their `callback_url`
+#[derive(Debug)]
+pub struct Callbacks {
+    secret: String,
+    allowed_hosts: Option<Vec<String>>,
+    client: HttpClient,
+}
+
+impl Callbacks {
+    pub fn new(
+        secret: String,
+        allowed_hosts: Option<Vec<String>>,
+        user_agent: HeaderValue,
+    ) -> Callbacks {
+        Callbacks {
+            secret,
+            allowed_hosts,
+            client: HttpClient::builder()
+                .timeout(TIMEOUT)
+                .user_agent(user_agent)
+                .build()
+                .expect("Couldn't create callback client"),
+        }
+    }
+
+    /// Parse a callback URL, refusing the ones to hosts that aren't allowed
+    pub(crate) fn check(&self, url: &str) -> Result<Url, Error> {
+        let url = Url::parse(url)
+            .ok()
+            .filter(|url| matches!(url.scheme(), "http" | "https"))
+            .ok_or(Error {
+                status: 400,
+                message: format!("Invalid callback URL '{url}'"),
+            })?;
+
+        if let Some(allowed_hosts) = &self.allowed_hosts
+            && !url
+                .host_str()
+                .is_some_and(|host| allowed_hosts.iter().any(|allowed| allowed == host))
+        {
+            return Err(Error {
+                status: 400,
+                message: format!("Callback URL '{url}' not allowed"),
+            });
+        }
+
+        Ok(url)
+    }
+
+    /// POST the outcome of a job, signed with HMAC-SHA256 of the body
+    #[instrument(skip_all, fields(job = job.id()))]
+    pub(crate) async fn send(&self, url: Url, job: &Job) {
+        let body = serde_json::to_vec(job).expect("Couldn't serialize job");
+
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.secret.as_bytes())
+            .expect("HMAC accepts keys of any size");
+        mac.update(&body);
+        let signature = format!("sha256={}", hex::encode(mac.finalize().into_bytes()));
+
+        for attempt in 1..=ATTEMPTS {
+            let response = self
+                .client
+                .post(url.clone())
+                .header("Content-Type", "application/json")
+                .header(SIGNATURE_HEADER, &signature)
+                .body(body.clone())
+                .send()
+                .await
+                .and_then(|response| response.error_for_status());
+
+            match response {
+                Ok(_) => {
+                    event!(Level::INFO, "Sent callback to {url}");
+
+                    return;
+                }
+                Err(error) => event!(
+                    Level::WARN,
+                    "Callback to {url} failed (attempt {attempt}/{ATTEMPTS}): {error}"
+                ),
+            }
+
+            if attempt < ATTEMPTS {
+                sleep(Duration::from_secs(2u64.pow(attempt - 1))).await;
+            }
+        }
+
+        event!(Level::ERROR, "Giving up on callback to {url}");
+    }
+}
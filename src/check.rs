@@ -0,0 +1,161 @@
+use std::{collections::BTreeMap, fmt, sync::Arc, time::Duration};
+
+use futures::future::join_all;
+use tokio::time::timeout;
+
+use crate::{
+    ManagerBody, ManagerConfig,
+    mcp::McpServer,
+    models::{AIModel, Message, Role, TextMessage},
+};
+
+/// Message sent to the models to check they accept the credentials
+const PROBE: &str = "ping";
+
+/// Time a model or MCP server is given to answer before it's reported as failed
+const CHECK_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Outcome of checking a model or a MCP server
+enum Outcome {
+    /// MCP server listing its tools
+    Tools(usize),
+    /// Model answering its probe
+    Answered,
+    Failed(String),
+}
+
+/// Outcome of checking every model and MCP server of a configuration, with the workspaces that
+/// can't answer because of them
+pub struct CheckReport {
+    models: BTreeMap<String, Outcome>,
+    mcps: BTreeMap<String, Outcome>,
+    /// Models and MCP servers each workspace depends on
+    workspaces: BTreeMap<String, (Vec<String>, Vec<String>)>,
+}
+
+impl CheckReport {
+    /// Models and MCP servers that failed their check
+    pub fn failures(&self) -> usize {
+        self.models
+            .values()
+            .chain(self.mcps.values())
+            .filter(|outcome| matches!(outcome, Outcome::Failed(_)))
+            .count()
+    }
+}
+
+impl fmt::Display for CheckReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let failed = |outcomes: &BTreeMap<String, Outcome>, name: &String| {
+            matches!(outcomes.get(name), Some(Outcome::Failed(_)))
+        };
+
+        writeln!(f, "Models:")?;
+        for (name, outcome) in self.models.iter() {
+            writeln!(f, "  {name}: {outcome}")?;
+        }
+
+        writeln!(f, "MCP servers:")?;
+        for (name, outcome) in self.mcps.iter() {
+            writeln!(f, "  {name}: {outcome}")?;
+        }
+
+        writeln!(f, "Workspaces:")?;
+        for (name, (models, mcps)) in self.workspaces.iter() {
+            let failing: Vec<&str> = models
+                .iter()
+                .filter(|model| failed(&self.models, model))
+                .chain(mcps.iter().filter(|mcp| failed(&self.mcps, mcp)))
+                .map(String::as_str)
+                .collect();
+
+            if failing.is_empty() {
+                writeln!(f, "  {name}: ok")?;
+            } else {
+                writeln!(f, "  {name}: failing {}", failing.join(", "))?;
+            }
+        }
+
+        write!(f, "{} failure(s)", self.failures())
+    }
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Outcome::Tools(tools) => write!(f, "ok, {tools} tool(s)"),
+            Outcome::Answered => write!(f, "ok"),
+            Outcome::Failed(error) => write!(f, "FAILED: {error}"),
+        }
+    }
+}
+
+/// Check every MCP server lists its tools and every model answers a 1-token request, at the same
+/// time
+pub async fn check(config: &ManagerConfig) -> CheckReport {
+    let mcps = join_all(
+        config
+            .mcps
+            .iter()
+            .map(|(name, mcp)| async move { (name.clone(), check_mcp(Arc::clone(mcp)).await) }),
+    );
+    let models =
+        join_all(config.models.iter().map(|(name, model)| async move {
+            (name.clone(), check_model(Arc::clone(model)).await)
+        }));
+
+    let (mcps, models) = futures::join!(mcps, models);
+
+    let workspaces = config
+        .workspaces
+        .iter()
+        .map(|(name, workspace)| {
+            (
+                name.clone(),
+                (
+                    workspace
+                        .models
+                        .iter()
+                        .map(|model| model.name.clone())
+                        .collect(),
+                    workspace.mcps.iter().map(|(mcp, _)| mcp.clone()).collect(),
+                ),
+            )
+        })
+        .collect();
+
+    CheckReport {
+        models: models.into_iter().collect(),
+        mcps: mcps.into_iter().collect(),
+        workspaces,
+    }
+}
+
+async fn check_mcp(mcp: Arc<dyn McpServer + Send>) -> Outcome {
+    match timeout(CHECK_TIMEOUT, mcp.list_tools()).await {
+        Ok(Ok(tools)) => Outcome::Tools(tools.len()),
+        Ok(Err(error)) => Outcome::Failed(error.to_string()),
+        Err(_) => Outcome::Failed(String::from("Timed out listing the tools")),
+    }
+}
+
+async fn check_model(model: Arc<dyn AIModel + Send>) -> Outcome {
+    let body = ManagerBody {
+        messages: vec![Message::TextMessage(TextMessage {
+            role: Role::User,
+            content: String::from(PROBE),
+        })],
+        max_tokens: Some(1),
+        ..Default::default()
+    };
+
+    // Run apart, so a model panicking on an unexpected answer is reported as failed
+    let call = tokio::spawn(async move { model.call(body, Vec::new()).await });
+
+    match timeout(CHECK_TIMEOUT, call).await {
+        Ok(Ok(Ok(_))) => Outcome::Answered,
+        Ok(Ok(Err(error))) => Outcome::Failed(error.message),
+        Ok(Err(_)) => Outcome::Failed(String::from("Invalid response from model")),
+        Err(_) => Outcome::Failed(String::from("Timed out answering")),
+    }
+}
@@ -0,0 +1,99 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Request, State},
+    http::Method,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use rmcp::model::Tool;
+use tracing::{Level, event};
+
+use crate::{Error, budgets::api_key, health::HEALTH_SUFFIX};
+
+/// Client of the workspaces, recognized by the API key of its requests
+#[derive(Debug)]
+pub struct Client {
+    pub(crate) name: String,
+    pub(crate) key: String,
+    /// Names of the workspaces it can use
+    pub(crate) workspaces: Vec<String>,
+    /// Tools its requests can use, every tool of the workspaces when not set
+    pub(crate) tools: Option<Vec<String>>,
+}
+
+impl Client {
+    /// Refuse the requests to the workspaces the client can't use
+    pub(crate) fn check(&self, workspace: &str) -> Result<(), Error> {
+        if self.workspaces.iter().any(|allowed| allowed == workspace) {
+            return Ok(());
+        }
+
+        event!(
+            Level::WARN,
+            "Client {} isn't allowed in workspace {workspace}",
+            self.name
+        );
+
+        Err(Error {
+            status: 403,
+            message: String::from("Forbidden"),
+        })
+    }
+
+    /// Leave out the tools, listed by MCP server, the client can't use
+    pub(crate) fn filter_tools(&self, tools: &mut [Vec<Tool>]) {
+        let Some(allowed) = &self.tools else {
+            return;
+        };
+
+        for tools in tools.iter_mut() {
+            tools.retain(|tool| allowed.iter().any(|name| *name == tool.name));
+        }
+    }
+}
+
+/// Clients allowed to use the workspaces, every request needing the key of one of them when
+/// there are some
+#[derive(Debug, Default)]
+pub struct Clients {
+    pub(crate) clients: Vec<Arc<Client>>,
+}
+
+/// Resolve the client of the request from its API key, for the handlers to check what it can use.
+/// The health of the workspaces stays open to the probes
+pub async fn clients_middleware(
+    State(clients): State<Arc<Clients>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    if request.method() == Method::GET && request.uri().path().ends_with(HEALTH_SUFFIX) {
+        return next.run(request).await;
+    }
+
+    let client = api_key(request.headers()).and_then(|key| {
+        clients
+            .clients
+            .iter()
+            .find(|client| client.key == key)
+            .map(Arc::clone)
+    });
+
+    let Some(client) = client else {
+        event!(
+            Level::WARN,
+            "Denied request to {} without a known API key",
+            request.uri().path()
+        );
+
+        return Error {
+            status: 401,
+            message: String::from("Unknown API key"),
+        }
+        .into_response();
+    };
+
+    request.extensions_mut().insert(client);
+
+    next.run(request).await
+}
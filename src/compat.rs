@@ -0,0 +1,78 @@
+use std::sync::Arc;
+
+use axum::{Extension, Json};
+use futures::future::join_all;
+use serde::Serialize;
+use tracing::instrument;
+
+use crate::{HandlerConfig, clients::Client};
+
+/// Owner given to the workspaces listed as models
+const OWNER: &str = "mcp-manager";
+
+/// Workspaces of a listener, listed as models for the OpenAI SDKs
+#[derive(Debug, Serialize)]
+pub struct ModelList {
+    object: &'static str,
+    data: Vec<ModelEntry>,
+}
+
+#[derive(Debug, Serialize)]
+struct ModelEntry {
+    /// Name of the workspace
+    id: String,
+    object: &'static str,
+    created: u64,
+    owned_by: &'static str,
+    path: String,
+    /// Type of the model of the workspace
+    provider: &'static str,
+    tools: usize,
+}
+
+#[instrument(skip(config, caller))]
+pub async fn models_handler(
+    Extension(config): Extension<HandlerConfig>,
+    caller: Option<Extension<Arc<Client>>>,
+) -> Json<ModelList> {
+    // Clients only see the workspaces they can use
+    let workspaces: Vec<_> = config
+        .read()
+        .await
+        .iter()
+        .filter(|(_, workspace)| {
+            caller
+                .as_ref()
+                .is_none_or(|Extension(caller)| caller.workspaces.contains(&workspace.name))
+        })
+        .map(|(path, workspace)| (path.clone(), Arc::clone(workspace)))
+        .collect();
+
+    let mut data = join_all(workspaces.into_iter().map(|(path, workspace)| async move {
+        // Servers failing to list their tools don't count
+        let tools = join_all(workspace.mcps.iter().map(|(_, mcp)| mcp.list_tools()))
+            .await
+            .into_iter()
+            .flatten()
+            .map(|tools| tools.len())
+            .sum();
+
+        ModelEntry {
+            id: workspace.name.clone(),
+            object: "model",
+            created: 0,
+            owned_by: OWNER,
+            path,
+            provider: workspace.models[0].provider,
+            tools,
+        }
+    }))
+    .await;
+
+    data.sort_by(|a, b| a.id.cmp(&b.id));
+
+    Json(ModelList {
+        object: "list",
+        data,
+    })
+}
@@ -0,0 +1,196 @@
+use serde_yaml::{Mapping, Value};
+
+const DEFAULTS_KEY: &str = "defaults";
+const WORKSPACES_KEY: &str = "workspaces";
+/// Mappings whose entries only make sense together, replaced as a whole by the workspaces
+const REPLACED_KEYS: [&str; 1] = ["split"];
+
+/// Fill in each workspace with the options of the `defaults` section it doesn't set, the mappings
+/// being merged key by key. The section is taken out of the configuration once applied
+pub(super) fn apply_defaults(config: &mut Value) -> Result<(), String> {
+    let Some(config) = config.as_mapping_mut() else {
+        return Ok(());
+    };
+
+    let defaults = match config.remove(DEFAULTS_KEY) {
+        None | Some(Value::Null) => return Ok(()),
+        Some(Value::Mapping(defaults)) => defaults,
+        Some(_) => {
+            return Err(String::from(
+                "defaults must be a mapping of workspace options",
+            ));
+        }
+    };
+
+    let Some(workspaces) = config
+        .get_mut(WORKSPACES_KEY)
+        .and_then(Value::as_mapping_mut)
+    else {
+        return Ok(());
+    };
+
+    for (name, workspace) in workspaces.iter_mut() {
+        match workspace {
+            Value::Mapping(workspace) => merge(workspace, &defaults),
+            // A workspace with nothing of its own takes every default
+            Value::Null => *workspace = Value::Mapping(defaults.clone()),
+            _ => {
+                return Err(format!(
+                    "workspaces.{} must be a mapping",
+                    name.as_str().unwrap_or_default()
+                ));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Add the defaults a mapping doesn't have, going into the mappings both have (but the weights of
+/// the model splits). Anything else set, including lists and `null`, overrides the default
+fn merge(target: &mut Mapping, defaults: &Mapping) {
+    for (key, default) in defaults.iter() {
+        match (target.get_mut(key), default) {
+            (None, _) => {
+                target.insert(key.clone(), default.clone());
+            }
+            (Some(Value::Mapping(target)), Value::Mapping(default))
+                if !key.as_str().is_some_and(|key| REPLACED_KEYS.contains(&key)) =>
+            {
+                merge(target, default)
+            }
+            (Some(_), _) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_yaml::from_str;
+
+    use super::*;
+
+    fn applied(yaml: &str) -> Value {
+        let mut config: Value = from_str(yaml).unwrap();
+
+        config.apply_merge().unwrap();
+        apply_defaults(&mut config).unwrap();
+
+        config
+    }
+
+    #[test]
+    fn fills_in_workspaces() {
+        let config = applied(
+            "
+defaults:
+  model: gemini
+  limits:
+    max_tool_calls: 10
+    max_body_bytes: 1000
+  mcps: [filesystem]
+workspaces:
+  empty:
+  own:
+    model: openai
+    limits:
+      max_tool_calls: 3
+    mcps: []
+",
+        );
+
+        assert!(config.get(DEFAULTS_KEY).is_none());
+        assert_eq!(
+            config["workspaces"]["empty"],
+            from_str::<Value>(
+                "
+model: gemini
+limits:
+  max_tool_calls: 10
+  max_body_bytes: 1000
+mcps: [filesystem]
+"
+            )
+            .unwrap()
+        );
+        assert_eq!(
+            config["workspaces"]["own"],
+            from_str::<Value>(
+                "
+model: openai
+limits:
+  max_tool_calls: 3
+  max_body_bytes: 1000
+mcps: []
+"
+            )
+            .unwrap()
+        );
+    }
+
+    #[test]
+    fn replaces_model_splits() {
+        let config = applied(
+            "
+defaults:
+  model:
+    split:
+      gemini: 90
+      openai: 10
+workspaces:
+  test:
+    model:
+      split:
+        azure: 1
+",
+        );
+
+        assert_eq!(
+            config["workspaces"]["test"]["model"]["split"],
+            from_str::<Value>("azure: 1").unwrap()
+        );
+    }
+
+    #[test]
+    fn merges_anchors_before_defaults() {
+        let config = applied(
+            "
+shared: &shared
+  model: gemini
+defaults:
+  mcps: [filesystem]
+workspaces:
+  test:
+    <<: *shared
+    path: /test
+",
+        );
+
+        assert_eq!(
+            config["workspaces"]["test"],
+            from_str::<Value>(
+                "
+model: gemini
+path: /test
+mcps: [filesystem]
+"
+            )
+            .unwrap()
+        );
+    }
+
+    #[test]
+    fn refuses_other_defaults() {
+        let mut config: Value = from_str("defaults: [model]").unwrap();
+
+        assert!(apply_defaults(&mut config).is_err());
+
+        let mut config: Value =
+            from_str("defaults: {model: gemini}\nworkspaces: {test: 1}").unwrap();
+
+        assert_eq!(
+            apply_defaults(&mut config),
+            Err(String::from("workspaces.test must be a mapping"))
+        );
+    }
+}
@@ -0,0 +1,2230 @@
+use defaults::apply_defaults;
+use futures::{FutureExt, future::join_all};
+use regex::Regex;
+use registry::ModelCommon;
+use reqwest::{
+    Client as HttpClient, Url,
+    header::{HeaderMap, HeaderName, HeaderValue, USER_AGENT},
+};
+use rmcp::{
+    ServiceExt,
+    model::{JsonObject, Root},
+    transport::{SseTransport, sse::SseTransportRetryCofnig},
+};
+use serde::{Deserialize, Serialize, Serializer};
+use serde_json::{Value, json};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::File,
+    io::{self, ErrorKind},
+    path::PathBuf,
+    str::FromStr,
+    sync::{Arc, OnceLock},
+    time::{Duration, Instant},
+};
+use tokio::sync::Semaphore;
+use tracing::{Level, event};
+use validate::{Severity, validate};
+
+use crate::{
+    DEFAULT_ARGUMENT_RETRIES, FailMode, ManagerConfig, ResponseMode, UnknownToolPolicy, Workspace,
+    WorkspaceModel,
+    acl::{Acl, parse_network},
+    admin::Admin,
+    anonymizing::Anonymizer,
+    budgets::{Budget, Budgets, Period, Price},
+    callbacks::Callbacks,
+    clients::{Client, Clients},
+    constraints::OutputConstraints,
+    context::{Context, DEFAULT_TEMPLATE, LOCALE_TEMPLATE, Timezone},
+    embeddings::{Embeddings, EmbeddingsProvider},
+    guardrails::{Guardrails, Pii},
+    health::{Activity, MonitoredMcp, MonitoredModel},
+    idempotency::IdempotencyCache,
+    jobs::Jobs,
+    limits::Limits,
+    logging::{self, DEFAULT_LOG_MAX_FILES, DEFAULT_LOG_MAX_SIZE, LogFile, LogFormat},
+    mcp::{
+        McpClient, McpServer,
+        cache::CachedMcp,
+        converting::{ContentType, ConvertingMcp},
+        filtered::FilteredMcp,
+        forwarding::{Connect, Forwarding, ForwardingMcp, meta_transport},
+        http::{self, HttpSessions},
+        isolated::{IsolatedMcp, McpRuntime},
+        local::LocalMcp,
+        negotiate,
+        process::{self, Exit, McpProcess},
+        remote::RemoteMcp,
+        replicated::{Replica, ReplicatedMcp},
+        restarting::{
+            Backoff, DEFAULT_INITIAL_DELAY, DEFAULT_MAX_DELAY, DEFAULT_MAX_FAILURES, RestartPolicy,
+            RestartingMcp, Start,
+        },
+        retrying::{DEFAULT_RETRIES, Reconnect, RetryingMcp},
+        starting::{self, StartingMcp},
+    },
+    mirroring::{DEFAULT_MIRROR_PERCENT, Mirror},
+    models::{
+        AIModel, Message, TextMessage,
+        auth::{Auth, AuthLocation, Tls},
+        client::{ClientOptions, Pool, with_tls},
+        schema::SchemaRules,
+    },
+    moderation::{Moderation, ModerationProvider},
+    paging::Paging,
+    readiness::{Readiness, Status},
+    recording::{RecordedMcp, RecordedModel, Recorder, RecordingMode},
+    router::ToolRouter,
+    selftest::{JsonAssertion, SelfTest, parse_path},
+    shadow::Shadow,
+    shaping::{RateLimit, ShapedModel},
+    slow_calls::SlowCalls,
+    storage::Storage,
+    summarization::{DEFAULT_KEEP_LAST, Summarizer},
+    templates::Templates,
+    trimming::Trimming,
+};
+
+#[cfg(feature = "oauth2")]
+use crate::models::tokens::TokenCache;
+
+mod defaults;
+mod registry;
+mod validate;
+
+const DEFAULT_PORT: u16 = 7000;
+const DEFAULT_LISTENER: &str = "127.0.0.1";
+const REDACTED: &str = "<redacted>";
+/// MCP servers started at once, unless configured otherwise
+const DEFAULT_STARTUP_PARALLELISM: usize = 8;
+/// `User-Agent` of the requests to the models and MCP servers, unless configured otherwise
+const DEFAULT_USER_AGENT: &str = concat!("mcp-manager/", env!("CARGO_PKG_VERSION"));
+
+#[derive(Debug, Deserialize, Serialize)]
+struct FileConfig {
+    models: HashMap<String, Model>,
+    mcps: Option<HashMap<String, Mcp>>,
+    /// MCP servers grouped under a name, with the tools the workspaces get from them
+    toolsets: Option<HashMap<String, ToolsetConfig>>,
+    workspaces: HashMap<String, WorkspaceConfig>,
+    idempotency: Option<IdempotencyConfig>,
+    jobs: Option<JobsConfig>,
+    callbacks: Option<CallbacksConfig>,
+    storage: Option<StorageConfig>,
+    admin: Option<AdminConfig>,
+    listeners: Option<HashMap<String, ListenerConfig>>,
+    oauth: Option<OAuthConfig>,
+    logging: Option<LoggingConfig>,
+    readiness: Option<ReadinessConfig>,
+    recording: Option<RecordingConfig>,
+    runtime: Option<RuntimeConfig>,
+    budgets: Option<Vec<BudgetConfig>>,
+    clients: Option<HashMap<String, ClientConfig>>,
+    user_agent: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct BudgetConfig {
+    name: String,
+    #[serde(serialize_with = "redact")]
+    key: String,
+    period: Period,
+    max_tokens: Option<u64>,
+    max_cost: Option<f64>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct ClientConfig {
+    #[serde(serialize_with = "redact")]
+    key: String,
+    /// Names of the workspaces the client can use
+    workspaces: Vec<String>,
+    /// Tools the requests of the client can use, every tool of the workspaces by default
+    tools: Option<Vec<String>>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct RuntimeConfig {
+    mcp_threads: Option<usize>,
+    max_concurrent_calls: Option<usize>,
+    max_output_bytes: Option<usize>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct RecordingConfig {
+    mode: RecordingMode,
+    path: String,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct ReadinessConfig {
+    #[serde(default)]
+    background: bool,
+    #[serde(default)]
+    unavailable_while_starting: bool,
+    #[serde(default)]
+    probe_models: bool,
+    /// MCP servers started at once
+    startup_parallelism: Option<usize>,
+    /// Seconds a MCP server is given to start
+    startup_timeout: Option<u64>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct LoggingConfig {
+    #[serde(default)]
+    log_payloads: bool,
+    #[serde(default)]
+    format: LogFormat,
+    /// File the logs are written to instead of the standard output
+    file: Option<LogFileConfig>,
+    /// Level of each target (e.g. a module), over the one of `RUST_LOG`
+    levels: Option<BTreeMap<String, String>>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct LogFileConfig {
+    path: String,
+    /// Megabytes the file grows up to before being rotated
+    max_size: Option<u64>,
+    /// Rotated files kept
+    max_files: Option<usize>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct OAuthConfig {
+    token_cache: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct ListenerConfig {
+    allow: Option<Vec<String>>,
+    deny: Option<Vec<String>>,
+    trusted_proxies: Option<Vec<String>>,
+    #[serde(default)]
+    fail_mode: FailMode,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct StorageConfig {
+    sqlite: String,
+    /// Record the messages of the requests, for their conversations to be exported
+    #[serde(default)]
+    transcripts: bool,
+    /// Store the contents of the messages and tool calls as salted hashes
+    anonymize: Option<AnonymizeConfig>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct AnonymizeConfig {
+    #[serde(serialize_with = "redact")]
+    salt: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct AdminConfig {
+    port: u16,
+    address: Option<String>,
+    /// Secret the requests to the administration API must give
+    #[serde(serialize_with = "redact")]
+    token: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct IdempotencyConfig {
+    ttl: u64,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct JobsConfig {
+    retention: u64,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct CallbacksConfig {
+    #[serde(serialize_with = "redact")]
+    secret: String,
+    allowed_hosts: Option<Vec<String>>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase", tag = "type")]
+enum Model {
+    #[cfg(feature = "gemini")]
+    Gemini {
+        url: Option<String>,
+        preset: Option<GeminiPreset>,
+        model: Option<String>,
+        #[serde(serialize_with = "redact_option")]
+        api_key: Option<String>,
+        auth: Option<AuthMethod>,
+        tls: Option<TlsConfig>,
+        schema: Option<SchemaConfig>,
+        #[serde(serialize_with = "redact_values")]
+        headers: Option<HashMap<String, String>>,
+        price: Option<Price>,
+        pool: Option<PoolConfig>,
+        rate_limit: Option<RateLimitConfig>,
+    },
+    #[cfg(feature = "openai")]
+    OpenAI(BaseModel),
+    #[cfg(feature = "cohere")]
+    Cohere(BaseModel),
+    #[cfg(feature = "openai-compatible")]
+    #[serde(rename = "openai-compatible")]
+    OpenAICompatible {
+        #[serde(flatten)]
+        base: BaseModel,
+        supports_parallel_tool_calls: Option<bool>,
+        tool_call_id_required: Option<bool>,
+        max_tokens_field: Option<String>,
+    },
+    #[cfg(feature = "azure")]
+    Azure {
+        url: String,
+        auth: Option<AuthMethod>,
+        tls: Option<TlsConfig>,
+        schema: Option<SchemaConfig>,
+        #[serde(serialize_with = "redact_values")]
+        headers: Option<HashMap<String, String>>,
+        price: Option<Price>,
+        pool: Option<PoolConfig>,
+        rate_limit: Option<RateLimitConfig>,
+        #[serde(rename = "api-version")]
+        api_version: String,
+    },
+    #[cfg(feature = "xai")]
+    #[serde(rename = "xai")]
+    Xai {
+        url: Option<String>,
+        model: String,
+        #[serde(serialize_with = "redact_option")]
+        api_key: Option<String>,
+        auth: Option<AuthMethod>,
+        tls: Option<TlsConfig>,
+        schema: Option<SchemaConfig>,
+        #[serde(serialize_with = "redact_values")]
+        headers: Option<HashMap<String, String>>,
+        price: Option<Price>,
+        pool: Option<PoolConfig>,
+        rate_limit: Option<RateLimitConfig>,
+    },
+    #[cfg(feature = "anthropic")]
+    Anthropic {
+        url: String,
+        auth: Option<AuthMethod>,
+        tls: Option<TlsConfig>,
+        schema: Option<SchemaConfig>,
+        #[serde(serialize_with = "redact_values")]
+        headers: Option<HashMap<String, String>>,
+        price: Option<Price>,
+        pool: Option<PoolConfig>,
+        rate_limit: Option<RateLimitConfig>,
+        #[serde(rename = "anthropic-version")]
+        anthropic_version: String,
+        model: String,
+    },
+}
+
+/// Shorthands filling the endpoint and authentication of well-known APIs
+#[cfg(feature = "gemini")]
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+enum GeminiPreset {
+    GeminiApi,
+}
+
+#[cfg(any(feature = "openai", feature = "cohere", feature = "openai-compatible"))]
+#[derive(Debug, Deserialize, Serialize)]
+struct BaseModel {
+    url: String,
+    auth: Option<AuthMethod>,
+    tls: Option<TlsConfig>,
+    schema: Option<SchemaConfig>,
+    #[serde(serialize_with = "redact_values")]
+    headers: Option<HashMap<String, String>>,
+    model: String,
+    price: Option<Price>,
+    pool: Option<PoolConfig>,
+    rate_limit: Option<RateLimitConfig>,
+}
+
+/// Pooling of the connections to a model, durations in seconds
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct PoolConfig {
+    max_idle_connections: Option<usize>,
+    idle_timeout: Option<u64>,
+    tcp_nodelay: Option<bool>,
+    tcp_keep_alive: Option<u64>,
+    #[serde(default)]
+    http2_only: bool,
+    http2_keep_alive: Option<u64>,
+    http2_keep_alive_timeout: Option<u64>,
+}
+
+/// Ceilings of the calls made to a model
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+struct RateLimitConfig {
+    requests_per_minute: Option<u32>,
+    tokens_per_minute: Option<u64>,
+    max_concurrent: Option<usize>,
+}
+
+impl From<RateLimitConfig> for RateLimit {
+    fn from(value: RateLimitConfig) -> Self {
+        RateLimit {
+            requests_per_minute: value.requests_per_minute,
+            tokens_per_minute: value.tokens_per_minute,
+            max_concurrent: value.max_concurrent,
+        }
+    }
+}
+
+impl From<PoolConfig> for Pool {
+    fn from(value: PoolConfig) -> Self {
+        Pool {
+            max_idle_connections: value.max_idle_connections,
+            idle_timeout: value.idle_timeout.map(Duration::from_secs),
+            tcp_nodelay: value.tcp_nodelay,
+            tcp_keep_alive: value.tcp_keep_alive.map(Duration::from_secs),
+            http2_only: value.http2_only,
+            http2_keep_alive: value.http2_keep_alive.map(Duration::from_secs),
+            http2_keep_alive_timeout: value.http2_keep_alive_timeout.map(Duration::from_secs),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct SchemaConfig {
+    remove: Option<Vec<String>>,
+    max_depth: Option<usize>,
+    string_enums: Option<bool>,
+}
+
+impl From<SchemaConfig> for SchemaRules {
+    fn from(value: SchemaConfig) -> Self {
+        SchemaRules {
+            unsupported_keywords: value.remove.unwrap_or_default(),
+            max_depth: value.max_depth,
+            string_enums: value.string_enums.unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct TlsConfig {
+    cert: String,
+    key: String,
+    ca: Option<String>,
+}
+
+impl From<GuardrailsConfig> for Guardrails {
+    fn from(value: GuardrailsConfig) -> Self {
+        let mut deny: Vec<Regex> = value
+            .deny
+            .unwrap_or_default()
+            .iter()
+            .map(|pattern| {
+                Regex::new(pattern)
+                    .unwrap_or_else(|error| panic!("Invalid pattern \"{pattern}\": {error}"))
+            })
+            .collect();
+
+        deny.extend(Guardrails::keywords(
+            &value.deny_keywords.unwrap_or_default(),
+        ));
+
+        Guardrails {
+            deny,
+            pii: value.pii.unwrap_or_default(),
+        }
+    }
+}
+
+impl From<TlsConfig> for Tls {
+    fn from(value: TlsConfig) -> Self {
+        Tls {
+            cert: value.cert,
+            key: value.key,
+            ca: value.ca,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase", tag = "type", content = "config")]
+enum AuthMethod {
+    ApiKey(AuthConfig),
+    #[cfg(feature = "oauth2")]
+    OAuth2 {
+        url: String,
+        client_id: String,
+        #[serde(serialize_with = "redact")]
+        client_secret: String,
+        scope: Option<String>,
+    },
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(tag = "location")]
+enum AuthConfig {
+    #[serde(rename = "header")]
+    Header {
+        name: String,
+        #[serde(serialize_with = "redact")]
+        value: String,
+        prefix: Option<String>,
+    },
+    #[serde(rename = "parameter")]
+    Parameter {
+        name: String,
+        #[serde(serialize_with = "redact")]
+        value: String,
+    },
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct WorkspaceConfig {
+    model: WorkspaceModelConfig,
+    mcps: Option<Vec<String>>,
+    /// Toolsets whose MCP servers the workspace uses, along with its own
+    toolsets: Option<Vec<String>>,
+    config: WorkspaceListener,
+    limits: Option<LimitsConfig>,
+    guardrails: Option<GuardrailsConfig>,
+    moderation: Option<ModerationConfig>,
+    router: Option<RouterConfig>,
+    /// Candidate model given a copy of the requests
+    shadow_model: Option<String>,
+    /// Workspace given a sample of the requests, to compare its answers
+    mirror_to: Option<MirrorConfig>,
+    /// Messages given to the model before the ones of every request
+    preamble_messages: Option<Vec<TextMessage>>,
+    /// Current date and time given to the model on every request
+    context: Option<ContextConfig>,
+    /// Language the model is told to answer in
+    output_language: Option<String>,
+    /// Tokens of each answer of the model
+    max_response_tokens: Option<usize>,
+    /// Phrases the answers of the model can't have
+    forbidden_phrases: Option<Vec<String>>,
+    /// Prompts by name, with `{{variable}}` placeholders, the requests can expand
+    templates: Option<HashMap<String, String>>,
+    /// Embedding model answering `<path>/embeddings`, apart from the chat model
+    embeddings: Option<EmbeddingsConfig>,
+    /// Keys of the metadata of the requests given to the model
+    forward_metadata: Option<Vec<String>>,
+    #[serde(default)]
+    unknown_tools: UnknownToolPolicy,
+    #[serde(default)]
+    response_mode: ResponseMode,
+    #[serde(default)]
+    stream_tool_calls: bool,
+    /// Seconds between the keep-alives sent to the clients while the answer isn't ready
+    keep_alive: Option<u64>,
+    /// Expose `<path>/tools/<tool>/call`, calling a tool without the model
+    #[serde(default)]
+    direct_tool_calls: bool,
+    /// Let the requests ask for the tool calls as a plan, run once the client approves it
+    #[serde(default)]
+    planning: bool,
+    /// Whether the clients can send system messages, true by default
+    allow_client_system: Option<bool>,
+    /// Times the model is asked to call a tool again after giving invalid arguments, per request
+    argument_retries: Option<usize>,
+    /// Model calls whose tool calls are run in a request, unlimited by default
+    max_tool_rounds: Option<usize>,
+    /// Call the model once more without tools after the last tool round, for it to answer
+    #[serde(default)]
+    final_answer: bool,
+    trimming: Option<TrimmingConfig>,
+    summarization: Option<SummarizationConfig>,
+    /// Give the tool outputs too long for the model a page at a time
+    paging: Option<PagingConfig>,
+    /// Alert on the tool calls still running past a threshold
+    slow_calls: Option<SlowCallsConfig>,
+    /// Tool calls checked by `mcp-manager selftest`
+    selftest: Option<Vec<SelfTestConfig>>,
+}
+
+/// Call of a tool with what its output must hold
+#[derive(Debug, Deserialize, Serialize)]
+struct SelfTestConfig {
+    tool: String,
+    arguments: Option<JsonObject>,
+    /// Texts the output must contain
+    contains: Option<Vec<String>>,
+    /// Values of the output, parsed as JSON, by their JSONPath
+    json: Option<BTreeMap<String, Value>>,
+    /// Whether the tool must report an error
+    #[serde(default)]
+    error: bool,
+}
+
+impl From<SelfTestConfig> for SelfTest {
+    fn from(value: SelfTestConfig) -> Self {
+        SelfTest {
+            tool: value.tool,
+            arguments: value.arguments,
+            contains: value.contains.unwrap_or_default(),
+            json: value
+                .json
+                .unwrap_or_default()
+                .into_iter()
+                .map(|(path, expected)| JsonAssertion {
+                    segments: parse_path(&path).unwrap_or_else(|error| panic!("{error}")),
+                    path,
+                    expected,
+                })
+                .collect(),
+            error: value.error,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case", tag = "strategy")]
+enum TrimmingConfig {
+    Drop { turns: usize },
+    Summarize { turns: usize, model: String },
+    KeepLast { count: usize },
+}
+
+/// Model answering the requests of a workspace, or the share of the requests answered by each model
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(untagged)]
+enum WorkspaceModelConfig {
+    Single(String),
+    Split { split: HashMap<String, u32> },
+}
+
+impl WorkspaceModelConfig {
+    /// Models with their weight, the one getting the largest share first
+    fn weights(&self) -> Vec<(&String, u32)> {
+        match self {
+            WorkspaceModelConfig::Single(model) => vec![(model, 1)],
+            WorkspaceModelConfig::Split { split } => {
+                let mut weights: Vec<_> = split
+                    .iter()
+                    .map(|(model, weight)| (model, *weight))
+                    .collect();
+                weights.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+
+                weights
+            }
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct ContextConfig {
+    /// System message with placeholders filled in on every request
+    template: Option<String>,
+    /// UTC, local or a fixed offset, UTC by default
+    timezone: Option<String>,
+    /// Locale of the users, e.g. en-GB
+    locale: Option<String>,
+}
+
+/// MCP servers given together to the workspaces naming the toolset
+#[derive(Debug, Deserialize, Serialize)]
+struct ToolsetConfig {
+    mcps: Vec<String>,
+    /// Tools of the servers given to the model, all of them by default
+    tools: Option<Vec<String>>,
+    /// Options of each tool, over the ones of its server
+    overrides: Option<HashMap<String, ToolOverrideConfig>>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct ToolOverrideConfig {
+    /// Description given to the model
+    description: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct MirrorConfig {
+    workspace: String,
+    /// Percentage of the requests mirrored, all of them by default
+    percent: Option<f64>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct SummarizationConfig {
+    model: String,
+    max_tokens: usize,
+    keep_last: Option<usize>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct PagingConfig {
+    max_chars: usize,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct SlowCallsConfig {
+    /// Seconds a tool call runs before an alert is raised
+    threshold: u64,
+    /// URL POSTed each slow call
+    webhook: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct ModerationConfig {
+    #[serde(rename = "type")]
+    provider: ModerationProvider,
+    url: String,
+    auth: Option<AuthMethod>,
+    tls: Option<TlsConfig>,
+    #[serde(serialize_with = "redact_values")]
+    headers: Option<HashMap<String, String>>,
+    model: Option<String>,
+    threshold: Option<f64>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct EmbeddingsConfig {
+    #[serde(rename = "type")]
+    provider: EmbeddingsProvider,
+    url: String,
+    auth: Option<AuthMethod>,
+    tls: Option<TlsConfig>,
+    #[serde(serialize_with = "redact_values")]
+    headers: Option<HashMap<String, String>>,
+    model: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct GuardrailsConfig {
+    deny: Option<Vec<String>>,
+    deny_keywords: Option<Vec<String>>,
+    pii: Option<Vec<Pii>>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct RouterConfig {
+    model: String,
+    top_k: usize,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct LimitsConfig {
+    max_body_bytes: Option<usize>,
+    max_messages: Option<usize>,
+    max_message_chars: Option<usize>,
+    /// Tool calls a request can make
+    max_tool_calls: Option<usize>,
+    /// Calls a request can make to each of these tools
+    max_calls_per_tool: Option<HashMap<String, usize>>,
+}
+
+impl From<LimitsConfig> for Limits {
+    fn from(value: LimitsConfig) -> Self {
+        Limits {
+            max_body_bytes: value.max_body_bytes,
+            max_messages: value.max_messages,
+            max_message_chars: value.max_message_chars,
+            max_tool_calls: value.max_tool_calls,
+            max_calls_per_tool: value.max_calls_per_tool.unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct WorkspaceListener {
+    path: WorkspacePaths,
+    port: Option<u16>,
+    address: Option<String>,
+}
+
+/// Path of a workspace, or the paths it's reachable at
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(untagged)]
+enum WorkspacePaths {
+    Single(String),
+    Aliases(Vec<String>),
+}
+
+impl WorkspacePaths {
+    fn all(&self) -> &[String] {
+        match self {
+            WorkspacePaths::Single(path) => std::slice::from_ref(path),
+            WorkspacePaths::Aliases(paths) => paths,
+        }
+    }
+}
+
+#[allow(clippy::large_enum_variant)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(untagged)]
+enum Mcp {
+    Local {
+        command: String,
+        args: Option<Vec<String>>,
+        #[serde(serialize_with = "redact_values")]
+        env: Option<HashMap<String, String>>,
+        cache: Option<HashMap<String, u64>>,
+        /// Content types of the text outputs of each tool converted before they're given to the model
+        convert: Option<HashMap<String, Vec<ContentType>>>,
+        /// Arguments of each tool masked in the logs and traces
+        redact_args: Option<HashMap<String, Vec<String>>>,
+        /// Headers of the requests given in the `_meta` of the tool calls
+        forward_headers: Option<Vec<String>>,
+        /// Times a call failing on the transport is tried again on a new connection
+        retries: Option<usize>,
+        /// Fail to start when the server lacks the tools capability or speaks an unsupported
+        /// protocol version, instead of warning about it
+        #[serde(default)]
+        strict_capabilities: bool,
+        /// Directories the server is told it can work in
+        roots: Option<Vec<String>>,
+        /// When the server is started again after stopping
+        restart: Option<RestartConfig>,
+    },
+    Remote {
+        url: String,
+        /// URLs of other replicas of the same server
+        replicas: Option<Vec<String>>,
+        tls: Option<TlsConfig>,
+        /// Headers sent with every request to the server
+        #[serde(serialize_with = "redact_values")]
+        headers: Option<HashMap<String, String>>,
+        cache: Option<HashMap<String, u64>>,
+        /// Content types of the text outputs of each tool converted before they're given to the model
+        convert: Option<HashMap<String, Vec<ContentType>>>,
+        /// Arguments of each tool masked in the logs and traces
+        redact_args: Option<HashMap<String, Vec<String>>>,
+        /// Headers of the requests sent to the server
+        forward_headers: Option<Vec<String>>,
+        /// Times a call failing on the transport is tried again on a new connection
+        retries: Option<usize>,
+        /// Fail to start when the server lacks the tools capability or speaks an unsupported
+        /// protocol version, instead of warning about it
+        #[serde(default)]
+        strict_capabilities: bool,
+        /// How the server is reached, SSE by default
+        #[serde(default)]
+        transport: TransportType,
+        /// Options used when reached over SSE
+        sse: Option<SseConfig>,
+        /// Options used when reached over streamable HTTP
+        http: Option<HttpConfig>,
+    },
+}
+
+/// Restarts of a local MCP server after it stops
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+struct RestartConfig {
+    #[serde(default)]
+    policy: RestartPolicy,
+    /// Seconds waited before the first restart, doubled after each failure in a row
+    initial_delay: Option<u64>,
+    /// Seconds the wait grows up to, and the server must stay up for its failures to be forgotten
+    max_delay: Option<u64>,
+    /// Failures in a row before the server isn't started again
+    max_failures: Option<usize>,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum TransportType {
+    #[default]
+    Sse,
+    Http,
+}
+
+/// Options of the connections to a remote MCP server, whatever its transport
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+struct TransportOptions {
+    /// Headers sent with every request over this transport, besides the ones of the server
+    #[serde(serialize_with = "redact_values")]
+    headers: Option<HashMap<String, String>>,
+    /// API key the server is authenticated with
+    auth: Option<AuthConfig>,
+    /// Seconds between the pings keeping the connection alive while it's idle
+    heartbeat: Option<u64>,
+    /// Seconds between the TCP keep-alive probes of the connection
+    keep_alive: Option<u64>,
+}
+
+/// Options of a remote MCP server reached over SSE
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+struct SseConfig {
+    #[serde(flatten)]
+    options: TransportOptions,
+    /// Seconds waited before reconnecting a dropped stream, unless the server asks for more
+    reconnect_delay: Option<u64>,
+    /// Times in a row a dropped stream is reconnected before giving up, unlimited by default
+    max_reconnects: Option<usize>,
+}
+
+/// Options of a remote MCP server reached over streamable HTTP
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+struct HttpConfig {
+    #[serde(flatten)]
+    options: TransportOptions,
+    /// File the sessions are saved to, to resume them after a restart
+    session_file: Option<String>,
+}
+
+/// How a remote MCP server is reached, with the options of its transport
+#[derive(Clone)]
+enum RemoteTransport {
+    Sse(SseConfig),
+    Http {
+        config: HttpConfig,
+        /// Sessions resumed by the new connections
+        sessions: Option<Arc<HttpSessions>>,
+    },
+}
+
+impl RemoteTransport {
+    fn options(&self) -> &TransportOptions {
+        match self {
+            RemoteTransport::Sse(config) => &config.options,
+            RemoteTransport::Http { config, .. } => &config.options,
+        }
+    }
+}
+
+pub async fn get_config(file: &str) -> io::Result<ManagerConfig> {
+    load_config(file, false).await
+}
+
+/// Configuration whose models and MCP servers are checked instead of serving it: the MCP servers
+/// failing to start are left to fail their calls, and their readiness isn't tracked
+pub async fn get_check_config(file: &str) -> io::Result<ManagerConfig> {
+    load_config(file, true).await
+}
+
+async fn load_config(file: &str, check: bool) -> io::Result<ManagerConfig> {
+    let file = File::open(file).expect("Couldn't open file");
+
+    let mut file_config: serde_yaml::Value =
+        serde_yaml::from_reader(file).expect("Invalid configuration");
+    file_config
+        .apply_merge()
+        .expect("Invalid configuration: bad merge key");
+    apply_defaults(&mut file_config)
+        .unwrap_or_else(|error| panic!("Invalid configuration: {error}"));
+    let file_config: FileConfig =
+        serde_yaml::from_value(file_config).expect("Invalid configuration");
+
+    logging::set_secrets(secrets(&file_config));
+    logging::set_redacted_args(redacted_args(&file_config));
+    logging::set_log_payloads(
+        file_config
+            .logging
+            .as_ref()
+            .is_some_and(|logging| logging.log_payloads),
+    );
+
+    if let Some(logging) = &file_config.logging {
+        logging::configure(
+            logging.format,
+            logging.file.as_ref().map(|file| LogFile {
+                path: file.path.clone(),
+                max_bytes: file.max_size.unwrap_or(DEFAULT_LOG_MAX_SIZE) * 1024 * 1024,
+                max_files: file.max_files.unwrap_or(DEFAULT_LOG_MAX_FILES),
+            }),
+            logging.levels.as_ref().unwrap_or(&BTreeMap::new()),
+        )?;
+    }
+
+    let diagnostics = validate(&file_config);
+
+    for diagnostic in diagnostics.iter() {
+        match diagnostic.severity {
+            Severity::Warning => event!(Level::WARN, "{diagnostic}"),
+            Severity::Error => event!(Level::ERROR, "{diagnostic}"),
+        }
+    }
+
+    let errors = diagnostics
+        .iter()
+        .filter(|diagnostic| diagnostic.severity == Severity::Error)
+        .count();
+
+    if errors > 0 {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            format!("Invalid configuration: {errors} error(s) found"),
+        ));
+    }
+
+    let mut config = ManagerConfig {
+        effective: Arc::new(effective_config(&file_config)),
+        ..Default::default()
+    };
+
+    let user_agent = HeaderValue::from_str(
+        file_config
+            .user_agent
+            .as_deref()
+            .unwrap_or(DEFAULT_USER_AGENT),
+    )
+    .expect("Invalid user agent");
+
+    if let Some(idempotency) = file_config.idempotency {
+        config.idempotency = Arc::new(IdempotencyCache::new(Duration::from_secs(idempotency.ttl)));
+    }
+
+    if let Some(jobs) = file_config.jobs {
+        config.jobs = Arc::new(Jobs::new(Duration::from_secs(jobs.retention)));
+    }
+
+    if let Some(callbacks) = file_config.callbacks {
+        config.callbacks = Some(Arc::new(Callbacks::new(
+            callbacks.secret,
+            callbacks.allowed_hosts,
+            user_agent.clone(),
+        )));
+    }
+
+    if let Some(storage) = file_config.storage {
+        config.storage = Some(Arc::new(
+            Storage::new(
+                &storage.sqlite,
+                storage.transcripts,
+                storage
+                    .anonymize
+                    .map(|anonymize| Anonymizer::new(anonymize.salt)),
+            )
+            .await
+            .unwrap_or_else(|error| panic!("Couldn't open storage {}: {error}", storage.sqlite)),
+        ));
+    }
+
+    if let Some(budgets) = file_config.budgets {
+        config.budgets = Arc::new(Budgets {
+            budgets: budgets
+                .into_iter()
+                .map(|budget| Budget {
+                    name: budget.name,
+                    key: budget.key,
+                    period: budget.period,
+                    max_tokens: budget.max_tokens,
+                    max_cost: budget.max_cost,
+                })
+                .collect(),
+        });
+    }
+
+    if let Some(clients) = file_config.clients {
+        config.clients = Arc::new(Clients {
+            clients: clients
+                .into_iter()
+                .map(|(name, client)| {
+                    Arc::new(Client {
+                        name,
+                        key: client.key,
+                        workspaces: client.workspaces,
+                        tools: client.tools,
+                    })
+                })
+                .collect(),
+        });
+    }
+
+    if let Some(listeners) = file_config.listeners {
+        for (listener, acl) in listeners {
+            config.fail_modes.insert(listener.clone(), acl.fail_mode);
+
+            let networks = |networks: Option<Vec<String>>| {
+                networks
+                    .unwrap_or_default()
+                    .iter()
+                    .map(|network| parse_network(network).unwrap())
+                    .collect()
+            };
+
+            config.acls.insert(
+                listener,
+                Arc::new(Acl {
+                    allow: networks(acl.allow),
+                    deny: networks(acl.deny),
+                    trusted_proxies: networks(acl.trusted_proxies),
+                }),
+            );
+        }
+    }
+
+    if let Some(admin) = file_config.admin {
+        config.admin = Some(Arc::new(Admin {
+            listener: listener_address(admin.address.as_deref(), Some(admin.port)),
+            token: admin.token,
+        }));
+    }
+
+    let mut readiness = file_config.readiness.unwrap_or_default();
+
+    if check {
+        readiness.background = true;
+    }
+
+    #[cfg(feature = "oauth2")]
+    let tokens =
+        Arc::new(TokenCache::new(file_config.oauth.and_then(|oauth| oauth.token_cache)).await);
+
+    let recorder = file_config.recording.map(|recording| {
+        event!(
+            Level::INFO,
+            "Using recorded exchanges in {} ({:?} mode)",
+            recording.path,
+            recording.mode
+        );
+
+        Arc::new(Recorder {
+            mode: recording.mode,
+            path: PathBuf::from(recording.path),
+        })
+    });
+    let replaying = recorder
+        .as_ref()
+        .is_some_and(|recorder| recorder.replaying());
+
+    let runtime = file_config.runtime.map(|runtime| {
+        Arc::new(McpRuntime::new(
+            runtime.mcp_threads,
+            runtime.max_concurrent_calls,
+            runtime.max_output_bytes,
+        ))
+    });
+
+    let mut providers = HashMap::new();
+    let mut prices = HashMap::new();
+    let mut model_activity = HashMap::new();
+    let mut mcp_activity = HashMap::new();
+
+    for (name, model) in file_config.models {
+        let common = model.common();
+
+        providers.insert(name.clone(), model.provider());
+
+        if let Some(price) = common.price {
+            prices.insert(name.clone(), *price);
+        }
+
+        let options = ClientOptions {
+            auth: get_auth(common.auth.to_owned()),
+            tls: common.tls.to_owned().map(Tls::from),
+            headers: identified(&user_agent, common.headers.to_owned()),
+            pool: common.pool.to_owned().map(Pool::from).unwrap_or_default(),
+            #[cfg(feature = "oauth2")]
+            tokens: Arc::clone(&tokens),
+        };
+        let schema = common
+            .schema
+            .to_owned()
+            .map(SchemaRules::from)
+            .unwrap_or_default();
+
+        let rate_limit = common.rate_limit.map(RateLimit::from);
+        let activity = Arc::new(Activity::default());
+
+        let inner: Arc<dyn AIModel + Send> = match &recorder {
+            Some(recorder) => Arc::new(RecordedModel {
+                name: name.clone(),
+                inner: if recorder.replaying() {
+                    None
+                } else {
+                    Some(model.build(options, schema).await)
+                },
+                recorder: Arc::clone(recorder),
+            }),
+            None => model.build(options, schema).await,
+        };
+        let inner: Arc<dyn AIModel + Send> = match rate_limit {
+            Some(limit) => Arc::new(ShapedModel::new(name.clone(), inner, limit)),
+            None => inner,
+        };
+
+        model_activity.insert(name.clone(), Arc::clone(&activity));
+        config
+            .models
+            .insert(name, Arc::new(MonitoredModel { inner, activity }));
+    }
+
+    if let Some(config_mcps) = file_config.mcps {
+        let permits = Arc::new(Semaphore::new(
+            readiness
+                .startup_parallelism
+                .unwrap_or(DEFAULT_STARTUP_PARALLELISM),
+        ));
+        let startup_timeout = readiness.startup_timeout.map(Duration::from_secs);
+        let mut startups = Vec::new();
+        // Options of each server, in the order of their startups
+        let mut options = Vec::new();
+
+        for (name, mcp) in config_mcps {
+            let (cache, convert) = match mcp {
+                Mcp::Local {
+                    ref cache,
+                    ref convert,
+                    ..
+                }
+                | Mcp::Remote {
+                    ref cache,
+                    ref convert,
+                    ..
+                } => (cache.to_owned(), convert.to_owned()),
+            };
+
+            let startup = match &runtime {
+                Some(runtime) => {
+                    let runtime = Arc::clone(runtime);
+                    let startup = start_mcp(name.clone(), mcp, user_agent.clone());
+
+                    async move {
+                        let server = runtime
+                            .run(startup)
+                            .await
+                            .unwrap_or_else(|error| Err(error.to_string()))?;
+
+                        Ok(Arc::new(IsolatedMcp {
+                            inner: server,
+                            runtime,
+                        }) as Arc<dyn McpServer + Send>)
+                    }
+                    .boxed()
+                }
+                None => start_mcp(name.clone(), mcp, user_agent.clone()).boxed(),
+            };
+
+            startups.push(starting::bounded(
+                name.clone(),
+                startup,
+                Arc::clone(&permits),
+                startup_timeout,
+            ));
+            options.push((name, cache, convert));
+        }
+
+        let start = Instant::now();
+
+        let servers: Vec<Option<Arc<dyn McpServer + Send>>> = if replaying {
+            startups.iter().map(|_| None).collect()
+        } else if readiness.background {
+            startups
+                .into_iter()
+                .map(|startup| {
+                    Some(Arc::new(StartingMcp::new(startup)) as Arc<dyn McpServer + Send>)
+                })
+                .collect()
+        } else {
+            // Started together, a failure stopping the manager once every server is done
+            let started = join_all(startups)
+                .await
+                .into_iter()
+                .map(|result| result.map(Some))
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap_or_else(|error| panic!("{error}"));
+
+            event!(
+                Level::INFO,
+                "Started {} MCP servers in {}ms",
+                started.len(),
+                start.elapsed().as_millis()
+            );
+
+            started
+        };
+
+        for ((name, cache, convert), server) in options.into_iter().zip(servers) {
+            let server: Arc<dyn McpServer + Send> = match &recorder {
+                Some(recorder) => Arc::new(RecordedMcp {
+                    name: name.clone(),
+                    inner: server,
+                    recorder: Arc::clone(recorder),
+                }),
+                None => server.expect("MCP server started when not replaying"),
+            };
+
+            let activity = Arc::new(Activity::default());
+
+            mcp_activity.insert(name.clone(), Arc::clone(&activity));
+
+            let server: Arc<dyn McpServer + Send> = Arc::new(MonitoredMcp {
+                inner: server,
+                activity,
+            });
+
+            let server: Arc<dyn McpServer + Send> = match convert {
+                Some(conversions) => Arc::new(ConvertingMcp {
+                    inner: server,
+                    conversions,
+                }),
+                None => server,
+            };
+
+            config.mcps.insert(
+                name,
+                if let Some(cache) = cache {
+                    Arc::new(CachedMcp::new(server, cache))
+                } else {
+                    server
+                },
+            );
+        }
+    }
+
+    let mut dependencies = HashMap::new();
+    let toolsets = file_config.toolsets.unwrap_or_default();
+
+    for (name, config_workspace) in file_config.workspaces {
+        dependencies.insert(
+            name.clone(),
+            (
+                config_workspace
+                    .model
+                    .weights()
+                    .into_iter()
+                    .map(|(model, _)| model.clone())
+                    .collect(),
+                config_workspace
+                    .mcps
+                    .iter()
+                    .flatten()
+                    .chain(
+                        config_workspace
+                            .toolsets
+                            .iter()
+                            .flatten()
+                            .filter_map(|toolset| toolsets.get(toolset))
+                            .flat_map(|toolset| toolset.mcps.iter()),
+                    )
+                    .cloned()
+                    .collect(),
+            ),
+        );
+
+        config.workspaces.insert(name.clone(), {
+            let mut workspace = Workspace {
+                name: name.clone(),
+                models: config_workspace
+                    .model
+                    .weights()
+                    .into_iter()
+                    .map(|(model, weight)| WorkspaceModel {
+                        name: model.clone(),
+                        provider: providers.get(model).copied().unwrap_or_default(),
+                        model: Arc::clone(config.models.get(model).unwrap_or_else(|| {
+                            panic!("Undefined model {model} in workspace {name}")
+                        })),
+                        price: prices.get(model).copied(),
+                        weight,
+                    })
+                    .collect(),
+                mcps: Vec::new(),
+                limits: config_workspace
+                    .limits
+                    .map(Limits::from)
+                    .unwrap_or_default(),
+                guardrails: config_workspace
+                    .guardrails
+                    .map(Guardrails::from)
+                    .unwrap_or_default(),
+                moderation: if let Some(moderation) = config_workspace.moderation {
+                    Some(
+                        Moderation::new(
+                            moderation.provider,
+                            moderation.url,
+                            ClientOptions {
+                                auth: get_auth(moderation.auth),
+                                tls: moderation.tls.map(Tls::from),
+                                headers: identified(&user_agent, moderation.headers),
+                                pool: Pool::default(),
+                                #[cfg(feature = "oauth2")]
+                                tokens: Arc::clone(&tokens),
+                            },
+                            moderation.model,
+                            moderation.threshold,
+                        )
+                        .await,
+                    )
+                } else {
+                    None
+                },
+                router: config_workspace.router.map(|router| ToolRouter {
+                    model: Arc::clone(config.models.get(&router.model).unwrap_or_else(|| {
+                        panic!(
+                            "Undefined router model {} in workspace {name}",
+                            router.model
+                        )
+                    })),
+                    top_k: router.top_k,
+                }),
+                shadow: config_workspace.shadow_model.map(|model| Shadow {
+                    model: Arc::clone(config.models.get(&model).unwrap_or_else(|| {
+                        panic!("Undefined shadow model {model} in workspace {name}")
+                    })),
+                    price: prices.get(&model).copied(),
+                    name: model,
+                }),
+                mirror: config_workspace.mirror_to.map(|mirror| Mirror {
+                    name: mirror.workspace,
+                    workspace: OnceLock::new(),
+                    percent: mirror.percent.unwrap_or(DEFAULT_MIRROR_PERCENT),
+                    storage: config.storage.clone(),
+                }),
+                preamble: config_workspace
+                    .preamble_messages
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(Message::TextMessage)
+                    .collect(),
+                context: config_workspace.context.map(|context| Context {
+                    template: context.template.unwrap_or_else(|| match context.locale {
+                        Some(_) => format!("{DEFAULT_TEMPLATE}{LOCALE_TEMPLATE}"),
+                        None => String::from(DEFAULT_TEMPLATE),
+                    }),
+                    timezone: context
+                        .timezone
+                        .as_deref()
+                        .map(|timezone| {
+                            timezone
+                                .parse()
+                                .unwrap_or_else(|error: String| panic!("{error}"))
+                        })
+                        .unwrap_or(Timezone::Utc),
+                    locale: context.locale,
+                }),
+                constraints: if config_workspace.output_language.is_some()
+                    || config_workspace.max_response_tokens.is_some()
+                    || config_workspace
+                        .forbidden_phrases
+                        .as_ref()
+                        .is_some_and(|phrases| !phrases.is_empty())
+                {
+                    Some(OutputConstraints::new(
+                        config_workspace.output_language,
+                        config_workspace.max_response_tokens,
+                        config_workspace.forbidden_phrases.unwrap_or_default(),
+                    ))
+                } else {
+                    None
+                },
+                templates: Templates {
+                    templates: config_workspace.templates.unwrap_or_default(),
+                },
+                embeddings: if let Some(embeddings) = config_workspace.embeddings {
+                    Some(
+                        Embeddings::new(
+                            embeddings.provider,
+                            embeddings.url,
+                            ClientOptions {
+                                auth: get_auth(embeddings.auth),
+                                tls: embeddings.tls.map(Tls::from),
+                                headers: identified(&user_agent, embeddings.headers),
+                                pool: Pool::default(),
+                                #[cfg(feature = "oauth2")]
+                                tokens: Arc::clone(&tokens),
+                            },
+                            embeddings.model,
+                        )
+                        .await,
+                    )
+                } else {
+                    None
+                },
+                forward_metadata: config_workspace.forward_metadata.unwrap_or_default(),
+                unknown_tools: config_workspace.unknown_tools,
+                response_mode: config_workspace.response_mode,
+                stream_tool_calls: config_workspace.stream_tool_calls,
+                keep_alive: config_workspace.keep_alive.map(Duration::from_secs),
+                direct_tool_calls: config_workspace.direct_tool_calls,
+                planning: config_workspace.planning,
+                allow_client_system: config_workspace.allow_client_system.unwrap_or(true),
+                argument_retries: config_workspace
+                    .argument_retries
+                    .unwrap_or(DEFAULT_ARGUMENT_RETRIES),
+                max_tool_rounds: config_workspace.max_tool_rounds,
+                final_answer: config_workspace.final_answer,
+                metrics: Arc::clone(&config.metrics),
+                trimming: config_workspace.trimming.map(|trimming| match trimming {
+                    TrimmingConfig::Drop { turns } => Trimming::Drop { turns },
+                    TrimmingConfig::Summarize { turns, model } => Trimming::Summarize {
+                        turns,
+                        model: Arc::clone(config.models.get(&model).unwrap_or_else(|| {
+                            panic!("Undefined trimming model {model} in workspace {name}")
+                        })),
+                    },
+                    TrimmingConfig::KeepLast { count } => Trimming::KeepLast { count },
+                }),
+                summarization: config_workspace
+                    .summarization
+                    .map(|summarization| Summarizer {
+                        model: Arc::clone(config.models.get(&summarization.model).unwrap_or_else(
+                            || {
+                                panic!(
+                                    "Undefined summarization model {} in workspace {name}",
+                                    summarization.model
+                                )
+                            },
+                        )),
+                        max_tokens: summarization.max_tokens,
+                        keep_last: summarization.keep_last.unwrap_or(DEFAULT_KEEP_LAST),
+                    }),
+                paging: config_workspace.paging.map(|paging| Paging {
+                    max_chars: paging.max_chars,
+                }),
+                slow_calls: config_workspace.slow_calls.map(|slow_calls| {
+                    SlowCalls::new(
+                        Duration::from_secs(slow_calls.threshold),
+                        slow_calls.webhook.map(|url| {
+                            Url::parse(&url).unwrap_or_else(|error| {
+                                panic!(
+                                    "Invalid slow call webhook {url} in workspace {name}: {error}"
+                                )
+                            })
+                        }),
+                        user_agent.clone(),
+                    )
+                }),
+                selftests: config_workspace
+                    .selftest
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(SelfTest::from)
+                    .collect(),
+            };
+
+            if let Some(workspace_mcps) = config_workspace.mcps {
+                for mcp in workspace_mcps {
+                    if let Some(server) = config.mcps.get(&mcp) {
+                        workspace.mcps.push((mcp, Arc::clone(server)))
+                    } else {
+                        panic!("Undefined MCP {mcp} in workspace {name}")
+                    }
+                }
+            }
+
+            for toolset_name in config_workspace.toolsets.unwrap_or_default() {
+                let toolset = toolsets.get(&toolset_name).unwrap_or_else(|| {
+                    panic!("Undefined toolset {toolset_name} in workspace {name}")
+                });
+
+                let descriptions: HashMap<_, _> = toolset
+                    .overrides
+                    .iter()
+                    .flatten()
+                    .filter_map(|(tool, options)| {
+                        options
+                            .description
+                            .clone()
+                            .map(|description| (tool.clone(), description))
+                    })
+                    .collect();
+
+                for mcp in toolset.mcps.iter() {
+                    let Some(server) = config.mcps.get(mcp) else {
+                        panic!("Undefined MCP {mcp} in toolset {toolset_name}")
+                    };
+
+                    workspace.mcps.push((
+                        mcp.clone(),
+                        Arc::new(FilteredMcp {
+                            inner: Arc::clone(server),
+                            tools: toolset.tools.clone(),
+                            descriptions: descriptions.clone(),
+                        }),
+                    ));
+                }
+            }
+
+            let workspace = Arc::new(workspace);
+
+            let listener = listener_address(
+                config_workspace.config.address.as_deref(),
+                config_workspace.config.port,
+            );
+
+            let routes = config.listeners.entry(listener).or_default();
+
+            for path in config_workspace.config.path.all() {
+                routes.insert(path.clone(), Arc::clone(&workspace));
+            }
+
+            workspace
+        });
+    }
+
+    // The workspaces mirroring the requests are given the others once they're all built
+    for workspace in config.workspaces.values() {
+        if let Some(mirror) = &workspace.mirror {
+            let target = config.workspaces.get(&mirror.name).unwrap_or_else(|| {
+                panic!(
+                    "Undefined mirror workspace {} in workspace {}",
+                    mirror.name, workspace.name
+                )
+            });
+
+            let _ = mirror.workspace.set(Arc::downgrade(target));
+        }
+    }
+
+    let status = Arc::new(Readiness::new(
+        readiness.unavailable_while_starting,
+        dependencies,
+        model_activity,
+        mcp_activity,
+    ));
+
+    // The checks report on the models and MCP servers themselves
+    if !check {
+        for (name, mcp) in config.mcps.iter() {
+            status.watch_mcp(name.clone(), Arc::clone(mcp));
+        }
+
+        for (name, model) in config.models.iter() {
+            if readiness.probe_models {
+                status.probe_model(name.clone(), Arc::clone(model));
+            } else {
+                status.set_model(name, Status::Ready);
+            }
+        }
+    }
+
+    config.readiness = status;
+
+    Ok(config)
+}
+
+/// Start a MCP server, connecting to it again when its calls fail on the transport, or when it
+/// stops for local servers with a restart policy
+async fn start_mcp(
+    name: String,
+    mcp: Mcp,
+    user_agent: HeaderValue,
+) -> Result<Arc<dyn McpServer + Send>, String> {
+    if let Mcp::Local {
+        restart: Some(restart),
+        ..
+    } = &mcp
+        && restart.policy != RestartPolicy::Never
+    {
+        let policy = restart.policy;
+        let backoff = Backoff {
+            initial: Duration::from_secs(restart.initial_delay.unwrap_or(DEFAULT_INITIAL_DELAY)),
+            max: Duration::from_secs(restart.max_delay.unwrap_or(DEFAULT_MAX_DELAY)),
+            max_failures: restart.max_failures.unwrap_or(DEFAULT_MAX_FAILURES),
+        };
+
+        let start: Start = {
+            let name = name.clone();
+
+            Arc::new(move || connect_local(name.clone(), mcp.clone()).boxed())
+        };
+
+        return Ok(Arc::new(RestartingMcp::new(name, policy, backoff, start)));
+    }
+
+    let retries = match &mcp {
+        Mcp::Local { retries, .. } | Mcp::Remote { retries, .. } => {
+            retries.unwrap_or(DEFAULT_RETRIES)
+        }
+    };
+
+    // The sessions outlive the connections, for the new ones to resume them
+    let sessions = match &mcp {
+        Mcp::Remote {
+            transport: TransportType::Http,
+            http,
+            ..
+        } => Some(Arc::new(
+            HttpSessions::new(http.as_ref().and_then(|http| http.session_file.clone())).await,
+        )),
+        _ => None,
+    };
+
+    let server = connect_mcp(
+        name.clone(),
+        mcp.clone(),
+        user_agent.clone(),
+        sessions.clone(),
+    )
+    .await?;
+
+    if retries == 0 {
+        return Ok(server);
+    }
+
+    let reconnect: Reconnect = {
+        let name = name.clone();
+
+        Arc::new(move || {
+            connect_mcp(
+                name.clone(),
+                mcp.clone(),
+                user_agent.clone(),
+                sessions.clone(),
+            )
+            .boxed()
+        })
+    };
+
+    Ok(Arc::new(RetryingMcp::new(name, retries, server, reconnect)))
+}
+
+/// Start a local MCP server as a child process, giving its exit along with it
+async fn connect_local(
+    name: String,
+    mcp: Mcp,
+) -> Result<(Arc<dyn McpServer + Send>, Exit), String> {
+    let Mcp::Local {
+        command,
+        args,
+        env,
+        forward_headers,
+        roots,
+        strict_capabilities,
+        ..
+    } = mcp
+    else {
+        unreachable!("Only local MCP servers are started as child processes");
+    };
+
+    let mut command = process::command(&command);
+
+    if let Some(args) = args {
+        command.args(args);
+    }
+
+    if let Some(env) = env {
+        command.envs(env);
+    }
+
+    let (process, exit, output, input) = McpProcess::spawn(&mut command)
+        .map_err(|error| format!("Couldn't start MCP server in tokio: {error}"))?;
+
+    let client = McpClient::new(name.clone()).with_roots(
+        roots
+            .iter()
+            .flatten()
+            .map(|path| root(path))
+            .collect::<Result<_, _>>()?,
+    );
+
+    let (command, forwarding) = match forward_headers.map(forwarding) {
+        Some(forwarding) => (
+            client.serve(meta_transport(output, input)).await,
+            Some(forwarding),
+        ),
+        None => (client.serve((output, input)).await, None),
+    };
+
+    let command = command.map_err(|error| format!("Couldn't start MCP server: {error}"))?;
+
+    negotiate(&name, command.peer_info(), strict_capabilities)?;
+
+    Ok((
+        Arc::new(LocalMcp {
+            command,
+            process,
+            forwarding,
+        }),
+        exit,
+    ))
+}
+
+/// Connect to a MCP server, either as a child process, over SSE or over streamable HTTP
+async fn connect_mcp(
+    name: String,
+    mcp: Mcp,
+    user_agent: HeaderValue,
+    sessions: Option<Arc<HttpSessions>>,
+) -> Result<Arc<dyn McpServer + Send>, String> {
+    Ok(match mcp {
+        Mcp::Local { .. } => connect_local(name, mcp).await?.0,
+        Mcp::Remote {
+            url,
+            replicas,
+            tls,
+            headers,
+            forward_headers,
+            strict_capabilities,
+            transport,
+            sse,
+            http,
+            ..
+        } => {
+            let transport = match transport {
+                TransportType::Sse => RemoteTransport::Sse(sse.unwrap_or_default()),
+                TransportType::Http => RemoteTransport::Http {
+                    config: http.unwrap_or_default(),
+                    sessions,
+                },
+            };
+
+            let mut headers = identified(&user_agent, headers);
+            headers.extend(get_headers(transport.options().headers.clone()));
+
+            if let Some(AuthConfig::Header {
+                name,
+                value,
+                prefix,
+            }) = &transport.options().auth
+            {
+                let value = match prefix {
+                    Some(prefix) => format!("{prefix} {value}"),
+                    None => value.clone(),
+                };
+
+                headers.insert(
+                    HeaderName::from_str(name)
+                        .unwrap_or_else(|_| panic!("Invalid header name \"{name}\"")),
+                    HeaderValue::from_str(&value)
+                        .unwrap_or_else(|_| panic!("Invalid value for header \"{name}\"")),
+                );
+            }
+
+            let server = start_remote(
+                name.clone(),
+                url.clone(),
+                replicas.clone(),
+                tls.clone(),
+                headers.clone(),
+                strict_capabilities,
+                transport.clone(),
+            )
+            .await?;
+
+            if let Some(forward_headers) = forward_headers {
+                let connect: Connect = {
+                    let name = name.clone();
+
+                    Arc::new(move |forwarded| {
+                        let mut headers = headers.clone();
+                        headers.extend(forwarded);
+
+                        start_remote(
+                            name.clone(),
+                            url.clone(),
+                            replicas.clone(),
+                            tls.clone(),
+                            headers,
+                            strict_capabilities,
+                            // Sessions are only resumed for the connection without forwarded
+                            // headers, which they'd be mixed with
+                            match &transport {
+                                RemoteTransport::Http { config, .. } => RemoteTransport::Http {
+                                    config: config.clone(),
+                                    sessions: None,
+                                },
+                                transport => transport.clone(),
+                            },
+                        )
+                        .boxed()
+                    })
+                };
+
+                Arc::new(ForwardingMcp::new(
+                    name,
+                    forwarding(forward_headers),
+                    server,
+                    connect,
+                ))
+            } else {
+                server
+            }
+        }
+    })
+}
+
+/// Root of a MCP server, named after its directory
+fn root(path: &str) -> Result<Root, String> {
+    let absolute = std::path::absolute(path)
+        .map_err(|error| format!("Invalid root directory {path}: {error}"))?;
+
+    let uri = Url::from_directory_path(&absolute)
+        .map_err(|_| format!("Invalid root directory {path}"))?;
+
+    Ok(Root {
+        uri: uri.to_string(),
+        name: absolute
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned()),
+    })
+}
+
+fn forwarding(headers: Vec<String>) -> Forwarding {
+    Forwarding {
+        headers: headers
+            .iter()
+            .map(|name| {
+                HeaderName::from_str(name)
+                    .unwrap_or_else(|_| panic!("Invalid header name \"{name}\""))
+            })
+            .collect(),
+    }
+}
+
+/// Connect to a remote MCP server and its replicas, sending the given headers with every request
+async fn start_remote(
+    name: String,
+    url: String,
+    replicas: Option<Vec<String>>,
+    tls: Option<TlsConfig>,
+    headers: HeaderMap,
+    strict: bool,
+    transport: RemoteTransport,
+) -> Result<Arc<dyn McpServer + Send>, String> {
+    let Some(replicas) = replicas else {
+        return connect_remote(name, url, tls, headers, strict, transport).await;
+    };
+
+    let urls: Vec<String> = std::iter::once(url).chain(replicas).collect();
+
+    let servers = join_all(urls.iter().map(|url| {
+        connect_remote(
+            name.clone(),
+            url.clone(),
+            tls.clone(),
+            headers.clone(),
+            strict,
+            transport.clone(),
+        )
+    }))
+    .await;
+
+    let mut replicas = Vec::new();
+    let mut errors = Vec::new();
+
+    for (url, server) in urls.into_iter().zip(servers) {
+        match server {
+            Ok(server) => replicas.push(Replica::new(url, server)),
+            Err(error) => {
+                event!(Level::ERROR, "{error}");
+                errors.push(error);
+            }
+        }
+    }
+
+    if replicas.is_empty() {
+        return Err(errors.join(", "));
+    }
+
+    Ok(Arc::new(ReplicatedMcp::new(replicas)))
+}
+
+/// Connect to a remote MCP server over SSE or streamable HTTP
+async fn connect_remote(
+    name: String,
+    url: String,
+    tls: Option<TlsConfig>,
+    headers: HeaderMap,
+    strict: bool,
+    transport: RemoteTransport,
+) -> Result<Arc<dyn McpServer + Send>, String> {
+    let options = transport.options();
+
+    let mut url = url;
+
+    if let Some(AuthConfig::Parameter { name, value }) = &options.auth {
+        let mut with_key =
+            Url::parse(&url).map_err(|error| format!("Invalid URL {url}: {error}"))?;
+        with_key.query_pairs_mut().append_pair(name, value);
+
+        url = with_key.to_string();
+    }
+
+    let mut client = HttpClient::builder().default_headers(headers);
+
+    if let Some(tls) = tls {
+        client = with_tls(client, &tls.into());
+    }
+
+    // Proxies drop the connections they see idle, even while the stream is open
+    if let Some(keep_alive) = options.keep_alive {
+        client = client.tcp_keepalive(Duration::from_secs(keep_alive));
+    }
+
+    let client = client.build().unwrap();
+
+    let heartbeat = options.heartbeat.map(Duration::from_secs);
+
+    let service = match transport {
+        RemoteTransport::Sse(config) => {
+            let mut transport = SseTransport::start_with_client(url.as_str(), client)
+                .await
+                .map_err(|error| format!("Couldn't connect to MCP server {url}: {error}"))?;
+
+            transport.retry_config = SseTransportRetryCofnig {
+                max_times: config.max_reconnects,
+                min_duration: config
+                    .reconnect_delay
+                    .map(Duration::from_secs)
+                    .unwrap_or(SseTransportRetryCofnig::DEFAULT_MIN_DURATION),
+            };
+
+            McpClient::new(name.clone())
+                .serve(transport)
+                .await
+                .map_err(|error| format!("Couldn't start MCP server: {error}"))?
+        }
+        RemoteTransport::Http { sessions, .. } => McpClient::new(name.clone())
+            .serve(http::transport(client, url, sessions))
+            .await
+            .map_err(|error| format!("Couldn't start MCP server: {error}"))?,
+    };
+
+    negotiate(&name, service.peer_info(), strict)?;
+
+    Ok(Arc::new(RemoteMcp::new(name, service, heartbeat)))
+}
+
+/// Values of the configuration that must not appear in the logs
+fn secrets(file_config: &FileConfig) -> Vec<String> {
+    let mut secrets = Vec::new();
+
+    let auth_secret = |auth: &AuthMethod| match auth {
+        AuthMethod::ApiKey(AuthConfig::Header { value, .. })
+        | AuthMethod::ApiKey(AuthConfig::Parameter { value, .. }) => value.clone(),
+        #[cfg(feature = "oauth2")]
+        AuthMethod::OAuth2 { client_secret, .. } => client_secret.clone(),
+    };
+
+    for model in file_config.models.values() {
+        let ModelCommon { auth, headers, .. } = model.common();
+
+        secrets.extend(auth.iter().map(auth_secret));
+        secrets.extend(headers.iter().flat_map(|headers| headers.values().cloned()));
+
+        #[cfg(feature = "gemini")]
+        #[allow(irrefutable_let_patterns)]
+        if let Model::Gemini {
+            api_key: Some(api_key),
+            ..
+        } = model
+        {
+            secrets.push(api_key.clone());
+        }
+
+        #[cfg(feature = "xai")]
+        if let Model::Xai {
+            api_key: Some(api_key),
+            ..
+        } = model
+        {
+            secrets.push(api_key.clone());
+        }
+    }
+
+    for workspace in file_config.workspaces.values() {
+        if let Some(moderation) = &workspace.moderation {
+            secrets.extend(moderation.auth.iter().map(auth_secret));
+            secrets.extend(
+                moderation
+                    .headers
+                    .iter()
+                    .flat_map(|headers| headers.values().cloned()),
+            );
+        }
+
+        if let Some(embeddings) = &workspace.embeddings {
+            secrets.extend(embeddings.auth.iter().map(auth_secret));
+            secrets.extend(
+                embeddings
+                    .headers
+                    .iter()
+                    .flat_map(|headers| headers.values().cloned()),
+            );
+        }
+    }
+
+    for mcp in file_config.mcps.iter().flat_map(|mcps| mcps.values()) {
+        match mcp {
+            Mcp::Local { env: Some(env), .. } => secrets.extend(env.values().cloned()),
+            Mcp::Remote {
+                headers, sse, http, ..
+            } => {
+                let options = [
+                    sse.as_ref().map(|sse| &sse.options),
+                    http.as_ref().map(|http| &http.options),
+                ];
+
+                secrets.extend(headers.iter().flat_map(|headers| headers.values().cloned()));
+
+                for options in options.into_iter().flatten() {
+                    secrets.extend(
+                        options
+                            .headers
+                            .iter()
+                            .flat_map(|headers| headers.values().cloned()),
+                    );
+                    secrets.extend(options.auth.iter().map(|auth| match auth {
+                        AuthConfig::Header { value, .. } | AuthConfig::Parameter { value, .. } => {
+                            value.clone()
+                        }
+                    }));
+                }
+            }
+            _ => (),
+        }
+    }
+
+    if let Some(callbacks) = &file_config.callbacks {
+        secrets.push(callbacks.secret.clone());
+    }
+
+    if let Some(anonymize) = file_config
+        .storage
+        .as_ref()
+        .and_then(|storage| storage.anonymize.as_ref())
+    {
+        secrets.push(anonymize.salt.clone());
+    }
+
+    for budget in file_config.budgets.iter().flatten() {
+        secrets.push(budget.key.clone());
+    }
+
+    if let Some(admin) = &file_config.admin {
+        secrets.push(admin.token.clone());
+    }
+
+    for client in file_config
+        .clients
+        .iter()
+        .flat_map(|clients| clients.values())
+    {
+        secrets.push(client.key.clone());
+    }
+
+    secrets
+}
+
+/// Arguments masked in the logs and traces, by tool, whatever MCP server offers it
+fn redacted_args(file_config: &FileConfig) -> BTreeMap<String, Vec<String>> {
+    let mut redacted: BTreeMap<String, Vec<String>> = BTreeMap::new();
+
+    for mcp in file_config.mcps.iter().flat_map(|mcps| mcps.values()) {
+        let (Mcp::Local { redact_args, .. } | Mcp::Remote { redact_args, .. }) = mcp;
+
+        for (tool, args) in redact_args.iter().flatten() {
+            redacted
+                .entry(tool.clone())
+                .or_default()
+                .extend(args.iter().cloned());
+        }
+    }
+
+    redacted
+}
+
+/// Configuration as loaded, with secrets redacted and the routes of each listener resolved
+fn effective_config(file_config: &FileConfig) -> Value {
+    let mut routes: HashMap<String, HashMap<&str, &str>> = HashMap::new();
+
+    for (name, workspace) in file_config.workspaces.iter() {
+        let listener_routes = routes
+            .entry(listener_address(
+                workspace.config.address.as_deref(),
+                workspace.config.port,
+            ))
+            .or_default();
+
+        for path in workspace.config.path.all() {
+            listener_routes.insert(path, name);
+        }
+    }
+
+    let mut effective = json!(file_config);
+    effective["routes"] = json!(routes);
+
+    effective
+}
+
+fn redact<S: Serializer>(_: &str, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(REDACTED)
+}
+
+#[cfg(any(feature = "gemini", feature = "xai"))]
+fn redact_option<S: Serializer>(value: &Option<String>, serializer: S) -> Result<S::Ok, S::Error> {
+    value.as_ref().map(|_| REDACTED).serialize(serializer)
+}
+
+fn redact_values<S: Serializer>(
+    map: &Option<HashMap<String, String>>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    map.as_ref()
+        .map(|map| {
+            map.keys()
+                .map(|key| (key, REDACTED))
+                .collect::<HashMap<_, _>>()
+        })
+        .serialize(serializer)
+}
+
+fn listener_address(address: Option<&str>, port: Option<u16>) -> String {
+    format!(
+        "{}:{}",
+        address.unwrap_or(DEFAULT_LISTENER),
+        port.unwrap_or(DEFAULT_PORT)
+    )
+}
+
+/// Headers sent with every request to a model or MCP server, identifying the manager unless they
+/// set their own `User-Agent`
+fn identified(user_agent: &HeaderValue, headers: Option<HashMap<String, String>>) -> HeaderMap {
+    let mut identified = HeaderMap::new();
+    identified.insert(USER_AGENT, user_agent.clone());
+    identified.extend(get_headers(headers));
+
+    identified
+}
+
+fn get_headers(headers: Option<HashMap<String, String>>) -> HeaderMap {
+    headers
+        .unwrap_or_default()
+        .into_iter()
+        .map(|(name, value)| {
+            (
+                HeaderName::from_str(&name)
+                    .unwrap_or_else(|_| panic!("Invalid header name \"{name}\"")),
+                HeaderValue::from_str(&value)
+                    .unwrap_or_else(|_| panic!("Invalid value for header \"{name}\"")),
+            )
+        })
+        .collect()
+}
+
+fn get_auth(auth: Option<AuthMethod>) -> Auth {
+    if let Some(auth) = auth {
+        match auth {
+            AuthMethod::ApiKey(location) => match location {
+                AuthConfig::Parameter { name, value } => {
+                    Auth::ApiKey(AuthLocation::Params(name, value))
+                }
+                AuthConfig::Header {
+                    name,
+                    value,
+                    prefix,
+                } => Auth::ApiKey(AuthLocation::Header(
+                    name,
+                    if let Some(prefix) = prefix {
+                        format!("{prefix} {value}")
+                    } else {
+                        value
+                    },
+                )),
+            },
+            #[cfg(feature = "oauth2")]
+            AuthMethod::OAuth2 {
+                url,
+                client_id,
+                client_secret,
+                scope,
+            } => Auth::OAuth2 {
+                url,
+                client_id,
+                client_secret,
+                scope,
+            },
+        }
+    } else {
+        Auth::NoAuth
+    }
+}
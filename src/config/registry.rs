@@ -0,0 +1,269 @@
+use std::{collections::HashMap, sync::Arc};
+
+use crate::{
+    budgets::Price,
+    config::{AuthMethod, Model, PoolConfig, RateLimitConfig, SchemaConfig, TlsConfig},
+    models::{AIModel, client::ClientOptions, schema::SchemaRules},
+};
+
+#[cfg(any(feature = "openai", feature = "cohere", feature = "openai-compatible"))]
+use crate::config::BaseModel;
+#[cfg(feature = "gemini")]
+use crate::config::GeminiPreset;
+#[cfg(feature = "anthropic")]
+use crate::models::anthropic::Anthropic;
+#[cfg(feature = "azure")]
+use crate::models::azure::Azure;
+#[cfg(feature = "cohere")]
+use crate::models::cohere::Cohere;
+#[cfg(feature = "gemini")]
+use crate::models::gemini::Gemini;
+#[cfg(feature = "openai")]
+use crate::models::openai::OpenAI;
+#[cfg(feature = "openai-compatible")]
+use crate::models::openai_compatible::{OpenAICompatible, Quirks};
+
+/// Settings every type of model has
+pub(super) struct ModelCommon<'a> {
+    pub(super) auth: &'a Option<AuthMethod>,
+    pub(super) tls: &'a Option<TlsConfig>,
+    pub(super) schema: &'a Option<SchemaConfig>,
+    pub(super) headers: &'a Option<HashMap<String, String>>,
+    pub(super) price: &'a Option<Price>,
+    pub(super) pool: &'a Option<PoolConfig>,
+    pub(super) rate_limit: &'a Option<RateLimitConfig>,
+}
+
+/// Each type of model only exists when its provider is compiled in, this is the only place
+/// mapping them to their implementation
+impl Model {
+    pub(super) fn common(&self) -> ModelCommon<'_> {
+        match self {
+            #[cfg(feature = "openai")]
+            Model::OpenAI(BaseModel {
+                auth,
+                tls,
+                schema,
+                headers,
+                price,
+                pool,
+                rate_limit,
+                ..
+            }) => ModelCommon {
+                auth,
+                tls,
+                schema,
+                headers,
+                price,
+                pool,
+                rate_limit,
+            },
+            #[cfg(feature = "cohere")]
+            Model::Cohere(BaseModel {
+                auth,
+                tls,
+                schema,
+                headers,
+                price,
+                pool,
+                rate_limit,
+                ..
+            }) => ModelCommon {
+                auth,
+                tls,
+                schema,
+                headers,
+                price,
+                pool,
+                rate_limit,
+            },
+            #[cfg(feature = "openai-compatible")]
+            Model::OpenAICompatible {
+                base:
+                    BaseModel {
+                        auth,
+                        tls,
+                        schema,
+                        headers,
+                        price,
+                        pool,
+                        rate_limit,
+                        ..
+                    },
+                ..
+            } => ModelCommon {
+                auth,
+                tls,
+                schema,
+                headers,
+                price,
+                pool,
+                rate_limit,
+            },
+            #[cfg(feature = "gemini")]
+            Model::Gemini {
+                auth,
+                tls,
+                schema,
+                headers,
+                price,
+                pool,
+                rate_limit,
+                ..
+            } => ModelCommon {
+                auth,
+                tls,
+                schema,
+                headers,
+                price,
+                pool,
+                rate_limit,
+            },
+            #[cfg(feature = "azure")]
+            Model::Azure {
+                auth,
+                tls,
+                schema,
+                headers,
+                price,
+                pool,
+                rate_limit,
+                ..
+            } => ModelCommon {
+                auth,
+                tls,
+                schema,
+                headers,
+                price,
+                pool,
+                rate_limit,
+            },
+            #[cfg(feature = "xai")]
+            Model::Xai {
+                auth,
+                tls,
+                schema,
+                headers,
+                price,
+                pool,
+                rate_limit,
+                ..
+            } => ModelCommon {
+                auth,
+                tls,
+                schema,
+                headers,
+                price,
+                pool,
+                rate_limit,
+            },
+            #[cfg(feature = "anthropic")]
+            Model::Anthropic {
+                auth,
+                tls,
+                schema,
+                headers,
+                price,
+                pool,
+                rate_limit,
+                ..
+            } => ModelCommon {
+                auth,
+                tls,
+                schema,
+                headers,
+                price,
+                pool,
+                rate_limit,
+            },
+        }
+    }
+
+    /// Type of the model, as in the configuration
+    pub(super) fn provider(&self) -> &'static str {
+        match self {
+            #[cfg(feature = "openai")]
+            Model::OpenAI(_) => "openai",
+            #[cfg(feature = "cohere")]
+            Model::Cohere(_) => "cohere",
+            #[cfg(feature = "openai-compatible")]
+            Model::OpenAICompatible { .. } => "openai-compatible",
+            #[cfg(feature = "gemini")]
+            Model::Gemini { .. } => "gemini",
+            #[cfg(feature = "azure")]
+            Model::Azure { .. } => "azure",
+            #[cfg(feature = "xai")]
+            Model::Xai { .. } => "xai",
+            #[cfg(feature = "anthropic")]
+            Model::Anthropic { .. } => "anthropic",
+        }
+    }
+
+    pub(super) async fn build(
+        self,
+        options: ClientOptions,
+        schema: SchemaRules,
+    ) -> Arc<dyn AIModel + Send> {
+        match self {
+            #[cfg(feature = "openai")]
+            Model::OpenAI(BaseModel { url, model, .. }) => {
+                Arc::new(OpenAI::new(url, options, schema, model).await)
+            }
+            #[cfg(feature = "cohere")]
+            Model::Cohere(BaseModel { url, model, .. }) => {
+                Arc::new(Cohere::new(url, options, schema, model).await)
+            }
+            #[cfg(feature = "openai-compatible")]
+            Model::OpenAICompatible {
+                base: BaseModel { url, model, .. },
+                supports_parallel_tool_calls,
+                tool_call_id_required,
+                max_tokens_field,
+            } => {
+                let defaults = Quirks::default();
+                let quirks = Quirks {
+                    supports_parallel_tool_calls: supports_parallel_tool_calls
+                        .unwrap_or(defaults.supports_parallel_tool_calls),
+                    tool_call_id_required: tool_call_id_required
+                        .unwrap_or(defaults.tool_call_id_required),
+                    max_tokens_field: max_tokens_field.unwrap_or(defaults.max_tokens_field),
+                };
+
+                Arc::new(OpenAICompatible::new(url, options, schema, model, quirks).await)
+            }
+            #[cfg(feature = "gemini")]
+            Model::Gemini {
+                preset: Some(GeminiPreset::GeminiApi),
+                model: Some(model),
+                api_key: Some(api_key),
+                ..
+            } => Arc::new(Gemini::from_api(model, api_key, options, schema).await),
+            #[cfg(feature = "gemini")]
+            Model::Gemini { url: Some(url), .. } => {
+                Arc::new(Gemini::new(url, options, schema).await)
+            }
+            #[cfg(feature = "gemini")]
+            Model::Gemini { .. } => {
+                unreachable!("Gemini model without url nor preset passed validation")
+            }
+            #[cfg(feature = "azure")]
+            Model::Azure {
+                url, api_version, ..
+            } => Arc::new(Azure::new(url, options, schema, api_version).await),
+            #[cfg(feature = "xai")]
+            Model::Xai {
+                url,
+                model,
+                api_key,
+                ..
+            } => Arc::new(OpenAICompatible::from_xai(url, model, api_key, options, schema).await),
+            #[cfg(feature = "anthropic")]
+            Model::Anthropic {
+                url,
+                anthropic_version,
+                model,
+                ..
+            } => Arc::new(Anthropic::new(url, options, schema, model, anthropic_version).await),
+        }
+    }
+}
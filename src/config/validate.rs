@@ -0,0 +1,1367 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::{self, Display},
+    path::Path,
+    str::FromStr,
+};
+
+use regex::Regex;
+use reqwest::{
+    Url,
+    header::{HeaderName, HeaderValue},
+};
+
+use crate::{
+    acl::parse_network,
+    config::{
+        AuthConfig, BudgetConfig, ClientConfig, ContextConfig, EmbeddingsConfig, FileConfig, Mcp,
+        Model, RestartConfig, TransportOptions, TransportType, TrimmingConfig,
+        WorkspaceModelConfig, WorkspacePaths, listener_address,
+    },
+    context::{PLACEHOLDERS, Timezone},
+    embeddings::EmbeddingsProvider,
+    logging::level_directive,
+    mcp::restarting::{DEFAULT_INITIAL_DELAY, DEFAULT_MAX_DELAY, RestartPolicy},
+    models::Role,
+    selftest::parse_path,
+};
+
+const JOBS_PATH: &str = "/jobs/";
+const MODELS_PATH: &str = "/v1/models";
+const OPENAPI_PATH: &str = "/openapi.json";
+const UNSPECIFIED_ADDRESSES: [&str; 2] = ["0.0.0.0", "[::]"];
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum Severity {
+    Warning,
+    Error,
+}
+
+/// Problem found in the configuration, located by its YAML path
+#[derive(Debug)]
+pub(crate) struct Diagnostic {
+    pub(crate) severity: Severity,
+    pub(crate) path: String,
+    pub(crate) message: String,
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.path, self.message)
+    }
+}
+
+impl Diagnostic {
+    fn error(path: String, message: String) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Error,
+            path,
+            message,
+        }
+    }
+
+    fn warning(path: String, message: String) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Warning,
+            path,
+            message,
+        }
+    }
+}
+
+/// Check the references and listeners of the whole configuration
+pub(crate) fn validate(config: &FileConfig) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+
+    if config.workspaces.is_empty() {
+        diagnostics.push(Diagnostic::error(
+            String::from("workspaces"),
+            String::from("No workspaces defined"),
+        ));
+    }
+
+    if let Some(user_agent) = &config.user_agent
+        && HeaderValue::from_str(user_agent).is_err()
+    {
+        diagnostics.push(Diagnostic::error(
+            String::from("user_agent"),
+            String::from("Invalid header value"),
+        ));
+    }
+
+    let mut models: Vec<_> = config.models.iter().collect();
+    models.sort_by_key(|(name, _)| *name);
+
+    for (name, model) in models {
+        diagnostics.extend(validate_model(&format!("models.{name}"), model));
+    }
+
+    let mut used_mcps = HashSet::new();
+    let mut used_models = HashSet::new();
+    let mut used_toolsets = HashSet::new();
+    // Workspace using each listener address and path
+    let mut routes: HashMap<(String, String), &str> = HashMap::new();
+
+    let mut workspaces: Vec<_> = config.workspaces.iter().collect();
+    workspaces.sort_by_key(|(name, _)| *name);
+
+    for (name, workspace) in workspaces {
+        let path = format!("workspaces.{name}");
+
+        match &workspace.model {
+            WorkspaceModelConfig::Single(model) => {
+                used_models.insert(model);
+
+                if !config.models.contains_key(model) {
+                    diagnostics.push(Diagnostic::error(
+                        format!("{path}.model"),
+                        format!("Undefined model '{model}'"),
+                    ));
+                }
+            }
+            WorkspaceModelConfig::Split { split } => {
+                if split.values().all(|weight| *weight == 0) {
+                    diagnostics.push(Diagnostic::error(
+                        format!("{path}.model.split"),
+                        String::from("No model is given any share of the requests"),
+                    ));
+                }
+
+                let mut models: Vec<_> = split.iter().collect();
+                models.sort();
+
+                for (model, weight) in models {
+                    used_models.insert(model);
+
+                    if !config.models.contains_key(model) {
+                        diagnostics.push(Diagnostic::error(
+                            format!("{path}.model.split.{model}"),
+                            format!("Undefined model '{model}'"),
+                        ));
+                    } else if *weight == 0 {
+                        diagnostics.push(Diagnostic::warning(
+                            format!("{path}.model.split.{model}"),
+                            String::from("Model never answers, its share is 0"),
+                        ));
+                    }
+                }
+            }
+        }
+
+        if let Some(guardrails) = &workspace.guardrails {
+            for (index, pattern) in guardrails.deny.iter().flatten().enumerate() {
+                if let Err(error) = Regex::new(pattern) {
+                    diagnostics.push(Diagnostic::error(
+                        format!("{path}.guardrails.deny[{index}]"),
+                        format!("Invalid pattern: {error}"),
+                    ));
+                }
+            }
+        }
+
+        if let Some(router) = &workspace.router {
+            used_models.insert(&router.model);
+
+            if !config.models.contains_key(&router.model) {
+                diagnostics.push(Diagnostic::error(
+                    format!("{path}.router.model"),
+                    format!("Undefined model '{}'", router.model),
+                ));
+            }
+
+            if router.top_k == 0 {
+                diagnostics.push(Diagnostic::error(
+                    format!("{path}.router.top_k"),
+                    String::from("The router must keep at least one tool"),
+                ));
+            }
+        }
+
+        for (index, message) in workspace.preamble_messages.iter().flatten().enumerate() {
+            if matches!(message.role, Role::Tool) {
+                diagnostics.push(Diagnostic::error(
+                    format!("{path}.preamble_messages[{index}].role"),
+                    String::from(
+                        "Preamble messages are from the user, the assistant or the system",
+                    ),
+                ));
+            }
+        }
+
+        if let Some(EmbeddingsConfig {
+            provider: EmbeddingsProvider::Gemini,
+            model: None,
+            ..
+        }) = &workspace.embeddings
+        {
+            diagnostics.push(Diagnostic::error(
+                format!("{path}.embeddings.model"),
+                String::from("Missing model, required by Gemini"),
+            ));
+        }
+
+        if let Some(context) = &workspace.context {
+            diagnostics.extend(validate_context(&format!("{path}.context"), context));
+        }
+
+        match &workspace.trimming {
+            Some(TrimmingConfig::Drop { turns } | TrimmingConfig::Summarize { turns, .. })
+                if *turns == 0 =>
+            {
+                diagnostics.push(Diagnostic::error(
+                    format!("{path}.trimming.turns"),
+                    String::from("The outputs of the last turn must be kept"),
+                ));
+            }
+            _ => {}
+        }
+
+        match workspace.max_tool_rounds {
+            Some(0) => diagnostics.push(Diagnostic::error(
+                format!("{path}.max_tool_rounds"),
+                String::from("The model must be able to call the tools at least once"),
+            )),
+            None if workspace.final_answer => diagnostics.push(Diagnostic::warning(
+                format!("{path}.final_answer"),
+                String::from("Without max_tool_rounds, the model always ends with an answer"),
+            )),
+            _ => {}
+        }
+
+        if let Some(model) = &workspace.shadow_model {
+            used_models.insert(model);
+
+            if !config.models.contains_key(model) {
+                diagnostics.push(Diagnostic::error(
+                    format!("{path}.shadow_model"),
+                    format!("Undefined model '{model}'"),
+                ));
+            }
+        }
+
+        if let Some(mirror) = &workspace.mirror_to {
+            if !config.workspaces.contains_key(&mirror.workspace) {
+                diagnostics.push(Diagnostic::error(
+                    format!("{path}.mirror_to.workspace"),
+                    format!("Undefined workspace '{}'", mirror.workspace),
+                ));
+            } else if mirror.workspace == *name {
+                diagnostics.push(Diagnostic::error(
+                    format!("{path}.mirror_to.workspace"),
+                    String::from("A workspace can't mirror its requests to itself"),
+                ));
+            }
+
+            if let Some(percent) = mirror.percent
+                && !(percent > 0.0 && percent <= 100.0)
+            {
+                diagnostics.push(Diagnostic::error(
+                    format!("{path}.mirror_to.percent"),
+                    String::from(
+                        "The percentage of the requests mirrored must be over 0 and up to 100",
+                    ),
+                ));
+            }
+
+            if config.storage.is_none() {
+                diagnostics.push(Diagnostic::warning(
+                    format!("{path}.mirror_to"),
+                    String::from("Without storage, the differences of the answers are only logged"),
+                ));
+            }
+        }
+
+        if let Some(TrimmingConfig::Summarize { model, .. }) = &workspace.trimming {
+            used_models.insert(model);
+
+            if !config.models.contains_key(model) {
+                diagnostics.push(Diagnostic::error(
+                    format!("{path}.trimming.model"),
+                    format!("Undefined model '{model}'"),
+                ));
+            }
+        }
+
+        if let Some(summarization) = &workspace.summarization {
+            used_models.insert(&summarization.model);
+
+            if !config.models.contains_key(&summarization.model) {
+                diagnostics.push(Diagnostic::error(
+                    format!("{path}.summarization.model"),
+                    format!("Undefined model '{}'", summarization.model),
+                ));
+            }
+
+            if summarization.max_tokens == 0 {
+                diagnostics.push(Diagnostic::error(
+                    format!("{path}.summarization.max_tokens"),
+                    String::from("The conversation can't be summarized below 1 token"),
+                ));
+            }
+        }
+
+        if workspace.keep_alive == Some(0) {
+            diagnostics.push(Diagnostic::error(
+                format!("{path}.keep_alive"),
+                String::from("Must be greater than 0"),
+            ));
+        }
+
+        if workspace.max_response_tokens == Some(0) {
+            diagnostics.push(Diagnostic::error(
+                format!("{path}.max_response_tokens"),
+                String::from("Must be greater than 0"),
+            ));
+        }
+
+        if workspace
+            .output_language
+            .as_ref()
+            .is_some_and(|language| language.trim().is_empty())
+        {
+            diagnostics.push(Diagnostic::error(
+                format!("{path}.output_language"),
+                String::from("Empty language"),
+            ));
+        }
+
+        for (index, phrase) in workspace.forbidden_phrases.iter().flatten().enumerate() {
+            if phrase.trim().is_empty() {
+                diagnostics.push(Diagnostic::error(
+                    format!("{path}.forbidden_phrases[{index}]"),
+                    String::from("Empty phrase"),
+                ));
+            }
+        }
+
+        if let Some(paging) = &workspace.paging
+            && paging.max_chars == 0
+        {
+            diagnostics.push(Diagnostic::error(
+                format!("{path}.paging.max_chars"),
+                String::from("The pages of the tool outputs must have at least 1 character"),
+            ));
+        }
+
+        if let Some(slow_calls) = &workspace.slow_calls {
+            if slow_calls.threshold == 0 {
+                diagnostics.push(Diagnostic::error(
+                    format!("{path}.slow_calls.threshold"),
+                    String::from("Must be greater than 0"),
+                ));
+            }
+
+            if let Some(webhook) = &slow_calls.webhook
+                && !Url::parse(webhook).is_ok_and(|url| matches!(url.scheme(), "http" | "https"))
+            {
+                diagnostics.push(Diagnostic::error(
+                    format!("{path}.slow_calls.webhook"),
+                    format!("Invalid URL '{webhook}'"),
+                ));
+            }
+        }
+
+        for (index, test) in workspace.selftest.iter().flatten().enumerate() {
+            for path_error in test
+                .json
+                .iter()
+                .flat_map(|json| json.keys())
+                .filter_map(|json_path| parse_path(json_path).err())
+            {
+                diagnostics.push(Diagnostic::error(
+                    format!("{path}.selftest[{index}].json"),
+                    path_error,
+                ));
+            }
+
+            if workspace.mcps.as_ref().is_none_or(Vec::is_empty)
+                && workspace.toolsets.as_ref().is_none_or(Vec::is_empty)
+            {
+                diagnostics.push(Diagnostic::error(
+                    format!("{path}.selftest[{index}]"),
+                    format!("Tool '{}' can't be called without MCP servers", test.tool),
+                ));
+            }
+        }
+
+        // The toolsets of the workspace can give it MCP servers without its own list
+        let no_toolsets = workspace.toolsets.as_ref().is_none_or(Vec::is_empty);
+
+        match &workspace.mcps {
+            Some(mcps) if mcps.is_empty() && no_toolsets => diagnostics.push(Diagnostic::warning(
+                format!("{path}.mcps"),
+                String::from("Empty list of MCP servers, the model won't have any tools"),
+            )),
+            Some(mcps) => {
+                for (index, mcp) in mcps.iter().enumerate() {
+                    used_mcps.insert(mcp);
+
+                    if !config
+                        .mcps
+                        .as_ref()
+                        .is_some_and(|defined| defined.contains_key(mcp))
+                    {
+                        diagnostics.push(Diagnostic::error(
+                            format!("{path}.mcps[{index}]"),
+                            format!("Undefined MCP server '{mcp}'"),
+                        ));
+                    }
+                }
+            }
+            None if workspace.toolsets.as_ref().is_some_and(Vec::is_empty) => {
+                diagnostics.push(Diagnostic::warning(
+                    format!("{path}.toolsets"),
+                    String::from("Empty list of toolsets, the model won't have any tools"),
+                ))
+            }
+            None => (),
+        }
+
+        // Where each MCP server of the workspace comes from, its tools being listed once per source
+        let mut sources: HashMap<&String, String> = workspace
+            .mcps
+            .iter()
+            .flatten()
+            .map(|mcp| (mcp, format!("{path}.mcps")))
+            .collect();
+
+        for (index, toolset_name) in workspace.toolsets.iter().flatten().enumerate() {
+            used_toolsets.insert(toolset_name);
+
+            let Some(toolset) = config
+                .toolsets
+                .as_ref()
+                .and_then(|toolsets| toolsets.get(toolset_name))
+            else {
+                diagnostics.push(Diagnostic::error(
+                    format!("{path}.toolsets[{index}]"),
+                    format!("Undefined toolset '{toolset_name}'"),
+                ));
+
+                continue;
+            };
+
+            for mcp in toolset.mcps.iter() {
+                used_mcps.insert(mcp);
+
+                if let Some(source) = sources.insert(mcp, format!("toolset '{toolset_name}'")) {
+                    diagnostics.push(Diagnostic::error(
+                        format!("{path}.toolsets[{index}]"),
+                        format!("MCP server '{mcp}' is already given by {source}"),
+                    ));
+                }
+            }
+        }
+
+        let listener = listener_address(workspace.config.address.as_deref(), workspace.config.port);
+
+        if workspace.config.path.all().is_empty() {
+            diagnostics.push(Diagnostic::error(
+                format!("{path}.config.path"),
+                String::from("No path to reach the workspace at"),
+            ));
+        }
+
+        for (index, workspace_path) in workspace.config.path.all().iter().enumerate() {
+            let location = match workspace.config.path {
+                WorkspacePaths::Single(_) => format!("{path}.config.path"),
+                WorkspacePaths::Aliases(_) => format!("{path}.config.path[{index}]"),
+            };
+
+            if !workspace_path.starts_with('/') {
+                diagnostics.push(Diagnostic::error(
+                    location.clone(),
+                    format!("Invalid path '{workspace_path}'. Paths start with '/'"),
+                ));
+            }
+
+            if workspace_path.starts_with(JOBS_PATH) {
+                diagnostics.push(Diagnostic::error(
+                    location.clone(),
+                    format!(
+                        "Invalid path '{workspace_path}'. Paths under '{JOBS_PATH}' are used to poll jobs"
+                    ),
+                ));
+            }
+
+            if workspace_path == MODELS_PATH {
+                diagnostics.push(Diagnostic::error(
+                    location.clone(),
+                    format!(
+                        "Invalid path '{MODELS_PATH}'. It lists the workspaces of the listener"
+                    ),
+                ));
+            }
+
+            if workspace_path == OPENAPI_PATH {
+                diagnostics.push(Diagnostic::error(
+                    location.clone(),
+                    format!("Invalid path '{OPENAPI_PATH}'. It describes the API of the listener"),
+                ));
+            }
+
+            if let Some(other) =
+                routes.insert((listener.clone(), workspace_path.clone()), name.as_str())
+            {
+                let message = if other == name {
+                    format!("Path '{workspace_path}' is given more than once")
+                } else {
+                    format!(
+                        "Path '{workspace_path}' on {listener} is already used by workspace '{other}'"
+                    )
+                };
+
+                diagnostics.push(Diagnostic::error(location, message));
+            }
+        }
+    }
+
+    let mut listeners: Vec<(String, &str)> = routes
+        .keys()
+        .map(|(listener, _)| (listener.clone(), "workspaces"))
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .collect();
+
+    if let Some(admin) = &config.admin {
+        let listener = listener_address(admin.address.as_deref(), Some(admin.port));
+
+        if listeners.iter().any(|(other, _)| *other == listener) {
+            diagnostics.push(Diagnostic::error(
+                String::from("admin"),
+                format!("Listener {listener} is already used by workspaces"),
+            ));
+        } else {
+            listeners.push((listener, "admin"));
+        }
+
+        if admin.token.is_empty() {
+            diagnostics.push(Diagnostic::error(
+                String::from("admin.token"),
+                String::from("Empty token, the administration API would be open to anyone"),
+            ));
+        }
+    }
+
+    if let Some(acls) = &config.listeners {
+        let mut acls: Vec<_> = acls.iter().collect();
+        acls.sort_by_key(|(listener, _)| *listener);
+
+        for (listener, acl) in acls {
+            let path = format!("listeners.\"{listener}\"");
+
+            if !listeners.iter().any(|(other, _)| other == listener) {
+                diagnostics.push(Diagnostic::warning(
+                    path.clone(),
+                    String::from("Listener not used by any workspace nor the admin API"),
+                ));
+            }
+
+            for (name, networks) in [
+                ("allow", &acl.allow),
+                ("deny", &acl.deny),
+                ("trusted_proxies", &acl.trusted_proxies),
+            ] {
+                for (index, network) in networks.iter().flatten().enumerate() {
+                    if let Err(error) = parse_network(network) {
+                        diagnostics
+                            .push(Diagnostic::error(format!("{path}.{name}[{index}]"), error));
+                    }
+                }
+            }
+        }
+    }
+
+    if let Some(readiness) = &config.readiness
+        && readiness.probe_models
+        && config.admin.is_none()
+    {
+        diagnostics.push(Diagnostic::warning(
+            String::from("readiness.probe_models"),
+            String::from("Probes only reported by the admin API, which isn't configured"),
+        ));
+    }
+
+    if let Some(readiness) = &config.readiness {
+        for (field, zero) in [
+            (
+                "startup_parallelism",
+                readiness.startup_parallelism == Some(0),
+            ),
+            ("startup_timeout", readiness.startup_timeout == Some(0)),
+        ] {
+            if zero {
+                diagnostics.push(Diagnostic::error(
+                    format!("readiness.{field}"),
+                    String::from("Must be greater than 0"),
+                ));
+            }
+        }
+    }
+
+    if let Some(runtime) = &config.runtime {
+        for (field, value) in [
+            ("mcp_threads", runtime.mcp_threads),
+            ("max_concurrent_calls", runtime.max_concurrent_calls),
+            ("max_output_bytes", runtime.max_output_bytes),
+        ] {
+            if value == Some(0) {
+                diagnostics.push(Diagnostic::error(
+                    format!("runtime.{field}"),
+                    String::from("Must be greater than 0"),
+                ));
+            }
+        }
+    }
+
+    if let Some(logging) = &config.logging {
+        for (target, level) in logging.levels.iter().flatten() {
+            if level_directive(target, level).is_none() {
+                diagnostics.push(Diagnostic::error(
+                    format!("logging.levels.{target}"),
+                    format!("Invalid level '{level}' or target"),
+                ));
+            }
+        }
+
+        if let Some(file) = &logging.file
+            && file.max_size == Some(0)
+        {
+            diagnostics.push(Diagnostic::error(
+                String::from("logging.file.max_size"),
+                String::from("Must be greater than 0"),
+            ));
+        }
+    }
+
+    if let Some(anonymize) = config
+        .storage
+        .as_ref()
+        .and_then(|storage| storage.anonymize.as_ref())
+        && anonymize.salt.is_empty()
+    {
+        diagnostics.push(Diagnostic::error(
+            String::from("storage.anonymize.salt"),
+            String::from("The hashes of the contents need a salt"),
+        ));
+    }
+
+    if let Some(budgets) = &config.budgets {
+        diagnostics.extend(validate_budgets(config, budgets));
+    }
+
+    if let Some(clients) = &config.clients {
+        diagnostics.extend(validate_clients(config, clients));
+    }
+
+    diagnostics.extend(port_conflicts(listeners));
+
+    let mut toolsets: Vec<_> = config.toolsets.iter().flatten().collect();
+    toolsets.sort_by_key(|(name, _)| *name);
+
+    for (name, toolset) in toolsets {
+        let path = format!("toolsets.{name}");
+
+        if toolset.mcps.is_empty() {
+            diagnostics.push(Diagnostic::warning(
+                format!("{path}.mcps"),
+                String::from("Empty list of MCP servers, the toolset doesn't give any tools"),
+            ));
+        }
+
+        for (index, mcp) in toolset.mcps.iter().enumerate() {
+            if !config
+                .mcps
+                .as_ref()
+                .is_some_and(|defined| defined.contains_key(mcp))
+            {
+                diagnostics.push(Diagnostic::error(
+                    format!("{path}.mcps[{index}]"),
+                    format!("Undefined MCP server '{mcp}'"),
+                ));
+            }
+        }
+
+        if !used_toolsets.contains(name) {
+            diagnostics.push(Diagnostic::warning(
+                path,
+                String::from("Toolset not used by any workspace"),
+            ));
+        }
+    }
+
+    let mut mcps: Vec<_> = config.mcps.iter().flatten().collect();
+    mcps.sort_by_key(|(name, _)| *name);
+
+    for (name, mcp) in mcps {
+        diagnostics.extend(validate_mcp(&format!("mcps.{name}"), mcp));
+    }
+
+    if let Some(mcps) = &config.mcps {
+        let mut unused: Vec<_> = mcps.keys().filter(|mcp| !used_mcps.contains(mcp)).collect();
+        unused.sort();
+
+        for mcp in unused {
+            diagnostics.push(Diagnostic::warning(
+                format!("mcps.{mcp}"),
+                String::from("MCP server not used by any workspace, it will be started anyway"),
+            ));
+        }
+    }
+
+    let mut unused: Vec<_> = config
+        .models
+        .keys()
+        .filter(|model| !used_models.contains(model))
+        .collect();
+    unused.sort();
+
+    for model in unused {
+        diagnostics.push(Diagnostic::warning(
+            format!("models.{model}"),
+            String::from("Model not used by any workspace"),
+        ));
+    }
+
+    diagnostics
+}
+
+/// Check the headers sent on every request to a model or MCP server
+fn validate_headers(path: &str, headers: &Option<HashMap<String, String>>) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+
+    let mut headers: Vec<_> = headers.iter().flatten().collect();
+    headers.sort();
+
+    for (name, value) in headers {
+        if HeaderName::from_str(name).is_err() {
+            diagnostics.push(Diagnostic::error(
+                format!("{path}.headers"),
+                format!("Invalid header name '{name}'"),
+            ));
+        } else if HeaderValue::from_str(value).is_err() {
+            diagnostics.push(Diagnostic::error(
+                format!("{path}.headers.{name}"),
+                String::from("Invalid header value"),
+            ));
+        }
+    }
+
+    diagnostics
+}
+
+/// Check the timezone of the context and the placeholders of its template
+fn validate_context(path: &str, context: &ContextConfig) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+
+    if let Some(timezone) = &context.timezone
+        && let Err(error) = Timezone::from_str(timezone)
+    {
+        diagnostics.push(Diagnostic::error(format!("{path}.timezone"), error));
+    }
+
+    if let Some(template) = &context.template {
+        let placeholders = Regex::new(r"\{([a-z_]+)\}").unwrap();
+
+        for placeholder in placeholders.captures_iter(template) {
+            let name = &placeholder[1];
+
+            if !PLACEHOLDERS.contains(&name) {
+                diagnostics.push(Diagnostic::warning(
+                    format!("{path}.template"),
+                    format!("Unknown placeholder '{{{name}}}', left as is"),
+                ));
+            } else if name == "locale" && context.locale.is_none() {
+                diagnostics.push(Diagnostic::warning(
+                    format!("{path}.template"),
+                    String::from("Placeholder '{locale}' left empty, there's no locale"),
+                ));
+            }
+        }
+    }
+
+    diagnostics
+}
+
+/// Check the options of the transport a remote MCP server is reached over
+fn validate_transport(path: &str, options: &TransportOptions) -> Vec<Diagnostic> {
+    let mut diagnostics = validate_headers(path, &options.headers);
+
+    for (field, value) in [
+        ("heartbeat", options.heartbeat),
+        ("keep_alive", options.keep_alive),
+    ] {
+        if value == Some(0) {
+            diagnostics.push(Diagnostic::error(
+                format!("{path}.{field}"),
+                String::from("Must be greater than 0"),
+            ));
+        }
+    }
+
+    if let Some(AuthConfig::Header { name, .. }) = &options.auth
+        && HeaderName::from_str(name).is_err()
+    {
+        diagnostics.push(Diagnostic::error(
+            format!("{path}.auth.name"),
+            format!("Invalid header name '{name}'"),
+        ));
+    }
+
+    diagnostics
+}
+
+/// Check the fields a model needs, which depend on whether it uses a preset
+fn validate_model(path: &str, model: &Model) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+
+    diagnostics.extend(validate_headers(path, model.common().headers));
+
+    if let Some(pool) = model.common().pool
+        && pool.http2_keep_alive_timeout.is_some()
+        && pool.http2_keep_alive.is_none()
+    {
+        diagnostics.push(Diagnostic::warning(
+            format!("{path}.pool.http2_keep_alive_timeout"),
+            String::from("Timeout ignored, no HTTP/2 pings are sent without http2_keep_alive"),
+        ));
+    }
+
+    if let Some(limit) = model.common().rate_limit {
+        for (field, zero) in [
+            ("requests_per_minute", limit.requests_per_minute == Some(0)),
+            ("tokens_per_minute", limit.tokens_per_minute == Some(0)),
+            ("max_concurrent", limit.max_concurrent == Some(0)),
+        ] {
+            if zero {
+                diagnostics.push(Diagnostic::error(
+                    format!("{path}.rate_limit.{field}"),
+                    String::from("Must be greater than 0"),
+                ));
+            }
+        }
+    }
+
+    // Irrefutable when it's the only provider compiled in
+    #[cfg(feature = "gemini")]
+    #[allow(irrefutable_let_patterns)]
+    if let Model::Gemini {
+        url,
+        preset,
+        model,
+        api_key,
+        auth,
+        ..
+    } = model
+    {
+        if preset.is_some() {
+            for (field, value) in [("model", model), ("api_key", api_key)] {
+                if value.is_none() {
+                    diagnostics.push(Diagnostic::error(
+                        format!("{path}.{field}"),
+                        format!("Missing {field}, required by the preset"),
+                    ));
+                }
+            }
+
+            if url.is_some() {
+                diagnostics.push(Diagnostic::warning(
+                    format!("{path}.url"),
+                    String::from("URL ignored, the preset sets it"),
+                ));
+            }
+
+            if auth.is_some() {
+                diagnostics.push(Diagnostic::warning(
+                    format!("{path}.auth"),
+                    String::from("Authentication ignored, the preset uses the API key"),
+                ));
+            }
+        } else {
+            if url.is_none() {
+                diagnostics.push(Diagnostic::error(
+                    format!("{path}.url"),
+                    String::from("Missing url, required when no preset is used"),
+                ));
+            }
+
+            for (field, value) in [("model", model), ("api_key", api_key)] {
+                if value.is_some() {
+                    diagnostics.push(Diagnostic::warning(
+                        format!("{path}.{field}"),
+                        format!("Field {field} ignored, it's only used by presets"),
+                    ));
+                }
+            }
+        }
+    }
+
+    #[cfg(feature = "xai")]
+    if let Model::Xai { api_key, auth, .. } = model {
+        match (api_key, auth) {
+            (None, None) => diagnostics.push(Diagnostic::error(
+                format!("{path}.api_key"),
+                String::from("Missing api_key, required when no auth is set"),
+            )),
+            (Some(_), Some(_)) => diagnostics.push(Diagnostic::warning(
+                format!("{path}.auth"),
+                String::from("Authentication ignored, the API key is used"),
+            )),
+            _ => (),
+        }
+    }
+
+    #[cfg(feature = "openai-compatible")]
+    #[allow(irrefutable_let_patterns)]
+    if let Model::OpenAICompatible {
+        max_tokens_field: Some(field),
+        ..
+    } = model
+        && field.trim().is_empty()
+    {
+        diagnostics.push(Diagnostic::error(
+            format!("{path}.max_tokens_field"),
+            String::from("Empty max_tokens_field"),
+        ));
+    }
+
+    diagnostics
+}
+
+/// Check the waits and failure budget of the restarts of a local MCP server
+fn validate_restart(path: &str, restart: &RestartConfig) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+
+    if restart.initial_delay == Some(0) {
+        diagnostics.push(Diagnostic::error(
+            format!("{path}.initial_delay"),
+            String::from("The server must be waited for before restarting it"),
+        ));
+    }
+
+    if restart.max_failures == Some(0) {
+        diagnostics.push(Diagnostic::error(
+            format!("{path}.max_failures"),
+            String::from("The server must be allowed to fail at least once"),
+        ));
+    }
+
+    let initial = restart.initial_delay.unwrap_or(DEFAULT_INITIAL_DELAY);
+    let max = restart.max_delay.unwrap_or(DEFAULT_MAX_DELAY);
+
+    if initial > max {
+        diagnostics.push(Diagnostic::warning(
+            format!("{path}.max_delay"),
+            format!("The wait starts at {initial} seconds, above the maximum of {max}"),
+        ));
+    }
+
+    if restart.policy == RestartPolicy::Never
+        && (restart.initial_delay.is_some()
+            || restart.max_delay.is_some()
+            || restart.max_failures.is_some())
+    {
+        diagnostics.push(Diagnostic::warning(
+            path.to_owned(),
+            String::from("Restart options ignored, the policy is never"),
+        ));
+    }
+
+    diagnostics
+}
+
+/// Check the roots of a MCP server and the headers sent or forwarded to it
+fn validate_mcp(path: &str, mcp: &Mcp) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+
+    if let Mcp::Local {
+        roots: Some(roots), ..
+    } = mcp
+    {
+        for (index, root) in roots.iter().enumerate() {
+            if !Path::new(root).is_dir() {
+                diagnostics.push(Diagnostic::warning(
+                    format!("{path}.roots[{index}]"),
+                    format!("Root '{root}' isn't an existing directory"),
+                ));
+            }
+        }
+    }
+
+    if let Mcp::Local {
+        restart: Some(restart),
+        retries,
+        ..
+    } = mcp
+    {
+        diagnostics.extend(validate_restart(&format!("{path}.restart"), restart));
+
+        if retries.is_some() && restart.policy != RestartPolicy::Never {
+            diagnostics.push(Diagnostic::warning(
+                format!("{path}.retries"),
+                String::from("Retries ignored, the server is restarted as its policy tells"),
+            ));
+        }
+    }
+
+    let (Mcp::Local { convert, .. } | Mcp::Remote { convert, .. }) = mcp;
+
+    for (tool, content_types) in convert.iter().flatten() {
+        if content_types.is_empty() {
+            diagnostics.push(Diagnostic::warning(
+                format!("{path}.convert.{tool}"),
+                String::from("No content type to convert, the outputs are given as they are"),
+            ));
+        }
+    }
+
+    if let Mcp::Remote {
+        headers,
+        transport,
+        sse,
+        http,
+        ..
+    } = mcp
+    {
+        diagnostics.extend(validate_headers(path, headers));
+
+        if let Some(sse) = sse {
+            diagnostics.extend(validate_transport(&format!("{path}.sse"), &sse.options));
+        }
+
+        if let Some(http) = http {
+            diagnostics.extend(validate_transport(&format!("{path}.http"), &http.options));
+        }
+
+        let unused = match transport {
+            TransportType::Sse => http.is_some().then_some(("http", "SSE")),
+            TransportType::Http => sse.is_some().then_some(("sse", "streamable HTTP")),
+        };
+
+        if let Some((field, transport)) = unused {
+            diagnostics.push(Diagnostic::warning(
+                format!("{path}.{field}"),
+                format!("Options ignored, the server is reached over {transport}"),
+            ));
+        }
+    }
+
+    let (Mcp::Local {
+        forward_headers: Some(forward_headers),
+        cache,
+        ..
+    }
+    | Mcp::Remote {
+        forward_headers: Some(forward_headers),
+        cache,
+        ..
+    }) = mcp
+    else {
+        return diagnostics;
+    };
+
+    for (index, name) in forward_headers.iter().enumerate() {
+        if HeaderName::from_str(name).is_err() {
+            diagnostics.push(Diagnostic::error(
+                format!("{path}.forward_headers[{index}]"),
+                format!("Invalid header name '{name}'"),
+            ));
+        }
+    }
+
+    if cache.is_some() {
+        diagnostics.push(Diagnostic::warning(
+            format!("{path}.cache"),
+            String::from(
+                "Cached results are shared by all requests, whatever headers they forward",
+            ),
+        ));
+    }
+
+    diagnostics
+}
+
+/// Check the budgets can be told apart and enforced
+fn validate_budgets(config: &FileConfig, budgets: &[BudgetConfig]) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+
+    if config.storage.is_none() && !budgets.is_empty() {
+        diagnostics.push(Diagnostic::error(
+            String::from("budgets"),
+            String::from("Budgets need the storage to keep track of the usage"),
+        ));
+    }
+
+    let mut names = HashSet::new();
+    let mut keys = HashSet::new();
+
+    for (index, budget) in budgets.iter().enumerate() {
+        let path = format!("budgets[{index}]");
+
+        if !names.insert(&budget.name) {
+            diagnostics.push(Diagnostic::error(
+                format!("{path}.name"),
+                format!("Duplicate budget {}", budget.name),
+            ));
+        }
+
+        if !keys.insert(&budget.key) {
+            diagnostics.push(Diagnostic::error(
+                format!("{path}.key"),
+                String::from("Key already used by another budget"),
+            ));
+        }
+
+        if budget.max_tokens.is_none() && budget.max_cost.is_none() {
+            diagnostics.push(Diagnostic::error(
+                path.clone(),
+                String::from("Missing max_tokens or max_cost"),
+            ));
+        }
+
+        if budget.max_cost.is_some()
+            && config
+                .models
+                .values()
+                .all(|model| model.common().price.is_none())
+        {
+            diagnostics.push(Diagnostic::warning(
+                format!("{path}.max_cost"),
+                String::from("No model has a price, the cost will stay at 0"),
+            ));
+        }
+    }
+
+    diagnostics
+}
+
+/// Check the clients can be told apart and only use existing workspaces
+fn validate_clients(
+    config: &FileConfig,
+    clients: &HashMap<String, ClientConfig>,
+) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+
+    let mut clients: Vec<_> = clients.iter().collect();
+    clients.sort_by_key(|(name, _)| *name);
+
+    let mut keys = HashSet::new();
+
+    for (name, client) in clients {
+        let path = format!("clients.{name}");
+
+        if !keys.insert(&client.key) {
+            diagnostics.push(Diagnostic::error(
+                format!("{path}.key"),
+                String::from("Key already used by another client"),
+            ));
+        }
+
+        if client.workspaces.is_empty() {
+            diagnostics.push(Diagnostic::warning(
+                format!("{path}.workspaces"),
+                String::from("Empty list of workspaces, the client can't use any"),
+            ));
+        }
+
+        for (index, workspace) in client.workspaces.iter().enumerate() {
+            if !config.workspaces.contains_key(workspace) {
+                diagnostics.push(Diagnostic::error(
+                    format!("{path}.workspaces[{index}]"),
+                    format!("Undefined workspace '{workspace}'"),
+                ));
+            }
+        }
+
+        // The usage of the client is recorded under its name, which its budget must find
+        for (index, budget) in config.budgets.iter().flatten().enumerate() {
+            if budget.key == client.key && budget.name != *name {
+                diagnostics.push(Diagnostic::error(
+                    format!("budgets[{index}].name"),
+                    format!("Budget of client {name} must be named after it"),
+                ));
+            }
+        }
+    }
+
+    diagnostics
+}
+
+/// Listeners that can't be bound together: same port on an unspecified address and another one
+fn port_conflicts(listeners: Vec<(String, &str)>) -> Vec<Diagnostic> {
+    let mut ports: HashMap<&str, Vec<(&str, &str)>> = HashMap::new();
+
+    for (listener, section) in listeners.iter() {
+        if let Some((address, port)) = listener.rsplit_once(':') {
+            ports.entry(port).or_default().push((address, section));
+        }
+    }
+
+    let mut ports: Vec<_> = ports.into_iter().collect();
+    ports.sort();
+
+    ports
+        .into_iter()
+        .filter(|(_, addresses)| {
+            addresses.len() > 1
+                && addresses
+                    .iter()
+                    .any(|(address, _)| UNSPECIFIED_ADDRESSES.contains(address))
+        })
+        .map(|(port, mut addresses)| {
+            addresses.sort();
+
+            Diagnostic::error(
+                addresses[0].1.to_owned(),
+                format!(
+                    "Port {port} can't be bound on all addresses and on specific ones: {}",
+                    addresses
+                        .iter()
+                        .map(|(address, _)| *address)
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_yaml::from_str;
+
+    use super::*;
+
+    const MODELS: &str = "
+models:
+  gemini:
+    type: gemini
+    url: https://generativelanguage.googleapis.com/v1beta/openai/chat/completions
+mcps:
+  filesystem:
+    command: npx
+  git:
+    command: uvx
+";
+
+    /// Diagnostics of the models and MCP servers above with the rest of the configuration, as
+    /// `<path>: <message>` for the errors and `warning <path>: <message>` for the warnings
+    fn diagnostics(rest: &str) -> Vec<String> {
+        let config: FileConfig = from_str(&format!("{MODELS}{rest}")).unwrap();
+
+        validate(&config)
+            .into_iter()
+            .map(|diagnostic| match diagnostic.severity {
+                Severity::Error => diagnostic.to_string(),
+                Severity::Warning => format!("warning {diagnostic}"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn accepts_valid_configuration() {
+        assert_eq!(
+            diagnostics(
+                "
+workspaces:
+  test:
+    config:
+      path: /test
+    model: gemini
+    mcps: [filesystem, git]
+"
+            ),
+            Vec::<String>::new()
+        );
+    }
+
+    #[test]
+    fn refuses_undefined_references() {
+        assert_eq!(
+            diagnostics(
+                "
+workspaces:
+  test:
+    config:
+      path: /test
+    model: openai
+    mcps: [filesystem, git, github]
+"
+            ),
+            [
+                "workspaces.test.model: Undefined model 'openai'",
+                "workspaces.test.mcps[2]: Undefined MCP server 'github'",
+                "warning models.gemini: Model not used by any workspace",
+            ]
+        );
+    }
+
+    #[test]
+    fn warns_about_workspaces_without_tools() {
+        assert_eq!(
+            diagnostics(
+                "
+toolsets:
+  files:
+    mcps: [filesystem]
+workspaces:
+  none:
+    config:
+      path: /none
+    model: gemini
+    mcps: []
+  toolsets:
+    config:
+      path: /toolsets
+    model: gemini
+    mcps: []
+    toolsets: [files]
+  empty:
+    config:
+      path: /empty
+    model: gemini
+    toolsets: []
+  both:
+    config:
+      path: /both
+    model: gemini
+    mcps: [filesystem]
+    toolsets: [files]
+"
+            ),
+            [
+                "workspaces.both.toolsets[0]: MCP server 'filesystem' is already given by \
+                 workspaces.both.mcps",
+                "warning workspaces.empty.toolsets: Empty list of toolsets, the model won't have \
+                 any tools",
+                "warning workspaces.none.mcps: Empty list of MCP servers, the model won't have \
+                 any tools",
+                "warning mcps.git: MCP server not used by any workspace, it will be started anyway",
+            ]
+        );
+    }
+
+    #[test]
+    fn checks_admin_listener() {
+        assert_eq!(
+            diagnostics(
+                "
+workspaces:
+  test:
+    config:
+      path: /test
+      port: 7070
+    model: gemini
+    mcps: [filesystem, git]
+admin:
+  port: 7070
+  token: ''
+"
+            ),
+            [
+                "admin: Listener 127.0.0.1:7070 is already used by workspaces",
+                "admin.token: Empty token, the administration API would be open to anyone",
+            ]
+        );
+    }
+}
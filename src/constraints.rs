@@ -0,0 +1,98 @@
+use regex::Regex;
+use tracing::{Level, event};
+
+use crate::{
+    Error, ManagerBody,
+    guardrails::Guardrails,
+    models::{Message, Role, TextMessage},
+};
+
+/// Constraints on the answers of a workspace, told to the model in a system message after the
+/// preamble and enforced on what it answers
+#[derive(Debug, Default)]
+pub struct OutputConstraints {
+    /// Language the model answers in
+    pub(crate) language: Option<String>,
+    /// Tokens of each answer of the model, lowering the `max_tokens` of the requests to it
+    pub(crate) max_response_tokens: Option<usize>,
+    pub(crate) forbidden_phrases: Vec<String>,
+    /// Forbidden phrases as whole words, regardless of case
+    pub(crate) forbidden: Vec<Regex>,
+}
+
+impl OutputConstraints {
+    pub fn new(
+        language: Option<String>,
+        max_response_tokens: Option<usize>,
+        forbidden_phrases: Vec<String>,
+    ) -> OutputConstraints {
+        OutputConstraints {
+            language,
+            max_response_tokens,
+            forbidden: Guardrails::keywords(&forbidden_phrases),
+            forbidden_phrases,
+        }
+    }
+
+    /// System message telling the model the constraints
+    pub(crate) fn message(&self) -> Message {
+        let mut instructions = Vec::new();
+
+        if let Some(language) = &self.language {
+            instructions.push(format!("Always answer in {language}."));
+        }
+
+        if let Some(max_response_tokens) = self.max_response_tokens {
+            instructions.push(format!(
+                "Keep each answer under {max_response_tokens} tokens."
+            ));
+        }
+
+        if !self.forbidden_phrases.is_empty() {
+            instructions.push(format!(
+                "Never use the following phrases: {}.",
+                self.forbidden_phrases
+                    .iter()
+                    .map(|phrase| format!("\"{phrase}\""))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ));
+        }
+
+        Message::TextMessage(TextMessage {
+            role: Role::System,
+            content: instructions.join(" "),
+        })
+    }
+
+    /// Lower the tokens the model can answer with to the maximum of the workspace
+    pub(crate) fn limit(&self, request: &mut ManagerBody) {
+        if let Some(max_response_tokens) = self.max_response_tokens {
+            let max = isize::try_from(max_response_tokens).unwrap_or(isize::MAX);
+
+            request.max_tokens = Some(request.max_tokens.map_or(max, |tokens| tokens.min(max)));
+        }
+    }
+
+    /// Whether the answers are checked, which needs their whole text
+    pub(crate) fn filters_output(&self) -> bool {
+        !self.forbidden.is_empty()
+    }
+
+    /// Refuse answers with a forbidden phrase
+    pub(crate) fn check(&self, text: &str) -> Result<(), Error> {
+        if let Some(pattern) = self.forbidden.iter().find(|pattern| pattern.is_match(text)) {
+            event!(
+                Level::WARN,
+                "Model output has forbidden phrase matching '{pattern}'"
+            );
+
+            return Err(Error {
+                status: 502,
+                message: String::from("Response has a forbidden phrase"),
+            });
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,85 @@
+use std::str::FromStr;
+
+use chrono::{DateTime, FixedOffset, Local, SecondsFormat, Utc};
+
+use crate::models::{Message, Role, TextMessage};
+
+/// Template of the context when none is set
+pub(crate) const DEFAULT_TEMPLATE: &str =
+    "Current date and time: {weekday}, {date} {time} ({timezone}).";
+/// Added to the default template when there's a locale
+pub(crate) const LOCALE_TEMPLATE: &str = " Locale of the user: {locale}.";
+/// Placeholders the templates can have
+pub(crate) const PLACEHOLDERS: [&str; 7] = [
+    "date", "time", "weekday", "datetime", "timezone", "offset", "locale",
+];
+
+/// Current date and time, and the locale of the users, given to the model as a system message
+/// filled in on every request, so it knows what "today" is
+pub struct Context {
+    pub(crate) template: String,
+    pub(crate) timezone: Timezone,
+    pub(crate) locale: Option<String>,
+}
+
+/// Timezone the date and time are given in
+#[derive(Clone, Copy, Debug)]
+pub enum Timezone {
+    Utc,
+    /// Timezone of the system, following its daylight saving time
+    Local,
+    Fixed(FixedOffset),
+}
+
+impl FromStr for Timezone {
+    type Err = String;
+
+    fn from_str(timezone: &str) -> Result<Self, Self::Err> {
+        match timezone {
+            "UTC" | "utc" => Ok(Timezone::Utc),
+            "local" => Ok(Timezone::Local),
+            offset => FixedOffset::from_str(offset)
+                .map(Timezone::Fixed)
+                .map_err(|_| {
+                    format!("Invalid timezone '{timezone}', expected UTC, local or an offset such as +02:00")
+                }),
+        }
+    }
+}
+
+impl Context {
+    /// System message with the template filled in with the current date and time
+    pub(crate) fn message(&self) -> Message {
+        let now = Utc::now();
+
+        let now: DateTime<FixedOffset> = match self.timezone {
+            Timezone::Utc => now.fixed_offset(),
+            Timezone::Local => now.with_timezone(&Local).fixed_offset(),
+            Timezone::Fixed(offset) => now.with_timezone(&offset),
+        };
+
+        let offset = now.format("%:z").to_string();
+        let timezone = match self.timezone {
+            Timezone::Utc => String::from("UTC"),
+            _ => format!("UTC{offset}"),
+        };
+
+        let content = self
+            .template
+            .replace("{date}", &now.format("%Y-%m-%d").to_string())
+            .replace("{time}", &now.format("%H:%M").to_string())
+            .replace("{weekday}", &now.format("%A").to_string())
+            .replace(
+                "{datetime}",
+                &now.to_rfc3339_opts(SecondsFormat::Secs, false),
+            )
+            .replace("{timezone}", &timezone)
+            .replace("{offset}", &offset)
+            .replace("{locale}", self.locale.as_deref().unwrap_or_default());
+
+        Message::TextMessage(TextMessage {
+            role: Role::System,
+            content,
+        })
+    }
+}
@@ -0,0 +1,172 @@
+use axum::{
+    Json,
+    body::Body,
+    http::header::CONTENT_TYPE,
+    response::{IntoResponse, Response},
+};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value, from_str, json};
+use tracing::{Level, event, instrument};
+
+use crate::{
+    Error, Workspace, error_path,
+    models::client::{ClientOptions, ModelClient},
+};
+
+/// Suffix of the path embedding texts with the model of a workspace
+const EMBEDDINGS_SUFFIX: &str = "/embeddings";
+
+/// API used to embed the texts
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EmbeddingsProvider {
+    OpenAI,
+    Azure,
+    Gemini,
+}
+
+/// Embedding model of a workspace, reached with its own credentials and answering in the format
+/// of the OpenAI embeddings API whatever the provider
+pub struct Embeddings {
+    provider: EmbeddingsProvider,
+    client: ModelClient,
+    url: Url,
+    model: Option<String>,
+}
+
+/// Texts to embed, one or several
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Input {
+    One(String),
+    Many(Vec<String>),
+}
+
+#[derive(Debug, Deserialize)]
+struct GeminiResponse {
+    embeddings: Vec<GeminiEmbedding>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GeminiEmbedding {
+    values: Vec<f64>,
+}
+
+impl Embeddings {
+    pub async fn new(
+        provider: EmbeddingsProvider,
+        url: String,
+        options: ClientOptions,
+        model: Option<String>,
+    ) -> Embeddings {
+        let (client, url) = ModelClient::new(url, options, None, None).await;
+
+        Embeddings {
+            provider,
+            client,
+            url,
+            model,
+        }
+    }
+
+    /// Embeddings of the `input` of an OpenAI embeddings request
+    #[instrument(skip_all)]
+    async fn embed(&self, mut request: Map<String, Value>) -> Result<Response, Error> {
+        match self.provider {
+            EmbeddingsProvider::OpenAI | EmbeddingsProvider::Azure => {
+                // Azure deployments have their model, the one of the clients is only replaced
+                if let Some(model) = &self.model {
+                    request.insert(String::from("model"), json!(model));
+                }
+
+                let response = self.client.call(self.url.clone(), &request).await?;
+
+                Ok(([(CONTENT_TYPE, "application/json")], Body::from(response)).into_response())
+            }
+            EmbeddingsProvider::Gemini => self.gemini(request).await,
+        }
+    }
+
+    /// Texts embedded in a batch by Gemini, its vectors given back as OpenAI ones
+    async fn gemini(&self, request: Map<String, Value>) -> Result<Response, Error> {
+        let input = request
+            .get("input")
+            .cloned()
+            .and_then(|input| serde_json::from_value(input).ok())
+            .ok_or_else(|| Error {
+                status: 422,
+                message: String::from("The input must be a text or a list of texts"),
+            })?;
+        let inputs = match input {
+            Input::One(text) => vec![text],
+            Input::Many(texts) => texts,
+        };
+
+        let model = format!("models/{}", self.model.as_deref().unwrap_or_default());
+        let dimensions = request.get("dimensions").and_then(Value::as_u64);
+
+        let requests: Vec<Value> = inputs
+            .iter()
+            .map(|text| {
+                let mut request = json!({
+                    "model": model,
+                    "content": {"parts": [{"text": text}]},
+                });
+
+                if let Some(dimensions) = dimensions {
+                    request["outputDimensionality"] = json!(dimensions);
+                }
+
+                request
+            })
+            .collect();
+
+        let response = self
+            .client
+            .call(self.url.clone(), &json!({ "requests": requests }))
+            .await?;
+
+        let response: GeminiResponse = from_str(&response).map_err(|error| {
+            event!(Level::ERROR, "Invalid embeddings response: {error}");
+
+            Error {
+                status: 502,
+                message: String::from("Invalid response from embedding model"),
+            }
+        })?;
+
+        let data: Vec<Value> = response
+            .embeddings
+            .into_iter()
+            .enumerate()
+            .map(|(index, embedding)| {
+                json!({"object": "embedding", "index": index, "embedding": embedding.values})
+            })
+            .collect();
+
+        Ok(Json(json!({
+            "object": "list",
+            "data": data,
+            "model": self.model,
+        }))
+        .into_response())
+    }
+}
+
+/// Path of the workspace whose embedding model answers `<path>/embeddings`
+pub(crate) fn path(path: &str) -> Option<&str> {
+    path.strip_suffix(EMBEDDINGS_SUFFIX)
+        .filter(|workspace| !workspace.is_empty())
+}
+
+/// Embed the texts of an OpenAI embeddings request with the embedding model of the workspace
+pub(crate) async fn embed(workspace: &Workspace, body: Body) -> Result<Response, Error> {
+    let Some(embeddings) = &workspace.embeddings else {
+        return Err(error_path().await);
+    };
+
+    let request: Map<String, Value> = workspace.limits.read_json(body).await?;
+
+    embeddings.embed(request).await
+}
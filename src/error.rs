@@ -2,20 +2,17 @@ use axum::{body::Body, http::Response, response::IntoResponse};
 use reqwest::Error as HttpError;
 use serde::Serialize;
 
-#[derive(Debug, Serialize)]
+#[derive(Clone, Debug, Serialize)]
 pub struct Error {
     pub(crate) status: u16,
     pub(crate) message: String,
 }
 
+/// Failure of a call to an upstream service (model, moderation...)
 impl From<HttpError> for Error {
     fn from(value: HttpError) -> Self {
         Error {
-            status: if let Some(status) = value.status() {
-                status.as_u16()
-            } else {
-                500
-            },
+            status: if value.is_timeout() { 504 } else { 502 },
             message: value.to_string(),
         }
     }
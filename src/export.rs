@@ -0,0 +1,187 @@
+use std::collections::HashMap;
+
+use axum::{
+    Json,
+    http::header::CONTENT_TYPE,
+    response::{IntoResponse, Response},
+};
+use serde::Deserialize;
+use serde_json::{Value, json};
+
+use crate::{
+    ManagerBody,
+    models::{Message, Role, TextMessage, openai::RequestBody},
+    storage::Conversation,
+};
+
+/// Format a conversation is exported in
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    /// Messages of the OpenAI chat completions API
+    #[default]
+    OpenAI,
+    /// System prompt and messages of the Anthropic messages API
+    Anthropic,
+    /// Human-readable document, with a section per tool call
+    Markdown,
+}
+
+impl ExportFormat {
+    /// Transcript of a conversation in this format
+    pub(crate) fn export(
+        self,
+        id: &str,
+        conversation: &Conversation,
+        messages: Vec<Message>,
+    ) -> Response {
+        match self {
+            ExportFormat::OpenAI => Json(openai(messages)).into_response(),
+            ExportFormat::Anthropic => Json(anthropic(messages)).into_response(),
+            ExportFormat::Markdown => (
+                [(CONTENT_TYPE, "text/markdown; charset=utf-8")],
+                markdown(id, conversation, &messages),
+            )
+                .into_response(),
+        }
+    }
+}
+
+fn openai(messages: Vec<Message>) -> Value {
+    let request = RequestBody::from(ManagerBody {
+        messages,
+        ..Default::default()
+    });
+
+    json!({ "messages": request.messages })
+}
+
+/// Messages of the Anthropic API, which takes the system prompt apart and needs the turns of the
+/// user and the assistant to alternate, the tool outputs being given by the user
+fn anthropic(messages: Vec<Message>) -> Value {
+    let mut system = Vec::new();
+    let mut turns: Vec<(&str, Vec<Value>)> = Vec::new();
+
+    for message in messages {
+        let (role, block) = match message {
+            Message::TextMessage(TextMessage {
+                role: Role::System,
+                content,
+            }) => {
+                system.push(content);
+                continue;
+            }
+            Message::TextMessage(TextMessage { role, content }) => (
+                if matches!(role, Role::Assistant) {
+                    "assistant"
+                } else {
+                    "user"
+                },
+                vec![json!({"type": "text", "text": content})],
+            ),
+            Message::ToolCalls { tool_calls, .. } => (
+                "assistant",
+                tool_calls
+                    .into_iter()
+                    .map(|call| {
+                        json!({
+                            "type": "tool_use",
+                            "id": call.id,
+                            "name": call.name,
+                            "input": call.arguments.unwrap_or_default(),
+                        })
+                    })
+                    .collect(),
+            ),
+            Message::ToolOutput {
+                call_id, output, ..
+            } => (
+                "user",
+                vec![json!({"type": "tool_result", "tool_use_id": call_id, "content": output})],
+            ),
+        };
+
+        match turns.last_mut() {
+            Some((last, content)) if *last == role => content.extend(block),
+            _ => turns.push((role, block)),
+        }
+    }
+
+    let messages: Vec<Value> = turns
+        .into_iter()
+        .map(|(role, content)| json!({"role": role, "content": content}))
+        .collect();
+
+    if system.is_empty() {
+        json!({ "messages": messages })
+    } else {
+        json!({ "system": system.join("\n\n"), "messages": messages })
+    }
+}
+
+fn markdown(id: &str, conversation: &Conversation, messages: &[Message]) -> String {
+    let mut document = format!(
+        "# Conversation {id}\n\nWorkspace `{}`, {}\n",
+        conversation.workspace,
+        conversation.started_at.to_rfc3339()
+    );
+    // Names of the tools, to title their outputs
+    let mut tools = HashMap::new();
+
+    for message in messages {
+        match message {
+            Message::TextMessage(TextMessage { role, content }) => {
+                let title = match role {
+                    Role::Assistant => "Assistant",
+                    Role::System => "System",
+                    Role::Tool => "Tool",
+                    Role::User => "User",
+                };
+
+                document.push_str(&format!("\n## {title}\n\n{content}\n"));
+            }
+            Message::ToolCalls { tool_calls, .. } => {
+                document.push_str("\n## Assistant\n");
+
+                for call in tool_calls {
+                    tools.insert(call.id.as_str(), call.name.as_str());
+
+                    let arguments =
+                        serde_json::to_string_pretty(&call.arguments.clone().unwrap_or_default())
+                            .unwrap_or_default();
+
+                    document.push_str(&format!(
+                        "\n### Tool call `{}` ({})\n\n{}\n",
+                        call.name,
+                        call.id,
+                        code_block(&arguments, "json")
+                    ));
+                }
+            }
+            Message::ToolOutput {
+                call_id, output, ..
+            } => {
+                let name = tools.get(call_id.as_str()).copied().unwrap_or("unknown");
+
+                document.push_str(&format!(
+                    "\n## Tool output `{name}` ({call_id})\n\n{}\n",
+                    code_block(output, "")
+                ));
+            }
+        }
+    }
+
+    document
+}
+
+/// Fenced block of text, the fence being longer than any run of backticks in it
+fn code_block(text: &str, language: &str) -> String {
+    let longest = text
+        .split(|character| character != '`')
+        .map(str::len)
+        .max()
+        .unwrap_or_default();
+    let fence = "`".repeat(longest.max(2) + 1);
+
+    format!("{fence}{language}\n{text}\n{fence}")
+}
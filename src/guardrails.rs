@@ -0,0 +1,149 @@
+use std::{borrow::Cow, sync::LazyLock};
+
+use regex::{Captures, Regex};
+use serde::{Deserialize, Serialize};
+use tracing::{Level, event};
+
+use crate::{
+    Error, ManagerBody,
+    models::{Message, Role, TextMessage},
+};
+
+static EMAIL: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"[A-Za-z0-9._%+-]+@[A-Za-z0-9.-]+\.[A-Za-z]{2,}").unwrap());
+static CREDIT_CARD: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\b(?:\d[ -]?){12,18}\d\b").unwrap());
+static PHONE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?:\+\d{1,3}[ .-]?)?(?:\(\d{1,4}\)[ .-]?)?\b\d{2,4}[ .-]?\d{3,4}[ .-]?\d{3,4}\b")
+        .unwrap()
+});
+
+/// Kinds of personal information that can be redacted
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Pii {
+    Email,
+    Phone,
+    CreditCard,
+}
+
+impl Pii {
+    fn placeholder(&self) -> &'static str {
+        match self {
+            Pii::Email => "[EMAIL]",
+            Pii::Phone => "[PHONE]",
+            Pii::CreditCard => "[CREDIT_CARD]",
+        }
+    }
+
+    fn redact<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        match self {
+            Pii::Email => EMAIL.replace_all(text, self.placeholder()),
+            Pii::Phone => PHONE.replace_all(text, self.placeholder()),
+            // Only numbers passing the Luhn check, to leave other long numbers alone
+            Pii::CreditCard => CREDIT_CARD.replace_all(text, |captures: &Captures| {
+                if luhn(&captures[0]) {
+                    self.placeholder().to_owned()
+                } else {
+                    captures[0].to_owned()
+                }
+            }),
+        }
+    }
+}
+
+/// Filters applied to what goes in and out of the model of a workspace
+#[derive(Debug, Default)]
+pub struct Guardrails {
+    pub(crate) deny: Vec<Regex>,
+    pub(crate) pii: Vec<Pii>,
+}
+
+impl Guardrails {
+    /// Patterns matching the keywords as whole words, regardless of case
+    pub fn keywords(keywords: &[String]) -> Vec<Regex> {
+        keywords
+            .iter()
+            .map(|keyword| {
+                Regex::new(&format!(r"(?i)\b{}\b", regex::escape(keyword)))
+                    .expect("Escaped keywords are valid patterns")
+            })
+            .collect()
+    }
+
+    fn denied(&self, text: &str) -> Option<&Regex> {
+        self.deny.iter().find(|pattern| pattern.is_match(text))
+    }
+
+    /// Refuse requests whose user messages match a denied pattern
+    pub(crate) fn check_input(&self, body: &ManagerBody) -> Result<(), Error> {
+        for message in body.messages.iter() {
+            if let Message::TextMessage(TextMessage {
+                role: Role::User,
+                content,
+            }) = message
+                && let Some(pattern) = self.denied(content)
+            {
+                event!(Level::WARN, "Request denied by pattern '{pattern}'");
+
+                return Err(Error {
+                    status: 400,
+                    message: String::from("Request blocked by guardrails"),
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Whether the outputs of the model are checked or redacted, which needs their whole text
+    pub(crate) fn filters_output(&self) -> bool {
+        !self.deny.is_empty() || !self.pii.is_empty()
+    }
+
+    /// Refuse model outputs matching a denied pattern
+    pub(crate) fn check_output(&self, text: &str) -> Result<(), Error> {
+        if let Some(pattern) = self.denied(text) {
+            event!(Level::WARN, "Model output denied by pattern '{pattern}'");
+
+            return Err(Error {
+                status: 502,
+                message: String::from("Response blocked by guardrails"),
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Replace the configured kinds of personal information by placeholders
+    pub(crate) fn redact(&self, text: String) -> String {
+        // Credit cards go first, as their numbers would also pass as phone numbers
+        let mut kinds = self.pii.clone();
+        kinds.sort_by_key(|kind| *kind != Pii::CreditCard);
+
+        kinds
+            .iter()
+            .fold(text, |text, kind| kind.redact(&text).into_owned())
+    }
+}
+
+fn luhn(number: &str) -> bool {
+    let digits: Vec<u32> = number.chars().filter_map(|c| c.to_digit(10)).collect();
+
+    let sum: u32 = digits
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(index, digit)| {
+            if index % 2 == 1 {
+                let double = digit * 2;
+
+                if double > 9 { double - 9 } else { double }
+            } else {
+                *digit
+            }
+        })
+        .sum();
+
+    sum.is_multiple_of(10)
+}
@@ -0,0 +1,229 @@
+use std::{
+    sync::{Arc, RwLock},
+    time::Instant,
+};
+
+use async_trait::async_trait;
+use axum::{
+    Extension, Json,
+    extract::Path,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use futures::StreamExt;
+use rmcp::{ServiceError, model::Tool};
+use serde::Serialize;
+use tracing::instrument;
+
+use crate::{
+    Error, HandlerConfig, ManagerBody,
+    clients::Client,
+    error_path,
+    mcp::{McpServer, ToolCall, ToolOutput},
+    models::{AIModel, DeltaStream, ModelDelta, ModelTurn},
+    not_allowed,
+    readiness::Readiness,
+    templates, tools,
+};
+
+/// Suffix of the path of a workspace answering its health
+pub(crate) const HEALTH_SUFFIX: &str = "/health";
+
+/// Outcome of the calls made to a model or MCP server
+#[derive(Debug, Default)]
+pub(crate) struct Activity {
+    state: RwLock<ActivityReport>,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub(crate) struct ActivityReport {
+    pub(crate) last_call: Option<CallOutcome>,
+    pub(crate) last_success: Option<DateTime<Utc>>,
+    /// Tools last listed, for MCP servers
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) tools: Option<usize>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub(crate) struct CallOutcome {
+    pub(crate) at: DateTime<Utc>,
+    pub(crate) ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) error: Option<String>,
+}
+
+impl Activity {
+    fn record(&self, result: Result<(), String>) {
+        let at = Utc::now();
+        let mut state = self.state.write().expect("Activity lock poisoned");
+
+        if result.is_ok() {
+            state.last_success = Some(at);
+        }
+
+        state.last_call = Some(CallOutcome {
+            at,
+            ok: result.is_ok(),
+            error: result.err(),
+        });
+    }
+
+    pub(crate) fn report(&self) -> ActivityReport {
+        self.state.read().expect("Activity lock poisoned").clone()
+    }
+}
+
+/// Model recording the outcome of its calls
+pub(crate) struct MonitoredModel {
+    pub(crate) inner: Arc<dyn AIModel + Send>,
+    pub(crate) activity: Arc<Activity>,
+}
+
+#[async_trait]
+impl AIModel for MonitoredModel {
+    async fn call(&self, body: ManagerBody, tools: Vec<Tool>) -> Result<ModelTurn, Error> {
+        let start = Instant::now();
+        let mut response = self.inner.call(body, tools).await;
+
+        if let Ok(turn) = &mut response {
+            turn.latency_ms.get_or_insert(start.elapsed().as_millis());
+        }
+
+        self.activity.record(
+            response
+                .as_ref()
+                .map(|_| ())
+                .map_err(|error| error.message.clone()),
+        );
+
+        response
+    }
+
+    /// Deltas of the inner model, the outcome being recorded once the stream ends or fails
+    async fn stream(&self, body: ManagerBody, tools: Vec<Tool>) -> Result<DeltaStream, Error> {
+        let start = Instant::now();
+
+        let deltas = match self.inner.stream(body, tools).await {
+            Ok(deltas) => deltas,
+            Err(error) => {
+                self.activity.record(Err(error.message.clone()));
+
+                return Err(error);
+            }
+        };
+
+        let activity = Arc::clone(&self.activity);
+
+        Ok(Box::pin(deltas.map(move |mut delta| {
+            match &mut delta {
+                Ok(ModelDelta::Finish(turn)) => {
+                    turn.latency_ms.get_or_insert(start.elapsed().as_millis());
+                    activity.record(Ok(()));
+                }
+                Err(error) => activity.record(Err(error.message.clone())),
+                Ok(_) => (),
+            }
+
+            delta
+        })))
+    }
+}
+
+/// MCP server recording the outcome of its calls and the number of tools it lists
+pub(crate) struct MonitoredMcp {
+    pub(crate) inner: Arc<dyn McpServer + Send>,
+    pub(crate) activity: Arc<Activity>,
+}
+
+#[async_trait]
+impl McpServer for MonitoredMcp {
+    async fn call(&self, call: ToolCall) -> Result<ToolOutput, ServiceError> {
+        let output = self.inner.call(call).await;
+
+        self.activity
+            .record(output.as_ref().map(|_| ()).map_err(ServiceError::to_string));
+
+        output
+    }
+
+    async fn list_tools(&self) -> Result<Vec<Tool>, ServiceError> {
+        let tools = self.inner.list_tools().await;
+
+        if let Ok(tools) = &tools {
+            self.activity
+                .state
+                .write()
+                .expect("Activity lock poisoned")
+                .tools = Some(tools.len());
+        }
+
+        self.activity
+            .record(tools.as_ref().map(|_| ()).map_err(ServiceError::to_string));
+
+        tools
+    }
+}
+
+#[instrument(skip(config, readiness, caller))]
+pub async fn health_handler(
+    Extension(config): Extension<HandlerConfig>,
+    Extension(readiness): Extension<Arc<Readiness>>,
+    caller: Option<Extension<Arc<Client>>>,
+    Path(mut path): Path<String>,
+) -> Result<Response, Error> {
+    path.insert(0, '/');
+
+    if let Some(path) = tools::list_path(&path) {
+        let Some(workspace) = config.read().await.get(path).map(Arc::clone) else {
+            return Err(error_path().await);
+        };
+
+        let caller = caller.map(|Extension(caller)| caller);
+
+        if let Some(caller) = &caller {
+            caller.check(&workspace.name)?;
+        }
+
+        readiness.check(&workspace.name)?;
+
+        return tools::list(&workspace, caller.as_deref()).await;
+    }
+
+    if let Some(path) = templates::list_path(&path) {
+        let Some(workspace) = config.read().await.get(path).map(Arc::clone) else {
+            return Err(error_path().await);
+        };
+
+        if let Some(Extension(caller)) = &caller {
+            caller.check(&workspace.name)?;
+        }
+
+        return Ok(templates::list(&workspace));
+    }
+
+    // Workspaces only answer POST on their own path
+    let Some(path) = path.strip_suffix(HEALTH_SUFFIX) else {
+        if config.read().await.contains_key(&path) {
+            return Ok(not_allowed("POST"));
+        }
+
+        return Err(error_path().await);
+    };
+
+    let Some(workspace) = config.read().await.get(path).map(Arc::clone) else {
+        return Err(error_path().await);
+    };
+
+    let Some(health) = readiness.health(&workspace.name) else {
+        return Err(error_path().await);
+    };
+
+    let status = if health.healthy {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+
+    Ok((status, Json(health)).into_response())
+}
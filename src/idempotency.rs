@@ -0,0 +1,203 @@
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use tokio::sync::{Mutex, OnceCell};
+use tracing::{Level, event};
+
+use crate::{Error, ManagerBody};
+
+const DEFAULT_TTL: u64 = 600;
+
+type Outcome = Result<ManagerBody, Error>;
+/// Workspace, client and key of a request, the clients not sharing their keys
+type EntryId = (String, Option<String>, String);
+
+/// Responses of requests made with an `Idempotency-Key`, replayed to retries of the same request
+#[derive(Debug)]
+pub struct IdempotencyCache {
+    ttl: Duration,
+    entries: Mutex<HashMap<EntryId, Entry>>,
+}
+
+#[derive(Debug)]
+struct Entry {
+    created: Instant,
+    /// Hash of the body of the first request, which the retries must have
+    fingerprint: String,
+    outcome: Arc<OnceCell<Outcome>>,
+}
+
+/// Hash of the body of a request, telling a retry from another request reusing its key
+pub(crate) fn fingerprint(body: &ManagerBody) -> String {
+    hex::encode(Sha256::digest(json!(body).to_string()))
+}
+
+impl Default for IdempotencyCache {
+    fn default() -> Self {
+        IdempotencyCache::new(Duration::from_secs(DEFAULT_TTL))
+    }
+}
+
+impl IdempotencyCache {
+    pub fn new(ttl: Duration) -> IdempotencyCache {
+        IdempotencyCache {
+            ttl,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Run `request` only if the client didn't make another request with the same key in the
+    /// workspace within the window, otherwise wait for (or reuse) the outcome of the first one.
+    ///
+    /// Failed requests are forgotten once they complete so they can be retried. A key reused
+    /// with a different body is refused with `422`.
+    pub(crate) async fn run<F>(
+        &self,
+        workspace: &str,
+        client: Option<&str>,
+        key: &str,
+        fingerprint: &str,
+        request: F,
+    ) -> Outcome
+    where
+        F: Future<Output = Outcome>,
+    {
+        let id = (
+            workspace.to_owned(),
+            client.map(str::to_owned),
+            key.to_owned(),
+        );
+
+        let outcome = {
+            let mut entries = self.entries.lock().await;
+
+            entries.retain(|_, entry| entry.created.elapsed() < self.ttl);
+
+            let entry = entries.entry(id.clone()).or_insert_with(|| Entry {
+                created: Instant::now(),
+                fingerprint: fingerprint.to_owned(),
+                outcome: Arc::new(OnceCell::new()),
+            });
+
+            if entry.fingerprint != fingerprint {
+                event!(
+                    Level::WARN,
+                    "Idempotency key {key} reused with a different request"
+                );
+
+                return Err(Error {
+                    status: 422,
+                    message: String::from("Idempotency key already used with a different request"),
+                });
+            }
+
+            Arc::clone(&entry.outcome)
+        };
+
+        if outcome.initialized() {
+            event!(Level::INFO, "Replaying response for idempotency key {key}");
+        }
+
+        let result = outcome.get_or_init(|| request).await.clone();
+
+        if result.is_err() {
+            let mut entries = self.entries.lock().await;
+
+            if let Some(entry) = entries.get(&id)
+                && Arc::ptr_eq(&entry.outcome, &outcome)
+            {
+                entries.remove(&id);
+            }
+        }
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{Message, Role, TextMessage};
+
+    fn answer(text: &str) -> Outcome {
+        Ok(ManagerBody {
+            messages: vec![Message::TextMessage(TextMessage {
+                role: Role::Assistant,
+                content: text.to_owned(),
+            })],
+            ..Default::default()
+        })
+    }
+
+    fn text(outcome: Outcome) -> String {
+        json!(outcome.unwrap().messages).to_string()
+    }
+
+    #[tokio::test]
+    async fn keeps_keys_of_clients_apart() {
+        let cache = IdempotencyCache::default();
+        let fingerprint = fingerprint(&ManagerBody::default());
+
+        let first = cache
+            .run("test", Some("alice"), "key", &fingerprint, async {
+                answer("alice")
+            })
+            .await;
+        let second = cache
+            .run("test", Some("bob"), "key", &fingerprint, async {
+                answer("bob")
+            })
+            .await;
+        let replay = cache
+            .run("test", Some("alice"), "key", &fingerprint, async {
+                answer("again")
+            })
+            .await;
+
+        assert_eq!(text(first.clone()), text(answer("alice")));
+        assert_eq!(text(second), text(answer("bob")));
+        assert_eq!(text(replay), text(first));
+    }
+
+    #[tokio::test]
+    async fn refuses_key_reused_with_other_body() {
+        let cache = IdempotencyCache::default();
+
+        cache
+            .run("test", None, "key", "first", async { answer("first") })
+            .await
+            .unwrap();
+
+        let error = cache
+            .run("test", None, "key", "second", async { answer("second") })
+            .await
+            .unwrap_err();
+
+        assert_eq!(error.status, 422);
+    }
+
+    #[tokio::test]
+    async fn forgets_failed_requests() {
+        let cache = IdempotencyCache::default();
+
+        let failed = cache
+            .run("test", None, "key", "body", async {
+                Err(Error {
+                    status: 502,
+                    message: String::from("Model failed"),
+                })
+            })
+            .await;
+        let retry = cache
+            .run("test", None, "key", "body", async { answer("retry") })
+            .await;
+
+        assert!(failed.is_err());
+        assert_eq!(text(retry), text(answer("retry")));
+    }
+}
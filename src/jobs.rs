@@ -0,0 +1,127 @@
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use axum::{
+    Extension, Json,
+    extract::Path,
+    response::{IntoResponse, Response},
+};
+use rand::distr::{Alphanumeric, SampleString};
+use serde::Serialize;
+use tokio::sync::Mutex;
+use tracing::instrument;
+
+use crate::{Answer, Error};
+
+const DEFAULT_RETENTION: u64 = 3600;
+const JOB_ID_LEN: usize = 24;
+
+/// Requests run in the background, kept until some time after they finish
+#[derive(Debug)]
+pub struct Jobs {
+    retention: Duration,
+    jobs: Mutex<HashMap<String, Job>>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Job {
+    id: String,
+    workspace: String,
+    #[serde(flatten)]
+    state: JobState,
+    #[serde(skip)]
+    finished: Option<Instant>,
+}
+
+#[allow(clippy::large_enum_variant)]
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "lowercase", tag = "status")]
+pub enum JobState {
+    Running,
+    Completed { result: Answer },
+    Failed { error: Error },
+}
+
+impl Default for Jobs {
+    fn default() -> Self {
+        Jobs::new(Duration::from_secs(DEFAULT_RETENTION))
+    }
+}
+
+impl Jobs {
+    pub fn new(retention: Duration) -> Jobs {
+        Jobs {
+            retention,
+            jobs: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Register a new running job of a workspace
+    pub(crate) async fn start(&self, workspace: &str) -> Job {
+        let job = Job {
+            id: Alphanumeric.sample_string(&mut rand::rng(), JOB_ID_LEN),
+            workspace: workspace.to_owned(),
+            state: JobState::Running,
+            finished: None,
+        };
+
+        let mut jobs = self.jobs.lock().await;
+
+        self.prune(&mut jobs);
+        jobs.insert(job.id.clone(), job.clone());
+
+        job
+    }
+
+    /// Store the outcome of a job, returning it as it can now be polled
+    pub(crate) async fn finish(&self, id: &str, outcome: Result<Answer, Error>) -> Option<Job> {
+        let mut jobs = self.jobs.lock().await;
+        let job = jobs.get_mut(id)?;
+
+        job.state = match outcome {
+            Ok(result) => JobState::Completed { result },
+            Err(error) => JobState::Failed { error },
+        };
+        job.finished = Some(Instant::now());
+
+        Some(job.clone())
+    }
+
+    pub(crate) async fn get(&self, id: &str) -> Option<Job> {
+        let mut jobs = self.jobs.lock().await;
+
+        self.prune(&mut jobs);
+
+        jobs.get(id).cloned()
+    }
+
+    fn prune(&self, jobs: &mut HashMap<String, Job>) {
+        jobs.retain(|_, job| {
+            job.finished
+                .is_none_or(|finished| finished.elapsed() < self.retention)
+        });
+    }
+}
+
+impl Job {
+    pub(crate) fn id(&self) -> &str {
+        &self.id
+    }
+}
+
+#[instrument(skip(jobs))]
+pub async fn job_handler(
+    Extension(jobs): Extension<Arc<Jobs>>,
+    Path(id): Path<String>,
+) -> Result<Response, Error> {
+    jobs.get(&id)
+        .await
+        .map(|job| Json(job).into_response())
+        .ok_or(Error {
+            status: 404,
+            message: String::from("Job not found"),
+        })
+}
@@ -1,158 +1,1485 @@
-#![feature(let_chains)]
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
-use std::{collections::HashMap, sync::Arc};
+use chrono::Utc;
 
-use axum::{Extension, Json, extract::Path, response::IntoResponse};
+use acl::Acl;
+use admin::Admin;
+use axum::{
+    Extension, Json, Router,
+    body::Body,
+    extract::{Path, Query},
+    http::{
+        HeaderMap, Method, StatusCode,
+        header::{ALLOW, LOCATION},
+    },
+    middleware,
+    response::{IntoResponse, Response},
+    routing::{get, post},
+};
+use budgets::{Budgets, Price};
+use callbacks::Callbacks;
+use clients::{Client, Clients};
+use constraints::OutputConstraints;
+use context::Context;
+use embeddings::Embeddings;
 use futures::future::try_join_all;
-use mcp::McpServer;
+use guardrails::Guardrails;
+use idempotency::IdempotencyCache;
+use jobs::Jobs;
+use limits::{Limits, ToolCalls};
+use mcp::{ContentAnnotations, McpServer, ToolCall, ToolOutput};
+use metrics::ToolMetrics;
+use mirroring::{Mirror, Outcome};
 use models::{
-    Message, ModelDecision, Role, TextMessage, ToolOutputType, openai::Tool as OpenAITool,
+    FinishReason, Message, ModelDecision, Role, TextMessage, ToolOutputType, Usage, collect,
+    openai::Tool as OpenAITool,
+};
+use moderation::Moderation;
+use paging::{Pages, Paging, READ_MORE_TOOL};
+use rand::{
+    Rng,
+    distr::{Alphanumeric, SampleString},
+};
+use readiness::Readiness;
+use rmcp::{
+    ServiceError,
+    model::{JsonObject, Tool},
 };
-use rmcp::model::Tool;
+use router::ToolRouter;
+use selftest::SelfTest;
 use serde::{Deserialize, Serialize};
-use tokio::sync::RwLock;
-use tracing::{Level, event, instrument};
+use serde_json::{Value, json};
+use shadow::Shadow;
+use slow_calls::SlowCalls;
+use storage::{RequestRecord, Storage};
+use streaming::ChunkStream;
+use summarization::{Summarizer, Summary};
+use templates::Templates;
+use tokio::sync::{RwLock, oneshot};
+use tower_http::add_extension::AddExtensionLayer;
+use tracing::{Level, Span, event, field, instrument};
+use trimming::Trimming;
 
 use crate::models::AIModel;
 
+pub mod acl;
+pub mod admin;
+pub mod anonymizing;
+pub mod budgets;
+pub mod callbacks;
+pub mod check;
+pub mod clients;
+pub mod compat;
 pub mod config;
+pub mod constraints;
+pub mod context;
+pub mod embeddings;
 pub(crate) mod error;
+pub mod export;
+pub mod guardrails;
+pub mod health;
+pub mod idempotency;
+pub mod jobs;
+pub mod limits;
+pub mod logging;
 pub mod mcp;
+pub mod metrics;
+pub mod mirroring;
 pub mod models;
+pub mod moderation;
+pub mod openapi;
+pub mod paging;
+pub mod readiness;
+pub mod recording;
+pub mod router;
+pub mod selftest;
+pub mod shadow;
+pub mod shaping;
+pub mod slow_calls;
+pub mod state;
+pub mod storage;
+pub(crate) mod streaming;
+pub mod summarization;
+pub mod systemd;
+pub mod templates;
+#[cfg(feature = "test-util")]
+pub mod testing;
+pub(crate) mod tools;
+pub(crate) mod transcript;
+pub mod trimming;
 
 pub use error::Error;
 
+#[cfg(not(any(
+    feature = "anthropic",
+    feature = "azure",
+    feature = "cohere",
+    feature = "gemini",
+    feature = "openai",
+    feature = "openai-compatible"
+)))]
+compile_error!("At least one model provider feature must be enabled");
+
 type HandlerConfig = Arc<RwLock<HashMap<String, Arc<Workspace>>>>;
 
+const IDEMPOTENCY_HEADER: &str = "Idempotency-Key";
+/// Times the model is asked to call a tool again after giving invalid arguments, per request
+pub(crate) const DEFAULT_ARGUMENT_RETRIES: usize = 2;
+const REQUEST_ID_LEN: usize = 24;
+/// Key of the metadata of a request given to the models as its end user
+const USER_METADATA: &str = "user_id";
+/// Given to the model when its tool calls are reviewed before being run
+const PLAN_INSTRUCTION: &str = "Plan the tool calls needed to answer and make all of them at \
+    once: they are reviewed before being run, and their outputs are then given back to you.";
+
 #[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct ManagerBody {
+    #[serde(default, deserialize_with = "transcript::deserialize_messages")]
     pub(crate) messages: Vec<Message>,
     pub(crate) temperature: Option<f64>,
     pub(crate) max_tokens: Option<isize>,
     pub(crate) top_p: Option<f64>,
+    /// Only taken by the providers sampling among the most likely tokens, left out for the others
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) top_k: Option<u64>,
     pub(crate) tools: Option<Vec<OpenAITool>>,
+    /// URL notified with the outcome, the request being run in the background
+    #[serde(skip_serializing)]
+    pub(crate) callback_url: Option<String>,
+    #[serde(skip_deserializing, skip_serializing_if = "Option::is_none")]
+    pub(crate) usage: Option<Usage>,
+    #[serde(skip_deserializing, skip_serializing_if = "Option::is_none")]
+    pub(crate) steps: Option<Vec<Step>>,
+    /// Headers of the request, some being forwarded to the MCP servers
+    #[serde(skip)]
+    pub(crate) headers: Arc<HeaderMap>,
+    /// Client sending the request, when the clients are configured
+    #[serde(skip)]
+    pub(crate) client: Option<Arc<Client>>,
+    /// Send the answer as OpenAI chat completion chunks while it's being made
+    #[serde(default, skip_serializing)]
+    pub(crate) stream: bool,
+    /// Data of the client about the request (user, session...), given back in the response
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) metadata: Option<JsonObject>,
+    /// Model that answered, when the requests of the workspace are split between models
+    #[serde(skip_deserializing, skip_serializing_if = "Option::is_none")]
+    pub(crate) model: Option<String>,
+    /// Template of the workspace expanded into the user message of the request
+    #[serde(default, skip_serializing)]
+    pub(crate) template: Option<String>,
+    /// Values of the variables of the template
+    #[serde(default, skip_serializing)]
+    pub(crate) variables: Option<HashMap<String, String>>,
+    /// Tool calls of the model waiting for the approval of the client, which weren't run
+    #[serde(skip_deserializing, skip_serializing_if = "Option::is_none")]
+    pub(crate) plan: Option<Vec<ToolCall>>,
+    /// Set when the last answer of the model was cut at the maximum number of tokens or by the
+    /// content filter of the provider
+    #[serde(skip_deserializing, skip_serializing_if = "Option::is_none")]
+    pub(crate) finish_reason: Option<FinishReason>,
+    /// Whether the tool calls of the model are given back as a plan instead of being run
+    #[serde(skip)]
+    pub(crate) planning: bool,
+    /// Whether the client approved the tool calls the request ends with, which are run first
+    #[serde(skip)]
+    pub(crate) approved: bool,
+    /// Stored message the request follows, the earlier messages being taken from the storage.
+    /// Following an earlier message than the last one forks the conversation
+    #[serde(default, skip_serializing)]
+    pub(crate) parent_message_id: Option<String>,
+    /// IDs of the stored messages the transcript starts with
+    #[serde(skip)]
+    pub(crate) history: Vec<String>,
+    /// IDs the messages of the transcript are stored under, to follow any of them later
+    #[serde(skip_deserializing, skip_serializing_if = "Option::is_none")]
+    pub(crate) message_ids: Option<Vec<String>>,
 }
 
 impl ManagerBody {
     pub fn append_message(&mut self, message: Message) {
         self.messages.push(message);
     }
+
+    /// End user of the request, from its metadata
+    pub(crate) fn user(&self) -> Option<String> {
+        self.metadata
+            .as_ref()?
+            .get(USER_METADATA)
+            .map(metadata_string)
+    }
+
+    /// Metadata of the request with every value as a string, the only ones models accept
+    pub(crate) fn string_metadata(&self) -> Option<HashMap<String, String>> {
+        self.metadata.as_ref().map(|metadata| {
+            metadata
+                .iter()
+                .map(|(key, value)| (key.clone(), metadata_string(value)))
+                .collect()
+        })
+    }
+}
+
+fn metadata_string(value: &Value) -> String {
+    match value {
+        Value::String(value) => value.clone(),
+        value => value.to_string(),
+    }
+}
+
+/// Metadata of a single iteration of the agent loop
+#[derive(Clone, Debug, Serialize)]
+pub struct Step {
+    model_latency_ms: u128,
+    usage: Option<Usage>,
+    /// Why the model stopped, when the provider tells it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    finish_reason: Option<FinishReason>,
+    /// Identifier the provider gave to the response, to find it in its logs
+    #[serde(skip_serializing_if = "Option::is_none")]
+    provider_id: Option<String>,
+    tool_calls: Vec<ToolCallStep>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ToolCallStep {
+    name: String,
+    id: String,
+    arguments: Option<JsonObject>,
+    duration_ms: u128,
+    /// Annotations of the contents returned by the tool
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    annotations: Vec<ContentAnnotations>,
+}
+
+/// Options given in the query string of a workspace request
+#[derive(Debug, Default, Deserialize)]
+pub struct RequestOptions {
+    /// Include the per-step metadata in the response
+    #[serde(default)]
+    steps: bool,
+    /// Run the request in the background, answering with the job to poll
+    #[serde(default, rename = "async")]
+    asynchronous: bool,
+    /// Part of the conversation answered, instead of the one of the workspace
+    response_mode: Option<ResponseMode>,
+    /// Give back the tool calls of the model as a plan, without running them
+    #[serde(default)]
+    plan: bool,
+    /// Run the planned tool calls the request ends with, approved by the client
+    #[serde(default)]
+    approve: bool,
 }
 
 #[derive(Default)]
 pub struct ManagerConfig {
     pub listeners: HashMap<String, HashMap<String, Arc<Workspace>>>,
     pub workspaces: HashMap<String, Arc<Workspace>>,
+    pub idempotency: Arc<IdempotencyCache>,
+    pub jobs: Arc<Jobs>,
+    pub callbacks: Option<Arc<Callbacks>>,
+    pub storage: Option<Arc<Storage>>,
+    pub budgets: Arc<Budgets>,
+    pub clients: Arc<Clients>,
+    pub metrics: Arc<ToolMetrics>,
+    pub readiness: Arc<Readiness>,
+    pub admin: Option<Arc<Admin>>,
+    pub acls: HashMap<String, Arc<Acl>>,
+    /// What to do with each listener that can't be started
+    pub fail_modes: HashMap<String, FailMode>,
+    /// Loaded configuration, with secrets redacted
+    pub effective: Arc<Value>,
     models: HashMap<String, Arc<dyn AIModel + Send>>,
     mcps: HashMap<String, Arc<dyn McpServer + Send>>,
 }
 
 pub struct Workspace {
     name: String,
-    pub model: Arc<dyn AIModel + Send>,
-    mcps: Vec<Arc<dyn McpServer + Send>>,
+    /// Models answering the requests, the one getting the largest share first
+    models: Vec<WorkspaceModel>,
+    /// MCP servers by name
+    mcps: Vec<(String, Arc<dyn McpServer + Send>)>,
+    limits: Limits,
+    guardrails: Guardrails,
+    moderation: Option<Moderation>,
+    router: Option<ToolRouter>,
+    /// Candidate model given a copy of the requests, without answering them
+    shadow: Option<Shadow>,
+    /// Workspace given a sample of the requests, its answers being compared
+    mirror: Option<Mirror>,
+    /// Messages given to the model before the ones of every request
+    preamble: Vec<Message>,
+    /// Current date and time given to the model after the preamble
+    context: Option<Context>,
+    /// Language, length and phrases of the answers of the model
+    constraints: Option<OutputConstraints>,
+    /// Prompts the requests can name instead of sending the user message
+    templates: Templates,
+    /// Model answering `<path>/embeddings`
+    embeddings: Option<Embeddings>,
+    /// Keys of the metadata of the requests given to the model
+    forward_metadata: Vec<String>,
+    unknown_tools: UnknownToolPolicy,
+    response_mode: ResponseMode,
+    /// Whether the tool calls of the model are sent to the clients streaming the answer
+    stream_tool_calls: bool,
+    /// Interval of the keep-alives sent to the clients while the answer isn't ready
+    keep_alive: Option<Duration>,
+    /// Whether the tools can be called directly, without the model
+    direct_tool_calls: bool,
+    /// Whether the requests can ask for the tool calls as a plan, run once approved
+    planning: bool,
+    /// Whether the requests can have system messages, or only the preamble
+    allow_client_system: bool,
+    /// Times the model can give invalid arguments to the tools in a request
+    argument_retries: usize,
+    /// Model calls whose tool calls can be run in a request
+    max_tool_rounds: Option<usize>,
+    /// Whether the model is called without tools once they can't be called anymore, for it to
+    /// answer
+    final_answer: bool,
+    /// Calls of the tools, shared by the workspaces
+    metrics: Arc<ToolMetrics>,
+    trimming: Option<Trimming>,
+    summarization: Option<Summarizer>,
+    paging: Option<Paging>,
+    /// Alerts on the tool calls running for too long
+    slow_calls: Option<SlowCalls>,
+    /// Tool calls checked by `mcp-manager selftest`
+    selftests: Vec<SelfTest>,
+}
+
+/// Model answering the requests of a workspace, or part of them when they're split between models
+pub(crate) struct WorkspaceModel {
+    name: String,
+    /// Type of the model
+    provider: &'static str,
+    model: Arc<dyn AIModel + Send>,
+    /// Price of the model, to know the cost of the requests
+    price: Option<Price>,
+    /// Share of the requests answered by the model
+    weight: u32,
+}
+
+impl Workspace {
+    /// Model answering a request, drawn by the weights of the models when they're split
+    fn pick_model(&self) -> &WorkspaceModel {
+        let total: u32 = self.models.iter().map(|model| model.weight).sum();
+
+        if self.models.len() > 1 && total > 0 {
+            let mut draw = rand::rng().random_range(0..total);
+
+            for model in self.models.iter() {
+                if draw < model.weight {
+                    return model;
+                }
+
+                draw -= model.weight;
+            }
+        }
+
+        &self.models[0]
+    }
+
+    /// Tools of each MCP server of the workspace, failing when any of them can't list its tools
+    async fn list_tools(&self) -> Result<Vec<Vec<Tool>>, Error> {
+        event!(Level::INFO, "Listing tools in {}", self.name);
+
+        try_join_all(self.mcps.iter().map(|(name, mcp)| async move {
+            mcp.list_tools()
+                .await
+                .map_err(|error| mcp_error(name, error))
+        }))
+        .await
+    }
+}
+
+/// What to do when a listener can't be started (e.g. its port is in use)
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FailMode {
+    /// Stop the manager
+    #[default]
+    Abort,
+    /// Start the other listeners without it
+    Skip,
+}
+
+/// What to do when the model calls a tool that doesn't exist
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UnknownToolPolicy {
+    /// Give the model the list of available tools as the output of the call
+    #[default]
+    Reinject,
+    /// Fail the request
+    Abort,
+}
+
+/// Part of the conversation given in the response to a request
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ResponseMode {
+    /// Every message exchanged, including the tool calls and their outputs
+    #[default]
+    Transcript,
+    /// Only the last message of the model
+    Final,
+    /// Last message of the model and the tokens used
+    FinalWithUsage,
+}
+
+impl ResponseMode {
+    fn answer(self, body: ManagerBody) -> Answer {
+        let message = || {
+            body.messages
+                .iter()
+                .rev()
+                .find_map(|message| match message {
+                    Message::TextMessage(message) if matches!(message.role, Role::Assistant) => {
+                        Some(message.clone())
+                    }
+                    _ => None,
+                })
+        };
+
+        match self {
+            ResponseMode::Transcript => Answer::Transcript(body),
+            ResponseMode::Final => Answer::Final {
+                message: message(),
+                usage: None,
+                metadata: body.metadata,
+                model: body.model,
+                plan: body.plan,
+                finish_reason: body.finish_reason,
+                message_id: body.message_ids.and_then(|ids| ids.last().cloned()),
+            },
+            ResponseMode::FinalWithUsage => Answer::Final {
+                message: message(),
+                usage: body.usage,
+                metadata: body.metadata,
+                model: body.model,
+                plan: body.plan,
+                finish_reason: body.finish_reason,
+                message_id: body.message_ids.and_then(|ids| ids.last().cloned()),
+            },
+        }
+    }
 }
 
-#[instrument(skip(config, body))]
+/// Response to a request, in the response mode asked for
+#[allow(clippy::large_enum_variant)]
+#[derive(Clone, Debug, Serialize)]
+#[serde(untagged)]
+pub enum Answer {
+    Transcript(ManagerBody),
+    Final {
+        /// `null` when the model didn't write any text
+        message: Option<TextMessage>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        usage: Option<Usage>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        metadata: Option<JsonObject>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        model: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        plan: Option<Vec<ToolCall>>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        finish_reason: Option<FinishReason>,
+        /// ID the last message of the transcript is stored under
+        #[serde(skip_serializing_if = "Option::is_none")]
+        message_id: Option<String>,
+    },
+}
+
+#[allow(clippy::too_many_arguments)]
+#[instrument(
+    skip(
+        config,
+        idempotency,
+        storage,
+        budgets,
+        jobs,
+        callbacks,
+        readiness,
+        caller,
+        headers,
+        body
+    ),
+    fields(metadata)
+)]
 pub async fn workspace_handler(
     Extension(config): Extension<HandlerConfig>,
+    Extension(idempotency): Extension<Arc<IdempotencyCache>>,
+    Extension(storage): Extension<Option<Arc<Storage>>>,
+    Extension(budgets): Extension<Arc<Budgets>>,
+    Extension(jobs): Extension<Arc<Jobs>>,
+    Extension(callbacks): Extension<Option<Arc<Callbacks>>>,
+    Extension(readiness): Extension<Arc<Readiness>>,
+    caller: Option<Extension<Arc<Client>>>,
     Path(mut path): Path<String>,
-    Json(mut body): Json<ManagerBody>,
-) -> Result<impl IntoResponse, Error> {
+    Query(options): Query<RequestOptions>,
+    headers: HeaderMap,
+    body: Body,
+) -> Result<Response, Error> {
     path.insert(0, '/');
 
-    if let Some(workspace) = config.read().await.get(&path) {
-        event!(Level::INFO, "Listing tools in {}", workspace.name);
+    let caller = caller.map(|Extension(caller)| caller);
+
+    let workspace = if let Some(workspace) = config.read().await.get(&path) {
+        Arc::clone(workspace)
+    } else if let Some((path, tool)) = tools::call_path(&path)
+        && let Some(workspace) = config.read().await.get(path).map(Arc::clone)
+    {
+        if let Some(caller) = &caller {
+            caller.check(&workspace.name)?;
+        }
+
+        readiness.check(&workspace.name)?;
+
+        return tools::call(&workspace, caller.as_deref(), tool, headers, body).await;
+    } else if let Some(path) = embeddings::path(&path)
+        && let Some(workspace) = config.read().await.get(path).map(Arc::clone)
+    {
+        if let Some(caller) = &caller {
+            caller.check(&workspace.name)?;
+        }
+
+        return embeddings::embed(&workspace, body).await;
+    } else {
+        // The tools, templates and health of the workspaces are only read
+        let read_only = tools::list_path(&path)
+            .or_else(|| templates::list_path(&path))
+            .or_else(|| path.strip_suffix(health::HEALTH_SUFFIX));
+
+        if let Some(path) = read_only
+            && config.read().await.contains_key(path)
+        {
+            return Ok(not_allowed("GET"));
+        }
+
+        return Err(error_path().await);
+    };
 
-        let tools_fut: Vec<_> = workspace.mcps.iter().map(|mcp| mcp.list_tools()).collect();
+    if let Some(caller) = &caller {
+        caller.check(&workspace.name)?;
+    }
+
+    readiness.check(&workspace.name)?;
 
-        let tools = try_join_all(tools_fut)
+    let budget = budgets.client(&headers);
+
+    if let (Some(budget), Some(storage)) = (budget, &storage) {
+        budget.check(storage).await?;
+    }
+
+    // Usage is recorded under the name of the client, or of its budget
+    let client = caller
+        .as_ref()
+        .map(|caller| caller.name.clone())
+        .or_else(|| budget.map(|budget| budget.name.clone()));
+
+    let mut body: ManagerBody = workspace.limits.read_json(body).await?;
+    workspace.templates.expand(&mut body)?;
+    workspace.limits.check(&body)?;
+    body.headers = Arc::new(headers.clone());
+    body.client = caller;
+
+    if !workspace.allow_client_system
+        && body.messages.iter().any(|message| {
+            matches!(
+                message,
+                Message::TextMessage(TextMessage {
+                    role: Role::System,
+                    ..
+                })
+            )
+        })
+    {
+        return Err(Error {
+            status: 400,
+            message: String::from("System messages aren't allowed in this workspace"),
+        });
+    }
+
+    if let Some(parent) = &body.parent_message_id {
+        let Some(storage) = storage.as_ref().filter(|storage| storage.transcripts()) else {
+            return Err(Error {
+                status: 400,
+                message: String::from(
+                    "Conversations can only be followed with the transcripts stored",
+                ),
+            });
+        };
+
+        if storage.anonymized() {
+            return Err(Error {
+                status: 400,
+                message: String::from(
+                    "Conversations can't be followed from anonymized transcripts",
+                ),
+            });
+        }
+
+        let branch = storage
+            .branch(&workspace.name, parent)
+            .await
+            .map_err(|error| {
+                event!(Level::ERROR, "Couldn't read conversation: {error}");
+
+                Error {
+                    status: 500,
+                    message: String::from("Couldn't read conversation"),
+                }
+            })?;
+
+        if branch.is_empty() {
+            return Err(Error {
+                status: 404,
+                message: format!("Message {parent} not found"),
+            });
+        }
+
+        let (ids, history): (Vec<_>, Vec<_>) = branch.into_iter().unzip();
+
+        body.messages.splice(0..0, history);
+        body.history = ids;
+    }
+
+    if (options.plan || options.approve) && !workspace.planning {
+        return Err(Error {
+            status: 400,
+            message: String::from("Planning isn't enabled in this workspace"),
+        });
+    }
+
+    body.planning = options.plan;
+    body.approved = options.approve;
+
+    transcript::sanitize(&mut body)?;
+
+    if let Some(metadata) = &body.metadata {
+        Span::current().record("metadata", field::display(json!(metadata)));
+    }
+
+    let callback = match (body.callback_url.take(), callbacks) {
+        (Some(url), Some(callbacks)) => Some((callbacks.check(&url)?, callbacks)),
+        (Some(_), None) => {
+            return Err(Error {
+                status: 400,
+                message: String::from("Callbacks not configured"),
+            });
+        }
+        (None, _) => None,
+    };
+
+    let mode = options.response_mode.unwrap_or(workspace.response_mode);
+
+    let key = headers
+        .get(IDEMPOTENCY_HEADER)
+        .and_then(|key| key.to_str().ok())
+        .map(str::to_owned);
+
+    if body.stream {
+        if options.asynchronous || callback.is_some() {
+            return Err(Error {
+                status: 400,
+                message: String::from("Requests run in the background can't be streamed"),
+            });
+        }
+
+        let (stream, events) =
+            ChunkStream::new(workspace.name.clone(), workspace.stream_tool_calls);
+        let keep_alive = workspace.keep_alive;
+        let from = body.messages.len();
+
+        tokio::spawn(async move {
+            match execute(
+                &workspace,
+                &idempotency,
+                storage.as_deref(),
+                client.as_deref(),
+                key.as_deref(),
+                body,
+                options.steps,
+                Some(&stream),
+            )
             .await
-            .expect("Couldn't get all tools");
-
-        let mcp_calls = workspace
-            .mcps
-            .iter()
-            .zip(tools.iter())
-            .flat_map(|(mcp, tools)| {
-                tools
-                    .iter()
-                    .map(|tool| (tool.name.clone().into_owned(), Arc::clone(&mcp)))
-                    .collect::<Vec<(String, Arc<dyn McpServer + Send>)>>()
+            {
+                Ok(body) => stream.finish(&body, from),
+                Err(error) => stream.fail(error),
+            }
+        });
+
+        return Ok(streaming::response(events, keep_alive).into_response());
+    }
+
+    if options.asynchronous || callback.is_some() {
+        let job = jobs.start(&workspace.name).await;
+        let id = job.id().to_owned();
+
+        event!(Level::INFO, "Running job {id} in the background");
+
+        tokio::spawn(async move {
+            let outcome = execute(
+                &workspace,
+                &idempotency,
+                storage.as_deref(),
+                client.as_deref(),
+                key.as_deref(),
+                body,
+                options.steps,
+                None,
+            )
+            .await
+            .map(|body| mode.answer(body));
+
+            let job = jobs.finish(&id, outcome).await;
+
+            if let (Some((url, callbacks)), Some(job)) = (callback, job) {
+                callbacks.send(url, &job).await;
+            }
+        });
+
+        return Ok((
+            StatusCode::ACCEPTED,
+            [(LOCATION, format!("/jobs/{}", job.id()))],
+            Json(job),
+        )
+            .into_response());
+    }
+
+    if let Some(interval) = workspace.keep_alive {
+        let (sender, result) = oneshot::channel();
+
+        // Run apart from the response, which keeps the connection alive meanwhile
+        tokio::spawn(async move {
+            let result = execute(
+                &workspace,
+                &idempotency,
+                storage.as_deref(),
+                client.as_deref(),
+                key.as_deref(),
+                body,
+                options.steps,
+                None,
+            )
+            .await
+            .map(|body| mode.answer(body));
+
+            let _ = sender.send(result);
+        });
+
+        return Ok(streaming::keep_alive(result, interval).await);
+    }
+
+    let body = execute(
+        &workspace,
+        &idempotency,
+        storage.as_deref(),
+        client.as_deref(),
+        key.as_deref(),
+        body,
+        options.steps,
+        None,
+    )
+    .await?;
+
+    Ok(Json(mode.answer(body)).into_response())
+}
+
+/// Run a request in a workspace, replaying it when its idempotency key was already used
+#[allow(clippy::too_many_arguments)]
+async fn execute(
+    workspace: &Workspace,
+    idempotency: &IdempotencyCache,
+    storage: Option<&Storage>,
+    client: Option<&str>,
+    key: Option<&str>,
+    body: ManagerBody,
+    steps: bool,
+    stream: Option<&ChunkStream>,
+) -> Result<ManagerBody, Error> {
+    let start = Instant::now();
+    let id = Alphanumeric.sample_string(&mut rand::rng(), REQUEST_ID_LEN);
+    let mirror = workspace
+        .mirror
+        .as_ref()
+        .and_then(|mirror| mirror.start(&body));
+
+    // A replay gives back the outcome of the first request, which was already recorded
+    let result = if let Some(key) = key {
+        let fingerprint = idempotency::fingerprint(&body);
+
+        idempotency
+            .run(
+                &workspace.name,
+                client,
+                key,
+                &fingerprint,
+                run_recorded(workspace, storage, client, &id, body, stream),
+            )
+            .await
+    } else {
+        run_recorded(workspace, storage, client, &id, body, stream).await
+    };
+
+    if let Some(mirror) = mirror {
+        let _ = mirror.send((id.clone(), Outcome::new(&result, start)));
+    }
+
+    let mut body = result?;
+
+    if !steps {
+        body.steps = None;
+    }
+
+    Ok(body)
+}
+
+/// Run a request in a workspace and record it
+async fn run_recorded(
+    workspace: &Workspace,
+    storage: Option<&Storage>,
+    client: Option<&str>,
+    id: &str,
+    body: ManagerBody,
+    stream: Option<&ChunkStream>,
+) -> Result<ManagerBody, Error> {
+    let started_at = Utc::now();
+    let start = Instant::now();
+    let metadata = body.metadata.clone();
+    let model = workspace.pick_model();
+
+    let result = run_workspace(workspace, model, body, stream).await;
+
+    // Only the messages added by the request are stored, after the ones it follows
+    let message_ids: Vec<String> = match (&result, storage) {
+        (Ok(body), Some(storage)) if storage.transcripts() => (body.history.len()
+            ..body.messages.len())
+            .map(|_| Alphanumeric.sample_string(&mut rand::rng(), REQUEST_ID_LEN))
+            .collect(),
+        _ => Vec::new(),
+    };
+
+    if let Some(storage) = storage {
+        let usage = result.as_ref().ok().and_then(|body| body.usage);
+
+        storage
+            .record(RequestRecord {
+                id,
+                workspace: &workspace.name,
+                model: &model.name,
+                client,
+                metadata: metadata.as_ref(),
+                started_at,
+                duration_ms: start.elapsed().as_millis(),
+                status: result.as_ref().map_or_else(|error| error.status, |_| 200),
+                usage,
+                cost: model
+                    .price
+                    .zip(usage)
+                    .map(|(price, usage)| price.cost(&usage)),
+                steps: result
+                    .as_ref()
+                    .ok()
+                    .and_then(|body| body.steps.as_deref())
+                    .unwrap_or_default(),
+                transcript: result.as_ref().ok().map(|body| body.messages.as_slice()),
+                parent_message_id: result
+                    .as_ref()
+                    .ok()
+                    .and_then(|body| body.history.last())
+                    .map(String::as_str),
+                message_ids: &message_ids,
             })
-            .collect::<HashMap<String, Arc<dyn McpServer + Send>>>();
+            .await;
+    }
 
-        let tools: Vec<Tool> = tools.into_iter().flatten().collect();
+    let mut body = result?;
 
-        loop {
-            let response = workspace
-                .model
-                .call(body.clone(), tools.clone())
-                .await
-                .unwrap();
-
-            let mut tool_call = false;
-
-            for decision in response.into_iter() {
-                match decision {
-                    ModelDecision::ToolCalls(calls) => {
-                        tool_call = true;
-
-                        body.append_message(Message::ToolCalls {
-                            role: Role::Assistant,
-                            tool_calls: calls.clone(),
-                        });
-
-                        for call in calls {
-                            let call_id = call.id.clone();
-
-                            let mcp_server = mcp_calls
-                                .get(&call.name)
-                                .ok_or(String::from("Function doesn't exist"));
-
-                            let response = if let Ok(mcp_server) = mcp_server {
-                                mcp_server.call(call).await.map_err(|_| Error {
-                                    status: 500,
-                                    message: String::from("Internal server error"),
-                                })?
-                            } else {
-                                mcp_server.err().unwrap()
-                            };
-
-                            body.append_message(Message::ToolOutput {
-                                r#type: ToolOutputType::FunctionCallOutput,
-                                output: response,
-                                call_id,
-                            });
-                        }
+    if storage.is_some_and(Storage::transcripts) {
+        body.message_ids = Some(body.history.drain(..).chain(message_ids).collect());
+    }
+
+    Ok(body)
+}
+
+/// Run the agent loop of a workspace until the model stops calling tools, sending the messages of
+/// the model to the stream as they come
+pub(crate) async fn run_workspace(
+    workspace: &Workspace,
+    model: &WorkspaceModel,
+    mut body: ManagerBody,
+    stream: Option<&ChunkStream>,
+) -> Result<ManagerBody, Error> {
+    workspace.guardrails.check_input(&body)?;
+
+    if workspace.models.len() > 1 {
+        event!(Level::INFO, "Answering with model {}", model.name);
+
+        body.model = Some(model.name.clone());
+    }
+
+    if let Some(moderation) = &workspace.moderation {
+        moderation.check(&body).await?;
+    }
+
+    let mut tools = workspace.list_tools().await?;
+
+    if let Some(client) = &body.client {
+        client.filter_tools(&mut tools);
+    }
+
+    let mcp_calls = workspace
+        .mcps
+        .iter()
+        .zip(tools.iter())
+        .flat_map(|((name, mcp), tools)| {
+            tools
+                .iter()
+                .map(|tool| {
+                    (
+                        tool.name.clone().into_owned(),
+                        (name.as_str(), Arc::clone(mcp)),
+                    )
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect::<HashMap<String, (&str, Arc<dyn McpServer + Send>)>>();
+
+    let tools: Vec<Tool> = tools.into_iter().flatten().collect();
+
+    let mut usage = Usage::default();
+
+    let tools = if let Some(router) = &workspace.router {
+        let (tools, router_usage) = router.select(&body, tools).await;
+
+        if let Some(router_usage) = router_usage {
+            usage += router_usage;
+        }
+
+        tools
+    } else {
+        tools
+    };
+
+    // The tools of the MCP servers come first, the builtin one is left out when they have it
+    let tools = match &workspace.paging {
+        Some(paging) if !mcp_calls.contains_key(READ_MORE_TOOL) => {
+            let mut tools = tools;
+            tools.push(paging.tool());
+            tools
+        }
+        Some(_) => {
+            event!(
+                Level::WARN,
+                "A MCP server has a tool named {READ_MORE_TOOL}, the long outputs can't be paged"
+            );
+
+            tools
+        }
+        None => tools,
+    };
+    let mut steps = Vec::new();
+    let mut argument_retries = workspace.argument_retries;
+    let mut calls_made = ToolCalls::default();
+    let mut pages = Pages::default();
+    let mut tool_rounds = 0;
+    let mut summaries = HashMap::new();
+    let mut summary = Summary::default();
+    // Filled in once, for the model to see the same time on every call of the request
+    let preamble: Vec<Message> = workspace
+        .preamble
+        .iter()
+        .cloned()
+        .chain(workspace.context.as_ref().map(Context::message))
+        .chain(
+            workspace
+                .constraints
+                .as_ref()
+                .map(OutputConstraints::message),
+        )
+        .collect();
+    let mut planned = Vec::new();
+    // The text of the model is sent as it writes it, unless whole messages must be checked first
+    let live = stream.filter(|_| {
+        !workspace.guardrails.filters_output()
+            && !workspace
+                .constraints
+                .as_ref()
+                .is_some_and(OutputConstraints::filters_output)
+    });
+
+    if body.approved {
+        let Some(Message::ToolCalls {
+            role: Role::Assistant,
+            tool_calls,
+        }) = body.messages.last()
+        else {
+            return Err(Error {
+                status: 400,
+                message: String::from(
+                    "Nothing to approve, the request must end with the planned tool calls",
+                ),
+            });
+        };
+
+        event!(
+            Level::INFO,
+            "Running {} approved tool calls",
+            tool_calls.len()
+        );
+
+        let calls = tool_calls.clone();
+        // No model was called for these calls, they were planned by the previous request
+        let mut step = Step {
+            model_latency_ms: 0,
+            usage: None,
+            finish_reason: None,
+            provider_id: None,
+            tool_calls: Vec::new(),
+        };
+
+        run_tool_calls(
+            workspace,
+            &mcp_calls,
+            &tools,
+            calls,
+            &mut body,
+            &mut argument_retries,
+            &mut calls_made,
+            &mut pages,
+            &mut step,
+        )
+        .await?;
+
+        steps.push(step);
+        tool_rounds += 1;
+    }
+
+    loop {
+        let request = if let Some(trimming) = &workspace.trimming {
+            let (request, trimming_usage) = trimming.trim(body.clone(), &mut summaries).await;
+
+            if let Some(trimming_usage) = trimming_usage {
+                usage += trimming_usage;
+            }
+
+            request
+        } else {
+            body.clone()
+        };
+
+        let mut request = if let Some(summarizer) = &workspace.summarization {
+            let (request, summary_usage) = summarizer.condense(request, &mut summary).await;
+
+            if let Some(summary_usage) = summary_usage {
+                usage += summary_usage;
+            }
+
+            request
+        } else {
+            request
+        };
+
+        // The preamble is only given to the model, it isn't part of the conversation
+        request.messages.splice(0..0, preamble.iter().cloned());
+
+        if let Some(constraints) = &workspace.constraints {
+            constraints.limit(&mut request);
+        }
+
+        request.metadata = request
+            .metadata
+            .take()
+            .map(|mut metadata| {
+                metadata.retain(|key, _| workspace.forward_metadata.contains(key));
+                metadata
+            })
+            .filter(|metadata| !metadata.is_empty());
+
+        // The shadow model gets the request as sent by the client, it can't go further without
+        // running its own tool calls
+        if steps.is_empty()
+            && let Some(shadow) = &workspace.shadow
+        {
+            shadow.mirror(request.clone(), tools.clone());
+        }
+
+        let final_call = workspace
+            .max_tool_rounds
+            .is_some_and(|max| tool_rounds >= max);
+
+        if body.planning && !final_call {
+            request.messages.insert(
+                preamble.len(),
+                Message::TextMessage(TextMessage {
+                    role: Role::System,
+                    content: String::from(PLAN_INSTRUCTION),
+                }),
+            );
+        }
+
+        let start = Instant::now();
+
+        let request_tools = if final_call {
+            Vec::new()
+        } else {
+            tools.clone()
+        };
+
+        let response = match live {
+            Some(live) => match model.model.stream(request, request_tools).await {
+                Ok(deltas) => collect(deltas, |text| live.text(text)).await,
+                Err(error) => Err(error),
+            },
+            None => model.model.call(request, request_tools).await,
+        }
+        .map_err(|error| Error {
+            status: error.status,
+            message: format!("Model {} failed: {}", model.name, error.message),
+        })?;
+
+        let mut step = Step {
+            model_latency_ms: response
+                .latency_ms
+                .unwrap_or_else(|| start.elapsed().as_millis()),
+            usage: response.usage,
+            finish_reason: response.finish_reason,
+            provider_id: response.provider_id,
+            tool_calls: Vec::new(),
+        };
+
+        if let Some(response_usage) = response.usage {
+            usage += response_usage;
+        }
+
+        let mut tool_call = false;
+
+        for decision in response.decisions.into_iter() {
+            match decision {
+                ModelDecision::ToolCalls(calls) if final_call => {
+                    event!(
+                        Level::WARN,
+                        "Model called {} tools without having any, leaving the calls out",
+                        calls.len()
+                    );
+                }
+                ModelDecision::ToolCalls(calls) => {
+                    tool_call = true;
+
+                    if let Some(stream) = stream {
+                        stream.tool_calls(&calls);
+                    }
+
+                    body.append_message(Message::ToolCalls {
+                        role: Role::Assistant,
+                        tool_calls: calls.clone(),
+                    });
+
+                    if body.planning {
+                        // Only run once the client approves them
+                        planned.extend(calls);
+                        continue;
                     }
-                    ModelDecision::TextMessage(message) => {
-                        body.append_message(Message::TextMessage(TextMessage {
-                            role: Role::Assistant,
-                            content: message,
-                        }))
+
+                    run_tool_calls(
+                        workspace,
+                        &mcp_calls,
+                        &tools,
+                        calls,
+                        &mut body,
+                        &mut argument_retries,
+                        &mut calls_made,
+                        &mut pages,
+                        &mut step,
+                    )
+                    .await?;
+                }
+                ModelDecision::TextMessage(message) => {
+                    workspace.guardrails.check_output(&message)?;
+
+                    if let Some(constraints) = &workspace.constraints {
+                        constraints.check(&message)?;
+                    }
+
+                    let content = workspace.guardrails.redact(message);
+
+                    // Already sent while the model wrote it
+                    if live.is_none()
+                        && let Some(stream) = stream
+                    {
+                        stream.text(&content);
                     }
+
+                    body.append_message(Message::TextMessage(TextMessage {
+                        role: Role::Assistant,
+                        content,
+                    }))
+                }
+            };
+        }
+
+        steps.push(step);
+
+        if !planned.is_empty() {
+            event!(
+                Level::INFO,
+                "Model planned {} tool calls, waiting for their approval",
+                planned.len()
+            );
+
+            body.plan = Some(planned);
+            break;
+        }
+
+        // If LLM doesn't want to call anything, just return all the messages
+        if !tool_call {
+            break;
+        }
+
+        tool_rounds += 1;
+
+        if !workspace.final_answer
+            && workspace
+                .max_tool_rounds
+                .is_some_and(|max| tool_rounds >= max)
+        {
+            event!(
+                Level::WARN,
+                "Model called tools {tool_rounds} times, ending with the outputs of the tools"
+            );
+
+            break;
+        }
+    }
+
+    body.finish_reason = steps
+        .last()
+        .and_then(|step| step.finish_reason)
+        .filter(|reason| matches!(reason, FinishReason::Length | FinishReason::ContentFilter));
+    body.usage = Some(usage);
+    body.steps = Some(steps);
+
+    Ok(body)
+}
+
+/// Run tool calls of the model, adding their outputs to the transcript and their metadata to the
+/// step
+#[allow(clippy::too_many_arguments)]
+async fn run_tool_calls(
+    workspace: &Workspace,
+    mcp_calls: &HashMap<String, (&str, Arc<dyn McpServer + Send>)>,
+    tools: &[Tool],
+    calls: Vec<ToolCall>,
+    body: &mut ManagerBody,
+    argument_retries: &mut usize,
+    calls_made: &mut ToolCalls,
+    pages: &mut Pages,
+    step: &mut Step,
+) -> Result<(), Error> {
+    for mut call in calls {
+        call.headers = Arc::clone(&body.headers);
+
+        let call_id = call.id.clone();
+        let call_name = call.name.clone();
+        let call_arguments = call.arguments.clone();
+        let start = Instant::now();
+
+        let response = if let Some(error) = call.invalid_arguments.take() {
+            event!(
+                Level::WARN,
+                "Model gave invalid arguments to tool {call_name}: {error}"
+            );
+
+            if *argument_retries == 0 {
+                return Err(Error {
+                    status: 502,
+                    message: format!("Model gave invalid arguments to tool '{call_name}'"),
+                });
+            }
+
+            *argument_retries -= 1;
+
+            ToolOutput {
+                text: format!(
+                    "Error: the arguments of tool {call_name} aren't valid JSON \
+                        ({error}), call it again with a JSON object as arguments"
+                ),
+                error: true,
+                ..Default::default()
+            }
+        } else if let Some((mcp_name, mcp_server)) = mcp_calls.get(&call.name) {
+            if let Err(refusal) = workspace.limits.call_tool(calls_made, &call_name) {
+                event!(
+                    Level::WARN,
+                    "Model called tool {call_name} over the limits of the request"
+                );
+
+                ToolOutput {
+                    text: refusal,
+                    error: true,
+                    ..Default::default()
+                }
+            } else {
+                let in_progress = workspace
+                    .metrics
+                    .start(&workspace.name, mcp_name, &call_name);
+
+                let result = match &workspace.slow_calls {
+                    Some(slow_calls) => {
+                        slow_calls
+                            .watch(
+                                &workspace.metrics,
+                                (&workspace.name, mcp_name, &call_name),
+                                call_arguments.as_ref(),
+                                mcp_server.call(call),
+                            )
+                            .await
+                    }
+                    None => mcp_server.call(call).await,
                 };
+
+                workspace.metrics.observe(
+                    &workspace.name,
+                    mcp_name,
+                    &call_name,
+                    start.elapsed(),
+                    result.as_ref().ok().map(|output| output.text.len()),
+                    !result.as_ref().is_ok_and(|output| !output.error),
+                );
+                drop(in_progress);
+
+                // The model can work around a failing tool, the request doesn't fail
+                let mut output = result.unwrap_or_else(|error| {
+                    event!(Level::ERROR, "Tool {call_name} failed: {error}");
+
+                    ToolOutput::from(format!("Error: tool {call_name} failed: {error}"))
+                });
+
+                if let Some(paging) = &workspace.paging {
+                    output.text = paging.cut(pages, &call_id, output.text);
+                }
+
+                output
             }
+        } else if let Some(paging) = &workspace.paging
+            && call_name == READ_MORE_TOOL
+        {
+            paging.read_more(pages, call.arguments.as_ref())
+        } else {
+            event!(Level::WARN, "Model called unknown tool {call_name}");
 
-            // If LLM doesn't want to call anything, just return all the messages
-            if !tool_call {
-                break;
+            match workspace.unknown_tools {
+                UnknownToolPolicy::Reinject => ToolOutput::from(unknown_tool(&call_name, tools)),
+                UnknownToolPolicy::Abort => {
+                    return Err(Error {
+                        status: 502,
+                        message: format!("Model called unknown tool '{call_name}'"),
+                    });
+                }
             }
-        }
+        };
 
-        Ok(Json(body))
-    } else {
-        Err(error_path().await)
+        step.tool_calls.push(ToolCallStep {
+            arguments: logging::redact_arguments(&call_name, call_arguments),
+            name: call_name,
+            id: call_id.clone(),
+            duration_ms: start.elapsed().as_millis(),
+            annotations: response.annotations,
+        });
+
+        body.append_message(Message::ToolOutput {
+            r#type: ToolOutputType::FunctionCallOutput,
+            output: workspace.guardrails.redact(response.text),
+            call_id,
+        });
     }
+
+    Ok(())
 }
 
-#[instrument]
-pub async fn error_method() -> Result<(), Error> {
-    Err(Error {
-        status: 406,
-        message: String::from("Method not allowed"),
+/// Failure of a MCP server of the workspace, which can't be used without all its tools
+fn mcp_error(name: &str, error: ServiceError) -> Error {
+    event!(Level::ERROR, "MCP server {name} failed: {error}");
+
+    Error {
+        status: if matches!(error, ServiceError::Timeout { .. }) {
+            504
+        } else {
+            424
+        },
+        message: format!("MCP server {name} is unavailable: {error}"),
+    }
+}
+
+/// Output given back to the model when it calls a tool that doesn't exist, so it can correct itself
+fn unknown_tool(name: &str, tools: &[Tool]) -> String {
+    json!({
+        "error": "unknown_tool",
+        "message": format!("Tool '{name}' doesn't exist, only the available tools can be called"),
+        "available_tools": tools.iter().map(|tool| tool.name.as_ref()).collect::<Vec<_>>(),
     })
+    .to_string()
+}
+
+/// Router of a listener serving these workspaces, by path, with the endpoints common to all
+pub fn listener_router(
+    workspaces: HashMap<String, Arc<Workspace>>,
+    config: &ManagerConfig,
+) -> Router {
+    let router = Router::new()
+        .route("/jobs/{id}", get(jobs::job_handler))
+        .route("/v1/models", get(compat::models_handler))
+        .route("/openapi.json", get(openapi::openapi_handler))
+        .route(
+            "/{*path}",
+            post(workspace_handler).get(health::health_handler),
+        )
+        .method_not_allowed_fallback(error_method)
+        .fallback(error_path)
+        .layer(AddExtensionLayer::new(Arc::new(RwLock::new(workspaces))))
+        .layer(AddExtensionLayer::new(Arc::clone(&config.idempotency)))
+        .layer(AddExtensionLayer::new(Arc::clone(&config.jobs)))
+        .layer(AddExtensionLayer::new(config.callbacks.clone()))
+        .layer(AddExtensionLayer::new(Arc::clone(&config.readiness)))
+        .layer(AddExtensionLayer::new(config.storage.clone()))
+        .layer(AddExtensionLayer::new(Arc::clone(&config.budgets)));
+
+    if config.clients.clients.is_empty() {
+        return router;
+    }
+
+    router.layer(middleware::from_fn_with_state(
+        Arc::clone(&config.clients),
+        clients::clients_middleware,
+    ))
+}
+
+/// Answer to the methods a route doesn't take, axum adding the `Allow` header with the ones it
+/// takes: `204` for the `OPTIONS` preflights, `405` for the others
+#[instrument]
+pub async fn error_method(method: Method) -> Response {
+    if method == Method::OPTIONS {
+        return StatusCode::NO_CONTENT.into_response();
+    }
+
+    (StatusCode::METHOD_NOT_ALLOWED, "Method not allowed").into_response()
+}
+
+/// `405` answer to a path of a route taking the method for other paths
+pub(crate) fn not_allowed(allow: &'static str) -> Response {
+    (
+        StatusCode::METHOD_NOT_ALLOWED,
+        [(ALLOW, allow)],
+        "Method not allowed",
+    )
+        .into_response()
 }
 
 #[instrument]
@@ -0,0 +1,187 @@
+use std::collections::HashMap;
+
+use axum::{
+    Json,
+    body::{Body, to_bytes},
+};
+use serde::de::DeserializeOwned;
+use serde_json::json;
+
+use crate::{
+    Error, ManagerBody,
+    models::{Message, TextMessage},
+};
+
+/// Same as the default body limit of axum
+const DEFAULT_MAX_BODY_BYTES: usize = 2 * 1024 * 1024;
+
+/// Size limits of the requests accepted by a workspace, and the tool calls they can make
+#[derive(Clone, Debug, Default)]
+pub struct Limits {
+    pub max_body_bytes: Option<usize>,
+    pub max_messages: Option<usize>,
+    pub max_message_chars: Option<usize>,
+    /// Tools the model can call in a request, whatever the tool
+    pub max_tool_calls: Option<usize>,
+    /// Times the model can call each of these tools in a request
+    pub max_calls_per_tool: HashMap<String, usize>,
+}
+
+/// Tool calls made in a request, in total and by tool
+#[derive(Debug, Default)]
+pub(crate) struct ToolCalls {
+    total: usize,
+    by_tool: HashMap<String, usize>,
+}
+
+impl Limits {
+    /// Read and parse a request body, refusing it as soon as it exceeds the maximum size
+    /// Read a JSON body, refusing it as soon as it exceeds the maximum size
+    pub(crate) async fn read_json<T: DeserializeOwned>(&self, body: Body) -> Result<T, Error> {
+        let limit = self.max_body_bytes.unwrap_or(DEFAULT_MAX_BODY_BYTES);
+
+        let bytes = to_bytes(body, limit).await.map_err(|_| Error {
+            status: 413,
+            message: format!("Request body is larger than {limit} bytes"),
+        })?;
+
+        let Json(body) = Json::<T>::from_bytes(&bytes).map_err(|rejection| Error {
+            status: 422,
+            message: rejection.body_text(),
+        })?;
+
+        Ok(body)
+    }
+
+    /// Count a call to a tool, or refuse it with the output given to the model instead when the
+    /// request already made the calls it can, for the model to answer with what it has
+    pub(crate) fn call_tool(&self, calls: &mut ToolCalls, tool: &str) -> Result<(), String> {
+        if let Some(max_calls) = self.max_tool_calls
+            && calls.total >= max_calls
+        {
+            return Err(format!(
+                "Error: the {max_calls} tool calls allowed in this request were made, don't call \
+                    any more tools and answer with what you already have"
+            ));
+        }
+
+        let made = calls.by_tool.entry(tool.to_owned()).or_default();
+
+        if let Some(max_calls) = self.max_calls_per_tool.get(tool)
+            && *made >= *max_calls
+        {
+            return Err(format!(
+                "Error: tool {tool} reached the {max_calls} calls allowed in this request, don't \
+                    call it again and answer with what you already have"
+            ));
+        }
+
+        *made += 1;
+        calls.total += 1;
+
+        Ok(())
+    }
+
+    /// Refuse the requests with too many messages or too long ones
+    pub(crate) fn check(&self, body: &ManagerBody) -> Result<(), Error> {
+        if let Some(max_messages) = self.max_messages
+            && body.messages.len() > max_messages
+        {
+            return Err(Error {
+                status: 422,
+                message: format!(
+                    "Request has {} messages, the maximum is {max_messages}",
+                    body.messages.len()
+                ),
+            });
+        }
+
+        if let Some(max_chars) = self.max_message_chars {
+            for (index, message) in body.messages.iter().enumerate() {
+                let chars = match message {
+                    Message::TextMessage(TextMessage { content, .. }) => content.chars().count(),
+                    Message::ToolOutput { output, .. } => output.chars().count(),
+                    Message::ToolCalls { tool_calls, .. } => tool_calls
+                        .iter()
+                        .map(|call| json!(call.arguments).to_string().chars().count())
+                        .sum(),
+                };
+
+                if chars > max_chars {
+                    return Err(Error {
+                        status: 422,
+                        message: format!(
+                            "Message {index} has {chars} characters, the maximum is {max_chars}"
+                        ),
+                    });
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limits_tool_calls() {
+        let limits = Limits {
+            max_tool_calls: Some(3),
+            max_calls_per_tool: HashMap::from([(String::from("search"), 1)]),
+            ..Limits::default()
+        };
+        let mut calls = ToolCalls::default();
+
+        assert_eq!(limits.call_tool(&mut calls, "search"), Ok(()));
+        assert!(
+            limits
+                .call_tool(&mut calls, "search")
+                .unwrap_err()
+                .starts_with("Error: tool search reached the 1 calls")
+        );
+
+        // The refused call isn't counted
+        assert_eq!(limits.call_tool(&mut calls, "read"), Ok(()));
+        assert_eq!(limits.call_tool(&mut calls, "read"), Ok(()));
+        assert!(
+            limits
+                .call_tool(&mut calls, "read")
+                .unwrap_err()
+                .starts_with("Error: the 3 tool calls allowed")
+        );
+    }
+
+    #[test]
+    fn allows_any_tool_calls_by_default() {
+        let mut calls = ToolCalls::default();
+
+        for _ in 0..100 {
+            assert_eq!(Limits::default().call_tool(&mut calls, "search"), Ok(()));
+        }
+    }
+
+    #[tokio::test]
+    async fn refuses_large_bodies() {
+        let limits = Limits {
+            max_body_bytes: Some(10),
+            ..Limits::default()
+        };
+
+        let error = limits
+            .read_json::<serde_json::Value>(Body::from("[1, 2, 3, 4, 5]"))
+            .await
+            .unwrap_err();
+
+        assert_eq!(error.status, 413);
+        assert_eq!(
+            limits
+                .read_json::<Vec<u8>>(Body::from("[1, 2]"))
+                .await
+                .unwrap(),
+            vec![1, 2]
+        );
+    }
+}
@@ -0,0 +1,422 @@
+use std::{
+    collections::BTreeMap,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    sync::{
+        LazyLock, Mutex, OnceLock, RwLock,
+        atomic::{AtomicBool, Ordering},
+    },
+};
+
+use regex::Regex;
+use rmcp::model::JsonObject;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::{Level, event, level_filters::LevelFilter};
+use tracing_subscriber::{
+    EnvFilter, Layer, Registry, filter::Directive, fmt, fmt::MakeWriter, layer::Layered,
+    layer::SubscriberExt, reload, util::SubscriberInitExt,
+};
+
+const REDACTED: &str = "<redacted>";
+/// Numbers, booleans and null values of the arguments
+const SCALAR: &str = r"-?[0-9][0-9.eE+-]*|true|false|null";
+/// Shorter values would mask too much unrelated output
+const MIN_SECRET_LEN: usize = 4;
+
+/// Values of the configuration masked in the logs
+static SECRETS: RwLock<Vec<String>> = RwLock::new(Vec::new());
+static LOG_PAYLOADS: AtomicBool = AtomicBool::new(false);
+/// Arguments of the tools masked in the logs and in the traces of the requests, by tool
+static REDACTED_ARGS: RwLock<BTreeMap<String, Vec<String>>> = RwLock::new(BTreeMap::new());
+/// Values of the redacted arguments in JSON, and in JSON encoded in a JSON string (e.g. the
+/// arguments of the OpenAI tool calls), with the replacement keeping their key
+static ARGUMENT_PATTERNS: RwLock<Vec<(Regex, String)>> = RwLock::new(Vec::new());
+
+/// Common formats of credentials, masked even when not configured, with the prefix to keep
+static TOKEN_PATTERNS: LazyLock<Vec<(Regex, &str)>> = LazyLock::new(|| {
+    [
+        (r"(?i)(bearer\s+)[A-Za-z0-9._~+/-]+=*", "${1}"),
+        (
+            r#"(?i)([?&](?:key|api[_-]?key|token|access_token)=)[^&\s"']+"#,
+            "${1}",
+        ),
+        (r"eyJ[A-Za-z0-9_-]+\.[A-Za-z0-9_-]+\.[A-Za-z0-9_-]+", ""),
+        (r"sk-[A-Za-z0-9_-]{16,}", ""),
+        (r"AIza[0-9A-Za-z_-]{35}", ""),
+    ]
+    .into_iter()
+    .map(|(pattern, prefix)| (Regex::new(pattern).unwrap(), prefix))
+    .collect()
+});
+
+/// Megabytes a log file grows up to before being rotated, when not configured
+pub(crate) const DEFAULT_LOG_MAX_SIZE: u64 = 10;
+/// Rotated log files kept, when not configured
+pub(crate) const DEFAULT_LOG_MAX_FILES: usize = 5;
+
+/// Where the logs are written, standard output until the configuration says otherwise
+static DESTINATION: Mutex<Destination> = Mutex::new(Destination::Stdout);
+/// Format of the logs, replaced once the configuration is loaded
+static FORMAT: OnceLock<reload::Handle<FormatLayer, Filtered>> = OnceLock::new();
+/// Filter of the logs, given the levels of the configuration
+static FILTER: OnceLock<reload::Handle<EnvFilter, Registry>> = OnceLock::new();
+
+type Filtered = Layered<reload::Layer<EnvFilter, Registry>, Registry>;
+type FormatLayer = Box<dyn Layer<Filtered> + Send + Sync>;
+
+/// How each event is written
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Human readable lines
+    #[default]
+    Text,
+    /// A JSON object per line, for the log aggregation stacks
+    Json,
+}
+
+/// File the logs are written to, moved aside once too large
+#[derive(Debug)]
+pub struct LogFile {
+    pub path: String,
+    /// Bytes the file grows up to before being rotated
+    pub max_bytes: u64,
+    /// Rotated files kept, as `<path>.1` (the most recent) to `<path>.<max_files>`
+    pub max_files: usize,
+}
+
+enum Destination {
+    Stdout,
+    File {
+        config: LogFile,
+        file: File,
+        /// Bytes written to the file
+        size: u64,
+    },
+}
+
+impl Destination {
+    fn write(&mut self, text: &[u8]) -> io::Result<()> {
+        match self {
+            Destination::Stdout => io::stdout().lock().write_all(text),
+            Destination::File { config, file, size } => {
+                if *size > 0 && *size + text.len() as u64 > config.max_bytes {
+                    *file = rotate(config)?;
+                    *size = 0;
+                }
+
+                file.write_all(text)?;
+                *size += text.len() as u64;
+
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Shift the rotated files of a log, dropping the oldest, and start a new file
+fn rotate(config: &LogFile) -> io::Result<File> {
+    let rotated = |index: usize| format!("{}.{index}", config.path);
+
+    if config.max_files == 0 {
+        fs::remove_file(&config.path)?;
+    } else {
+        let _ = fs::remove_file(rotated(config.max_files));
+
+        for index in (1..config.max_files).rev() {
+            let _ = fs::rename(rotated(index), rotated(index + 1));
+        }
+
+        fs::rename(&config.path, rotated(1))?;
+    }
+
+    open(&config.path)
+}
+
+fn open(path: &str) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+/// Levels SIGUSR1 cycles through, from the least to the most verbose
+const LEVELS: [Level; 5] = [
+    Level::ERROR,
+    Level::WARN,
+    Level::INFO,
+    Level::DEBUG,
+    Level::TRACE,
+];
+
+/// Filter of the logs, changeable while running
+pub struct LogFilter {
+    handle: reload::Handle<EnvFilter, Registry>,
+}
+
+impl LogFilter {
+    /// Install the global subscriber, filtered by `RUST_LOG` (default: info)
+    pub fn init() -> LogFilter {
+        let (filter, handle) = reload::Layer::new(
+            EnvFilter::builder()
+                .with_default_directive(Level::INFO.into())
+                .from_env_lossy(),
+        );
+
+        let (format, format_handle) = reload::Layer::new(format_layer(LogFormat::Text, true));
+
+        tracing_subscriber::registry()
+            .with(filter)
+            .with(format)
+            .try_init()
+            .expect("Failed to set tracing subscriber");
+
+        let _ = FORMAT.set(format_handle);
+        let _ = FILTER.set(handle.clone());
+
+        LogFilter { handle }
+    }
+
+    /// Directives of the filter in use
+    pub fn current(&self) -> String {
+        self.handle
+            .with_current(|filter| filter.to_string())
+            .unwrap_or_default()
+    }
+
+    /// Replace the filter by the given directives, with the same syntax as `RUST_LOG`
+    pub fn set(&self, directives: &str) -> Result<(), String> {
+        let filter = EnvFilter::try_new(directives)
+            .map_err(|error| format!("Invalid filter '{directives}': {error}"))?;
+
+        self.handle
+            .reload(filter)
+            .map_err(|error| format!("Couldn't change filter: {error}"))?;
+
+        event!(Level::WARN, "Log filter set to '{directives}'");
+
+        Ok(())
+    }
+
+    /// Switch to the next, more verbose, level for everything, going back to errors after trace
+    pub fn cycle(&self) {
+        let current = self
+            .handle
+            .with_current(|filter| filter.max_level_hint())
+            .ok()
+            .flatten()
+            .and_then(LevelFilter::into_level);
+
+        let next = match current.and_then(|level| LEVELS.iter().position(|other| *other == level)) {
+            Some(index) => LEVELS[(index + 1) % LEVELS.len()],
+            None => Level::ERROR,
+        };
+
+        if let Err(error) = self.set(&next.to_string().to_lowercase()) {
+            event!(Level::ERROR, "{error}");
+        }
+    }
+}
+
+/// Write the logs in a format and to a file, and set the level of some targets over the one of
+/// `RUST_LOG`. Only applies to the subscriber installed by [`LogFilter::init`]
+pub(crate) fn configure(
+    format: LogFormat,
+    file: Option<LogFile>,
+    levels: &BTreeMap<String, String>,
+) -> io::Result<()> {
+    let Some(format_handle) = FORMAT.get() else {
+        return Ok(());
+    };
+
+    let to_file = file.is_some();
+
+    if let Some(config) = file {
+        let file = open(&config.path)?;
+        let size = file.metadata()?.len();
+
+        event!(Level::INFO, "Writing the logs to {}", config.path);
+
+        *DESTINATION.lock().unwrap() = Destination::File { config, file, size };
+    }
+
+    if format != LogFormat::Text || to_file {
+        format_handle
+            .reload(format_layer(format, !to_file))
+            .map_err(io::Error::other)?;
+    }
+
+    if !levels.is_empty()
+        && let Some(filter_handle) = FILTER.get()
+    {
+        let current = filter_handle
+            .with_current(|filter| filter.to_string())
+            .map_err(io::Error::other)?;
+        let mut filter = EnvFilter::try_new(current).map_err(io::Error::other)?;
+
+        // The invalid ones are reported by the validation of the configuration
+        for directive in levels
+            .iter()
+            .filter_map(|(target, level)| level_directive(target, level))
+        {
+            filter = filter.add_directive(directive);
+        }
+
+        filter_handle.reload(filter).map_err(io::Error::other)?;
+    }
+
+    Ok(())
+}
+
+/// Directive of the filter setting the level of a target, when both are valid
+pub(crate) fn level_directive(target: &str, level: &str) -> Option<Directive> {
+    level.parse::<LevelFilter>().ok()?;
+
+    format!("{target}={level}").parse().ok()
+}
+
+/// Layer writing the events in a format, with colors only for a terminal
+fn format_layer(format: LogFormat, ansi: bool) -> FormatLayer {
+    match format {
+        LogFormat::Text => fmt::layer()
+            .with_ansi(ansi)
+            .with_writer(ScrubbingOutput)
+            .boxed(),
+        LogFormat::Json => fmt::layer().json().with_writer(ScrubbingOutput).boxed(),
+    }
+}
+
+/// Mask these values wherever they appear in the logs
+pub(crate) fn set_secrets(secrets: Vec<String>) {
+    *SECRETS.write().unwrap() = secrets
+        .into_iter()
+        .filter(|secret| secret.len() >= MIN_SECRET_LEN)
+        .collect();
+}
+
+/// Mask the values of these arguments of each tool wherever they appear
+pub(crate) fn set_redacted_args(args: BTreeMap<String, Vec<String>>) {
+    let mut names: Vec<&String> = args.values().flatten().collect();
+    names.sort();
+    names.dedup();
+
+    // Tool names aren't always next to their arguments in the logs, the arguments of every tool
+    // are masked
+    *ARGUMENT_PATTERNS.write().unwrap() = names
+        .into_iter()
+        .flat_map(|name| {
+            let name = regex::escape(name);
+
+            [
+                (
+                    format!(r#"("{name}"\s*:\s*(?:String\()?)(?:"(?:[^"\\]|\\.)*"|{SCALAR})"#),
+                    format!(r#"${{1}}"{REDACTED}""#),
+                ),
+                (
+                    format!(
+                        r#"(\\"{name}\\"\s*:\s*)(?:\\"(?:[^"\\]|\\\\[^"\\]|\\\\\\"|\\\\\\\\)*\\"|{SCALAR})"#
+                    ),
+                    format!(r#"${{1}}\"{REDACTED}\""#),
+                ),
+            ]
+        })
+        .map(|(pattern, replacement)| (Regex::new(&pattern).unwrap(), replacement))
+        .collect();
+
+    *REDACTED_ARGS.write().unwrap() = args;
+}
+
+/// Arguments of a call to a tool, with the values of the redacted ones masked
+pub(crate) fn redact_arguments(tool: &str, arguments: Option<JsonObject>) -> Option<JsonObject> {
+    let redacted = REDACTED_ARGS.read().unwrap();
+
+    let Some(names) = redacted.get(tool) else {
+        return arguments;
+    };
+
+    arguments.map(|mut arguments| {
+        for name in names {
+            if let Some(value) = arguments.get_mut(name) {
+                *value = Value::String(String::from(REDACTED));
+            }
+        }
+
+        arguments
+    })
+}
+
+pub(crate) fn set_log_payloads(enabled: bool) {
+    LOG_PAYLOADS.store(enabled, Ordering::Relaxed);
+}
+
+/// Whether the bodies exchanged with models and MCP servers can be logged
+pub(crate) fn log_payloads() -> bool {
+    LOG_PAYLOADS.load(Ordering::Relaxed)
+}
+
+fn scrub(text: &str) -> String {
+    let mut text = text.to_owned();
+
+    for secret in SECRETS.read().unwrap().iter() {
+        if text.contains(secret.as_str()) {
+            text = text.replace(secret.as_str(), REDACTED);
+        }
+    }
+
+    for (pattern, replacement) in ARGUMENT_PATTERNS.read().unwrap().iter() {
+        if pattern.is_match(&text) {
+            text = pattern
+                .replace_all(&text, replacement.as_str())
+                .into_owned();
+        }
+    }
+
+    for (pattern, prefix) in TOKEN_PATTERNS.iter() {
+        if pattern.is_match(&text) {
+            text = pattern
+                .replace_all(&text, format!("{prefix}{REDACTED}"))
+                .into_owned();
+        }
+    }
+
+    text
+}
+
+/// Destination of the logs, with secrets masked from every event written to it
+struct ScrubbingOutput;
+
+/// Buffer of a single event, scrubbed and written once complete
+struct ScrubbingWriter {
+    buffer: Vec<u8>,
+}
+
+impl<'a> MakeWriter<'a> for ScrubbingOutput {
+    type Writer = ScrubbingWriter;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        ScrubbingWriter { buffer: Vec::new() }
+    }
+}
+
+impl Write for ScrubbingWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.buffer.is_empty() {
+            let text = scrub(&String::from_utf8_lossy(&self.buffer));
+            self.buffer.clear();
+
+            DESTINATION.lock().unwrap().write(text.as_bytes())?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for ScrubbingWriter {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
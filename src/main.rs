@@ -1,58 +1,291 @@
-use std::{env, sync::Arc};
+use std::{collections::HashMap, env, net::SocketAddr, sync::Arc};
 
-use axum::{
-    Router,
-    routing::{any, post},
-};
+use axum::{Router, middleware, routing::get};
 use futures::future::try_join_all;
-use mcp_manager::{config::get_config, error_method, workspace_handler};
-use tokio::{io, net::TcpListener, sync::RwLock};
+use mcp_manager::{
+    FailMode,
+    acl::acl_middleware,
+    admin::{
+        admin_middleware, config_handler, export_handler, log_level_handler, set_log_level_handler,
+        usage_handler,
+    },
+    budgets::budgets_handler,
+    check::check,
+    config::{get_check_config, get_config},
+    error_method, error_path, listener_router,
+    logging::LogFilter,
+    mcp::process,
+    metrics::metrics_handler,
+    openapi::admin_openapi_handler,
+    readiness::ready_handler,
+    selftest::selftest,
+    state, systemd,
+};
+use tokio::{io, net::TcpListener};
 use tower_http::add_extension::AddExtensionLayer;
 use tracing::{Level, event};
-use tracing_subscriber::EnvFilter;
 
 const CONFIG_FILE: &str = "config.yaml";
+/// Environment variable with the path of the state file, locked by the running manager
+const STATE_FILE_VAR: &str = "MCP_MANAGER_STATE";
+/// Argument checking the models and MCP servers of the configuration instead of serving it
+const CHECK_ARG: &str = "--check";
+/// Command running the tool calls of the `selftest` sections of the workspaces instead of serving
+const SELFTEST_COMMAND: &str = "selftest";
 
 #[tokio::main]
 async fn main() -> io::Result<()> {
-    let subscriber = tracing_subscriber::fmt()
-        .with_env_filter(
-            EnvFilter::builder()
-                .with_default_directive(Level::INFO.into())
-                .from_env_lossy(),
-        )
-        .finish();
+    let logs = Arc::new(LogFilter::init());
 
-    tracing::subscriber::set_global_default(subscriber).expect("Failed to set tracing subscriber");
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{SignalKind, signal};
 
-    let config_file = env::var_os("MCP_MANAGER_CONFIG").map_or(CONFIG_FILE.to_owned(), |var| {
-        var.into_string().unwrap_or(CONFIG_FILE.to_owned())
-    });
+        let logs = Arc::clone(&logs);
+        let mut signals = signal(SignalKind::user_defined1()).expect("Couldn't listen to SIGUSR1");
+
+        tokio::spawn(async move {
+            while signals.recv().await.is_some() {
+                logs.cycle();
+            }
+        });
+    }
+
+    let result = if env::args()
+        .nth(1)
+        .is_some_and(|arg| arg == SELFTEST_COMMAND)
+    {
+        run_selftest().await
+    } else if env::args().skip(1).any(|arg| arg == CHECK_ARG) {
+        run_check().await
+    } else {
+        serve(logs).await
+    };
 
-    let config = get_config(&config_file).await?;
+    // The local MCP servers may have started processes of their own, which would be left behind
+    process::stop_all();
+    state::release();
+
+    result
+}
+
+/// Start the MCP servers and the listeners, serving until the manager is stopped
+async fn serve(logs: Arc<LogFilter>) -> io::Result<()> {
+    // Taken before starting the MCP servers, which would inherit them
+    let mut sockets = systemd::listen_fds();
+
+    let config_file = config_file();
+
+    // Taken before starting the MCP servers, which another manager may be running
+    if let Some(state_file) = env::var_os(STATE_FILE_VAR) {
+        state::acquire(&state_file.to_string_lossy(), &config_file)?;
+    }
+
+    let mut config = get_config(&config_file).await?;
 
     let mut futures = Vec::new();
+    let mut started = Vec::new();
+    let mut skipped = Vec::new();
+
+    for (listener, workspaces) in std::mem::take(&mut config.listeners) {
+        let mut router = listener_router(workspaces, &config);
 
-    for (listener, config) in config.listeners {
-        let router = Router::new()
-            .route("/{*path}", post(workspace_handler))
-            .route("/{*path}", any(error_method))
-            .layer(AddExtensionLayer::new(Arc::new(RwLock::new(config))));
+        if let Some(acl) = config.acls.get(&listener) {
+            router = router.layer(middleware::from_fn_with_state(
+                Arc::clone(acl),
+                acl_middleware,
+            ));
+        }
 
         event!(Level::INFO, "Starting listener {listener}");
 
-        futures.push(
-            axum::serve(
-                TcpListener::bind(listener)
-                    .await
-                    .expect("Couldn't start listener: {listener}"),
-                router,
+        match bind(&listener, &mut sockets).await {
+            Ok(tcp) => {
+                futures.push(
+                    axum::serve(
+                        tcp,
+                        router.into_make_service_with_connect_info::<SocketAddr>(),
+                    )
+                    .into_future(),
+                );
+                started.push(listener);
+            }
+            Err(error) => {
+                bind_failed(&config.fail_modes, listener, error, &mut skipped)?;
+            }
+        }
+    }
+
+    if let Some(admin) = config.admin {
+        let listener = admin.listener.clone();
+        let mut router = Router::new()
+            .route("/admin/config", get(config_handler))
+            .route("/admin/usage", get(usage_handler))
+            .route("/admin/conversations/{id}/export", get(export_handler))
+            .route("/admin/budgets", get(budgets_handler))
+            .route("/admin/ready", get(ready_handler))
+            .route("/admin/metrics", get(metrics_handler))
+            .route("/openapi.json", get(admin_openapi_handler))
+            .route(
+                "/admin/log-level",
+                get(log_level_handler).put(set_log_level_handler),
             )
-            .into_future(),
+            .method_not_allowed_fallback(error_method)
+            .fallback(error_path)
+            .layer(AddExtensionLayer::new(config.storage.clone()))
+            .layer(AddExtensionLayer::new(Arc::clone(&config.effective)))
+            .layer(AddExtensionLayer::new(Arc::clone(&logs)))
+            .layer(AddExtensionLayer::new(Arc::clone(&config.readiness)))
+            .layer(AddExtensionLayer::new(Arc::clone(&config.budgets)))
+            .layer(AddExtensionLayer::new(Arc::clone(&config.metrics)))
+            .layer(middleware::from_fn_with_state(admin, admin_middleware));
+
+        if let Some(acl) = config.acls.get(&listener) {
+            router = router.layer(middleware::from_fn_with_state(
+                Arc::clone(acl),
+                acl_middleware,
+            ));
+        }
+
+        event!(Level::INFO, "Starting admin listener {listener}");
+
+        match bind(&listener, &mut sockets).await {
+            Ok(tcp) => {
+                futures.push(
+                    axum::serve(
+                        tcp,
+                        router.into_make_service_with_connect_info::<SocketAddr>(),
+                    )
+                    .into_future(),
+                );
+                started.push(listener);
+            }
+            Err(error) => {
+                bind_failed(&config.fail_modes, listener, error, &mut skipped)?;
+            }
+        }
+    }
+
+    if skipped.is_empty() {
+        event!(Level::INFO, "Started listeners: {}", started.join(", "));
+    } else {
+        event!(
+            Level::WARN,
+            "Started listeners: {}. Skipped listeners: {}",
+            started.join(", "),
+            skipped.join(", ")
+        );
+    }
+
+    if started.is_empty() {
+        return Err(io::Error::other("No listener could be started"));
+    }
+
+    for address in sockets.keys() {
+        event!(
+            Level::WARN,
+            "Socket {address} of systemd isn't used by any listener"
         );
     }
 
-    try_join_all(futures).await?;
+    state::listening(&started);
+    systemd::notify_ready(&format!("Serving {} listeners", started.len()));
+
+    tokio::select! {
+        result = try_join_all(futures) => {
+            result?;
+        }
+        () = shutdown() => {
+            event!(Level::INFO, "Stopping");
+        }
+    }
 
     Ok(())
 }
+
+/// Connect to every MCP server and model of the configuration and print how they answered,
+/// failing when any of them didn't
+async fn run_check() -> io::Result<()> {
+    let config = get_check_config(&config_file()).await?;
+    let report = check(&config).await;
+
+    println!("{report}");
+
+    match report.failures() {
+        0 => Ok(()),
+        failures => Err(io::Error::other(format!("{failures} check(s) failed"))),
+    }
+}
+
+/// Call the tools of the `selftest` sections of the workspaces and print how their outputs matched,
+/// failing when any of them didn't
+async fn run_selftest() -> io::Result<()> {
+    let config = get_check_config(&config_file()).await?;
+    let report = selftest(&config).await;
+
+    println!("{report}");
+
+    match report.failures() {
+        0 => Ok(()),
+        failures => Err(io::Error::other(format!("{failures} test(s) failed"))),
+    }
+}
+
+fn config_file() -> String {
+    env::var_os("MCP_MANAGER_CONFIG").map_or(CONFIG_FILE.to_owned(), |var| {
+        var.into_string().unwrap_or(CONFIG_FILE.to_owned())
+    })
+}
+
+/// Wait for the manager to be asked to stop, with Ctrl+C or SIGTERM
+async fn shutdown() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{SignalKind, signal};
+
+        let mut terminate = signal(SignalKind::terminate()).expect("Couldn't listen to SIGTERM");
+
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => (),
+            _ = terminate.recv() => (),
+        }
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+    }
+}
+
+/// Socket of a listener, passed by systemd or bound by the manager
+async fn bind(
+    listener: &str,
+    sockets: &mut HashMap<String, std::net::TcpListener>,
+) -> io::Result<TcpListener> {
+    if let Some(socket) = sockets.remove(listener) {
+        socket.set_nonblocking(true)?;
+
+        return TcpListener::from_std(socket);
+    }
+
+    TcpListener::bind(listener).await
+}
+
+/// Handle a listener that couldn't be started, failing unless it's configured to be skipped
+fn bind_failed(
+    fail_modes: &HashMap<String, FailMode>,
+    listener: String,
+    error: io::Error,
+    skipped: &mut Vec<String>,
+) -> io::Result<()> {
+    event!(Level::ERROR, "Couldn't start listener {listener}: {error}");
+
+    match fail_modes.get(&listener).copied().unwrap_or_default() {
+        FailMode::Abort => Err(error),
+        FailMode::Skip => {
+            skipped.push(listener);
+
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,76 @@
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use rmcp::{ServiceError, model::Tool};
+use serde_json::json;
+use tokio::sync::Mutex;
+use tracing::{Level, event, instrument};
+
+use crate::mcp::{McpServer, ToolCall, ToolOutput};
+
+/// MCP server whose tool results are reused while they're fresh, for the tools with a TTL
+pub(crate) struct CachedMcp {
+    pub(crate) inner: Arc<dyn McpServer + Send>,
+    pub(crate) ttls: HashMap<String, Duration>,
+    pub(crate) results: Mutex<HashMap<(String, String), (Instant, ToolOutput)>>,
+}
+
+impl CachedMcp {
+    pub(crate) fn new(inner: Arc<dyn McpServer + Send>, ttls: HashMap<String, u64>) -> CachedMcp {
+        CachedMcp {
+            inner,
+            ttls: ttls
+                .into_iter()
+                .map(|(tool, ttl)| (tool, Duration::from_secs(ttl)))
+                .collect(),
+            results: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+#[async_trait]
+impl McpServer for CachedMcp {
+    #[instrument(skip_all, fields(tool = call.name))]
+    async fn call(&self, call: ToolCall) -> Result<ToolOutput, ServiceError> {
+        let Some(ttl) = self.ttls.get(&call.name).copied() else {
+            return self.inner.call(call).await;
+        };
+
+        let key = (call.name.clone(), json!(call.arguments).to_string());
+
+        {
+            let mut results = self.results.lock().await;
+
+            results.retain(|(tool, _), (created, _)| {
+                self.ttls
+                    .get(tool)
+                    .is_some_and(|ttl| created.elapsed() < *ttl)
+            });
+
+            if let Some((_, output)) = results.get(&key) {
+                event!(Level::INFO, "Using cached result of {}", call.name);
+
+                return Ok(output.clone());
+            }
+        }
+
+        let output = self.inner.call(call).await?;
+
+        if !ttl.is_zero() {
+            self.results
+                .lock()
+                .await
+                .insert(key, (Instant::now(), output.clone()));
+        }
+
+        Ok(output)
+    }
+
+    async fn list_tools(&self) -> Result<Vec<Tool>, ServiceError> {
+        self.inner.list_tools().await
+    }
+}
@@ -0,0 +1,495 @@
+use std::{collections::HashMap, sync::Arc, sync::LazyLock};
+
+use async_trait::async_trait;
+use regex::Regex;
+use rmcp::{ServiceError, model::Tool};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::{Level, event, instrument};
+
+use crate::mcp::{McpServer, ToolCall, ToolOutput};
+
+/// Elements whose content isn't meant to be read
+const HIDDEN_ELEMENTS: [&str; 7] = [
+    "head", "script", "style", "noscript", "template", "svg", "iframe",
+];
+
+/// Elements put on lines of their own
+const BLOCK_ELEMENTS: [&str; 17] = [
+    "p",
+    "div",
+    "section",
+    "article",
+    "header",
+    "footer",
+    "main",
+    "nav",
+    "aside",
+    "ul",
+    "ol",
+    "table",
+    "blockquote",
+    "form",
+    "figure",
+    "dl",
+    "body",
+];
+
+static ATTRIBUTE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"(?i)([a-z-]+)\s*=\s*(?:"([^"]*)"|'([^']*)'|([^\s"'>]+))"#)
+        .expect("Valid attribute pattern")
+});
+
+/// Kind of text a tool output can be converted from
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum ContentType {
+    /// Prettified
+    Json,
+    /// Made a Markdown table
+    Csv,
+    /// Made Markdown
+    Html,
+}
+
+impl ContentType {
+    /// Kind of a text, told from its first characters and whether it parses
+    fn detect(text: &str) -> Option<ContentType> {
+        let text = text.trim();
+
+        if (text.starts_with('{') || text.starts_with('['))
+            && serde_json::from_str::<Value>(text).is_ok()
+        {
+            Some(ContentType::Json)
+        } else if text.starts_with('<') && text.ends_with('>') && text.contains("</") {
+            Some(ContentType::Html)
+        } else if csv_rows(text).is_some() {
+            Some(ContentType::Csv)
+        } else {
+            None
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            ContentType::Json => "JSON",
+            ContentType::Csv => "CSV",
+            ContentType::Html => "HTML",
+        }
+    }
+}
+
+/// MCP server whose text outputs are converted to what the models read best, for the tools with
+/// content types to convert
+pub(crate) struct ConvertingMcp {
+    pub(crate) inner: Arc<dyn McpServer + Send>,
+    pub(crate) conversions: HashMap<String, Vec<ContentType>>,
+}
+
+#[async_trait]
+impl McpServer for ConvertingMcp {
+    #[instrument(skip_all, fields(tool = call.name))]
+    async fn call(&self, call: ToolCall) -> Result<ToolOutput, ServiceError> {
+        let Some(content_types) = self.conversions.get(&call.name) else {
+            return self.inner.call(call).await;
+        };
+
+        let name = call.name.clone();
+        let mut output = self.inner.call(call).await?;
+
+        if output.error {
+            return Ok(output);
+        }
+
+        let Some(content_type) =
+            ContentType::detect(&output.text).filter(|detected| content_types.contains(detected))
+        else {
+            return Ok(output);
+        };
+
+        let text = output.text.trim();
+        let converted = match content_type {
+            ContentType::Json => serde_json::from_str::<Value>(text)
+                .ok()
+                .and_then(|value| serde_json::to_string_pretty(&value).ok()),
+            ContentType::Csv => csv_rows(text).map(|rows| markdown_table(&rows)),
+            ContentType::Html => Some(html_to_markdown(text)),
+        };
+
+        if let Some(converted) = converted {
+            event!(
+                Level::DEBUG,
+                "Converted output of {name} from {}",
+                content_type.name()
+            );
+
+            output.text = converted;
+        }
+
+        Ok(output)
+    }
+
+    async fn list_tools(&self) -> Result<Vec<Tool>, ServiceError> {
+        self.inner.list_tools().await
+    }
+}
+
+/// Fields of the rows of a CSV text, when it has a header and all its rows have the same number of
+/// fields, at least 2
+fn csv_rows(text: &str) -> Option<Vec<Vec<String>>> {
+    let rows: Vec<Vec<String>> = text
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(csv_fields)
+        .collect::<Option<_>>()?;
+
+    let columns = rows.first()?.len();
+
+    (rows.len() > 1 && columns > 1 && rows.iter().all(|row| row.len() == columns)).then_some(rows)
+}
+
+/// Fields of a line of CSV, with `"` quoting fields holding commas and `""` escaping quotes
+fn csv_fields(line: &str) -> Option<Vec<String>> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.trim_end_matches('\r').chars().peekable();
+
+    while let Some(char) = chars.next() {
+        match char {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            '"' if quoted => quoted = false,
+            '"' if field.trim().is_empty() => {
+                field.clear();
+                quoted = true;
+            }
+            ',' if !quoted => fields.push(std::mem::take(&mut field).trim().to_owned()),
+            char => field.push(char),
+        }
+    }
+
+    if quoted {
+        return None;
+    }
+
+    fields.push(field.trim().to_owned());
+
+    Some(fields)
+}
+
+/// Markdown table of rows, the first one being the header
+fn markdown_table(rows: &[Vec<String>]) -> String {
+    let line = |row: &[String]| {
+        let cells: Vec<String> = row.iter().map(|cell| cell.replace('|', "\\|")).collect();
+
+        format!("| {} |", cells.join(" | "))
+    };
+
+    let mut lines = Vec::with_capacity(rows.len() + 1);
+
+    for (index, row) in rows.iter().enumerate() {
+        lines.push(line(row));
+
+        if index == 0 {
+            lines.push(format!("|{}", " --- |".repeat(row.len())));
+        }
+    }
+
+    lines.join("\n")
+}
+
+/// Markdown of a HTML document, keeping the headings, lists, links, emphasis, code and tables
+/// while leaving out the scripts, styles and other elements not meant to be read
+fn html_to_markdown(html: &str) -> String {
+    let mut markdown = String::new();
+    let mut rest = html;
+    // Element whose content is left out, until it's closed
+    let mut hidden: Option<String> = None;
+    let mut preformatted = false;
+    // Lists being in, with the number of the next item of the ordered ones
+    let mut lists: Vec<Option<usize>> = Vec::new();
+    // Targets of the links being in
+    let mut links: Vec<Option<String>> = Vec::new();
+    // Cells of the table row being in, and whether they're headers
+    let mut cells = 0;
+    let mut header_row = false;
+    let mut header_done = false;
+
+    while !rest.is_empty() {
+        let Some(start) = rest.find('<') else {
+            if hidden.is_none() {
+                push_text(&mut markdown, rest, preformatted);
+            }
+            break;
+        };
+
+        if hidden.is_none() {
+            push_text(&mut markdown, &rest[..start], preformatted);
+        }
+
+        rest = &rest[start..];
+
+        if let Some(comment) = rest.strip_prefix("<!--") {
+            rest = comment.find("-->").map_or("", |end| &comment[end + 3..]);
+            continue;
+        }
+
+        let Some(end) = rest.find('>') else {
+            if hidden.is_none() {
+                push_text(&mut markdown, rest, preformatted);
+            }
+            break;
+        };
+
+        let tag = &rest[1..end];
+        rest = &rest[end + 1..];
+
+        let closing = tag.starts_with('/');
+        let tag = tag.trim_start_matches('/');
+        let name_end = tag
+            .find(|char: char| char.is_whitespace() || char == '/')
+            .unwrap_or(tag.len());
+        let name = tag[..name_end].to_ascii_lowercase();
+        let attributes = &tag[name_end..];
+
+        if let Some(element) = &hidden {
+            if closing && *element == name {
+                hidden = None;
+            }
+            continue;
+        }
+
+        if !closing && HIDDEN_ELEMENTS.contains(&name.as_str()) && !tag.ends_with('/') {
+            hidden = Some(name);
+            continue;
+        }
+
+        match (name.as_str(), closing) {
+            ("h1" | "h2" | "h3" | "h4" | "h5" | "h6", false) => {
+                let level = name[1..].parse().unwrap_or(1);
+                push_block(&mut markdown);
+                markdown.push_str(&"#".repeat(level));
+                markdown.push(' ');
+            }
+            ("h1" | "h2" | "h3" | "h4" | "h5" | "h6", true) => push_block(&mut markdown),
+            ("br", _) => markdown.push('\n'),
+            ("hr", _) => {
+                push_block(&mut markdown);
+                markdown.push_str("---");
+                push_block(&mut markdown);
+            }
+            ("ul" | "ol", false) => {
+                // Nested lists go on the line after their item
+                if lists.is_empty() {
+                    push_block(&mut markdown);
+                } else {
+                    push_line(&mut markdown);
+                }
+                lists.push((name == "ol").then_some(1));
+            }
+            ("ul" | "ol", true) => {
+                lists.pop();
+                if lists.is_empty() {
+                    push_block(&mut markdown);
+                } else {
+                    push_line(&mut markdown);
+                }
+            }
+            ("li", false) => {
+                push_line(&mut markdown);
+                markdown.push_str(&"  ".repeat(lists.len().saturating_sub(1)));
+
+                match lists.last_mut() {
+                    Some(Some(number)) => {
+                        markdown.push_str(&format!("{number}. "));
+                        *number += 1;
+                    }
+                    _ => markdown.push_str("- "),
+                }
+            }
+            ("li", true) => push_line(&mut markdown),
+            ("a", false) => {
+                let href = attribute(attributes, "href")
+                    .filter(|href| !href.is_empty() && !href.starts_with('#'));
+
+                if href.is_some() {
+                    markdown.push('[');
+                }
+                links.push(href);
+            }
+            ("a", true) => {
+                if let Some(Some(href)) = links.pop() {
+                    markdown.push_str(&format!("]({href})"));
+                }
+            }
+            ("img", _) => {
+                if let Some(src) = attribute(attributes, "src") {
+                    let alt = attribute(attributes, "alt").unwrap_or_default();
+                    markdown.push_str(&format!("![{alt}]({src})"));
+                }
+            }
+            ("strong" | "b", _) => markdown.push_str("**"),
+            ("em" | "i", _) => markdown.push('*'),
+            ("code", _) if !preformatted => markdown.push('`'),
+            ("pre", false) => {
+                push_block(&mut markdown);
+                markdown.push_str("```\n");
+                preformatted = true;
+            }
+            ("pre", true) => {
+                push_line(&mut markdown);
+                markdown.push_str("```");
+                push_block(&mut markdown);
+                preformatted = false;
+            }
+            ("tr", false) => {
+                push_line(&mut markdown);
+                markdown.push('|');
+                cells = 0;
+                header_row = false;
+            }
+            ("tr", true) => {
+                if header_row && !header_done {
+                    markdown.push_str(&format!("\n|{}", " --- |".repeat(cells)));
+                    header_done = true;
+                }
+                push_line(&mut markdown);
+            }
+            ("td" | "th", false) => {
+                markdown.push(' ');
+                cells += 1;
+                header_row |= name == "th";
+            }
+            ("td" | "th", true) => markdown.push_str(" |"),
+            ("table", false) => {
+                push_block(&mut markdown);
+                header_done = false;
+            }
+            (name, _) if BLOCK_ELEMENTS.contains(&name) => push_block(&mut markdown),
+            _ => {}
+        }
+    }
+
+    tidy(&markdown)
+}
+
+/// Text of the document, with its entities decoded and, unless preformatted, its whitespace
+/// collapsed
+fn push_text(markdown: &mut String, text: &str, preformatted: bool) {
+    let text = decode_entities(text);
+
+    if preformatted {
+        markdown.push_str(&text);
+        return;
+    }
+
+    for (index, word) in text.split_whitespace().enumerate() {
+        let spaced = index > 0 || text.starts_with(char::is_whitespace);
+
+        if spaced && !markdown.is_empty() && !markdown.ends_with(char::is_whitespace) {
+            markdown.push(' ');
+        }
+
+        markdown.push_str(word);
+    }
+
+    if text.ends_with(char::is_whitespace) && !text.trim().is_empty() {
+        markdown.push(' ');
+    }
+}
+
+/// Start a new paragraph
+fn push_block(markdown: &mut String) {
+    if !markdown.is_empty() {
+        markdown.push_str("\n\n");
+    }
+}
+
+/// Start a new line
+fn push_line(markdown: &mut String) {
+    if !markdown.is_empty() && !markdown.ends_with('\n') {
+        markdown.push('\n');
+    }
+}
+
+/// Value of an attribute of an element
+fn attribute(attributes: &str, name: &str) -> Option<String> {
+    ATTRIBUTE
+        .captures_iter(attributes)
+        .find(|captures| captures[1].eq_ignore_ascii_case(name))
+        .and_then(|captures| captures.get(2).or(captures.get(3)).or(captures.get(4)))
+        .map(|value| decode_entities(value.as_str()))
+}
+
+/// Text with the named entities in common use and the numeric ones decoded
+fn decode_entities(text: &str) -> String {
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let entity = rest[1..]
+            .find(';')
+            .filter(|end| *end <= 10)
+            .map(|end| &rest[1..end + 1]);
+        let char = entity.and_then(|entity| match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            "nbsp" => Some(' '),
+            _ => entity
+                .strip_prefix("#x")
+                .or_else(|| entity.strip_prefix("#X"))
+                .map(|hex| u32::from_str_radix(hex, 16))
+                .or_else(|| entity.strip_prefix('#').map(str::parse))
+                .and_then(Result::ok)
+                .and_then(char::from_u32),
+        });
+
+        match (entity, char) {
+            (Some(entity), Some(char)) => {
+                decoded.push(char);
+                rest = &rest[entity.len() + 2..];
+            }
+            _ => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+
+    decoded.push_str(rest);
+
+    decoded
+}
+
+/// Markdown without trailing spaces on its lines nor more than one blank line in a row
+fn tidy(markdown: &str) -> String {
+    let mut tidy = String::with_capacity(markdown.len());
+    let mut blank = 0;
+
+    for line in markdown.lines() {
+        let line = line.trim_end();
+
+        if line.is_empty() {
+            blank += 1;
+            if blank > 1 {
+                continue;
+            }
+        } else {
+            blank = 0;
+        }
+
+        tidy.push_str(line);
+        tidy.push('\n');
+    }
+
+    tidy.trim().to_owned()
+}
@@ -0,0 +1,59 @@
+use std::{collections::HashMap, sync::Arc};
+
+use async_trait::async_trait;
+use rmcp::{ServiceError, model::Tool};
+use tracing::{Level, event};
+
+use crate::mcp::{McpServer, ToolCall, ToolOutput};
+
+/// MCP server as given by a toolset: only some of its tools, with their descriptions overridden
+pub(crate) struct FilteredMcp {
+    pub(crate) inner: Arc<dyn McpServer + Send>,
+    /// Tools kept, every tool of the server when not set
+    pub(crate) tools: Option<Vec<String>>,
+    /// Description given to the model for each tool, instead of the one of the server
+    pub(crate) descriptions: HashMap<String, String>,
+}
+
+impl FilteredMcp {
+    fn allows(&self, tool: &str) -> bool {
+        self.tools
+            .as_ref()
+            .is_none_or(|tools| tools.iter().any(|allowed| allowed == tool))
+    }
+}
+
+#[async_trait]
+impl McpServer for FilteredMcp {
+    async fn call(&self, call: ToolCall) -> Result<ToolOutput, ServiceError> {
+        if !self.allows(&call.name) {
+            event!(
+                Level::WARN,
+                "Tool {} isn't part of the toolset, not calling it",
+                call.name
+            );
+
+            return Ok(ToolOutput {
+                text: format!("Error: tool {} isn't available", call.name),
+                error: true,
+                ..Default::default()
+            });
+        }
+
+        self.inner.call(call).await
+    }
+
+    async fn list_tools(&self) -> Result<Vec<Tool>, ServiceError> {
+        let mut tools = self.inner.list_tools().await?;
+
+        tools.retain(|tool| self.allows(&tool.name));
+
+        for tool in tools.iter_mut() {
+            if let Some(description) = self.descriptions.get(tool.name.as_ref()) {
+                tool.description = description.clone().into();
+            }
+        }
+
+        Ok(tools)
+    }
+}
@@ -0,0 +1,186 @@
+use std::{
+    collections::HashMap,
+    io,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use async_trait::async_trait;
+use futures::{Sink, SinkExt, Stream, future::BoxFuture};
+use reqwest::header::{HeaderMap, HeaderName};
+use rmcp::{
+    ServiceError,
+    model::{ClientJsonRpcMessage, JsonObject, ServerJsonRpcMessage, Tool},
+    transport::io::{from_async_read, from_async_write},
+};
+use serde_json::{Value, to_value};
+use tokio::{
+    process::{ChildStdin, ChildStdout},
+    sync::OnceCell,
+};
+use tracing::{Level, event, instrument};
+
+use crate::mcp::{McpServer, ToolCall, ToolOutput};
+
+/// Argument carrying the forwarded headers of a call until it's written to a local MCP server
+pub(crate) const META_ARGUMENT: &str = "_meta";
+/// Sessions kept open with a remote MCP server, one per set of forwarded headers
+const MAX_SESSIONS: usize = 64;
+
+/// Headers of the requests forwarded to a MCP server
+#[derive(Debug)]
+pub(crate) struct Forwarding {
+    pub(crate) headers: Vec<HeaderName>,
+}
+
+impl Forwarding {
+    /// Forwarded headers present in a request
+    pub(crate) fn select(&self, headers: &HeaderMap) -> HeaderMap {
+        self.headers
+            .iter()
+            .filter_map(|name| Some((name.clone(), headers.get(name)?.clone())))
+            .collect()
+    }
+
+    /// Forwarded headers present in a request, as the `_meta` of a tool call
+    pub(crate) fn meta(&self, headers: &HeaderMap) -> Option<JsonObject> {
+        let meta: JsonObject = self
+            .select(headers)
+            .iter()
+            .filter_map(|(name, value)| Some((name.to_string(), Value::from(value.to_str().ok()?))))
+            .collect();
+
+        (!meta.is_empty()).then_some(meta)
+    }
+}
+
+/// Transport of a local MCP server moving the forwarded headers of the tool calls from their
+/// arguments to their `_meta`, which the MCP client doesn't let set
+pub(crate) fn meta_transport(
+    output: ChildStdout,
+    input: ChildStdin,
+) -> (
+    impl Sink<ClientJsonRpcMessage, Error = io::Error> + Send + 'static,
+    impl Stream<Item = ServerJsonRpcMessage> + Send + 'static,
+) {
+    let sink =
+        from_async_write::<Value, _>(input).with(|message: ClientJsonRpcMessage| {
+            futures::future::ready(to_value(message).map_err(io::Error::other).map(
+                |mut message| {
+                    if let Some(params) = message.get_mut("params").and_then(Value::as_object_mut)
+                        && let Some(meta) = params
+                            .get_mut("arguments")
+                            .and_then(Value::as_object_mut)
+                            .and_then(|arguments| arguments.remove(META_ARGUMENT))
+                    {
+                        params.insert(String::from(META_ARGUMENT), meta);
+                    }
+
+                    message
+                },
+            ))
+        });
+
+    (Box::pin(sink), from_async_read(output))
+}
+
+/// Start of a session with a remote MCP server, sending the given headers with every request
+pub(crate) type Connect = Arc<
+    dyn Fn(HeaderMap) -> BoxFuture<'static, Result<Arc<dyn McpServer + Send>, String>>
+        + Send
+        + Sync,
+>;
+
+/// Forwarded headers of a session, by name and value
+type SessionKey = Vec<(String, Vec<u8>)>;
+
+struct Session {
+    server: Arc<OnceCell<Arc<dyn McpServer + Send>>>,
+    used_at: Instant,
+}
+
+/// Remote MCP server given the forwarded headers of the requests, with a session per set of
+/// values since the headers of a session can't change
+pub(crate) struct ForwardingMcp {
+    pub(crate) name: String,
+    pub(crate) forwarding: Forwarding,
+    /// Session used by the requests without any of the forwarded headers
+    pub(crate) default: Arc<dyn McpServer + Send>,
+    pub(crate) connect: Connect,
+    sessions: Mutex<HashMap<SessionKey, Session>>,
+}
+
+impl ForwardingMcp {
+    pub(crate) fn new(
+        name: String,
+        forwarding: Forwarding,
+        default: Arc<dyn McpServer + Send>,
+        connect: Connect,
+    ) -> ForwardingMcp {
+        ForwardingMcp {
+            name,
+            forwarding,
+            default,
+            connect,
+            sessions: Mutex::new(HashMap::new()),
+        }
+    }
+
+    async fn session(&self, headers: HeaderMap) -> Result<Arc<dyn McpServer + Send>, ServiceError> {
+        let key: SessionKey = headers
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.as_bytes().to_vec()))
+            .collect();
+
+        let server = {
+            let mut sessions = self.sessions.lock().expect("Session lock poisoned");
+
+            if !sessions.contains_key(&key)
+                && sessions.len() >= MAX_SESSIONS
+                && let Some(oldest) = sessions
+                    .iter()
+                    .min_by_key(|(_, session)| session.used_at)
+                    .map(|(key, _)| key.clone())
+            {
+                sessions.remove(&oldest);
+            }
+
+            let session = sessions.entry(key).or_insert_with(|| Session {
+                server: Arc::new(OnceCell::new()),
+                used_at: Instant::now(),
+            });
+
+            session.used_at = Instant::now();
+
+            Arc::clone(&session.server)
+        };
+
+        server
+            .get_or_try_init(|| {
+                event!(Level::INFO, "Opening new session with {}", self.name);
+
+                (self.connect)(headers)
+            })
+            .await
+            .cloned()
+            .map_err(|error| ServiceError::Transport(io::Error::other(error)))
+    }
+}
+
+#[async_trait]
+impl McpServer for ForwardingMcp {
+    #[instrument(skip_all, fields(mcp = self.name, tool = call.name))]
+    async fn call(&self, call: ToolCall) -> Result<ToolOutput, ServiceError> {
+        let headers = self.forwarding.select(&call.headers);
+
+        if headers.is_empty() {
+            return self.default.call(call).await;
+        }
+
+        self.session(headers).await?.call(call).await
+    }
+
+    async fn list_tools(&self) -> Result<Vec<Tool>, ServiceError> {
+        self.default.list_tools().await
+    }
+}
@@ -0,0 +1,419 @@
+use std::{
+    collections::HashMap,
+    io,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use futures::{Sink, SinkExt, Stream, StreamExt, channel::mpsc::unbounded, stream};
+use reqwest::{
+    Client as HttpClient, Response, StatusCode,
+    header::{ACCEPT, CONTENT_TYPE},
+};
+use rmcp::model::{ClientJsonRpcMessage, ServerJsonRpcMessage};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use sse_stream::SseStream;
+use tokio::{io::AsyncWriteExt, sync::mpsc};
+use tracing::{Level, event};
+
+/// Header holding the session given by the server at initialization
+const SESSION_HEADER: &str = "mcp-session-id";
+const EVENT_STREAM: &str = "text/event-stream";
+/// Responses the client accepts to the messages it posts
+const ACCEPTED: &str = "application/json, text/event-stream";
+const INITIALIZE: &str = "initialize";
+const INITIALIZED: &str = "notifications/initialized";
+
+/// Session given by a MCP server, with its answer to the initialization to give it again to the
+/// connections resuming the session
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct Session {
+    id: String,
+    initialize: Value,
+}
+
+/// Sessions of a MCP server reached over streamable HTTP, by URL, resumed by the next connections
+/// and saved to a file to be resumed after a restart
+#[derive(Debug, Default)]
+pub(crate) struct HttpSessions {
+    path: Option<String>,
+    sessions: Mutex<HashMap<String, Session>>,
+}
+
+impl HttpSessions {
+    /// Sessions saved to `path`, starting with the ones it has
+    pub(crate) async fn new(path: Option<String>) -> HttpSessions {
+        let mut sessions = HashMap::new();
+
+        if let Some(path) = &path {
+            match load(path).await {
+                Ok(saved) => {
+                    event!(Level::INFO, "Loaded {} session(s) from {path}", saved.len());
+
+                    sessions = saved;
+                }
+                Err(error) if error.kind() == io::ErrorKind::NotFound => (),
+                Err(error) => event!(Level::WARN, "Couldn't load sessions from {path}: {error}"),
+            }
+        }
+
+        HttpSessions {
+            path,
+            sessions: Mutex::new(sessions),
+        }
+    }
+
+    fn get(&self, url: &str) -> Option<Session> {
+        self.sessions
+            .lock()
+            .expect("Sessions lock poisoned")
+            .get(url)
+            .cloned()
+    }
+
+    /// Keep the session of a server, or forget it when it's over
+    async fn set(&self, url: &str, session: Option<Session>) {
+        let sessions = {
+            let mut sessions = self.sessions.lock().expect("Sessions lock poisoned");
+
+            match session {
+                Some(session) => sessions.insert(url.to_owned(), session),
+                None => sessions.remove(url),
+            };
+
+            sessions.clone()
+        };
+
+        if let Some(path) = &self.path
+            && let Err(error) = save(path, &sessions).await
+        {
+            event!(Level::WARN, "Couldn't save sessions to {path}: {error}");
+        }
+    }
+}
+
+async fn load(path: &str) -> io::Result<HashMap<String, Session>> {
+    let content = tokio::fs::read(path).await?;
+
+    serde_json::from_slice(&content).map_err(io::Error::other)
+}
+
+/// Write the sessions to a file only readable by the current user, as they let act on the servers
+async fn save(path: &str, sessions: &HashMap<String, Session>) -> io::Result<()> {
+    let content = serde_json::to_vec(sessions).map_err(io::Error::other)?;
+
+    let mut options = tokio::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+
+    #[cfg(unix)]
+    options.mode(0o600);
+
+    let temporary = format!("{path}.tmp");
+
+    let mut file = options.open(&temporary).await?;
+    file.write_all(&content).await?;
+    file.flush().await?;
+
+    tokio::fs::rename(&temporary, Path::new(path)).await
+}
+
+/// Connection to a MCP server over streamable HTTP, every message being posted to its URL and
+/// answered with JSON or a stream of events. The session of the server is resumed when there's
+/// one, the initialization being answered with the one of the session
+pub(crate) fn transport(
+    client: HttpClient,
+    url: String,
+    sessions: Option<Arc<HttpSessions>>,
+) -> (
+    impl Sink<ClientJsonRpcMessage, Error = io::Error> + Send + 'static,
+    impl Stream<Item = ServerJsonRpcMessage> + Send + 'static,
+) {
+    let (outgoing, mut messages) = unbounded::<ClientJsonRpcMessage>();
+    let (incoming, received) = mpsc::unbounded_channel();
+
+    let resumed = sessions.as_ref().and_then(|sessions| sessions.get(&url));
+
+    let connection = Arc::new(Connection {
+        client,
+        session: Mutex::new(resumed.as_ref().map(|session| session.id.clone())),
+        url,
+        sessions,
+        incoming,
+    });
+
+    tokio::spawn(async move {
+        while let Some(message) = messages.next().await {
+            let message = match serde_json::to_value(message) {
+                Ok(message) => message,
+                Err(error) => {
+                    event!(Level::ERROR, "Couldn't serialize message: {error}");
+                    continue;
+                }
+            };
+
+            let method = message.get("method").and_then(Value::as_str);
+            let (initialize, initialized) =
+                (method == Some(INITIALIZE), method == Some(INITIALIZED));
+
+            if initialize && let Some(resumed) = &resumed {
+                event!(
+                    Level::INFO,
+                    "Resuming session of MCP server {}",
+                    connection.url
+                );
+
+                connection.deliver(json!({
+                    "jsonrpc": "2.0",
+                    "id": message["id"],
+                    "result": resumed.initialize,
+                }));
+
+                continue;
+            }
+
+            if initialized && resumed.is_some() {
+                // The server already knows the client is initialized when resuming its session
+            } else if message.get("id").is_none() {
+                // Notifications are quickly accepted, and must come before the next requests
+                Arc::clone(&connection).post(message).await;
+            } else {
+                tokio::spawn(Arc::clone(&connection).post(message));
+            }
+
+            if initialized {
+                tokio::spawn(Arc::clone(&connection).listen());
+            }
+        }
+    });
+
+    let received = stream::unfold(received, |mut received| async move {
+        match received.recv().await {
+            Some(Some(message)) => Some((message, received)),
+            _ => None,
+        }
+    });
+
+    (outgoing.sink_map_err(io::Error::other), received)
+}
+
+struct Connection {
+    client: HttpClient,
+    url: String,
+    /// Session of the connection, once the server gave one
+    session: Mutex<Option<String>>,
+    sessions: Option<Arc<HttpSessions>>,
+    /// Messages of the server, the connection being closed with `None`
+    incoming: mpsc::UnboundedSender<Option<ServerJsonRpcMessage>>,
+}
+
+impl Connection {
+    fn session(&self) -> Option<String> {
+        self.session.lock().expect("Session lock poisoned").clone()
+    }
+
+    fn deliver(&self, message: Value) {
+        match serde_json::from_value(message) {
+            Ok(message) => {
+                let _ = self.incoming.send(Some(message));
+            }
+            Err(error) => event!(
+                Level::ERROR,
+                "Invalid message from MCP server {}: {error}",
+                self.url
+            ),
+        }
+    }
+
+    /// Close the connection, a new one being made for the next calls
+    fn close(&self) {
+        let _ = self.incoming.send(None);
+    }
+
+    /// Post a message to the server and deliver its answer
+    async fn post(self: Arc<Self>, message: Value) {
+        let initialize = message.get("method").and_then(Value::as_str) == Some(INITIALIZE);
+        let session = self.session();
+
+        let mut request = self
+            .client
+            .post(&self.url)
+            .header(ACCEPT, ACCEPTED)
+            .json(&message);
+
+        if let Some(session) = &session {
+            request = request.header(SESSION_HEADER, session);
+        }
+
+        let response = match request.send().await {
+            Ok(response) => response,
+            Err(error) => {
+                event!(
+                    Level::ERROR,
+                    "Couldn't reach MCP server {}: {error}",
+                    self.url
+                );
+
+                return self.close();
+            }
+        };
+
+        if response.status() == StatusCode::NOT_FOUND && session.is_some() {
+            event!(
+                Level::WARN,
+                "Session of MCP server {} expired, starting a new one",
+                self.url
+            );
+
+            if let Some(sessions) = &self.sessions {
+                sessions.set(&self.url, None).await;
+            }
+
+            return self.close();
+        }
+
+        if !response.status().is_success() {
+            event!(
+                Level::ERROR,
+                "MCP server {} answered with status {}",
+                self.url,
+                response.status()
+            );
+
+            return self.close();
+        }
+
+        if initialize
+            && let Some(session) = response
+                .headers()
+                .get(SESSION_HEADER)
+                .and_then(|session| session.to_str().ok())
+        {
+            *self.session.lock().expect("Session lock poisoned") = Some(session.to_owned());
+        }
+
+        if response.status() == StatusCode::ACCEPTED {
+            return;
+        }
+
+        let answers = if is_event_stream(&response) {
+            let mut answers = Vec::new();
+            let mut events = SseStream::from_byte_stream(response.bytes_stream());
+
+            while let Some(event) = events.next().await {
+                match event {
+                    Ok(event) => answers.extend(event.data.and_then(|data| parse(&data))),
+                    Err(error) => {
+                        event!(
+                            Level::ERROR,
+                            "Stream of MCP server {} failed: {error}",
+                            self.url
+                        );
+
+                        return self.close();
+                    }
+                }
+            }
+
+            answers
+        } else {
+            match response.text().await {
+                Ok(text) => parse(&text).into_iter().collect(),
+                Err(error) => {
+                    event!(
+                        Level::ERROR,
+                        "Couldn't read answer of MCP server {}: {error}",
+                        self.url
+                    );
+
+                    return self.close();
+                }
+            }
+        };
+
+        for answer in answers {
+            for answer in batch(answer) {
+                if initialize
+                    && answer.get("id") == message.get("id")
+                    && let Some(result) = answer.get("result")
+                    && let Some(sessions) = &self.sessions
+                    && let Some(id) = self.session()
+                {
+                    sessions
+                        .set(
+                            &self.url,
+                            Some(Session {
+                                id,
+                                initialize: result.clone(),
+                            }),
+                        )
+                        .await;
+                }
+
+                self.deliver(answer);
+            }
+        }
+    }
+
+    /// Deliver the messages the server sends on its own (e.g. notifications), for servers
+    /// streaming them
+    async fn listen(self: Arc<Self>) {
+        let mut request = self.client.get(&self.url).header(ACCEPT, EVENT_STREAM);
+
+        if let Some(session) = self.session() {
+            request = request.header(SESSION_HEADER, session);
+        }
+
+        let response = match request.send().await {
+            Ok(response) if response.status().is_success() && is_event_stream(&response) => {
+                response
+            }
+            _ => {
+                event!(
+                    Level::DEBUG,
+                    "MCP server {} doesn't stream messages of its own",
+                    self.url
+                );
+
+                return;
+            }
+        };
+
+        let mut events = SseStream::from_byte_stream(response.bytes_stream());
+
+        loop {
+            tokio::select! {
+                _ = self.incoming.closed() => return,
+                event = events.next() => match event {
+                    Some(Ok(event)) => {
+                        for message in event.data.and_then(|data| parse(&data)).into_iter().flat_map(batch) {
+                            self.deliver(message);
+                        }
+                    }
+                    _ => return,
+                },
+            }
+        }
+    }
+}
+
+fn is_event_stream(response: &Response) -> bool {
+    response
+        .headers()
+        .get(CONTENT_TYPE)
+        .is_some_and(|content_type| content_type.as_bytes().starts_with(EVENT_STREAM.as_bytes()))
+}
+
+fn parse(data: &str) -> Option<Value> {
+    serde_json::from_str(data)
+        .inspect_err(|error| event!(Level::ERROR, "Invalid message from MCP server: {error}"))
+        .ok()
+}
+
+/// Messages of a JSON-RPC batch, or the message itself
+fn batch(message: Value) -> Vec<Value> {
+    match message {
+        Value::Array(messages) => messages,
+        message => vec![message],
+    }
+}
@@ -0,0 +1,119 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use rmcp::{
+    Error as McpError, RoleClient, RoleServer, ServerHandler, ServiceError, ServiceExt,
+    model::{
+        CallToolRequestParam, CallToolResult, Content, JsonObject, ListToolsResult,
+        PaginatedRequestParam, ServerCapabilities, ServerInfo, Tool,
+    },
+    service::{RequestContext, RunningService},
+};
+use tokio::io::duplex;
+use tracing::{Level, event, instrument};
+
+use crate::mcp::{McpClient, McpServer, ToolCall, ToolOutput, call_tool, list_tools};
+
+/// Bytes buffered by the pipe between the client and the server
+const PIPE_BUFFER: usize = 64 * 1024;
+
+/// Handler of a tool, answering the text of its result or of the error it reports
+pub(crate) type ToolHandler =
+    Arc<dyn Fn(Option<JsonObject>) -> Result<String, String> + Send + Sync>;
+
+/// Server side, calling the handlers of its tools
+#[derive(Clone)]
+struct InProcessServer {
+    tools: Arc<Vec<(Tool, ToolHandler)>>,
+}
+
+impl ServerHandler for InProcessServer {
+    fn list_tools(
+        &self,
+        _request: PaginatedRequestParam,
+        _context: RequestContext<RoleServer>,
+    ) -> impl Future<Output = Result<ListToolsResult, McpError>> + Send + '_ {
+        std::future::ready(Ok(ListToolsResult {
+            next_cursor: None,
+            tools: self.tools.iter().map(|(tool, _)| tool.clone()).collect(),
+        }))
+    }
+
+    fn call_tool(
+        &self,
+        request: CallToolRequestParam,
+        _context: RequestContext<RoleServer>,
+    ) -> impl Future<Output = Result<CallToolResult, McpError>> + Send + '_ {
+        let Some((_, handler)) = self
+            .tools
+            .iter()
+            .find(|(tool, _)| tool.name == request.name)
+        else {
+            return std::future::ready(Err(McpError::invalid_params(
+                format!("Unknown tool {}", request.name),
+                None,
+            )));
+        };
+
+        std::future::ready(Ok(match handler(request.arguments) {
+            Ok(text) => CallToolResult::success(vec![Content::text(text)]),
+            Err(text) => CallToolResult::error(vec![Content::text(text)]),
+        }))
+    }
+
+    fn get_info(&self) -> ServerInfo {
+        ServerInfo {
+            capabilities: ServerCapabilities::builder().enable_tools().build(),
+            ..Default::default()
+        }
+    }
+}
+
+/// MCP server running in the process of the manager, the protocol going through an in-memory pipe
+#[derive(Debug)]
+pub(crate) struct InProcessMcp {
+    service: RunningService<RoleClient, McpClient>,
+}
+
+impl InProcessMcp {
+    pub(crate) async fn start(
+        name: String,
+        tools: Vec<(Tool, ToolHandler)>,
+    ) -> Result<InProcessMcp, String> {
+        let (client, server) = duplex(PIPE_BUFFER);
+
+        let handler = InProcessServer {
+            tools: Arc::new(tools),
+        };
+
+        // The server only answers the initialization once the client sends it
+        tokio::spawn(async move {
+            match handler.serve(server).await {
+                Ok(service) => {
+                    let _ = service.waiting().await;
+                }
+                Err(error) => event!(Level::ERROR, "In-process MCP server failed: {error}"),
+            }
+        });
+
+        let service = McpClient::new(name.clone())
+            .serve(client)
+            .await
+            .map_err(|error| format!("Couldn't connect to MCP server {name}: {error}"))?;
+
+        Ok(InProcessMcp { service })
+    }
+}
+
+#[async_trait]
+impl McpServer for InProcessMcp {
+    #[instrument(skip_all, fields(tool = call.name, id = call.id))]
+    async fn call(&self, call: ToolCall) -> Result<ToolOutput, ServiceError> {
+        call_tool(&self.service, call).await
+    }
+
+    #[instrument(skip(self))]
+    async fn list_tools(&self) -> Result<Vec<Tool>, ServiceError> {
+        list_tools(&self.service).await
+    }
+}
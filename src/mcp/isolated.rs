@@ -0,0 +1,143 @@
+use std::{
+    io,
+    sync::{Arc, mpsc},
+    thread,
+};
+
+use async_trait::async_trait;
+use rmcp::{ServiceError, model::Tool};
+use tokio::{
+    runtime::{Builder, Handle},
+    sync::Semaphore,
+    task::JoinError,
+};
+use tracing::{Level, event};
+
+use crate::mcp::{McpServer, ToolCall, ToolOutput};
+
+/// Runtime dedicated to the MCP servers, so tools blocking or flooding their output don't hold up
+/// the one serving the requests
+#[derive(Debug)]
+pub(crate) struct McpRuntime {
+    handle: Handle,
+    /// Tool calls allowed to run at the same time
+    calls: Option<Semaphore>,
+    /// Bytes of a tool output given back, the rest being cut
+    max_output_bytes: Option<usize>,
+}
+
+impl McpRuntime {
+    pub(crate) fn new(
+        threads: Option<usize>,
+        max_concurrent_calls: Option<usize>,
+        max_output_bytes: Option<usize>,
+    ) -> McpRuntime {
+        let (sender, receiver) = mpsc::channel();
+
+        // The runtime lives on its own thread for the whole life of the process
+        thread::Builder::new()
+            .name(String::from("mcp-runtime"))
+            .spawn(move || {
+                let mut builder = Builder::new_multi_thread();
+
+                if let Some(threads) = threads {
+                    builder.worker_threads(threads);
+                }
+
+                let runtime = builder
+                    .thread_name("mcp-worker")
+                    .enable_all()
+                    .build()
+                    .expect("Couldn't start MCP runtime");
+
+                sender
+                    .send(runtime.handle().clone())
+                    .expect("Couldn't start MCP runtime");
+
+                runtime.block_on(std::future::pending::<()>());
+            })
+            .expect("Couldn't start MCP runtime");
+
+        McpRuntime {
+            handle: receiver.recv().expect("Couldn't start MCP runtime"),
+            calls: max_concurrent_calls.map(Semaphore::new),
+            max_output_bytes,
+        }
+    }
+
+    /// Run a future on the MCP runtime, e.g. the startup of a server so its I/O is handled there
+    pub(crate) async fn run<F>(&self, future: F) -> Result<F::Output, JoinError>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        self.handle.spawn(future).await
+    }
+
+    fn truncate(&self, mut output: ToolOutput, tool: &str) -> ToolOutput {
+        let Some(max) = self.max_output_bytes else {
+            return output;
+        };
+
+        if output.text.len() > max {
+            event!(
+                Level::WARN,
+                "Output of {tool} is {} bytes, cutting it to {max}",
+                output.text.len()
+            );
+
+            let mut end = max;
+
+            while !output.text.is_char_boundary(end) {
+                end -= 1;
+            }
+
+            output.text.truncate(end);
+            output
+                .text
+                .push_str(&format!("\n[Output cut at {max} bytes]"));
+        }
+
+        output
+    }
+}
+
+/// MCP server whose calls run on the MCP runtime
+pub(crate) struct IsolatedMcp {
+    pub(crate) inner: Arc<dyn McpServer + Send>,
+    pub(crate) runtime: Arc<McpRuntime>,
+}
+
+fn join_error(error: JoinError) -> ServiceError {
+    ServiceError::Transport(io::Error::other(error))
+}
+
+#[async_trait]
+impl McpServer for IsolatedMcp {
+    async fn call(&self, call: ToolCall) -> Result<ToolOutput, ServiceError> {
+        let _permit = match &self.runtime.calls {
+            Some(calls) => Some(calls.acquire().await.expect("MCP call semaphore closed")),
+            None => None,
+        };
+
+        let tool = call.name.clone();
+        let inner = Arc::clone(&self.inner);
+
+        let output = self
+            .runtime
+            .run(async move { inner.call(call).await })
+            .await
+            .map_err(join_error)??;
+
+        Ok(self.runtime.truncate(output, &tool))
+    }
+
+    async fn list_tools(&self) -> Result<Vec<Tool>, ServiceError> {
+        let inner = Arc::clone(&self.inner);
+
+        self.runtime
+            .run(async move { inner.list_tools().await })
+            .await
+            .map_err(join_error)?
+    }
+}
@@ -1,60 +1,44 @@
 use async_trait::async_trait;
-use rmcp::{
-    RoleClient, ServiceError,
-    model::{CallToolRequestParam, RawContent, Tool},
-    service::RunningService,
+use rmcp::{RoleClient, ServiceError, model::Tool, service::RunningService};
+use serde_json::Value;
+use tracing::instrument;
+
+use crate::mcp::{
+    McpClient, McpServer, ToolCall, ToolOutput, call_tool,
+    forwarding::{Forwarding, META_ARGUMENT},
+    list_tools,
+    process::McpProcess,
 };
-use tracing::{Level, event, instrument};
-
-use crate::mcp::{McpServer, ToolCall};
 
 #[derive(Debug)]
 pub(crate) struct LocalMcp {
-    pub(crate) command: RunningService<RoleClient, ()>,
+    pub(crate) command: RunningService<RoleClient, McpClient>,
+    /// Process of the server, only kept to be stopped along with it
+    #[allow(dead_code)]
+    pub(crate) process: McpProcess,
+    /// Headers given in the `_meta` of the tool calls, its transport taking them from the arguments
+    pub(crate) forwarding: Option<Forwarding>,
 }
 
 #[async_trait]
 impl McpServer for LocalMcp {
-    #[instrument(skip(self))]
-    async fn call(&self, call: ToolCall) -> Result<String, ServiceError> {
-        let result = self
-            .command
-            .call_tool(CallToolRequestParam {
-                name: call.name.into(),
-                arguments: call.arguments,
-            })
-            .await?;
-
-        if let Some(error) = result.is_error
-            && error
+    #[instrument(skip_all, fields(tool = call.name, id = call.id))]
+    async fn call(&self, mut call: ToolCall) -> Result<ToolOutput, ServiceError> {
+        if let Some(meta) = self
+            .forwarding
+            .as_ref()
+            .and_then(|forwarding| forwarding.meta(&call.headers))
         {
-            event!(Level::ERROR, "{result:?}");
-        } else {
-            event!(Level::INFO, "{result:?}");
-        }
-
-        // FIXME: Handle multiple content responses
-        if result.content.len() != 1 {
-            event!(
-                Level::ERROR,
-                "Unknown MCP response needs to be handled: {:?}",
-                result.content
-            );
-        }
-
-        // FIXME: Handle annotations
-        if result.content[0].annotations.is_some() {
-            event!(Level::WARN, "Annotations not handled");
+            call.arguments
+                .get_or_insert_default()
+                .insert(String::from(META_ARGUMENT), Value::Object(meta));
         }
 
-        Ok(match result.content[0].clone().raw {
-            RawContent::Text(text) => text.text,
-            _ => unimplemented!(),
-        })
+        call_tool(&self.command, call).await
     }
 
     #[instrument(skip(self))]
     async fn list_tools(&self) -> Result<Vec<Tool>, ServiceError> {
-        Ok(self.command.list_all_tools().await?)
+        list_tools(&self.command).await
     }
 }
@@ -1,15 +1,44 @@
+use std::sync::{Arc, Mutex};
+
 use async_trait::async_trait;
+use reqwest::header::HeaderMap;
 use rmcp::{
-    ServiceError,
-    model::{JsonObject, Tool},
+    ClientHandler, Error as McpError, Peer, RoleClient, ServiceError,
+    model::{
+        CallToolRequestParam, ClientInfo, JsonObject, ListRootsResult, LoggingLevel,
+        LoggingMessageNotificationParam, RawContent, Role as AudienceRole, Root, RootsCapabilities,
+        ServerInfo, Tool,
+    },
+    service::{RequestContext, RunningService},
 };
 use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use tracing::{Level, event};
+
+use crate::logging::log_payloads;
 
+pub(crate) mod cache;
+pub(crate) mod converting;
+pub(crate) mod filtered;
+pub(crate) mod forwarding;
+pub(crate) mod http;
+#[cfg(feature = "test-util")]
+pub(crate) mod in_process;
+pub(crate) mod isolated;
 pub(crate) mod local;
+pub mod process;
+pub(crate) mod remote;
+pub(crate) mod replicated;
+pub(crate) mod restarting;
+pub(crate) mod retrying;
+pub(crate) mod starting;
+
+/// Versions of the protocol whose tool calls the client understands
+const PROTOCOL_VERSIONS: [&str; 2] = ["2024-11-05", "2025-03-26"];
 
 #[async_trait]
 pub(crate) trait McpServer: Sync {
-    async fn call(&self, call: ToolCall) -> Result<String, ServiceError>;
+    async fn call(&self, call: ToolCall) -> Result<ToolOutput, ServiceError>;
     async fn list_tools(&self) -> Result<Vec<Tool>, ServiceError>;
 }
 
@@ -18,4 +47,300 @@ pub struct ToolCall {
     pub(crate) name: String,
     pub(crate) id: String,
     pub(crate) arguments: Option<JsonObject>,
+    /// Signature of the reasoning behind the call, to give back to the model (Gemini)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) thought_signature: Option<String>,
+    /// Why the arguments given by the model couldn't be parsed, the tool not being called
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) invalid_arguments: Option<String>,
+    /// Headers of the request the call was made in, for the MCP servers they're forwarded to
+    #[serde(skip)]
+    pub(crate) headers: Arc<HeaderMap>,
+}
+
+/// Result of a tool call, as given to the model
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct ToolOutput {
+    /// Text of the contents intended for the model
+    pub(crate) text: String,
+    /// Annotations of the contents, including the ones left out
+    pub(crate) annotations: Vec<ContentAnnotations>,
+    /// Whether the tool reported an error
+    #[serde(default)]
+    pub(crate) error: bool,
+}
+
+impl From<String> for ToolOutput {
+    fn from(text: String) -> Self {
+        ToolOutput {
+            text,
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ContentAnnotations {
+    /// Position of the content in the result of the tool
+    index: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    audience: Option<Vec<AudienceRole>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    priority: Option<f32>,
+    /// Whether the content was given to the model
+    forwarded: bool,
+}
+
+/// Client side of the connection to a MCP server, which lists the tools again after the server
+/// notifies they changed
+#[derive(Debug, Default)]
+pub(crate) struct McpClient {
+    name: String,
+    tools: Arc<Mutex<ToolList>>,
+    /// Directories the server can work in, only advertised when there are some
+    roots: Vec<Root>,
+}
+
+#[derive(Debug, Default)]
+struct ToolList {
+    /// Number of changes notified, to not keep a list obtained before the last one
+    changes: u64,
+    tools: Option<Vec<Tool>>,
+}
+
+impl McpClient {
+    pub(crate) fn new(name: String) -> McpClient {
+        McpClient {
+            name,
+            ..Default::default()
+        }
+    }
+
+    pub(crate) fn with_roots(mut self, roots: Vec<Root>) -> McpClient {
+        self.roots = roots;
+        self
+    }
+}
+
+impl ClientHandler for McpClient {
+    fn on_tool_list_changed(&self) -> impl Future<Output = ()> + Send + '_ {
+        event!(Level::INFO, "Tools of MCP server {} changed", self.name);
+
+        let mut list = self.tools.lock().expect("Tool list lock poisoned");
+        list.changes += 1;
+        list.tools = None;
+
+        std::future::ready(())
+    }
+
+    fn on_logging_message(
+        &self,
+        params: LoggingMessageNotificationParam,
+    ) -> impl Future<Output = ()> + Send + '_ {
+        let message = match params.data {
+            Value::String(message) => message,
+            data => data.to_string(),
+        };
+        let message = match params.logger {
+            Some(logger) => format!("{logger}: {message}"),
+            None => message,
+        };
+
+        // Levels of the events must be known at compile time
+        match params.level {
+            LoggingLevel::Debug => {
+                event!(Level::DEBUG, mcp = self.name, "{message}")
+            }
+            LoggingLevel::Info | LoggingLevel::Notice => {
+                event!(Level::INFO, mcp = self.name, "{message}")
+            }
+            LoggingLevel::Warning => {
+                event!(Level::WARN, mcp = self.name, "{message}")
+            }
+            LoggingLevel::Error
+            | LoggingLevel::Critical
+            | LoggingLevel::Alert
+            | LoggingLevel::Emergency => {
+                event!(Level::ERROR, mcp = self.name, "{message}")
+            }
+        }
+
+        std::future::ready(())
+    }
+
+    fn get_peer(&self) -> Option<Peer<RoleClient>> {
+        None
+    }
+
+    fn set_peer(&mut self, peer: Peer<RoleClient>) {
+        drop(peer);
+    }
+
+    fn list_roots(
+        &self,
+        _context: RequestContext<RoleClient>,
+    ) -> impl Future<Output = Result<ListRootsResult, McpError>> + Send + '_ {
+        event!(Level::DEBUG, "MCP server {} listed its roots", self.name);
+
+        std::future::ready(Ok(ListRootsResult {
+            roots: self.roots.clone(),
+        }))
+    }
+
+    fn get_info(&self) -> ClientInfo {
+        let mut info = ClientInfo::default();
+
+        if !self.roots.is_empty() {
+            // The roots are fixed by the configuration, they never change
+            info.capabilities.roots = Some(RootsCapabilities {
+                list_changed: Some(false),
+            });
+        }
+
+        info
+    }
+}
+
+/// Check a MCP server can be used once started, failing when `strict` instead of warning about
+/// the capabilities and protocol version it declared
+pub(crate) fn negotiate(name: &str, info: &ServerInfo, strict: bool) -> Result<(), String> {
+    let version = match json!(info.protocol_version) {
+        Value::String(version) => version,
+        version => version.to_string(),
+    };
+
+    event!(
+        Level::INFO,
+        "MCP server {name} started: {} {} (protocol {version})",
+        info.server_info.name,
+        info.server_info.version
+    );
+
+    let mut problems = Vec::new();
+
+    if info.capabilities.tools.is_none() {
+        problems.push(String::from("doesn't declare the tools capability"));
+    }
+
+    if !PROTOCOL_VERSIONS.contains(&version.as_str()) {
+        problems.push(format!("speaks the unsupported protocol version {version}"));
+    }
+
+    if problems.is_empty() {
+        return Ok(());
+    }
+
+    let message = format!("MCP server {name} {}", problems.join(" and "));
+
+    if strict {
+        return Err(message);
+    }
+
+    event!(Level::WARN, "{message}");
+
+    Ok(())
+}
+
+/// Tools of a running MCP service, only asked to the server until they change
+async fn list_tools(
+    service: &RunningService<RoleClient, McpClient>,
+) -> Result<Vec<Tool>, ServiceError> {
+    let changes = {
+        let list = service
+            .service()
+            .tools
+            .lock()
+            .expect("Tool list lock poisoned");
+
+        if let Some(tools) = &list.tools {
+            return Ok(tools.clone());
+        }
+
+        list.changes
+    };
+
+    let tools = service.list_all_tools().await?;
+
+    let mut list = service
+        .service()
+        .tools
+        .lock()
+        .expect("Tool list lock poisoned");
+
+    if list.changes == changes {
+        list.tools = Some(tools.clone());
+    }
+
+    Ok(tools)
+}
+
+/// Call a tool on a running MCP service and extract the text of the contents intended for the model
+async fn call_tool(
+    service: &RunningService<RoleClient, McpClient>,
+    call: ToolCall,
+) -> Result<ToolOutput, ServiceError> {
+    let name = call.name.clone();
+
+    let result = service
+        .call_tool(CallToolRequestParam {
+            name: call.name.into(),
+            arguments: call.arguments,
+        })
+        .await?;
+
+    let error = result.is_error.unwrap_or_default();
+
+    if error {
+        event!(Level::ERROR, "Tool {name} failed");
+    } else {
+        event!(Level::INFO, "Tool {name} succeeded");
+    }
+
+    if log_payloads() {
+        event!(Level::DEBUG, "{result:?}");
+    }
+
+    let mut texts = Vec::new();
+    let mut annotations = Vec::new();
+
+    for (index, content) in result.content.into_iter().enumerate() {
+        // Content without audience is meant for everyone
+        let forwarded = content
+            .annotations
+            .as_ref()
+            .and_then(|annotations| annotations.audience.as_ref())
+            .is_none_or(|audience| audience.contains(&AudienceRole::Assistant));
+
+        if let Some(content_annotations) = content.annotations {
+            annotations.push(ContentAnnotations {
+                index,
+                audience: content_annotations.audience,
+                priority: content_annotations.priority,
+                forwarded,
+            });
+        }
+
+        if !forwarded {
+            event!(
+                Level::DEBUG,
+                "Leaving out content {index} of {name}, not meant for the model"
+            );
+            continue;
+        }
+
+        match content.raw {
+            RawContent::Text(text) => texts.push(text.text),
+            // FIXME: Handle images and resources
+            _ => event!(
+                Level::WARN,
+                "Content {index} of {name} isn't text, leaving it out"
+            ),
+        }
+    }
+
+    Ok(ToolOutput {
+        text: texts.join("\n"),
+        annotations,
+        error,
+    })
 }
@@ -0,0 +1,253 @@
+use std::{
+    io,
+    process::{ExitStatus, Stdio},
+};
+
+use tokio::{
+    process::{ChildStdin, ChildStdout, Command},
+    sync::oneshot,
+};
+
+use crate::state;
+
+/// Exit status of a local MCP server, given once it stops on its own
+pub(crate) type Exit = oneshot::Receiver<io::Result<ExitStatus>>;
+
+/// Process groups of the running local MCP servers, to stop them along with the manager
+#[cfg(unix)]
+static GROUPS: std::sync::Mutex<std::collections::BTreeSet<i32>> =
+    std::sync::Mutex::new(std::collections::BTreeSet::new());
+
+/// Command starting a local MCP server, in a process group of its own so the processes it starts
+/// (e.g. the server started by `npx`) can be stopped with it
+#[cfg(unix)]
+pub(crate) fn command(program: &str) -> Command {
+    let mut command = Command::new(program);
+    command.process_group(0);
+
+    command
+}
+
+#[cfg(not(any(unix, windows)))]
+pub(crate) fn command(program: &str) -> Command {
+    Command::new(program)
+}
+
+/// Command starting a local MCP server, through `cmd /C` unless it's an executable, since the
+/// scripts many servers are started with (e.g. `npx.cmd`) can't be started directly
+#[cfg(windows)]
+pub(crate) fn command(program: &str) -> Command {
+    use std::path::Path;
+
+    use windows_sys::Win32::System::Threading::CREATE_NO_WINDOW;
+
+    let executable = Path::new(program)
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("exe"));
+
+    let mut command = if executable {
+        Command::new(program)
+    } else {
+        let mut command = Command::new("cmd");
+        command.arg("/C").arg(program);
+        command
+    };
+
+    // Servers started as console programs would open a window each
+    command.creation_flags(CREATE_NO_WINDOW);
+
+    command
+}
+
+/// Stop every local MCP server still running and the processes they started, when the manager
+/// stops. On Windows, their jobs are closed along with the manager
+pub fn stop_all() {
+    #[cfg(unix)]
+    for group in std::mem::take(&mut *GROUPS.lock().expect("Process group lock poisoned")) {
+        kill_group(group);
+    }
+}
+
+#[cfg(unix)]
+pub(crate) fn kill_group(group: i32) {
+    // SAFETY: only sends a signal, to a group started by the manager
+    unsafe {
+        libc::kill(-group, libc::SIGKILL);
+    }
+}
+
+/// Running local MCP server, stopped along with the processes it started when dropped
+#[derive(Debug)]
+pub(crate) struct McpProcess {
+    /// Kills the server when dropped, unless it already exited
+    #[allow(dead_code)]
+    stop: oneshot::Sender<()>,
+    pid: Option<u32>,
+    /// Process group of the server, which it leads
+    #[cfg(unix)]
+    group: Option<i32>,
+    /// Job of the server and the processes it starts, all killed once the job is closed
+    #[cfg(windows)]
+    #[allow(dead_code)]
+    job: job::Job,
+}
+
+impl McpProcess {
+    /// Start a local MCP server, giving its output and input to talk to it, and its exit
+    pub(crate) fn spawn(
+        command: &mut Command,
+    ) -> io::Result<(McpProcess, Exit, ChildStdout, ChildStdin)> {
+        let program = command
+            .as_std()
+            .get_program()
+            .to_string_lossy()
+            .into_owned();
+        let mut child = command
+            .kill_on_drop(true)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+
+        let (Some(stdout), Some(stdin)) = (child.stdout.take(), child.stdin.take()) else {
+            return Err(io::Error::other("Standard input and output not piped"));
+        };
+
+        #[cfg(windows)]
+        let job = job::Job::assign(&child)?;
+
+        let pid = child.id();
+
+        if let Some(pid) = pid {
+            state::process_started(pid, program);
+        }
+
+        #[cfg(unix)]
+        let group = pid.map(|id| id as i32);
+
+        #[cfg(unix)]
+        if let Some(group) = group {
+            GROUPS
+                .lock()
+                .expect("Process group lock poisoned")
+                .insert(group);
+        }
+
+        let (stop, stopped) = oneshot::channel::<()>();
+        let (exited, exit) = oneshot::channel();
+
+        tokio::spawn(async move {
+            tokio::select! {
+                status = child.wait() => {
+                    let _ = exited.send(status);
+                }
+                // The child is killed when dropped
+                _ = stopped => (),
+            }
+        });
+
+        Ok((
+            McpProcess {
+                stop,
+                pid,
+                #[cfg(unix)]
+                group,
+                #[cfg(windows)]
+                job,
+            },
+            exit,
+            stdout,
+            stdin,
+        ))
+    }
+}
+
+impl Drop for McpProcess {
+    fn drop(&mut self) {
+        if let Some(pid) = self.pid {
+            state::process_stopped(pid);
+        }
+
+        // Servers restarted (e.g. to retry a call) don't leave the processes they started behind
+        #[cfg(unix)]
+        if let Some(group) = self.group
+            && GROUPS
+                .lock()
+                .expect("Process group lock poisoned")
+                .remove(&group)
+        {
+            kill_group(group);
+        }
+    }
+}
+
+#[cfg(windows)]
+mod job {
+    use std::{ffi::c_void, io, mem, ptr};
+
+    use tokio::process::Child;
+    use windows_sys::Win32::{
+        Foundation::{CloseHandle, HANDLE},
+        System::JobObjects::{
+            AssignProcessToJobObject, CreateJobObjectW, JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE,
+            JOBOBJECT_EXTENDED_LIMIT_INFORMATION, JobObjectExtendedLimitInformation,
+            SetInformationJobObject,
+        },
+    };
+
+    #[derive(Debug)]
+    pub(super) struct Job(HANDLE);
+
+    // SAFETY: the handle of a job can be used from any thread
+    unsafe impl Send for Job {}
+    unsafe impl Sync for Job {}
+
+    impl Job {
+        /// Put a process in a new job, killed along with the processes it started once the job
+        /// is closed. Processes started before it's assigned aren't part of the job
+        pub(super) fn assign(child: &Child) -> io::Result<Job> {
+            let process = child
+                .raw_handle()
+                .ok_or_else(|| io::Error::other("MCP server already exited"))?;
+
+            // SAFETY: the job is closed when dropped, and the process handle is valid while the
+            // child isn't waited for
+            unsafe {
+                let handle = CreateJobObjectW(ptr::null(), ptr::null());
+
+                if handle.is_null() {
+                    return Err(io::Error::last_os_error());
+                }
+
+                let job = Job(handle);
+
+                let mut limits: JOBOBJECT_EXTENDED_LIMIT_INFORMATION = mem::zeroed();
+                limits.BasicLimitInformation.LimitFlags = JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE;
+
+                if SetInformationJobObject(
+                    job.0,
+                    JobObjectExtendedLimitInformation,
+                    &limits as *const _ as *const c_void,
+                    mem::size_of::<JOBOBJECT_EXTENDED_LIMIT_INFORMATION>() as u32,
+                ) == 0
+                {
+                    return Err(io::Error::last_os_error());
+                }
+
+                if AssignProcessToJobObject(job.0, process) == 0 {
+                    return Err(io::Error::last_os_error());
+                }
+
+                Ok(job)
+            }
+        }
+    }
+
+    impl Drop for Job {
+        fn drop(&mut self) {
+            // SAFETY: the handle was created by the job and isn't used anymore
+            unsafe {
+                CloseHandle(self.0);
+            }
+        }
+    }
+}
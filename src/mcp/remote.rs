@@ -0,0 +1,85 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use rmcp::{
+    RoleClient, ServiceError,
+    model::{ClientRequest, PingRequest, PingRequestMethod, Tool},
+    service::RunningService,
+};
+use tokio::{task::JoinHandle, time::MissedTickBehavior};
+use tracing::{Level, event, instrument};
+
+use crate::mcp::{McpClient, McpServer, ToolCall, ToolOutput, call_tool, list_tools};
+
+#[derive(Debug)]
+pub(crate) struct RemoteMcp {
+    pub(crate) service: RunningService<RoleClient, McpClient>,
+    /// Task pinging the server, stopped along with the connection
+    heartbeat: Option<JoinHandle<()>>,
+}
+
+impl RemoteMcp {
+    /// Connection to a remote server, pinged at every `heartbeat` for it to stay alive while idle
+    pub(crate) fn new(
+        name: String,
+        service: RunningService<RoleClient, McpClient>,
+        heartbeat: Option<Duration>,
+    ) -> RemoteMcp {
+        let heartbeat = heartbeat.map(|period| {
+            let peer = service.peer().clone();
+
+            tokio::spawn(async move {
+                let mut interval = tokio::time::interval(period);
+                interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+                // The first tick completes right away, the connection was just made
+                interval.tick().await;
+
+                loop {
+                    interval.tick().await;
+
+                    let ping = ClientRequest::PingRequest(PingRequest {
+                        method: PingRequestMethod,
+                    });
+
+                    let error = match tokio::time::timeout(period, peer.send_request(ping)).await {
+                        Ok(Ok(_)) => continue,
+                        Ok(Err(error)) => error.to_string(),
+                        Err(_) => String::from("timed out"),
+                    };
+
+                    // Once lost, the connection is made again by the next call failing on it
+                    event!(
+                        Level::WARN,
+                        "MCP server {name} didn't answer the heartbeat: {error}"
+                    );
+
+                    return;
+                }
+            })
+        });
+
+        RemoteMcp { service, heartbeat }
+    }
+}
+
+impl Drop for RemoteMcp {
+    fn drop(&mut self) {
+        if let Some(heartbeat) = &self.heartbeat {
+            heartbeat.abort();
+        }
+    }
+}
+
+#[async_trait]
+impl McpServer for RemoteMcp {
+    #[instrument(skip_all, fields(tool = call.name, id = call.id))]
+    async fn call(&self, call: ToolCall) -> Result<ToolOutput, ServiceError> {
+        call_tool(&self.service, call).await
+    }
+
+    #[instrument(skip(self))]
+    async fn list_tools(&self) -> Result<Vec<Tool>, ServiceError> {
+        list_tools(&self.service).await
+    }
+}
@@ -0,0 +1,125 @@
+use std::{
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use rmcp::{ServiceError, model::Tool};
+use tracing::{Level, event, instrument};
+
+use crate::mcp::{McpServer, ToolCall, ToolOutput};
+
+/// Time a failing replica is left aside before being tried again
+const RETRY_AFTER: Duration = Duration::from_secs(30);
+
+/// Replica of a MCP server, only used while its last call didn't fail
+pub(crate) struct Replica {
+    pub(crate) url: String,
+    pub(crate) server: Arc<dyn McpServer + Send>,
+    failed_at: Mutex<Option<Instant>>,
+}
+
+impl Replica {
+    pub(crate) fn new(url: String, server: Arc<dyn McpServer + Send>) -> Replica {
+        Replica {
+            url,
+            server,
+            failed_at: Mutex::new(None),
+        }
+    }
+
+    fn healthy(&self) -> bool {
+        self.failed_at
+            .lock()
+            .expect("Replica lock poisoned")
+            .is_none_or(|failed_at| failed_at.elapsed() >= RETRY_AFTER)
+    }
+
+    fn record<T>(&self, result: &Result<T, ServiceError>) {
+        let mut failed_at = self.failed_at.lock().expect("Replica lock poisoned");
+
+        match result {
+            Ok(_) => *failed_at = None,
+            Err(error) => {
+                event!(
+                    Level::WARN,
+                    "Replica {} failed, trying another one: {error}",
+                    self.url
+                );
+
+                *failed_at = Some(Instant::now());
+            }
+        }
+    }
+}
+
+/// MCP server with several replicas, calls going to each healthy one in turn and moving on to the
+/// next one when a replica fails
+pub(crate) struct ReplicatedMcp {
+    replicas: Vec<Replica>,
+    next: AtomicUsize,
+}
+
+impl ReplicatedMcp {
+    pub(crate) fn new(replicas: Vec<Replica>) -> ReplicatedMcp {
+        ReplicatedMcp {
+            replicas,
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    /// Replicas in the order to try them from `start`, the healthy ones first
+    fn order(&self, start: usize) -> Vec<&Replica> {
+        let (mut healthy, failing): (Vec<&Replica>, Vec<&Replica>) = (0..self.replicas.len())
+            .map(|offset| &self.replicas[(start + offset) % self.replicas.len()])
+            .partition(|replica| replica.healthy());
+
+        healthy.extend(failing);
+
+        healthy
+    }
+}
+
+#[async_trait]
+impl McpServer for ReplicatedMcp {
+    #[instrument(skip_all, fields(tool = call.name))]
+    async fn call(&self, call: ToolCall) -> Result<ToolOutput, ServiceError> {
+        let mut result = None;
+
+        for replica in self.order(self.next.fetch_add(1, Ordering::Relaxed)) {
+            let output = replica.server.call(call.clone()).await;
+
+            replica.record(&output);
+
+            if output.is_ok() {
+                return output;
+            }
+
+            result = Some(output);
+        }
+
+        result.expect("MCP server without replicas")
+    }
+
+    async fn list_tools(&self) -> Result<Vec<Tool>, ServiceError> {
+        let mut result = None;
+
+        // Listing the tools doesn't take a turn from the calls
+        for replica in self.order(self.next.load(Ordering::Relaxed)) {
+            let tools = replica.server.list_tools().await;
+
+            replica.record(&tools);
+
+            if tools.is_ok() {
+                return tools;
+            }
+
+            result = Some(tools);
+        }
+
+        result.expect("MCP server without replicas")
+    }
+}
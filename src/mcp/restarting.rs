@@ -0,0 +1,231 @@
+use std::{
+    io,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use futures::future::BoxFuture;
+use rand::Rng;
+use rmcp::{ServiceError, model::Tool};
+use serde::{Deserialize, Serialize};
+use tokio::{sync::watch, task::JoinHandle};
+use tracing::{Level, event};
+
+use crate::mcp::{McpServer, ToolCall, ToolOutput, process::Exit};
+
+/// Seconds waited before the first restart
+pub(crate) const DEFAULT_INITIAL_DELAY: u64 = 1;
+/// Seconds the wait between restarts grows up to
+pub(crate) const DEFAULT_MAX_DELAY: u64 = 60;
+/// Failures in a row before the server is given up on
+pub(crate) const DEFAULT_MAX_FAILURES: usize = 5;
+
+/// Start of a local MCP server, with its exit
+pub(crate) type Start = Arc<
+    dyn Fn() -> BoxFuture<'static, Result<(Arc<dyn McpServer + Send>, Exit), String>> + Send + Sync,
+>;
+
+/// When a local MCP server is started again after stopping
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RestartPolicy {
+    /// Only connected again by the calls failing on it, as the `retries` allow
+    #[default]
+    Never,
+    /// When it fails to start, exits with an error or is killed
+    OnFailure,
+    /// Whenever it stops, even successfully
+    Always,
+}
+
+/// Waits between the restarts of a server, doubling up to `max` after each failure in a row,
+/// until `max_failures`
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Backoff {
+    pub(crate) initial: Duration,
+    pub(crate) max: Duration,
+    pub(crate) max_failures: usize,
+}
+
+impl Backoff {
+    /// Wait after a number of failures in a row, between half and all of the doubled delay for
+    /// the servers failing together not to restart together
+    fn delay(&self, failures: usize) -> Duration {
+        let exponent = failures.saturating_sub(1).min(u32::MAX as usize) as u32;
+        let delay = self
+            .initial
+            .saturating_mul(2u32.saturating_pow(exponent))
+            .min(self.max);
+
+        delay.mul_f64(rand::rng().random_range(0.5..=1.0))
+    }
+}
+
+#[derive(Clone)]
+enum State {
+    /// Not started yet, the calls waiting for it
+    Starting,
+    Running(Arc<dyn McpServer + Send>),
+    /// Waiting to be started again, the calls failing meanwhile
+    Restarting,
+    /// Not started again, as its policy or failure budget tells
+    Stopped(String),
+}
+
+/// Local MCP server started again by a supervisor when it stops, as its policy tells, waiting
+/// longer after each failure and giving up once too many happen in a row instead of looping
+pub(crate) struct RestartingMcp {
+    name: String,
+    state: watch::Receiver<State>,
+    supervisor: JoinHandle<()>,
+}
+
+impl RestartingMcp {
+    pub(crate) fn new(
+        name: String,
+        policy: RestartPolicy,
+        backoff: Backoff,
+        start: Start,
+    ) -> RestartingMcp {
+        let (state, receiver) = watch::channel(State::Starting);
+
+        let supervisor = tokio::spawn(supervise(name.clone(), policy, backoff, start, state));
+
+        RestartingMcp {
+            name,
+            state: receiver,
+            supervisor,
+        }
+    }
+
+    async fn server(&self) -> Result<Arc<dyn McpServer + Send>, ServiceError> {
+        let mut state = self.state.clone();
+
+        let state = state
+            .wait_for(|state| !matches!(state, State::Starting))
+            .await
+            .map(|state| state.clone())
+            .unwrap_or_else(|_| State::Stopped(String::from("supervisor stopped")));
+
+        match state {
+            State::Running(server) => Ok(server),
+            State::Restarting => Err(unavailable(format!(
+                "MCP server {} is restarting",
+                self.name
+            ))),
+            State::Stopped(reason) => Err(unavailable(format!(
+                "MCP server {} is down: {reason}",
+                self.name
+            ))),
+            State::Starting => unreachable!("Waited for the server to start"),
+        }
+    }
+}
+
+impl Drop for RestartingMcp {
+    fn drop(&mut self) {
+        self.supervisor.abort();
+    }
+}
+
+fn unavailable(message: String) -> ServiceError {
+    ServiceError::Transport(io::Error::other(message))
+}
+
+/// Start the server and start it again whenever it stops, as long as the policy and the failure
+/// budget allow. Every stop counts as a failure, those in a row being forgotten once the server
+/// stays up for the longest delay
+async fn supervise(
+    name: String,
+    policy: RestartPolicy,
+    backoff: Backoff,
+    start: Start,
+    state: watch::Sender<State>,
+) {
+    let mut failures = 0;
+    let mut started_once = false;
+
+    loop {
+        match start().await {
+            Ok((server, exit)) => {
+                let started = Instant::now();
+
+                state.send_replace(State::Running(server));
+
+                let status = match exit.await {
+                    Ok(status) => status,
+                    // Only closed when the server is stopped along with the manager
+                    Err(_) => return,
+                };
+
+                let failed = !status.as_ref().is_ok_and(|status| status.success());
+                let status = match status {
+                    Ok(status) => status.to_string(),
+                    Err(error) => error.to_string(),
+                };
+
+                if !failed && policy == RestartPolicy::OnFailure {
+                    event!(Level::INFO, "MCP server {name} stopped ({status})");
+
+                    state.send_replace(State::Stopped(format!("stopped ({status})")));
+
+                    return;
+                }
+
+                event!(Level::WARN, "MCP server {name} stopped ({status})");
+
+                if started.elapsed() >= backoff.max {
+                    failures = 0;
+                }
+
+                failures += 1;
+                started_once = true;
+            }
+            Err(error) => {
+                event!(Level::ERROR, "MCP server {name} failed to start: {error}");
+
+                failures += 1;
+            }
+        }
+
+        if failures >= backoff.max_failures {
+            event!(
+                Level::ERROR,
+                "MCP server {name} failed {failures} times in a row, not starting it again"
+            );
+
+            state.send_replace(State::Stopped(format!(
+                "degraded after {failures} failures in a row"
+            )));
+
+            return;
+        }
+
+        let delay = backoff.delay(failures);
+
+        event!(
+            Level::INFO,
+            "Starting MCP server {name} again in {}ms",
+            delay.as_millis()
+        );
+
+        // The calls keep waiting until the server first starts
+        if started_once {
+            state.send_replace(State::Restarting);
+        }
+
+        tokio::time::sleep(delay).await;
+    }
+}
+
+#[async_trait]
+impl McpServer for RestartingMcp {
+    async fn call(&self, call: ToolCall) -> Result<ToolOutput, ServiceError> {
+        self.server().await?.call(call).await
+    }
+
+    async fn list_tools(&self) -> Result<Vec<Tool>, ServiceError> {
+        self.server().await?.list_tools().await
+    }
+}
@@ -0,0 +1,118 @@
+use std::{io, sync::Arc};
+
+use async_trait::async_trait;
+use futures::future::BoxFuture;
+use rmcp::{ServiceError, model::Tool};
+use tokio::sync::RwLock;
+use tracing::{Level, event, instrument};
+
+use crate::mcp::{McpServer, ToolCall, ToolOutput};
+
+/// Times a call failing on the transport is tried again, on a new connection
+pub(crate) const DEFAULT_RETRIES: usize = 1;
+
+/// Start of a new connection to a MCP server
+pub(crate) type Reconnect =
+    Arc<dyn Fn() -> BoxFuture<'static, Result<Arc<dyn McpServer + Send>, String>> + Send + Sync>;
+
+/// Whether an error comes from the connection rather than from the server, so the call may
+/// succeed on a new one
+fn transient(error: &ServiceError) -> bool {
+    matches!(
+        error,
+        ServiceError::Transport(_) | ServiceError::Cancelled { .. } | ServiceError::Timeout { .. }
+    )
+}
+
+/// MCP server connected again when its calls fail on the transport (e.g. a dropped SSE
+/// connection), the call being tried again on the new connection
+pub(crate) struct RetryingMcp {
+    name: String,
+    retries: usize,
+    reconnect: Reconnect,
+    /// Connection in use, with the number of times it was replaced
+    server: RwLock<(u64, Arc<dyn McpServer + Send>)>,
+}
+
+impl RetryingMcp {
+    pub(crate) fn new(
+        name: String,
+        retries: usize,
+        server: Arc<dyn McpServer + Send>,
+        reconnect: Reconnect,
+    ) -> RetryingMcp {
+        RetryingMcp {
+            name,
+            retries,
+            reconnect,
+            server: RwLock::new((0, server)),
+        }
+    }
+
+    /// Replace the connection, unless another call already did since it failed
+    async fn replace(&self, generation: u64) -> Result<(), ServiceError> {
+        let mut server = self.server.write().await;
+
+        if server.0 != generation {
+            return Ok(());
+        }
+
+        event!(Level::INFO, "Reconnecting to MCP server {}", self.name);
+
+        let new = (self.reconnect)()
+            .await
+            .map_err(|error| ServiceError::Transport(io::Error::other(error)))?;
+
+        *server = (generation + 1, new);
+
+        Ok(())
+    }
+
+    async fn retry<T, F, Fut>(&self, operation: F) -> Result<T, ServiceError>
+    where
+        F: Fn(Arc<dyn McpServer + Send>) -> Fut,
+        Fut: Future<Output = Result<T, ServiceError>>,
+    {
+        let mut attempt = 0;
+
+        loop {
+            let (generation, server) = {
+                let server = self.server.read().await;
+
+                (server.0, Arc::clone(&server.1))
+            };
+
+            match operation(server).await {
+                Err(error) if attempt < self.retries && transient(&error) => {
+                    event!(
+                        Level::WARN,
+                        "Call to MCP server {} failed, trying again: {error}",
+                        self.name
+                    );
+
+                    attempt += 1;
+                    self.replace(generation).await?;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl McpServer for RetryingMcp {
+    #[instrument(skip_all, fields(mcp = self.name, tool = call.name))]
+    async fn call(&self, call: ToolCall) -> Result<ToolOutput, ServiceError> {
+        self.retry(|server| {
+            let call = call.clone();
+
+            async move { server.call(call).await }
+        })
+        .await
+    }
+
+    async fn list_tools(&self) -> Result<Vec<Tool>, ServiceError> {
+        self.retry(|server| async move { server.list_tools().await })
+            .await
+    }
+}
@@ -0,0 +1,91 @@
+use std::{
+    io,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use futures::future::{BoxFuture, FutureExt, Shared};
+use rmcp::{ServiceError, model::Tool};
+use tokio::{sync::Semaphore, time::timeout};
+use tracing::{Level, event};
+
+use crate::mcp::{McpServer, ToolCall, ToolOutput};
+
+type Startup = Shared<BoxFuture<'static, Result<Arc<dyn McpServer + Send>, String>>>;
+
+/// Startup of a MCP server waiting for its turn among the ones started at once, failing when it
+/// takes longer than the timeout, and logging how long it took
+pub(crate) fn bounded(
+    name: String,
+    startup: BoxFuture<'static, Result<Arc<dyn McpServer + Send>, String>>,
+    permits: Arc<Semaphore>,
+    limit: Option<Duration>,
+) -> BoxFuture<'static, Result<Arc<dyn McpServer + Send>, String>> {
+    async move {
+        let _permit = permits
+            .acquire_owned()
+            .await
+            .expect("Startup semaphore is never closed");
+        let start = Instant::now();
+
+        let result = match limit {
+            Some(limit) => timeout(limit, startup).await.unwrap_or_else(|_| {
+                Err(format!(
+                    "MCP server {name} didn't start within {}s",
+                    limit.as_secs()
+                ))
+            }),
+            None => startup.await,
+        };
+
+        match &result {
+            Ok(_) => event!(
+                Level::INFO,
+                "Started MCP server {name} in {}ms",
+                start.elapsed().as_millis()
+            ),
+            Err(error) => event!(
+                Level::ERROR,
+                "MCP server {name} failed to start after {}ms: {error}",
+                start.elapsed().as_millis()
+            ),
+        }
+
+        result
+    }
+    .boxed()
+}
+
+/// MCP server started in the background, whose calls wait for it to be up
+pub(crate) struct StartingMcp {
+    startup: Startup,
+}
+
+impl StartingMcp {
+    pub(crate) fn new(
+        startup: BoxFuture<'static, Result<Arc<dyn McpServer + Send>, String>>,
+    ) -> StartingMcp {
+        StartingMcp {
+            startup: startup.shared(),
+        }
+    }
+
+    async fn server(&self) -> Result<Arc<dyn McpServer + Send>, ServiceError> {
+        self.startup
+            .clone()
+            .await
+            .map_err(|error| ServiceError::Transport(io::Error::other(error)))
+    }
+}
+
+#[async_trait]
+impl McpServer for StartingMcp {
+    async fn call(&self, call: ToolCall) -> Result<ToolOutput, ServiceError> {
+        self.server().await?.call(call).await
+    }
+
+    async fn list_tools(&self) -> Result<Vec<Tool>, ServiceError> {
+        self.server().await?.list_tools().await
+    }
+}
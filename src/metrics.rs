@@ -0,0 +1,264 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use axum::{Extension, http::header::CONTENT_TYPE, response::IntoResponse};
+use tracing::instrument;
+
+/// Upper bounds of the buckets of the duration of the tool calls, in seconds
+const DURATION_BUCKETS: [f64; 13] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0,
+];
+/// Upper bounds of the buckets of the size of the tool outputs, in bytes
+const OUTPUT_BUCKETS: [f64; 8] = [
+    64.0, 256.0, 1024.0, 4096.0, 16384.0, 65536.0, 262144.0, 1048576.0,
+];
+/// Content type of the Prometheus text format
+const TEXT_FORMAT: &str = "text/plain; version=0.0.4";
+
+/// Workspace, MCP server and tool a call was made to
+type ToolKey = (String, String, String);
+
+/// Call to a tool counted as in progress while it's kept
+#[must_use]
+pub(crate) struct InProgress<'a> {
+    metrics: &'a ToolMetrics,
+    key: ToolKey,
+}
+
+impl Drop for InProgress<'_> {
+    fn drop(&mut self) {
+        if let Ok(mut tools) = self.metrics.tools.lock()
+            && let Some(stats) = tools.get_mut(&self.key)
+        {
+            stats.in_progress = stats.in_progress.saturating_sub(1);
+        }
+    }
+}
+
+/// Calls of every tool, by workspace and MCP server
+#[derive(Debug, Default)]
+pub struct ToolMetrics {
+    tools: Mutex<BTreeMap<ToolKey, ToolStats>>,
+}
+
+#[derive(Debug)]
+struct ToolStats {
+    duration: Histogram,
+    output: Histogram,
+    errors: u64,
+    /// Calls still running past the slow call threshold of their workspace
+    slow: u64,
+    /// Calls started and not ended yet
+    in_progress: u64,
+}
+
+#[derive(Debug)]
+struct Histogram {
+    bounds: &'static [f64],
+    /// Observations in each bucket, not cumulated
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn new(bounds: &'static [f64]) -> Histogram {
+        Histogram {
+            bounds,
+            counts: vec![0; bounds.len()],
+            sum: 0.0,
+            count: 0,
+        }
+    }
+
+    fn observe(&mut self, value: f64) {
+        if let Some(bucket) = self.bounds.iter().position(|bound| value <= *bound) {
+            self.counts[bucket] += 1;
+        }
+
+        self.sum += value;
+        self.count += 1;
+    }
+
+    fn render(&self, output: &mut String, name: &str, labels: &str) {
+        let mut cumulated = 0;
+
+        for (bound, count) in self.bounds.iter().zip(self.counts.iter()) {
+            cumulated += count;
+
+            let _ = writeln!(
+                output,
+                "{name}_bucket{{{labels},le=\"{bound}\"}} {cumulated}"
+            );
+        }
+
+        let _ = writeln!(
+            output,
+            "{name}_bucket{{{labels},le=\"+Inf\"}} {}",
+            self.count
+        );
+        let _ = writeln!(output, "{name}_sum{{{labels}}} {}", self.sum);
+        let _ = writeln!(output, "{name}_count{{{labels}}} {}", self.count);
+    }
+}
+
+impl ToolMetrics {
+    /// Record the start of a call to a tool, in progress until the guard is dropped, even when
+    /// the request is abandoned before the call ends
+    pub(crate) fn start(&self, workspace: &str, mcp: &str, tool: &str) -> InProgress<'_> {
+        let mut tools = self.tools.lock().expect("Tool metrics lock poisoned");
+
+        stats(&mut tools, workspace, mcp, tool).in_progress += 1;
+
+        InProgress {
+            metrics: self,
+            key: (workspace.to_owned(), mcp.to_owned(), tool.to_owned()),
+        }
+    }
+
+    /// Record a call to a tool still running past the slow call threshold
+    pub(crate) fn slow_call(&self, workspace: &str, mcp: &str, tool: &str) {
+        let mut tools = self.tools.lock().expect("Tool metrics lock poisoned");
+
+        stats(&mut tools, workspace, mcp, tool).slow += 1;
+    }
+
+    /// Record the end of a call to a tool, with the size of its output (none when it failed)
+    pub(crate) fn observe(
+        &self,
+        workspace: &str,
+        mcp: &str,
+        tool: &str,
+        duration: Duration,
+        output: Option<usize>,
+        error: bool,
+    ) {
+        let mut tools = self.tools.lock().expect("Tool metrics lock poisoned");
+
+        let stats = stats(&mut tools, workspace, mcp, tool);
+
+        stats.duration.observe(duration.as_secs_f64());
+
+        if let Some(output) = output {
+            stats.output.observe(output as f64);
+        }
+
+        if error {
+            stats.errors += 1;
+        }
+    }
+
+    /// Metrics in the Prometheus text format
+    fn render(&self) -> String {
+        let tools = self.tools.lock().expect("Tool metrics lock poisoned");
+
+        let labels: Vec<_> = tools
+            .keys()
+            .map(|(workspace, mcp, tool)| {
+                format!(
+                    "workspace=\"{}\",mcp=\"{}\",tool=\"{}\"",
+                    escape(workspace),
+                    escape(mcp),
+                    escape(tool)
+                )
+            })
+            .collect();
+
+        let mut output = String::new();
+
+        output.push_str(
+            "# HELP mcp_manager_tool_call_duration_seconds Duration of the tool calls\n\
+            # TYPE mcp_manager_tool_call_duration_seconds histogram\n",
+        );
+        for (stats, labels) in tools.values().zip(labels.iter()) {
+            stats.duration.render(
+                &mut output,
+                "mcp_manager_tool_call_duration_seconds",
+                labels,
+            );
+        }
+
+        output.push_str(
+            "# HELP mcp_manager_tool_output_bytes Size of the outputs of the tools\n\
+            # TYPE mcp_manager_tool_output_bytes histogram\n",
+        );
+        for (stats, labels) in tools.values().zip(labels.iter()) {
+            stats
+                .output
+                .render(&mut output, "mcp_manager_tool_output_bytes", labels);
+        }
+
+        output.push_str(
+            "# HELP mcp_manager_tool_call_errors_total Tool calls that failed or returned an error\n\
+            # TYPE mcp_manager_tool_call_errors_total counter\n",
+        );
+        for (stats, labels) in tools.values().zip(labels.iter()) {
+            let _ = writeln!(
+                output,
+                "mcp_manager_tool_call_errors_total{{{labels}}} {}",
+                stats.errors
+            );
+        }
+
+        output.push_str(
+            "# HELP mcp_manager_tool_slow_calls_total Tool calls still running past the slow call threshold\n\
+            # TYPE mcp_manager_tool_slow_calls_total counter\n",
+        );
+        for (stats, labels) in tools.values().zip(labels.iter()) {
+            let _ = writeln!(
+                output,
+                "mcp_manager_tool_slow_calls_total{{{labels}}} {}",
+                stats.slow
+            );
+        }
+
+        output.push_str(
+            "# HELP mcp_manager_tool_calls_in_progress Tool calls started and not ended yet\n\
+            # TYPE mcp_manager_tool_calls_in_progress gauge\n",
+        );
+        for (stats, labels) in tools.values().zip(labels.iter()) {
+            let _ = writeln!(
+                output,
+                "mcp_manager_tool_calls_in_progress{{{labels}}} {}",
+                stats.in_progress
+            );
+        }
+
+        output
+    }
+}
+
+/// Calls of a tool, created on its first call
+fn stats<'a>(
+    tools: &'a mut BTreeMap<ToolKey, ToolStats>,
+    workspace: &str,
+    mcp: &str,
+    tool: &str,
+) -> &'a mut ToolStats {
+    tools
+        .entry((workspace.to_owned(), mcp.to_owned(), tool.to_owned()))
+        .or_insert_with(|| ToolStats {
+            duration: Histogram::new(&DURATION_BUCKETS),
+            output: Histogram::new(&OUTPUT_BUCKETS),
+            errors: 0,
+            slow: 0,
+            in_progress: 0,
+        })
+}
+
+/// Value of a label, escaped as the text format requires
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[instrument(skip(metrics))]
+pub async fn metrics_handler(Extension(metrics): Extension<Arc<ToolMetrics>>) -> impl IntoResponse {
+    ([(CONTENT_TYPE, TEXT_FORMAT)], metrics.render())
+}
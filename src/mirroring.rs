@@ -0,0 +1,163 @@
+use std::{
+    sync::{Arc, OnceLock, Weak},
+    time::Instant,
+};
+
+use chrono::Utc;
+use rand::Rng;
+use tokio::sync::oneshot;
+use tracing::{Instrument, Level, Span, event};
+
+use crate::{
+    Error, ManagerBody, Workspace,
+    models::{Message, Role, TextMessage, Usage},
+    run_workspace,
+    storage::{MirrorRecord, Storage},
+};
+
+/// Percentage of the requests mirrored when not configured
+pub(crate) const DEFAULT_MIRROR_PERCENT: f64 = 100.0;
+
+/// Workspace given a sample of the requests of another one in the background (e.g. with another
+/// model or other MCP servers), what it answers being compared with the answer to the client
+pub struct Mirror {
+    pub(crate) name: String,
+    /// Set once every workspace is built, a workspace can't mirror the requests to itself
+    pub(crate) workspace: OnceLock<Weak<Workspace>>,
+    /// Percentage of the requests given to the workspace
+    pub(crate) percent: f64,
+    /// Store of the differences, which are only logged without it
+    pub(crate) storage: Option<Arc<Storage>>,
+}
+
+/// What a request ended with, in the workspace of the client or its mirror
+#[derive(Debug, Default)]
+pub(crate) struct Outcome {
+    pub(crate) status: u16,
+    pub(crate) duration_ms: u128,
+    pub(crate) usage: Option<Usage>,
+    /// Tools called, in order
+    pub(crate) tools: Vec<String>,
+    /// Last text message of the model
+    pub(crate) answer: Option<String>,
+}
+
+impl Outcome {
+    pub(crate) fn new(result: &Result<ManagerBody, Error>, start: Instant) -> Outcome {
+        let body = match result {
+            Ok(body) => body,
+            Err(error) => {
+                return Outcome {
+                    status: error.status,
+                    duration_ms: start.elapsed().as_millis(),
+                    ..Default::default()
+                };
+            }
+        };
+
+        Outcome {
+            status: 200,
+            duration_ms: start.elapsed().as_millis(),
+            usage: body.usage,
+            tools: body
+                .steps
+                .iter()
+                .flatten()
+                .flat_map(|step| step.tool_calls.iter())
+                .map(|call| call.name.clone())
+                .collect(),
+            answer: body
+                .messages
+                .iter()
+                .rev()
+                .find_map(|message| match message {
+                    Message::TextMessage(TextMessage {
+                        role: Role::Assistant,
+                        content,
+                    }) => Some(content.clone()),
+                    _ => None,
+                }),
+        }
+    }
+}
+
+impl Mirror {
+    /// Run a sampled request in the mirror workspace, in the background. Its outcome is compared
+    /// with the one of the request, sent with the ID it's recorded under to what's given back
+    pub(crate) fn start(&self, body: &ManagerBody) -> Option<oneshot::Sender<(String, Outcome)>> {
+        if !rand::rng().random_bool(self.percent / 100.0) {
+            return None;
+        }
+
+        let Some(workspace) = self.workspace.get().and_then(Weak::upgrade) else {
+            event!(Level::WARN, "Mirror workspace {} is gone", self.name);
+
+            return None;
+        };
+
+        let (sender, receiver) = oneshot::channel::<(String, Outcome)>();
+        let storage = self.storage.clone();
+        let body = body.clone();
+
+        tokio::spawn(
+            async move {
+                let started_at = Utc::now();
+                let start = Instant::now();
+                let model = workspace.pick_model();
+                let result = run_workspace(&workspace, model, body, None).await;
+                let mirror = Outcome::new(&result, start);
+
+                if let Err(error) = &result {
+                    event!(
+                        Level::WARN,
+                        "Mirror workspace {} failed: {}",
+                        workspace.name,
+                        error.message
+                    );
+                }
+
+                // The request failed before being answered, or its outcome wasn't sent
+                let Ok((id, outcome)) = receiver.await else {
+                    return;
+                };
+
+                let same_status = outcome.status == mirror.status;
+                let same_tools = outcome.tools == mirror.tools;
+                let same_answer = outcome.answer == mirror.answer;
+
+                event!(
+                    Level::INFO,
+                    mirror = workspace.name,
+                    latency_ms = mirror.duration_ms,
+                    same_status,
+                    same_tools,
+                    same_answer,
+                    "Mirror workspace {} answered with status {} after calling {} tool(s)",
+                    workspace.name,
+                    mirror.status,
+                    mirror.tools.len()
+                );
+
+                if let Some(storage) = storage {
+                    storage
+                        .record_mirror(MirrorRecord {
+                            request_id: &id,
+                            mirror: &workspace.name,
+                            model: &model.name,
+                            started_at,
+                            outcome: &outcome,
+                            mirror_outcome: &mirror,
+                            cost: model
+                                .price
+                                .zip(mirror.usage)
+                                .map(|(price, usage)| price.cost(&usage)),
+                        })
+                        .await;
+                }
+            }
+            .instrument(Span::current()),
+        );
+
+        Some(sender)
+    }
+}
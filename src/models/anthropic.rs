@@ -1,4 +1,4 @@
-use std::str::FromStr;
+use std::sync::Arc;
 
 use async_trait::async_trait;
 use axum::http::{HeaderMap, HeaderName, HeaderValue};
@@ -10,31 +10,46 @@ use tracing::{Level, event, instrument};
 use crate::{
     Error as ManagerError,
     models::{
-        AIModel, ManagerBody, ModelDecision, TextMessage, ToolCall as GeneralToolCall,
-        auth::Auth,
-        client::ModelClient,
-        openai::{FinishReason, Function, Message, RequestBody, ResponseBody, Tool, ToolType},
+        AIModel, ManagerBody, ModelTurn,
+        client::{ClientOptions, ModelClient},
+        openai::{Function, RequestBody, ResponseBody, Tool, ToolType},
+        sampling::{Sampling, Support},
+        schema::SchemaRules,
     },
 };
 
+const VERSION_HEADER: &str = "anthropic-version";
+
 pub struct Anthropic {
     url: Url,
     client: ModelClient,
+    schema: SchemaRules,
     model: String,
 }
 
 impl Anthropic {
-    pub async fn new(url: String, auth: Auth, model: String, version: String) -> Anthropic {
+    pub async fn new(
+        url: String,
+        options: ClientOptions,
+        schema: SchemaRules,
+        model: String,
+        version: String,
+    ) -> Anthropic {
         let mut headers = HeaderMap::new();
 
         headers.insert(
-            HeaderName::from_str("anthropic_version").unwrap(),
+            HeaderName::from_static(VERSION_HEADER),
             HeaderValue::from_str(&version).unwrap(),
         );
 
-        let (client, url) = ModelClient::new(url, auth, Some(headers), None).await;
+        let (client, url) = ModelClient::new(url, options, Some(headers), None).await;
 
-        Anthropic { client, url, model }
+        Anthropic {
+            client,
+            url,
+            schema,
+            model,
+        }
     }
 }
 
@@ -45,9 +60,11 @@ impl AIModel for Anthropic {
         &self,
         body: ManagerBody,
         tools: Vec<RmcpTool>,
-    ) -> Result<Vec<ModelDecision>, ManagerError> {
+    ) -> Result<ModelTurn, ManagerError> {
+        let sampling = Sampling::new(&body, &Support::ANTHROPIC);
         let mut body: RequestBody = body.into();
 
+        body.sample(sampling);
         body.model = self.model.clone();
         body.tools = Some(
             tools
@@ -57,7 +74,7 @@ impl AIModel for Anthropic {
                     function: Function {
                         name: tool.name.into_owned(),
                         description: tool.description.into_owned(),
-                        parameters: tool.input_schema,
+                        parameters: Arc::new(self.schema.sanitize(&tool.input_schema)),
                     },
                 })
                 .collect(),
@@ -65,11 +82,23 @@ impl AIModel for Anthropic {
 
         let response = self.client.call(self.url.clone(), &body).await?;
 
-        let mut response = from_str::<ResponseBody>(&response).unwrap_or_else(|error| {
+        let mut response = from_str::<ResponseBody>(&response).map_err(|error| {
             event!(Level::ERROR, "Couldn't deserialize response: {error}");
 
-            panic!()
-        });
+            ManagerError {
+                status: 502,
+                message: String::from("Invalid response from model"),
+            }
+        })?;
+
+        if response.choices.is_empty() {
+            event!(Level::ERROR, "Model gave no choices");
+
+            return Err(ManagerError {
+                status: 502,
+                message: String::from("Invalid response from model"),
+            });
+        }
 
         if response.choices.len() > 1 {
             event!(
@@ -80,25 +109,14 @@ impl AIModel for Anthropic {
 
         let choice = response.choices.remove(0);
 
-        Ok(vec![match choice.finish_reason {
-            FinishReason::Stop => ModelDecision::TextMessage(match choice.message {
-                Message::TextMessage(TextMessage { role: _, content }) => content,
-                _ => todo!("Unknown response needs to be handled: {response:#?}"),
-            }),
-            FinishReason::ToolCalls => ModelDecision::ToolCalls(match choice.message {
-                Message::ToolCalls {
-                    role: _,
-                    tool_calls,
-                } => tool_calls
-                    .into_iter()
-                    .map(|call| GeneralToolCall {
-                        name: call.function.name,
-                        id: call.id,
-                        arguments: from_str(&call.function.arguments).unwrap(),
-                    })
-                    .collect(),
-                _ => todo!("Unknown response needs to be handled: {response:#?}"),
-            }),
-        }])
+        let (decision, finish_reason) = choice.decide()?;
+
+        Ok(ModelTurn {
+            decisions: vec![decision],
+            usage: Some(response.usage.into()),
+            finish_reason: Some(finish_reason),
+            provider_id: response.id,
+            ..Default::default()
+        })
     }
 }
@@ -1,5 +1,6 @@
 pub enum Auth {
     ApiKey(AuthLocation),
+    #[cfg(feature = "oauth2")]
     OAuth2 {
         url: String,
         client_id: String,
@@ -13,3 +14,14 @@ pub enum AuthLocation {
     Header(String, String),
     Params(String, String),
 }
+
+/// Client certificate used for mutual TLS with the upstream server
+#[derive(Clone, Debug)]
+pub struct Tls {
+    /// Path to the PEM encoded client certificate (chain)
+    pub cert: String,
+    /// Path to the PEM encoded private key
+    pub key: String,
+    /// Path to a PEM encoded CA certificate to trust in addition to the system ones
+    pub ca: Option<String>,
+}
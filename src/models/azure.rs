@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, sync::Arc};
 
 use async_trait::async_trait;
 use reqwest::Url;
@@ -9,19 +9,18 @@ use tracing::{Level, event, instrument};
 
 use crate::{
     Error as ManagerError, ManagerBody,
-    mcp::ToolCall as GeneralToolCall,
     models::{
-        AIModel, Message as ManagerMessage, ModelDecision, Role, TextMessage,
-        auth::Auth,
-        client::ModelClient,
+        AIModel, Message as ManagerMessage, ModelTurn, Role,
+        client::{ClientOptions, ModelClient},
         openai::{
-            FinishReason, Function, Message, ResponseBody, Tool, ToolCall, ToolCallParams,
-            ToolChoice, ToolType,
+            Function, Message, ResponseBody, Tool, ToolCall, ToolCallParams, ToolChoice, ToolType,
         },
+        sampling::{Sampling, Support},
+        schema::SchemaRules,
     },
 };
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Default, Serialize)]
 pub(crate) struct RequestBody {
     pub(crate) messages: Vec<Message>,
     pub(crate) temperature: Option<f64>,
@@ -29,14 +28,15 @@ pub(crate) struct RequestBody {
     pub(crate) top_p: Option<f64>,
     pub(crate) tools: Option<Vec<Tool>>,
     pub(crate) tool_choice: ToolChoice,
+    /// End user of the request
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) user: Option<String>,
 }
 
 impl From<ManagerBody> for RequestBody {
     fn from(value: ManagerBody) -> Self {
         RequestBody {
-            temperature: value.temperature,
-            max_tokens: value.max_tokens,
-            top_p: value.top_p,
+            user: value.user(),
             messages: value
                 .messages
                 .into_iter()
@@ -67,6 +67,7 @@ impl From<ManagerBody> for RequestBody {
                 .collect(),
             tool_choice: ToolChoice::Auto,
             tools: None,
+            ..Default::default()
         }
     }
 }
@@ -74,17 +75,27 @@ impl From<ManagerBody> for RequestBody {
 pub struct Azure {
     url: Url,
     client: ModelClient,
+    schema: SchemaRules,
 }
 
 impl Azure {
-    pub async fn new(url: String, auth: Auth, api_version: String) -> Azure {
+    pub async fn new(
+        url: String,
+        options: ClientOptions,
+        schema: SchemaRules,
+        api_version: String,
+    ) -> Azure {
         let mut params = HashMap::new();
 
         params.insert(String::from("api-version"), api_version);
 
-        let (client, url) = ModelClient::new(url, auth, None, Some(params)).await;
+        let (client, url) = ModelClient::new(url, options, None, Some(params)).await;
 
-        Azure { client, url }
+        Azure {
+            client,
+            url,
+            schema,
+        }
     }
 }
 
@@ -95,9 +106,13 @@ impl AIModel for Azure {
         &self,
         body: ManagerBody,
         tools: Vec<RcmpTool>,
-    ) -> Result<Vec<ModelDecision>, ManagerError> {
+    ) -> Result<ModelTurn, ManagerError> {
+        let sampling = Sampling::new(&body, &Support::AZURE);
         let mut body: RequestBody = body.into();
 
+        body.temperature = sampling.temperature;
+        body.top_p = sampling.top_p;
+        body.max_tokens = sampling.max_tokens;
         body.tools = Some(
             tools
                 .into_iter()
@@ -106,7 +121,7 @@ impl AIModel for Azure {
                     function: Function {
                         name: tool.name.into_owned(),
                         description: tool.description.into_owned(),
-                        parameters: tool.input_schema,
+                        parameters: Arc::new(self.schema.sanitize(&tool.input_schema)),
                     },
                 })
                 .collect(),
@@ -114,11 +129,23 @@ impl AIModel for Azure {
 
         let response: String = self.client.call(self.url.clone(), &body).await?;
 
-        let mut response = from_str::<ResponseBody>(&response).unwrap_or_else(|error| {
+        let mut response = from_str::<ResponseBody>(&response).map_err(|error| {
             event!(Level::ERROR, "Couldn't deserialize response: {error}");
 
-            panic!()
-        });
+            ManagerError {
+                status: 502,
+                message: String::from("Invalid response from model"),
+            }
+        })?;
+
+        if response.choices.is_empty() {
+            event!(Level::ERROR, "Model gave no choices");
+
+            return Err(ManagerError {
+                status: 502,
+                message: String::from("Invalid response from model"),
+            });
+        }
 
         if response.choices.len() > 1 {
             event!(
@@ -129,25 +156,14 @@ impl AIModel for Azure {
 
         let choice = response.choices.remove(0);
 
-        Ok(vec![match choice.finish_reason {
-            FinishReason::Stop => ModelDecision::TextMessage(match choice.message {
-                Message::TextMessage(TextMessage { role: _, content }) => content,
-                _ => todo!("Unknown response needs to be handled: {response:#?}"),
-            }),
-            FinishReason::ToolCalls => ModelDecision::ToolCalls(match choice.message {
-                Message::ToolCalls {
-                    role: _,
-                    tool_calls,
-                } => tool_calls
-                    .into_iter()
-                    .map(|call| GeneralToolCall {
-                        name: call.function.name,
-                        id: call.id,
-                        arguments: from_str(&call.function.arguments).unwrap(),
-                    })
-                    .collect(),
-                _ => todo!("Unknown response needs to be handled: {response:#?}"),
-            }),
-        }])
+        let (decision, finish_reason) = choice.decide()?;
+
+        Ok(ModelTurn {
+            decisions: vec![decision],
+            usage: Some(response.usage.into()),
+            finish_reason: Some(finish_reason),
+            provider_id: response.id,
+            ..Default::default()
+        })
     }
 }
@@ -1,7 +1,11 @@
-use std::{collections::HashMap, fmt::Debug, str::FromStr};
+#[cfg(feature = "oauth2")]
+use std::sync::Arc;
+use std::{collections::HashMap, fmt::Debug, str::FromStr, time::Duration};
 
 use axum::http::{HeaderName, HeaderValue};
-use chrono::{DateTime, TimeDelta, Utc};
+#[cfg(feature = "oauth2")]
+use chrono::{TimeDelta, Utc};
+#[cfg(feature = "oauth2")]
 use oauth2::{
     Client as OAuthClient, ClientId, ClientSecret, EmptyExtraTokenFields, EndpointNotSet,
     EndpointSet, HttpClientError, RequestTokenError, RevocationErrorResponseType, Scope,
@@ -9,17 +13,28 @@ use oauth2::{
     StandardTokenResponse, TokenResponse, TokenUrl,
     basic::{BasicClient, BasicErrorResponseType, BasicTokenType},
 };
-use reqwest::{Client as HttpClient, Error as HttpError, Url, header::HeaderMap};
+#[cfg(feature = "oauth2")]
+use reqwest::Error as HttpError;
+#[cfg(feature = "oauth2")]
+use reqwest::header::USER_AGENT;
+use reqwest::{
+    Certificate, Client as HttpClient, ClientBuilder, Identity, Response, Url, header::HeaderMap,
+};
 use serde::Serialize;
-use tokio::sync::Mutex;
+use serde_json::{Value, from_str, json};
 use tracing::{Level, event, instrument};
 
+#[cfg(feature = "oauth2")]
+use crate::models::tokens::{TokenCache, TokenData, TokenKey};
 use crate::{
     Error as ManagerError,
-    models::auth::{Auth, AuthLocation},
+    logging::log_payloads,
+    models::auth::{Auth, AuthLocation, Tls},
 };
 
+#[cfg(feature = "oauth2")]
 type Token = StandardTokenResponse<EmptyExtraTokenFields, BasicTokenType>;
+#[cfg(feature = "oauth2")]
 type AuthClient = OAuthClient<
     StandardErrorResponse<BasicErrorResponseType>,
     Token,
@@ -32,26 +47,92 @@ type AuthClient = OAuthClient<
     EndpointNotSet,
     EndpointSet,
 >;
+#[cfg(feature = "oauth2")]
 type AuthError =
     RequestTokenError<HttpClientError<HttpError>, StandardErrorResponse<BasicErrorResponseType>>;
 
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug)]
 pub(crate) enum ModelClient {
+    #[cfg(feature = "oauth2")]
     ClientCredentials {
         http: HttpClient,
         auth_params: AuthClient,
         auth_client: HttpClient,
         scope: Option<Scope>,
-        token_data: Mutex<TokenData>,
+        tokens: Arc<TokenCache>,
+        token_key: TokenKey,
     },
     ApiKey(SimpleClient),
     NoAuth(SimpleClient),
 }
 
-#[derive(Debug)]
-pub(crate) struct TokenData {
-    token: String,
-    expiration: DateTime<Utc>,
+/// Connection settings of a model, common to every provider
+pub struct ClientOptions {
+    pub auth: Auth,
+    pub tls: Option<Tls>,
+    /// Headers sent on every request, overriding the ones set by the provider
+    pub headers: HeaderMap,
+    /// Pooling of the connections to the model
+    pub pool: Pool,
+    #[cfg(feature = "oauth2")]
+    pub tokens: Arc<TokenCache>,
+}
+
+/// How the connections to a model are kept and reused, the defaults of the HTTP client being
+/// used for what isn't set
+#[derive(Clone, Debug, Default)]
+pub struct Pool {
+    /// Idle connections kept open per host
+    pub max_idle_connections: Option<usize>,
+    /// Time an idle connection is kept open
+    pub idle_timeout: Option<Duration>,
+    /// Send the small writes right away instead of grouping them (Nagle's algorithm)
+    pub tcp_nodelay: Option<bool>,
+    /// Time between the TCP keep-alive probes
+    pub tcp_keep_alive: Option<Duration>,
+    /// Speak HTTP/2 from the start, without negotiating it
+    pub http2_only: bool,
+    /// Time between the HTTP/2 pings, also sent while the connection is idle
+    pub http2_keep_alive: Option<Duration>,
+    /// Time a HTTP/2 ping is given to be answered before the connection is closed
+    pub http2_keep_alive_timeout: Option<Duration>,
+}
+
+impl Pool {
+    fn configure(&self, mut builder: ClientBuilder) -> ClientBuilder {
+        if let Some(max_idle) = self.max_idle_connections {
+            builder = builder.pool_max_idle_per_host(max_idle);
+        }
+
+        if let Some(timeout) = self.idle_timeout {
+            builder = builder.pool_idle_timeout(timeout);
+        }
+
+        if let Some(nodelay) = self.tcp_nodelay {
+            builder = builder.tcp_nodelay(nodelay);
+        }
+
+        if let Some(interval) = self.tcp_keep_alive {
+            builder = builder.tcp_keepalive(interval);
+        }
+
+        if self.http2_only {
+            builder = builder.http2_prior_knowledge();
+        }
+
+        if let Some(interval) = self.http2_keep_alive {
+            builder = builder
+                .http2_keep_alive_interval(interval)
+                .http2_keep_alive_while_idle(true);
+        }
+
+        if let Some(timeout) = self.http2_keep_alive_timeout {
+            builder = builder.http2_keep_alive_timeout(timeout);
+        }
+
+        builder
+    }
 }
 
 #[derive(Debug)]
@@ -62,10 +143,28 @@ pub(crate) struct SimpleClient {
 impl ModelClient {
     pub async fn new(
         url: String,
-        auth: Auth,
+        options: ClientOptions,
         headers: Option<HeaderMap>,
         parameters: Option<HashMap<String, String>>,
     ) -> (ModelClient, Url) {
+        let ClientOptions {
+            auth,
+            tls,
+            headers: extra_headers,
+            pool,
+            #[cfg(feature = "oauth2")]
+            tokens,
+        } = options;
+
+        let headers = if extra_headers.is_empty() {
+            headers
+        } else {
+            let mut headers = headers.unwrap_or_default();
+            headers.extend(extra_headers);
+
+            Some(headers)
+        };
+
         match auth {
             Auth::ApiKey(location) => match location {
                 AuthLocation::Params(key, value) => {
@@ -80,7 +179,7 @@ impl ModelClient {
                         params
                     };
 
-                    let (client, url) = create_http_client(url, headers, Some(params));
+                    let (client, url) = create_http_client(url, tls, &pool, headers, Some(params));
 
                     (ModelClient::ApiKey(SimpleClient { client }), url)
                 }
@@ -103,11 +202,13 @@ impl ModelClient {
                         headers
                     };
 
-                    let (client, url) = create_http_client(url, Some(headers), parameters);
+                    let (client, url) =
+                        create_http_client(url, tls, &pool, Some(headers), parameters);
 
                     (ModelClient::ApiKey(SimpleClient { client }), url)
                 }
             },
+            #[cfg(feature = "oauth2")]
             Auth::OAuth2 {
                 url: auth_url,
                 client_id,
@@ -118,10 +219,30 @@ impl ModelClient {
                     .set_client_secret(ClientSecret::new(client_secret))
                     .set_token_uri(
                         TokenUrl::new(auth_url.clone())
-                            .expect(&format!("Invalid auth url \"{auth_url}\"")),
+                            .unwrap_or_else(|_| panic!("Invalid auth url \"{auth_url}\"")),
                     );
 
-                let auth_client = HttpClient::new();
+                // Only the identification of the manager is sent to the token endpoint, which can
+                // be behind the same gateway requiring the client certificate
+                let mut auth_client = HttpClient::builder();
+
+                if let Some(user_agent) =
+                    headers.as_ref().and_then(|headers| headers.get(USER_AGENT))
+                {
+                    auth_client = auth_client.user_agent(user_agent);
+                }
+
+                if let Some(tls) = &tls {
+                    auth_client = with_tls(auth_client, tls);
+                }
+
+                let auth_client = auth_client.build().expect("Couldn't create auth client");
+
+                let token_key = TokenKey {
+                    url: auth_url,
+                    client_id: auth_params.client_id().to_string(),
+                    scope: scope.clone(),
+                };
 
                 let client_scope: Option<Scope>;
 
@@ -131,12 +252,18 @@ impl ModelClient {
                     client_scope = None;
                 }
 
-                let (token, expiration) =
-                    get_client_credentials_token(&auth_params, client_scope.clone(), &auth_client)
-                        .await
-                        .expect("Couldn't get token");
+                tokens
+                    .get(&token_key, || {
+                        get_client_credentials_token(
+                            &auth_params,
+                            client_scope.clone(),
+                            &auth_client,
+                        )
+                    })
+                    .await
+                    .expect("Couldn't get token");
 
-                let (http_client, url) = create_http_client(url, headers, parameters);
+                let (http_client, url) = create_http_client(url, tls, &pool, headers, parameters);
 
                 (
                     ModelClient::ClientCredentials {
@@ -144,13 +271,14 @@ impl ModelClient {
                         auth_params,
                         auth_client,
                         scope: client_scope,
-                        token_data: Mutex::new(TokenData { token, expiration }),
+                        tokens,
+                        token_key,
                     },
                     url,
                 )
             }
             Auth::NoAuth => {
-                let (client, url) = create_http_client(url, headers, parameters);
+                let (client, url) = create_http_client(url, tls, &pool, headers, parameters);
 
                 (ModelClient::NoAuth(SimpleClient { client }), url)
             }
@@ -163,100 +291,172 @@ impl ModelClient {
         url: Url,
         body: &T,
     ) -> Result<String, ManagerError> {
-        event!(Level::DEBUG, "Request: {body:#?}");
+        let response = self.send(url, body).await?;
+
+        let status = response.status();
+        let response = response.text().await?;
+
+        if !status.is_success() {
+            event!(Level::ERROR, "Model answered {status}: {response}");
+
+            return Err(ManagerError {
+                status: 502,
+                message: format!("Model answered {status}"),
+            });
+        }
+
+        if log_payloads() {
+            match from_str::<Value>(&response) {
+                Ok(value) => event!(Level::DEBUG, "Response: {value}"),
+                Err(_) => event!(Level::DEBUG, "Response: {response:?}"),
+            }
+        }
+
+        Ok(response)
+    }
+
+    /// Call whose answer is streamed, its events being read by the caller once the model accepted
+    /// the request
+    #[instrument(skip_all)]
+    pub async fn stream<T: Debug + Serialize + ?Sized>(
+        &self,
+        url: Url,
+        body: &T,
+    ) -> Result<Response, ManagerError> {
+        let response = self.send(url, body).await?;
+        let status = response.status();
+
+        if !status.is_success() {
+            let response = response.text().await?;
+
+            event!(Level::ERROR, "Model answered {status}: {response}");
+
+            return Err(ManagerError {
+                status: 502,
+                message: format!("Model answered {status}"),
+            });
+        }
+
+        Ok(response)
+    }
+
+    /// POST a request to the model, authenticated as configured
+    async fn send<T: Debug + Serialize + ?Sized>(
+        &self,
+        url: Url,
+        body: &T,
+    ) -> Result<Response, ManagerError> {
+        // As JSON, for the redacted arguments of the tools to be found in it
+        if log_payloads() {
+            event!(Level::DEBUG, "Request: {}", json!(body));
+        }
 
-        let response: String = match self {
+        let response = match self {
             ModelClient::ApiKey(http) | ModelClient::NoAuth(http) => {
-                http.client
-                    .post(url)
-                    .json(&body)
-                    .send()
-                    .await?
-                    .text()
-                    .await?
+                http.client.post(url).json(&body).send().await?
             }
+            #[cfg(feature = "oauth2")]
             ModelClient::ClientCredentials {
                 http,
                 auth_params,
                 auth_client,
                 scope,
-                token_data,
+                tokens,
+                token_key,
             } => {
-                let token: String;
-
+                let token = match tokens
+                    .get(token_key, || {
+                        get_client_credentials_token(auth_params, scope.to_owned(), auth_client)
+                    })
+                    .await
                 {
-                    let mut guard = token_data.lock().await;
-
-                    if guard.expiration < Utc::now() {
-                        match get_client_credentials_token(
-                            auth_params,
-                            scope.to_owned(),
-                            auth_client,
-                        )
-                        .await
-                        {
-                            Ok(values) => {
-                                (guard.token, guard.expiration) = values;
-                            }
-                            Err(error) => {
-                                event!(Level::ERROR, "Couldn't get token: {error}");
-
-                                return Err(ManagerError {
-                                    status: 500,
-                                    message: String::from("Couldn't renew token"),
-                                });
-                            }
-                        };
+                    Ok(token) => token,
+                    Err(error) => {
+                        event!(Level::ERROR, "Couldn't get token: {error}");
+
+                        return Err(ManagerError {
+                            status: 502,
+                            message: String::from("Couldn't renew token"),
+                        });
                     }
-                    token = guard.token.clone();
-                }
+                };
 
                 http.post(url)
                     .header("Authorization", format!("Bearer {token}"))
                     .json(&body)
                     .send()
                     .await?
-                    .text()
-                    .await?
             }
         };
 
-        event!(Level::DEBUG, "Response: {response:?}");
-
         Ok(response)
     }
 }
 
 fn create_http_client(
     url: String,
+    tls: Option<Tls>,
+    pool: &Pool,
     headers: Option<HeaderMap>,
     parameters: Option<HashMap<String, String>>,
 ) -> (HttpClient, Url) {
     let url = if let Some(params) = parameters {
-        Url::parse_with_params(&url, params.iter()).expect(&format!(
-            "Invalid URL \"{url}\" with parameters \"{params:?}\""
-        ))
+        Url::parse_with_params(&url, params.iter())
+            .unwrap_or_else(|_| panic!("Invalid URL \"{url}\" with parameters \"{params:?}\""))
     } else {
-        Url::parse(&url).expect(&format!("Invalid URL \"{url}\""))
+        Url::parse(&url).unwrap_or_else(|_| panic!("Invalid URL \"{url}\""))
     };
 
-    let client = if let Some(headers) = headers {
-        HttpClient::builder()
-            .default_headers(headers)
-            .build()
-            .unwrap()
-    } else {
-        HttpClient::new()
-    };
+    let mut builder = pool.configure(HttpClient::builder());
+
+    if let Some(headers) = headers {
+        builder = builder.default_headers(headers);
+    }
+
+    if let Some(tls) = tls {
+        builder = with_tls(builder, &tls);
+    }
+
+    (builder.build().unwrap(), url)
+}
+
+/// Configure the client certificate (and extra CA) on an HTTP client
+pub(crate) fn with_tls(builder: ClientBuilder, tls: &Tls) -> ClientBuilder {
+    let mut pem = std::fs::read(&tls.cert)
+        .unwrap_or_else(|error| panic!("Couldn't read certificate \"{}\": {error}", tls.cert));
+    pem.extend(
+        std::fs::read(&tls.key)
+            .unwrap_or_else(|error| panic!("Couldn't read key \"{}\": {error}", tls.key)),
+    );
+
+    let identity = Identity::from_pem(&pem).unwrap_or_else(|error| {
+        panic!(
+            "Invalid client certificate \"{}\" or key \"{}\": {error}",
+            tls.cert, tls.key
+        )
+    });
+
+    let mut builder = builder.use_rustls_tls().identity(identity);
+
+    if let Some(ca) = &tls.ca {
+        let ca_cert = std::fs::read(ca)
+            .unwrap_or_else(|error| panic!("Couldn't read CA certificate \"{ca}\": {error}"));
+
+        builder = builder.add_root_certificate(
+            Certificate::from_pem(&ca_cert)
+                .unwrap_or_else(|error| panic!("Invalid CA certificate \"{ca}\": {error}")),
+        );
+    }
 
-    (client, url)
+    builder
 }
 
+#[cfg(feature = "oauth2")]
 async fn get_client_credentials_token(
     config: &AuthClient,
     scope: Option<Scope>,
     client: &HttpClient,
-) -> Result<(String, DateTime<Utc>), AuthError> {
+) -> Result<TokenData, AuthError> {
     let mut auth_client = config.exchange_client_credentials();
 
     if let Some(scope) = scope {
@@ -265,9 +465,9 @@ async fn get_client_credentials_token(
 
     let token = auth_client.request_async(client).await?;
 
-    Ok((
-        token.access_token().secret().to_owned(),
-        Utc::now()
+    Ok(TokenData {
+        token: token.access_token().secret().to_owned(),
+        expiration: Utc::now()
             .checked_add_signed(TimeDelta::seconds(
                 token
                     .expires_in()
@@ -277,5 +477,5 @@ async fn get_client_credentials_token(
                     .unwrap(),
             ))
             .expect("Date out of range"),
-    ))
+    })
 }
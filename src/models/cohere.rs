@@ -0,0 +1,350 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use reqwest::Url;
+use rmcp::model::{JsonObject, Tool as RmcpTool};
+use serde::{Deserialize, Serialize};
+use serde_json::{from_str, json};
+use tracing::{Level, event, instrument};
+
+use crate::{
+    Error as ManagerError, ManagerBody,
+    models::{
+        AIModel, Message as ManagerMessage, ModelDecision, ModelTurn, Role, TextMessage, Usage,
+        client::{ClientOptions, ModelClient},
+        sampling::{Sampling, Support},
+        schema::SchemaRules,
+        tool_call,
+    },
+};
+
+#[derive(Debug, Default, Serialize)]
+struct RequestBody {
+    model: String,
+    messages: Vec<Message>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tools: Option<Vec<Tool>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_tokens: Option<isize>,
+    #[serde(rename = "p", skip_serializing_if = "Option::is_none")]
+    top_p: Option<f64>,
+    #[serde(rename = "k", skip_serializing_if = "Option::is_none")]
+    top_k: Option<u64>,
+}
+
+impl From<ManagerBody> for RequestBody {
+    fn from(value: ManagerBody) -> Self {
+        let mut messages: Vec<Message> = Vec::new();
+
+        for message in value.messages.into_iter() {
+            match message {
+                ManagerMessage::TextMessage(TextMessage { role, content }) => {
+                    messages.push(Message::Text { role, content })
+                }
+                ManagerMessage::ToolCalls { role, tool_calls } => {
+                    // The plan Cohere gave along with the calls was kept as the message before them
+                    let tool_plan = match messages.last() {
+                        Some(Message::Text {
+                            role: Role::Assistant,
+                            ..
+                        }) => match messages.pop() {
+                            Some(Message::Text { content, .. }) => Some(content),
+                            _ => None,
+                        },
+                        _ => None,
+                    };
+
+                    messages.push(Message::ToolCalls {
+                        role,
+                        tool_plan,
+                        tool_calls: tool_calls
+                            .into_iter()
+                            .map(|call| ToolCall {
+                                id: call.id,
+                                r#type: ToolType::Function,
+                                function: ToolCallParams {
+                                    name: call.name,
+                                    arguments: json!(call.arguments).to_string(),
+                                },
+                            })
+                            .collect(),
+                    })
+                }
+                ManagerMessage::ToolOutput {
+                    call_id, output, ..
+                } => messages.push(Message::ToolResults {
+                    role: Role::Tool,
+                    tool_call_id: call_id,
+                    content: vec![ToolResult::Document {
+                        document: Document { data: output },
+                    }],
+                }),
+            }
+        }
+
+        RequestBody {
+            messages,
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+enum Message {
+    Text {
+        role: Role,
+        content: String,
+    },
+    ToolCalls {
+        role: Role,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        tool_plan: Option<String>,
+        tool_calls: Vec<ToolCall>,
+    },
+    ToolResults {
+        role: Role,
+        tool_call_id: String,
+        content: Vec<ToolResult>,
+    },
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "lowercase", tag = "type")]
+enum ToolResult {
+    Document { document: Document },
+}
+
+#[derive(Debug, Serialize)]
+struct Document {
+    data: String,
+}
+
+#[derive(Debug, Serialize)]
+struct Tool {
+    r#type: ToolType,
+    function: Function,
+}
+
+#[derive(Debug, Serialize)]
+struct Function {
+    name: String,
+    description: String,
+    parameters: Arc<JsonObject>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum ToolType {
+    Function,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct ToolCall {
+    id: String,
+    r#type: ToolType,
+    function: ToolCallParams,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct ToolCallParams {
+    name: String,
+    arguments: String,
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Deserialize)]
+struct ResponseBody {
+    id: String,
+    finish_reason: FinishReason,
+    message: ResponseMessage,
+    usage: Option<ResponseUsage>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+enum FinishReason {
+    Complete,
+    StopSequence,
+    MaxTokens,
+    ToolCall,
+    Error,
+    Timeout,
+}
+
+#[derive(Debug, Deserialize)]
+struct ResponseMessage {
+    content: Option<Vec<ResponseContent>>,
+    tool_plan: Option<String>,
+    tool_calls: Option<Vec<ToolCall>>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "lowercase", tag = "type")]
+enum ResponseContent {
+    Text {
+        text: String,
+    },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Debug, Deserialize)]
+struct ResponseUsage {
+    tokens: Option<Tokens>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Tokens {
+    input_tokens: f64,
+    output_tokens: f64,
+}
+
+impl From<Tokens> for Usage {
+    fn from(value: Tokens) -> Self {
+        let prompt_tokens = value.input_tokens as usize;
+        let completion_tokens = value.output_tokens as usize;
+
+        Usage {
+            prompt_tokens,
+            completion_tokens,
+            total_tokens: prompt_tokens + completion_tokens,
+        }
+    }
+}
+
+pub struct Cohere {
+    url: Url,
+    client: ModelClient,
+    schema: SchemaRules,
+    model: String,
+}
+
+impl Cohere {
+    pub async fn new(
+        url: String,
+        options: ClientOptions,
+        schema: SchemaRules,
+        model: String,
+    ) -> Cohere {
+        let (client, url) = ModelClient::new(url, options, None, None).await;
+
+        Cohere {
+            client,
+            url,
+            schema,
+            model,
+        }
+    }
+}
+
+#[async_trait]
+impl AIModel for Cohere {
+    #[instrument(skip_all)]
+    async fn call(
+        &self,
+        body: ManagerBody,
+        tools: Vec<RmcpTool>,
+    ) -> Result<ModelTurn, ManagerError> {
+        let sampling = Sampling::new(&body, &Support::COHERE);
+        let mut body: RequestBody = body.into();
+
+        body.model = self.model.clone();
+        body.temperature = sampling.temperature;
+        body.top_p = sampling.top_p;
+        body.top_k = sampling.top_k;
+        body.max_tokens = sampling.max_tokens;
+
+        if !tools.is_empty() {
+            body.tools = Some(
+                tools
+                    .into_iter()
+                    .map(|tool: RmcpTool| Tool {
+                        r#type: ToolType::Function,
+                        function: Function {
+                            name: tool.name.into_owned(),
+                            description: tool.description.into_owned(),
+                            parameters: Arc::new(self.schema.sanitize(&tool.input_schema)),
+                        },
+                    })
+                    .collect(),
+            );
+        }
+
+        let response = self.client.call(self.url.clone(), &body).await?;
+
+        let response = from_str::<ResponseBody>(&response).map_err(|error| {
+            event!(Level::ERROR, "Couldn't deserialize response: {error}");
+
+            ManagerError {
+                status: 502,
+                message: String::from("Invalid response from model"),
+            }
+        })?;
+
+        let mut decisions = Vec::new();
+
+        let finish_reason = match response.finish_reason {
+            FinishReason::Complete | FinishReason::StopSequence => Some(super::FinishReason::Stop),
+            FinishReason::MaxTokens => Some(super::FinishReason::Length),
+            FinishReason::ToolCall => Some(super::FinishReason::ToolCalls),
+            FinishReason::Error | FinishReason::Timeout => None,
+        };
+
+        match response.finish_reason {
+            FinishReason::ToolCall => {
+                if let Some(tool_plan) = response.message.tool_plan {
+                    decisions.push(ModelDecision::TextMessage(tool_plan));
+                }
+
+                let calls = response
+                    .message
+                    .tool_calls
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|call| tool_call(call.id, call.function.name, &call.function.arguments))
+                    .collect::<Vec<_>>();
+
+                decisions.push(ModelDecision::ToolCalls(calls));
+            }
+            FinishReason::Error | FinishReason::Timeout => {
+                event!(
+                    Level::ERROR,
+                    "Model failed with {:?}",
+                    response.finish_reason
+                );
+
+                return Err(ManagerError {
+                    status: 502,
+                    message: String::from("Model failed to generate a response"),
+                });
+            }
+            _ => decisions.push(ModelDecision::TextMessage(
+                response
+                    .message
+                    .content
+                    .unwrap_or_default()
+                    .into_iter()
+                    .filter_map(|content| match content {
+                        ResponseContent::Text { text } => Some(text),
+                        ResponseContent::Other => None,
+                    })
+                    .collect::<Vec<_>>()
+                    .join(""),
+            )),
+        }
+
+        Ok(ModelTurn {
+            decisions,
+            usage: response
+                .usage
+                .and_then(|usage| usage.tokens)
+                .map(Usage::from),
+            finish_reason,
+            provider_id: Some(response.id),
+            ..Default::default()
+        })
+    }
+}
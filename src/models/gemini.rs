@@ -1,36 +1,102 @@
+use std::collections::HashMap;
+
 use async_trait::async_trait;
 use rand::distr::{Alphanumeric, SampleString};
 use reqwest::Url;
 use rmcp::model::{JsonObject, Tool as RcmpTool};
 use serde::{Deserialize, Serialize};
-use serde_json::{Value, from_str};
+use serde_json::from_str;
 use tracing::{Level, event, instrument};
 
 use crate::{
     Error as ManagerError, ManagerBody,
     mcp::ToolCall as GeneralToolCall,
     models::{
-        AIModel, Message as ManagerMessage, ModelDecision, Role as ManagerRole, TextMessage,
-        auth::Auth, client::ModelClient,
+        AIModel, Message as ManagerMessage, ModelDecision, ModelTurn, Role as ManagerRole,
+        TextMessage, Usage,
+        auth::{Auth, AuthLocation},
+        client::{ClientOptions, ModelClient},
+        sampling::{Sampling, Support},
+        schema::SchemaRules,
     },
 };
 
 const ID_LEN: usize = 24;
+/// Separates the random part of the IDs given to the tool calls from the name of their function
+const ID_SEPARATOR: char = '-';
+const API_URL: &str = "https://generativelanguage.googleapis.com/v1beta/models";
+const API_KEY_HEADER: &str = "x-goog-api-key";
 
 #[derive(Debug, Default, Deserialize, Serialize)]
 pub(crate) struct RequestBody {
+    /// System messages, which Gemini takes apart from the conversation
+    #[serde(rename = "systemInstruction", skip_serializing_if = "Option::is_none")]
+    pub(crate) system_instruction: Option<SystemInstruction>,
     pub(crate) contents: Vec<Message>,
     pub(crate) tools: Option<Vec<Tool>>,
+    #[serde(rename = "generationConfig", skip_serializing_if = "Option::is_none")]
+    pub(crate) generation_config: Option<GenerationConfig>,
+}
+
+/// Sampling parameters, which Gemini takes apart from the conversation
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct GenerationConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_p: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_k: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_output_tokens: Option<isize>,
+}
+
+impl GenerationConfig {
+    /// Configuration of the sampling parameters set, if any
+    fn new(sampling: Sampling) -> Option<GenerationConfig> {
+        let Sampling {
+            temperature,
+            top_p,
+            top_k,
+            max_tokens,
+        } = sampling;
+
+        (temperature.is_some() || top_p.is_some() || top_k.is_some() || max_tokens.is_some())
+            .then_some(GenerationConfig {
+                temperature,
+                top_p,
+                top_k,
+                max_output_tokens: max_tokens,
+            })
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub(crate) struct SystemInstruction {
+    parts: Vec<Part>,
 }
 
 impl From<ManagerBody> for RequestBody {
     fn from(value: ManagerBody) -> Self {
         let mut contents = Vec::new();
+        let mut system = Vec::new();
+        // Function responses are matched with their call by name, found from the ID of the call
+        // when the history doesn't have it
+        let mut names = HashMap::new();
 
         let mut last_output: Option<&mut Message> = None;
 
         for message in value.messages.into_iter() {
             match message {
+                ManagerMessage::TextMessage(TextMessage {
+                    role: ManagerRole::System,
+                    content,
+                }) => system.push(Part::Text {
+                    text: content,
+                    thought: false,
+                    thought_signature: None,
+                }),
                 ManagerMessage::TextMessage(TextMessage { role, content }) => {
                     last_output = None;
 
@@ -40,7 +106,11 @@ impl From<ManagerBody> for RequestBody {
                             ManagerRole::User => Role::User,
                             _ => unreachable!("Role not possible for text message"),
                         },
-                        parts: vec![Part::Text { text: content }],
+                        parts: vec![Part::Text {
+                            text: content,
+                            thought: false,
+                            thought_signature: None,
+                        }],
                     });
                 }
                 ManagerMessage::ToolCalls { role, tool_calls } => {
@@ -53,11 +123,16 @@ impl From<ManagerBody> for RequestBody {
                         },
                         parts: tool_calls
                             .into_iter()
-                            .map(|call| Part::FunctionCall {
-                                function_call: FunctionCall {
-                                    name: call.name,
-                                    args: call.arguments,
-                                },
+                            .map(|call| {
+                                names.insert(call.id, call.name.clone());
+
+                                Part::FunctionCall {
+                                    function_call: FunctionCall {
+                                        name: call.name,
+                                        args: call.arguments,
+                                    },
+                                    thought_signature: call.thought_signature,
+                                }
                             })
                             .collect(),
                     });
@@ -65,28 +140,27 @@ impl From<ManagerBody> for RequestBody {
                 ManagerMessage::ToolOutput {
                     call_id, output, ..
                 } => {
-                    if let Some(last) = last_output {
-                        last.parts.push(Part::FunctionOutput {
-                            function_response: FunctionResponse {
-                                name: call_id.clone(),
-                                response: FunctionContent {
-                                    name: call_id,
-                                    content: output,
-                                },
+                    let name = names
+                        .get(&call_id)
+                        .cloned()
+                        .or_else(|| call_name(&call_id).map(str::to_owned))
+                        .unwrap_or(call_id);
+                    let part = Part::FunctionOutput {
+                        function_response: FunctionResponse {
+                            name: name.clone(),
+                            response: FunctionContent {
+                                name,
+                                content: output,
                             },
-                        })
+                        },
+                    };
+
+                    if let Some(last) = last_output {
+                        last.parts.push(part)
                     } else {
                         contents.push(Message {
                             role: Role::Function,
-                            parts: vec![Part::FunctionOutput {
-                                function_response: FunctionResponse {
-                                    name: call_id.clone(),
-                                    response: FunctionContent {
-                                        name: call_id,
-                                        content: output,
-                                    },
-                                },
-                            }],
+                            parts: vec![part],
                         });
                     }
 
@@ -96,6 +170,7 @@ impl From<ManagerBody> for RequestBody {
         }
 
         RequestBody {
+            system_instruction: (!system.is_empty()).then_some(SystemInstruction { parts: system }),
             contents,
             ..Default::default()
         }
@@ -132,32 +207,44 @@ pub enum Role {
 #[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(untagged)]
 pub(crate) enum Part {
+    /// Answer of the model, or a summary of its reasoning when `thought` is set
+    #[serde(rename_all = "camelCase")]
     Text {
         text: String,
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        thought: bool,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        thought_signature: Option<String>,
     },
+    /// Reasoning models sign the first call of a turn, the signature having to be sent back
+    #[serde(rename_all = "camelCase")]
     FunctionCall {
-        #[serde(rename = "functionCall")]
         function_call: FunctionCall,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        thought_signature: Option<String>,
     },
-    FunctionOutput {
-        function_response: FunctionResponse,
-    },
+    #[serde(rename_all = "camelCase")]
+    FunctionOutput { function_response: FunctionResponse },
 }
 
+#[allow(dead_code)]
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
 struct ResponseBody {
     candidates: Vec<Candidate>,
     usage_metadata: UsageTokens,
     model_version: String,
+    #[serde(default)]
+    response_id: Option<String>,
 }
 
+#[allow(dead_code)]
 #[derive(Clone, Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
 struct Candidate {
     content: Message,
     finish_reason: FinishReason,
-    avg_logprobs: f64,
+    avg_logprobs: Option<f64>,
 }
 
 #[derive(Clone, Debug, Deserialize)]
@@ -166,16 +253,31 @@ enum FinishReason {
     Stop,
 }
 
+#[allow(dead_code)]
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
 struct UsageTokens {
     prompt_token_count: usize,
     candidates_token_count: usize,
     total_token_count: usize,
+    #[serde(default)]
     prompt_tokens_details: Vec<TokenDetails>,
+    /// Not given by reasoning models
+    #[serde(default)]
     candidates_tokens_details: Vec<TokenDetails>,
 }
 
+impl From<UsageTokens> for Usage {
+    fn from(value: UsageTokens) -> Self {
+        Usage {
+            prompt_tokens: value.prompt_token_count,
+            completion_tokens: value.candidates_token_count,
+            total_tokens: value.total_token_count,
+        }
+    }
+}
+
+#[allow(dead_code)]
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
 struct TokenDetails {
@@ -210,13 +312,36 @@ pub(crate) struct FunctionContent {
 pub struct Gemini {
     url: Url,
     client: ModelClient,
+    schema: SchemaRules,
 }
 
 impl Gemini {
-    pub async fn new(url: String, auth: Auth) -> Gemini {
-        let (client, url) = ModelClient::new(url, auth, None, None).await;
+    pub async fn new(url: String, options: ClientOptions, schema: SchemaRules) -> Gemini {
+        let (client, url) = ModelClient::new(url, options, None, None).await;
 
-        Gemini { client, url }
+        Gemini {
+            client,
+            url,
+            schema: SchemaRules::gemini().merge(schema),
+        }
+    }
+
+    /// Client for a model of the official Gemini API, authenticated with an API key
+    pub async fn from_api(
+        model: String,
+        api_key: String,
+        options: ClientOptions,
+        schema: SchemaRules,
+    ) -> Gemini {
+        Gemini::new(
+            format!("{API_URL}/{model}:generateContent"),
+            ClientOptions {
+                auth: Auth::ApiKey(AuthLocation::Header(String::from(API_KEY_HEADER), api_key)),
+                ..options
+            },
+            schema,
+        )
+        .await
     }
 }
 
@@ -227,32 +352,41 @@ impl AIModel for Gemini {
         &self,
         body: ManagerBody,
         tools: Vec<RcmpTool>,
-    ) -> Result<Vec<ModelDecision>, ManagerError> {
+    ) -> Result<ModelTurn, ManagerError> {
+        let sampling = Sampling::new(&body, &Support::GEMINI);
         let mut body: RequestBody = body.into();
 
+        body.generation_config = GenerationConfig::new(sampling);
         body.tools = Some(vec![Tool {
             function_declarations: tools
                 .into_iter()
-                .map(|tool: RcmpTool| {
-                    let mut schema = JsonObject::clone(&tool.input_schema);
-                    remove_keys(&mut schema);
-
-                    FunctionDeclaration {
-                        name: tool.name.to_string(),
-                        description: tool.description.to_string(),
-                        parameters: schema,
-                    }
+                .map(|tool: RcmpTool| FunctionDeclaration {
+                    name: tool.name.to_string(),
+                    description: tool.description.to_string(),
+                    parameters: self.schema.sanitize(&tool.input_schema),
                 })
                 .collect(),
         }]);
 
         let response: String = self.client.call(self.url.clone(), &body).await?;
 
-        let mut response = from_str::<ResponseBody>(&response).unwrap_or_else(|error| {
+        let mut response = from_str::<ResponseBody>(&response).map_err(|error| {
             event!(Level::ERROR, "Couldn't deserialize response: {error}");
 
-            panic!()
-        });
+            ManagerError {
+                status: 502,
+                message: String::from("Invalid response from model"),
+            }
+        })?;
+
+        if response.candidates.is_empty() {
+            event!(Level::ERROR, "Model gave no candidates");
+
+            return Err(ManagerError {
+                status: 502,
+                message: String::from("Invalid response from model"),
+            });
+        }
 
         if response.candidates.len() > 1 {
             event!(
@@ -270,12 +404,18 @@ impl AIModel for Gemini {
                 let mut last_call: Option<&mut ModelDecision> = None;
                 for part in choice.content.parts.into_iter() {
                     match part {
-                        Part::Text { text } => {
+                        Part::Text { thought: true, .. } => {
+                            event!(Level::DEBUG, "Skipping thought of the model");
+                        }
+                        Part::Text { text, .. } => {
                             last_call = None;
                             result.push(ModelDecision::TextMessage(text));
                         }
-                        Part::FunctionCall { function_call } => {
-                            let id = Alphanumeric.sample_string(&mut rand::rng(), ID_LEN);
+                        Part::FunctionCall {
+                            function_call,
+                            thought_signature,
+                        } => {
+                            let id = call_id(&function_call.name);
 
                             if let Some(last) = last_call
                                 && let ModelDecision::ToolCalls(calls) = last
@@ -284,12 +424,18 @@ impl AIModel for Gemini {
                                     id,
                                     name: function_call.name,
                                     arguments: function_call.args,
+                                    thought_signature,
+                                    invalid_arguments: None,
+                                    headers: Default::default(),
                                 });
                             } else {
                                 result.push(ModelDecision::ToolCalls(vec![GeneralToolCall {
                                     id,
                                     name: function_call.name,
                                     arguments: function_call.args,
+                                    thought_signature,
+                                    invalid_arguments: None,
+                                    headers: Default::default(),
                                 }]));
                             }
 
@@ -301,18 +447,38 @@ impl AIModel for Gemini {
             }
         }
 
-        Ok(result)
+        let finish_reason = if result
+            .iter()
+            .any(|decision| matches!(decision, ModelDecision::ToolCalls(_)))
+        {
+            super::FinishReason::ToolCalls
+        } else {
+            super::FinishReason::Stop
+        };
+
+        Ok(ModelTurn {
+            decisions: result,
+            usage: Some(response.usage_metadata.into()),
+            finish_reason: Some(finish_reason),
+            provider_id: response.response_id,
+            ..Default::default()
+        })
     }
 }
 
-fn remove_keys(map: &mut JsonObject) {
-    map.remove("$schema");
-    map.remove("additionalProperties");
+/// ID of a tool call, Gemini not giving any. The name of the function is part of it, for the
+/// output of the call to be given back under that name even without the call in the history
+fn call_id(name: &str) -> String {
+    format!(
+        "{}{ID_SEPARATOR}{name}",
+        Alphanumeric.sample_string(&mut rand::rng(), ID_LEN)
+    )
+}
 
-    for value in map.values_mut() {
-        match value {
-            Value::Object(map) => remove_keys(map),
-            _ => (),
-        }
-    }
+/// Name of the function of a tool call, from an ID given by [`call_id`]
+fn call_name(id: &str) -> Option<&str> {
+    let (random, name) = id.split_at_checked(ID_LEN)?;
+
+    name.strip_prefix(ID_SEPARATOR)
+        .filter(|name| !name.is_empty() && random.chars().all(|char| char.is_ascii_alphanumeric()))
 }
@@ -1,23 +1,193 @@
+use std::{ops::AddAssign, pin::Pin};
+
 use async_trait::async_trait;
+use futures::{Stream, StreamExt, stream};
 use rmcp::model::Tool;
 use serde::{Deserialize, Serialize};
+use serde_json::from_str;
 
 use crate::{Error as ManagerError, ManagerBody, mcp::ToolCall};
 
+#[cfg(feature = "anthropic")]
 pub mod anthropic;
 pub mod auth;
+#[cfg(feature = "azure")]
 pub mod azure;
 pub mod client;
+#[cfg(feature = "cohere")]
+pub mod cohere;
+#[cfg(feature = "gemini")]
 pub mod gemini;
 pub mod openai;
+#[cfg(feature = "openai-compatible")]
+pub mod openai_compatible;
+pub(crate) mod sampling;
+pub mod schema;
+#[cfg(feature = "oauth2")]
+pub mod tokens;
+
+/// Call of a model to a tool whose arguments are given as a JSON string. Arguments that can't be
+/// parsed are kept as an error, for the model to be told and call the tool again
+pub(crate) fn tool_call(id: String, name: String, arguments: &str) -> ToolCall {
+    let (arguments, invalid_arguments) = match from_str(arguments) {
+        Ok(arguments) => (arguments, None),
+        Err(error) => (None, Some(error.to_string())),
+    };
+
+    ToolCall {
+        name,
+        id,
+        arguments,
+        thought_signature: None,
+        invalid_arguments,
+        headers: Default::default(),
+    }
+}
+
+/// Deltas of the answer of a model, as they're made
+pub type DeltaStream = Pin<Box<dyn Stream<Item = Result<ModelDelta, ManagerError>> + Send>>;
 
 #[async_trait]
 pub trait AIModel: Sync {
-    async fn call(
+    async fn call(&self, body: ManagerBody, tools: Vec<Tool>) -> Result<ModelTurn, ManagerError>;
+
+    /// Answer as deltas while it's being made. Models that can't stream give their whole turn
+    /// once made
+    async fn stream(
         &self,
         body: ManagerBody,
         tools: Vec<Tool>,
-    ) -> Result<Vec<ModelDecision>, ManagerError>;
+    ) -> Result<DeltaStream, ManagerError> {
+        let turn = self.call(body, tools).await?;
+
+        Ok(Box::pin(stream::iter(turn.into_deltas().map(Ok))))
+    }
+}
+
+/// Answer of a model to a request, with the metadata of the call
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct ModelTurn {
+    pub decisions: Vec<ModelDecision>,
+    pub usage: Option<Usage>,
+    /// Why the model stopped, when the provider tells
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub finish_reason: Option<FinishReason>,
+    /// Identifier the provider gave to the response
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provider_id: Option<String>,
+    /// Time taken by the call
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub latency_ms: Option<u128>,
+}
+
+impl ModelTurn {
+    /// Decisions of the turn as deltas, ending with its metadata
+    pub fn into_deltas(self) -> impl Iterator<Item = ModelDelta> + Send {
+        let decisions = self.decisions.into_iter().map(|decision| match decision {
+            ModelDecision::TextMessage(text) => ModelDelta::Text(text),
+            ModelDecision::ToolCalls(calls) => ModelDelta::ToolCalls(calls),
+        });
+
+        decisions.chain(std::iter::once(ModelDelta::Finish(ModelTurn {
+            decisions: Vec::new(),
+            ..self
+        })))
+    }
+
+    /// Add a delta to the turn being put together, the text fragments following each other
+    /// making a single message
+    pub(crate) fn push(&mut self, delta: &ModelDelta) {
+        match delta {
+            ModelDelta::Text(text) => match self.decisions.last_mut() {
+                Some(ModelDecision::TextMessage(message)) => message.push_str(text),
+                _ => self
+                    .decisions
+                    .push(ModelDecision::TextMessage(text.clone())),
+            },
+            ModelDelta::ToolCalls(calls) => {
+                self.decisions.push(ModelDecision::ToolCalls(calls.clone()))
+            }
+            ModelDelta::Finish(turn) => {
+                self.usage = turn.usage;
+                self.finish_reason = turn.finish_reason;
+                self.provider_id = turn.provider_id.clone();
+                self.latency_ms = turn.latency_ms;
+            }
+        }
+    }
+}
+
+/// Turn of a streamed answer, giving each text fragment to `on_text` as it comes
+pub(crate) async fn collect(
+    mut deltas: DeltaStream,
+    mut on_text: impl FnMut(&str),
+) -> Result<ModelTurn, ManagerError> {
+    let mut turn = ModelTurn::default();
+
+    while let Some(delta) = deltas.next().await {
+        let delta = delta?;
+
+        if let ModelDelta::Text(text) = &delta {
+            on_text(text);
+        }
+
+        turn.push(&delta);
+    }
+
+    Ok(turn)
+}
+
+/// Part of the answer of a model, streamed while it's being made
+#[derive(Debug)]
+pub enum ModelDelta {
+    /// Fragment of a text message
+    Text(String),
+    /// Tool calls, once complete
+    ToolCalls(Vec<ToolCall>),
+    /// End of the turn, with its metadata but without the decisions already streamed
+    Finish(ModelTurn),
+}
+
+/// Why a model stopped answering
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FinishReason {
+    /// The answer is complete
+    Stop,
+    /// The model is waiting for the outputs of its tool calls
+    ToolCalls,
+    /// The answer was cut at the maximum number of tokens
+    Length,
+    /// The answer was left out or cut by the content filter of the provider
+    ContentFilter,
+}
+
+impl FinishReason {
+    /// Name of the reason, as in the OpenAI chat completions
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FinishReason::Stop => "stop",
+            FinishReason::ToolCalls => "tool_calls",
+            FinishReason::Length => "length",
+            FinishReason::ContentFilter => "content_filter",
+        }
+    }
+}
+
+/// Tokens consumed by one or more model calls
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize)]
+pub struct Usage {
+    pub prompt_tokens: usize,
+    pub completion_tokens: usize,
+    pub total_tokens: usize,
+}
+
+impl AddAssign for Usage {
+    fn add_assign(&mut self, other: Self) {
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+        self.total_tokens += other.total_tokens;
+    }
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -47,11 +217,41 @@ pub struct TextMessage {
     pub(crate) content: String,
 }
 
+#[derive(Debug, Deserialize, Serialize)]
 pub enum ModelDecision {
     TextMessage(String),
     ToolCalls(Vec<ToolCall>),
 }
 
+/// Text answer of a model to a single prompt, for the models helping with a conversation
+pub(crate) async fn complete(
+    model: &(dyn AIModel + Send),
+    prompt: String,
+) -> Result<(String, Option<Usage>), ManagerError> {
+    let request = ManagerBody {
+        messages: vec![Message::TextMessage(TextMessage {
+            role: Role::User,
+            content: prompt,
+        })],
+        temperature: Some(0.0),
+        ..Default::default()
+    };
+
+    let response = model.call(request, Vec::new()).await?;
+
+    let answer = response
+        .decisions
+        .into_iter()
+        .filter_map(|decision| match decision {
+            ModelDecision::TextMessage(text) => Some(text),
+            ModelDecision::ToolCalls(_) => None,
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    Ok((answer, response.usage))
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(rename_all = "lowercase")]
 pub enum Role {
@@ -1,38 +1,73 @@
-use std::sync::Arc;
+// Only the wire format, shared with Azure and Anthropic, is used without the OpenAI provider
+#![cfg_attr(not(feature = "openai"), allow(dead_code, unused_imports))]
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+};
 
 use async_trait::async_trait;
-use reqwest::{Error, Url};
+use futures::{StreamExt, stream};
+use reqwest::{Response, Url};
 use rmcp::model::{JsonObject, Tool as RmcpTool};
 use serde::{Deserialize, Serialize};
 use serde_json::{from_str, json};
+use sse_stream::SseStream;
+use tokio::sync::mpsc::{UnboundedSender, unbounded_channel};
 use tracing::{Level, event};
 
 use crate::{
     Error as ManagerError, ManagerBody,
-    mcp::ToolCall as GeneralToolCall,
+    logging::log_payloads,
+    mcp::ToolCall as ManagerToolCall,
     models::{
-        AIModel, Message as ManagerMessage, ModelDecision, Role, TextMessage, auth::Auth,
-        client::ModelClient,
+        AIModel, DeltaStream, Message as ManagerMessage, ModelDecision, ModelDelta, ModelTurn,
+        Role, TextMessage, Usage,
+        client::{ClientOptions, ModelClient},
+        sampling::{Sampling, Support},
+        schema::SchemaRules,
+        tool_call,
     },
 };
 
+/// Last event of a stream
+const DONE: &str = "[DONE]";
+
 #[derive(Debug, Default, Serialize)]
 pub(crate) struct RequestBody {
     pub(crate) messages: Vec<Message>,
     pub(crate) temperature: Option<f64>,
     pub(crate) max_tokens: Option<isize>,
     pub(crate) top_p: Option<f64>,
+    /// Only sent to Anthropic, the only provider with this format taking it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) top_k: Option<u64>,
     pub(crate) tools: Option<Vec<Tool>>,
     pub(crate) tool_choice: ToolChoice,
     pub(crate) model: String,
+    /// End user of the request
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) user: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) metadata: Option<HashMap<String, String>>,
+    /// Whether the answer is streamed as chunks
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) stream: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) stream_options: Option<StreamOptions>,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct StreamOptions {
+    /// Whether the tokens used are given in a last chunk
+    pub(crate) include_usage: bool,
 }
 
 impl From<ManagerBody> for RequestBody {
     fn from(value: ManagerBody) -> Self {
         RequestBody {
-            temperature: value.temperature,
-            max_tokens: value.max_tokens,
-            top_p: value.top_p,
+            user: value.user(),
+            metadata: value.string_metadata(),
             messages: value
                 .messages
                 .into_iter()
@@ -68,6 +103,15 @@ impl From<ManagerBody> for RequestBody {
     }
 }
 
+impl RequestBody {
+    pub(crate) fn sample(&mut self, sampling: Sampling) {
+        self.temperature = sampling.temperature;
+        self.top_p = sampling.top_p;
+        self.top_k = sampling.top_k;
+        self.max_tokens = sampling.max_tokens;
+    }
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Tool {
     pub(crate) r#type: ToolType,
@@ -94,13 +138,16 @@ pub(crate) enum ToolChoice {
     Auto,
 }
 
+#[allow(dead_code)]
 #[derive(Debug, Deserialize)]
 pub(crate) struct ResponseBody {
+    #[serde(default)]
+    pub(crate) id: Option<String>,
     pub(crate) choices: Vec<Choice>,
     created: usize,
     model: String,
     object: String,
-    usage: UsageTokens,
+    pub(crate) usage: UsageTokens,
 }
 
 #[derive(Debug, Deserialize)]
@@ -110,6 +157,17 @@ pub(crate) struct UsageTokens {
     total_tokens: usize,
 }
 
+impl From<UsageTokens> for Usage {
+    fn from(value: UsageTokens) -> Self {
+        Usage {
+            prompt_tokens: value.prompt_tokens,
+            completion_tokens: value.completion_tokens,
+            total_tokens: value.total_tokens,
+        }
+    }
+}
+
+#[allow(dead_code)]
 #[derive(Debug, Deserialize)]
 pub(crate) struct Choice {
     pub(crate) finish_reason: FinishReason,
@@ -117,11 +175,79 @@ pub(crate) struct Choice {
     pub(crate) message: Message,
 }
 
-#[derive(Debug, Deserialize)]
+impl Choice {
+    /// Decision of the model, with why it stopped. An answer cut at the maximum number of tokens or
+    /// by the content filter is kept as it is (possibly empty), but not tool calls cut halfway
+    pub(crate) fn decide(self) -> Result<(ModelDecision, super::FinishReason), ManagerError> {
+        let decision = match (self.finish_reason, self.message) {
+            (FinishReason::ToolCalls, Message::ToolCalls { tool_calls, .. }) => {
+                ModelDecision::ToolCalls(
+                    tool_calls
+                        .into_iter()
+                        .map(|call| {
+                            tool_call(call.id, call.function.name, &call.function.arguments)
+                        })
+                        .collect(),
+                )
+            }
+            (
+                FinishReason::Stop | FinishReason::Length | FinishReason::ContentFilter,
+                Message::TextMessage(TextMessage { role: _, content }),
+            ) => ModelDecision::TextMessage(content),
+            (FinishReason::Length | FinishReason::ContentFilter, Message::Empty { .. }) => {
+                ModelDecision::TextMessage(String::new())
+            }
+            (FinishReason::Length, Message::ToolCalls { .. }) => {
+                event!(
+                    Level::ERROR,
+                    "Model reached the maximum number of tokens while calling tools"
+                );
+
+                return Err(ManagerError {
+                    status: 502,
+                    message: String::from(
+                        "Model reached the maximum number of tokens while calling tools",
+                    ),
+                });
+            }
+            _ => return Err(unexpected_message()),
+        };
+
+        match self.finish_reason {
+            FinishReason::Length => event!(
+                Level::WARN,
+                "Model answer was cut at the maximum number of tokens"
+            ),
+            FinishReason::ContentFilter => {
+                event!(Level::WARN, "Model answer was filtered by the provider")
+            }
+            _ => (),
+        }
+
+        Ok((decision, self.finish_reason.into()))
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub(crate) enum FinishReason {
     ToolCalls,
     Stop,
+    Length,
+    ContentFilter,
+    /// Deprecated calls of `functions`, which are never given to the model
+    FunctionCall,
+}
+
+impl From<FinishReason> for super::FinishReason {
+    fn from(value: FinishReason) -> Self {
+        match value {
+            FinishReason::ToolCalls | FinishReason::FunctionCall => super::FinishReason::ToolCalls,
+            FinishReason::Stop => super::FinishReason::Stop,
+            FinishReason::Length => super::FinishReason::Length,
+            FinishReason::ContentFilter => super::FinishReason::ContentFilter,
+        }
+    }
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -137,11 +263,10 @@ pub(crate) enum Message {
         tool_call_id: String,
         content: String,
     },
-}
-
-#[derive(Clone, Debug, Deserialize, Serialize)]
-pub(crate) struct ToolOutputResult {
-    result: String,
+    /// Answer without content, left out by the content filter or cut before it started
+    Empty {
+        role: Role,
+    },
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -157,29 +282,297 @@ pub(crate) struct ToolCallParams {
     pub(crate) name: String,
 }
 
+/// Event of a streamed response, with a fragment of the answer
+#[derive(Debug, Deserialize)]
+pub(crate) struct ChunkBody {
+    #[serde(default)]
+    pub(crate) id: Option<String>,
+    #[serde(default)]
+    pub(crate) choices: Vec<ChunkChoice>,
+    /// Only in the last chunk, when asked for
+    #[serde(default)]
+    pub(crate) usage: Option<UsageTokens>,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct ChunkChoice {
+    #[serde(default)]
+    pub(crate) index: usize,
+    #[serde(default)]
+    pub(crate) delta: ChunkDelta,
+    #[serde(default)]
+    pub(crate) finish_reason: Option<FinishReason>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub(crate) struct ChunkDelta {
+    #[serde(default)]
+    pub(crate) content: Option<String>,
+    #[serde(default)]
+    pub(crate) tool_calls: Option<Vec<ToolCallChunk>>,
+}
+
+/// Fragment of a tool call, the calls being told apart by their index
+#[derive(Debug, Deserialize)]
+pub(crate) struct ToolCallChunk {
+    pub(crate) index: usize,
+    #[serde(default)]
+    pub(crate) id: Option<String>,
+    #[serde(default)]
+    pub(crate) function: Option<FunctionChunk>,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct FunctionChunk {
+    #[serde(default)]
+    pub(crate) name: Option<String>,
+    #[serde(default)]
+    pub(crate) arguments: Option<String>,
+}
+
+/// Tool calls of a streamed response, put together from their fragments by their index
+#[derive(Debug, Default)]
+pub(crate) struct ToolCallAssembly {
+    calls: BTreeMap<usize, ToolCall>,
+}
+
+impl ToolCallAssembly {
+    /// Add a fragment to its call. The ID and name come whole in the first fragment of a call
+    /// (some servers giving them again in the next ones), the arguments being split between them
+    pub(crate) fn push(&mut self, fragment: ToolCallChunk) {
+        let call = self
+            .calls
+            .entry(fragment.index)
+            .or_insert_with(|| ToolCall {
+                function: ToolCallParams {
+                    arguments: String::new(),
+                    name: String::new(),
+                },
+                r#type: ToolType::Function,
+                id: String::new(),
+            });
+
+        if let Some(id) = fragment.id
+            && call.id.is_empty()
+        {
+            call.id = id;
+        }
+
+        if let Some(function) = fragment.function {
+            if let Some(name) = function.name
+                && call.function.name.is_empty()
+            {
+                call.function.name = name;
+            }
+
+            if let Some(arguments) = function.arguments {
+                call.function.arguments.push_str(&arguments);
+            }
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.calls.is_empty()
+    }
+
+    /// Calls in the order of their index, as they'd be in a response that isn't streamed
+    pub(crate) fn finish(self) -> Vec<ManagerToolCall> {
+        self.calls
+            .into_values()
+            .map(|call| tool_call(call.id, call.function.name, &call.function.arguments))
+            .collect()
+    }
+}
+
+/// Deltas of a streamed response, read from its events in the background until the stream ends
+/// or the deltas aren't wanted anymore
+pub(crate) fn stream_deltas(response: Response) -> DeltaStream {
+    let (sender, receiver) = unbounded_channel();
+
+    tokio::spawn(async move {
+        let result = read_chunks(response, &sender).await;
+
+        let _ = sender.send(result.map(ModelDelta::Finish));
+    });
+
+    Box::pin(stream::unfold(receiver, |mut receiver| async move {
+        let delta = receiver.recv().await?;
+
+        Some((delta, receiver))
+    }))
+}
+
+/// Send the text of the chunks as it comes and the tool calls once complete, answering the
+/// metadata of the turn. Only the first choice is followed, as in a response that isn't streamed
+async fn read_chunks(
+    response: Response,
+    sender: &UnboundedSender<Result<ModelDelta, ManagerError>>,
+) -> Result<ModelTurn, ManagerError> {
+    let mut events = SseStream::from_byte_stream(response.bytes_stream());
+    let mut calls = ToolCallAssembly::default();
+    let mut finish_reason = None;
+    let mut turn = ModelTurn::default();
+
+    while let Some(event) = events.next().await {
+        let data = match event {
+            Ok(event) => event.data,
+            Err(error) => {
+                event!(Level::ERROR, "Stream of model failed: {error}");
+
+                return Err(ManagerError {
+                    status: 502,
+                    message: String::from("Stream of model failed"),
+                });
+            }
+        };
+
+        let Some(data) = data else {
+            continue;
+        };
+
+        if data == DONE {
+            break;
+        }
+
+        if log_payloads() {
+            event!(Level::DEBUG, "Chunk: {data}");
+        }
+
+        let chunk = from_str::<ChunkBody>(&data).map_err(|error| {
+            event!(Level::ERROR, "Couldn't deserialize chunk: {error}");
+
+            ManagerError {
+                status: 502,
+                message: String::from("Invalid response from model"),
+            }
+        })?;
+
+        if turn.provider_id.is_none() {
+            turn.provider_id = chunk.id;
+        }
+
+        if let Some(usage) = chunk.usage {
+            turn.usage = Some(usage.into());
+        }
+
+        for choice in chunk.choices.into_iter().filter(|choice| choice.index == 0) {
+            // Nothing reads the deltas anymore
+            if let Some(content) = choice.delta.content
+                && !content.is_empty()
+                && sender.send(Ok(ModelDelta::Text(content))).is_err()
+            {
+                return Ok(turn);
+            }
+
+            for fragment in choice.delta.tool_calls.into_iter().flatten() {
+                calls.push(fragment);
+            }
+
+            if choice.finish_reason.is_some() {
+                finish_reason = choice.finish_reason;
+            }
+        }
+    }
+
+    let Some(finish_reason) = finish_reason else {
+        event!(
+            Level::ERROR,
+            "Stream of model ended without a finish reason"
+        );
+
+        return Err(ManagerError {
+            status: 502,
+            message: String::from("Invalid response from model"),
+        });
+    };
+
+    match finish_reason {
+        FinishReason::ToolCalls | FinishReason::FunctionCall if !calls.is_empty() => {
+            let _ = sender.send(Ok(ModelDelta::ToolCalls(calls.finish())));
+        }
+        FinishReason::Length if !calls.is_empty() => {
+            event!(
+                Level::ERROR,
+                "Model reached the maximum number of tokens while calling tools"
+            );
+
+            return Err(ManagerError {
+                status: 502,
+                message: String::from(
+                    "Model reached the maximum number of tokens while calling tools",
+                ),
+            });
+        }
+        FinishReason::Stop | FinishReason::Length | FinishReason::ContentFilter
+            if calls.is_empty() =>
+        {
+            match finish_reason {
+                FinishReason::Length => event!(
+                    Level::WARN,
+                    "Model answer was cut at the maximum number of tokens"
+                ),
+                FinishReason::ContentFilter => {
+                    event!(Level::WARN, "Model answer was filtered by the provider")
+                }
+                _ => (),
+            }
+        }
+        _ => return Err(unexpected_message()),
+    }
+
+    turn.finish_reason = Some(finish_reason.into());
+
+    Ok(turn)
+}
+
+/// Failure of a response whose message doesn't match its finish reason
+pub(crate) fn unexpected_message() -> ManagerError {
+    event!(
+        Level::ERROR,
+        "Model gave a message not matching its finish reason"
+    );
+
+    ManagerError {
+        status: 502,
+        message: String::from("Invalid response from model"),
+    }
+}
+
+#[cfg(feature = "openai")]
 pub struct OpenAI {
     url: Url,
     client: ModelClient,
+    schema: SchemaRules,
     model: String,
 }
 
+#[cfg(feature = "openai")]
 impl OpenAI {
-    pub async fn new(url: String, auth: Auth, model: String) -> OpenAI {
-        let (client, url) = ModelClient::new(url, auth, None, None).await;
+    pub async fn new(
+        url: String,
+        options: ClientOptions,
+        schema: SchemaRules,
+        model: String,
+    ) -> OpenAI {
+        let (client, url) = ModelClient::new(url, options, None, None).await;
 
-        OpenAI { client, url, model }
+        OpenAI {
+            client,
+            url,
+            schema,
+            model,
+        }
     }
 }
 
-#[async_trait]
-impl AIModel for OpenAI {
-    async fn call(
-        &self,
-        body: ManagerBody,
-        tools: Vec<RmcpTool>,
-    ) -> Result<Vec<ModelDecision>, ManagerError> {
+#[cfg(feature = "openai")]
+impl OpenAI {
+    /// Request of the workspace in the format of the API, with the tools of the workspace
+    fn request(&self, body: ManagerBody, tools: Vec<RmcpTool>) -> RequestBody {
+        let sampling = Sampling::new(&body, &Support::OPENAI);
         let mut body: RequestBody = body.into();
 
+        body.sample(sampling);
         body.model = self.model.clone();
         body.tools = Some(
             tools
@@ -189,19 +582,45 @@ impl AIModel for OpenAI {
                     function: Function {
                         name: tool.name.into_owned(),
                         description: tool.description.into_owned(),
-                        parameters: tool.input_schema,
+                        parameters: Arc::new(self.schema.sanitize(&tool.input_schema)),
                     },
                 })
                 .collect(),
         );
 
+        body
+    }
+}
+
+#[cfg(feature = "openai")]
+#[async_trait]
+impl AIModel for OpenAI {
+    async fn call(
+        &self,
+        body: ManagerBody,
+        tools: Vec<RmcpTool>,
+    ) -> Result<ModelTurn, ManagerError> {
+        let body = self.request(body, tools);
+
         let response = self.client.call(self.url.clone(), &body).await?;
 
-        let mut response = from_str::<ResponseBody>(&response).unwrap_or_else(|error| {
+        let mut response = from_str::<ResponseBody>(&response).map_err(|error| {
             event!(Level::ERROR, "Couldn't deserialize response: {error}");
 
-            panic!()
-        });
+            ManagerError {
+                status: 502,
+                message: String::from("Invalid response from model"),
+            }
+        })?;
+
+        if response.choices.is_empty() {
+            event!(Level::ERROR, "Model gave no choices");
+
+            return Err(ManagerError {
+                status: 502,
+                message: String::from("Invalid response from model"),
+            });
+        }
 
         if response.choices.len() > 1 {
             event!(
@@ -212,25 +631,119 @@ impl AIModel for OpenAI {
 
         let choice = response.choices.remove(0);
 
-        Ok(vec![match choice.finish_reason {
-            FinishReason::Stop => ModelDecision::TextMessage(match choice.message {
-                Message::TextMessage(TextMessage { role: _, content }) => content,
-                _ => todo!("Unknown response needs to be handled: {response:#?}"),
-            }),
-            FinishReason::ToolCalls => ModelDecision::ToolCalls(match choice.message {
-                Message::ToolCalls {
-                    role: _,
-                    tool_calls,
-                } => tool_calls
-                    .into_iter()
-                    .map(|call| GeneralToolCall {
-                        name: call.function.name,
-                        id: call.id,
-                        arguments: from_str(&call.function.arguments).unwrap(),
-                    })
-                    .collect(),
-                _ => todo!("Unknown response needs to be handled: {response:#?}"),
-            }),
-        }])
+        let (decision, finish_reason) = choice.decide()?;
+
+        Ok(ModelTurn {
+            decisions: vec![decision],
+            usage: Some(response.usage.into()),
+            finish_reason: Some(finish_reason),
+            provider_id: response.id,
+            ..Default::default()
+        })
+    }
+
+    async fn stream(
+        &self,
+        body: ManagerBody,
+        tools: Vec<RmcpTool>,
+    ) -> Result<DeltaStream, ManagerError> {
+        let mut body = self.request(body, tools);
+
+        body.stream = Some(true);
+        body.stream_options = Some(StreamOptions {
+            include_usage: true,
+        });
+
+        let response = self.client.stream(self.url.clone(), &body).await?;
+
+        Ok(stream_deltas(response))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http;
+
+    use super::*;
+
+    const ARGUMENTS: &str = r#"{\"path\": \"/tmp\", \"depth\": 2}"#;
+
+    /// Tool calls of a turn, with everything they hold
+    fn calls(decision: &ModelDecision) -> String {
+        match decision {
+            ModelDecision::ToolCalls(calls) => format!("{calls:?}"),
+            ModelDecision::TextMessage(text) => panic!("Unexpected answer: {text}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn streams_same_tool_calls() {
+        let response: ResponseBody = from_str(&format!(
+            r#"{{
+                "id": "chatcmpl-1",
+                "created": 0,
+                "model": "gpt",
+                "object": "chat.completion",
+                "usage": {{"completion_tokens": 1, "prompt_tokens": 1, "total_tokens": 2}},
+                "choices": [{{
+                    "finish_reason": "tool_calls",
+                    "index": 0,
+                    "message": {{
+                        "role": "assistant",
+                        "tool_calls": [
+                            {{
+                                "id": "call_1",
+                                "type": "function",
+                                "function": {{"name": "list", "arguments": "{ARGUMENTS}"}}
+                            }},
+                            {{
+                                "id": "call_2",
+                                "type": "function",
+                                "function": {{"name": "read", "arguments": "{{\"path\""}}
+                            }}
+                        ]
+                    }}
+                }}]
+            }}"#
+        ))
+        .unwrap();
+        let (decision, _) = response
+            .choices
+            .into_iter()
+            .next()
+            .unwrap()
+            .decide()
+            .unwrap();
+
+        // The second call comes first, and some servers give the ID and name again
+        let chunks = [
+            r#"{"choices": [{"delta": {"role": "assistant", "content": null}}]}"#,
+            r#"{"choices": [{"delta": {"tool_calls": [{"index": 1, "id": "call_2", "type": "function", "function": {"name": "read", "arguments": ""}}]}}]}"#,
+            r#"{"choices": [{"delta": {"tool_calls": [{"index": 0, "id": "call_1", "type": "function", "function": {"name": "list", "arguments": "{\"path\": "}}]}}]}"#,
+            r#"{"choices": [{"delta": {"tool_calls": [{"index": 1, "function": {"arguments": "{\"path\""}}]}}]}"#,
+            r#"{"choices": [{"delta": {"tool_calls": [{"index": 0, "id": "call_1", "function": {"name": "list", "arguments": "\"/tmp\", \"depth\": 2}"}}]}}]}"#,
+            r#"{"choices": [{"delta": {}, "finish_reason": "tool_calls"}]}"#,
+            r#"{"choices": [], "usage": {"completion_tokens": 1, "prompt_tokens": 1, "total_tokens": 2}}"#,
+        ];
+        let events: String = chunks
+            .iter()
+            .chain([&DONE])
+            .map(|data| format!("data: {data}\n\n"))
+            .collect();
+
+        let deltas: Vec<_> = stream_deltas(Response::from(http::Response::new(events)))
+            .collect()
+            .await;
+
+        let streamed = deltas
+            .into_iter()
+            .find_map(|delta| match delta.unwrap() {
+                ModelDelta::ToolCalls(calls) => Some(ModelDecision::ToolCalls(calls)),
+                _ => None,
+            })
+            .unwrap();
+
+        assert_eq!(calls(&streamed), calls(&decision));
+        assert!(calls(&streamed).contains("invalid_arguments: Some("));
     }
 }
@@ -0,0 +1,381 @@
+use std::sync::{
+    Arc,
+    atomic::{AtomicUsize, Ordering},
+};
+
+use async_trait::async_trait;
+use reqwest::Url;
+use rmcp::model::{JsonObject, Tool as RmcpTool};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value, from_str, json};
+use tracing::{Level, event, instrument};
+
+use crate::{
+    Error as ManagerError, ManagerBody,
+    models::{
+        AIModel, FinishReason, Message as ManagerMessage, ModelDecision, ModelTurn, Role,
+        TextMessage, Usage,
+        client::{ClientOptions, ModelClient},
+        sampling::{Sampling, Support},
+        schema::SchemaRules,
+        tool_call,
+    },
+};
+
+#[cfg(feature = "xai")]
+use crate::models::auth::{Auth, AuthLocation};
+
+const DEFAULT_MAX_TOKENS_FIELD: &str = "max_tokens";
+/// Chat completions endpoint of the xAI API
+#[cfg(feature = "xai")]
+const XAI_URL: &str = "https://api.x.ai/v1/chat/completions";
+
+/// Deviations from the OpenAI chat completions API of a compatible gateway
+#[derive(Clone, Debug)]
+pub struct Quirks {
+    /// Whether an assistant message can hold several tool calls
+    pub supports_parallel_tool_calls: bool,
+    /// Whether tool calls and their outputs are matched by ID
+    pub tool_call_id_required: bool,
+    /// Name of the field limiting the generated tokens
+    pub max_tokens_field: String,
+}
+
+impl Quirks {
+    /// Grok takes parallel tool calls matched by ID, and deprecated `max_tokens` in favor of
+    /// `max_completion_tokens`, which its reasoning models also count their reasoning in
+    #[cfg(feature = "xai")]
+    fn xai() -> Quirks {
+        Quirks {
+            max_tokens_field: String::from("max_completion_tokens"),
+            ..Default::default()
+        }
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Quirks {
+            supports_parallel_tool_calls: true,
+            tool_call_id_required: true,
+            max_tokens_field: String::from(DEFAULT_MAX_TOKENS_FIELD),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+enum Message {
+    TextMessage(TextMessage),
+    ToolCalls {
+        role: Role,
+        tool_calls: Vec<ToolCall>,
+    },
+    ToolOutput {
+        role: Role,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        tool_call_id: Option<String>,
+        content: String,
+    },
+}
+
+#[derive(Debug, Serialize)]
+struct Tool {
+    r#type: ToolType,
+    function: Function,
+}
+
+#[derive(Debug, Serialize)]
+struct Function {
+    name: String,
+    description: String,
+    parameters: Arc<JsonObject>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum ToolType {
+    #[default]
+    Function,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct ToolCall {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<String>,
+    #[serde(default)]
+    r#type: ToolType,
+    function: ToolCallParams,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct ToolCallParams {
+    name: String,
+    arguments: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ResponseBody {
+    #[serde(default)]
+    id: Option<String>,
+    choices: Vec<Choice>,
+    usage: Option<ResponseUsage>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Choice {
+    message: ResponseMessage,
+}
+
+#[derive(Debug, Deserialize)]
+struct ResponseMessage {
+    content: Option<String>,
+    tool_calls: Option<Vec<ToolCall>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ResponseUsage {
+    prompt_tokens: usize,
+    completion_tokens: usize,
+    total_tokens: Option<usize>,
+}
+
+impl From<ResponseUsage> for Usage {
+    fn from(value: ResponseUsage) -> Self {
+        Usage {
+            prompt_tokens: value.prompt_tokens,
+            completion_tokens: value.completion_tokens,
+            total_tokens: value
+                .total_tokens
+                .unwrap_or(value.prompt_tokens + value.completion_tokens),
+        }
+    }
+}
+
+/// Gateway implementing the OpenAI chat completions API with some deviations (e.g. vLLM, LM Studio, DeepSeek)
+pub struct OpenAICompatible {
+    url: Url,
+    client: ModelClient,
+    schema: SchemaRules,
+    model: String,
+    quirks: Quirks,
+    calls: AtomicUsize,
+}
+
+impl OpenAICompatible {
+    pub async fn new(
+        url: String,
+        options: ClientOptions,
+        schema: SchemaRules,
+        model: String,
+        quirks: Quirks,
+    ) -> OpenAICompatible {
+        let (client, url) = ModelClient::new(url, options, None, None).await;
+
+        OpenAICompatible {
+            client,
+            url,
+            schema,
+            model,
+            quirks,
+            calls: AtomicUsize::new(0),
+        }
+    }
+
+    /// Grok model of the xAI API, authenticated with its API key unless another authentication
+    /// is configured (e.g. behind a gateway)
+    #[cfg(feature = "xai")]
+    pub async fn from_xai(
+        url: Option<String>,
+        model: String,
+        api_key: Option<String>,
+        options: ClientOptions,
+        schema: SchemaRules,
+    ) -> OpenAICompatible {
+        let options = match api_key {
+            Some(api_key) => ClientOptions {
+                auth: Auth::ApiKey(AuthLocation::Header(
+                    String::from("Authorization"),
+                    format!("Bearer {api_key}"),
+                )),
+                ..options
+            },
+            None => options,
+        };
+
+        OpenAICompatible::new(
+            url.unwrap_or_else(|| String::from(XAI_URL)),
+            options,
+            schema,
+            model,
+            Quirks::xai(),
+        )
+        .await
+    }
+
+    fn messages(&self, messages: Vec<ManagerMessage>) -> Vec<Message> {
+        let id = |id: String| Some(id).filter(|_| self.quirks.tool_call_id_required);
+        let mut result = Vec::new();
+        // Tool calls still waiting to be placed before their output, when they go one per message
+        let mut pending = Vec::new();
+
+        for message in messages.into_iter() {
+            match message {
+                ManagerMessage::TextMessage(message) => result.push(Message::TextMessage(message)),
+                ManagerMessage::ToolCalls { role, tool_calls } => {
+                    let tool_calls = tool_calls.into_iter().map(|call| ToolCall {
+                        id: id(call.id),
+                        r#type: ToolType::Function,
+                        function: ToolCallParams {
+                            name: call.name,
+                            arguments: json!(call.arguments).to_string(),
+                        },
+                    });
+
+                    if self.quirks.supports_parallel_tool_calls {
+                        result.push(Message::ToolCalls {
+                            role,
+                            tool_calls: tool_calls.collect(),
+                        });
+                    } else {
+                        result.extend(pending.drain(..).rev());
+                        pending = tool_calls
+                            .map(|call| Message::ToolCalls {
+                                role: role.clone(),
+                                tool_calls: vec![call],
+                            })
+                            .rev()
+                            .collect();
+                    }
+                }
+                ManagerMessage::ToolOutput {
+                    call_id, output, ..
+                } => {
+                    if let Some(call) = pending.pop() {
+                        result.push(call);
+                    }
+
+                    result.push(Message::ToolOutput {
+                        role: Role::Tool,
+                        tool_call_id: id(call_id),
+                        content: output,
+                    });
+                }
+            }
+        }
+
+        result.extend(pending.into_iter().rev());
+
+        result
+    }
+}
+
+#[async_trait]
+impl AIModel for OpenAICompatible {
+    #[instrument(skip_all)]
+    async fn call(
+        &self,
+        body: ManagerBody,
+        tools: Vec<RmcpTool>,
+    ) -> Result<ModelTurn, ManagerError> {
+        let sampling = Sampling::new(&body, &Support::OPENAI_COMPATIBLE);
+        let mut request = Map::new();
+
+        request.insert(String::from("model"), json!(self.model));
+        request.insert(String::from("temperature"), json!(sampling.temperature));
+        request.insert(String::from("top_p"), json!(sampling.top_p));
+        request.insert(
+            self.quirks.max_tokens_field.clone(),
+            json!(sampling.max_tokens),
+        );
+        request.insert(String::from("user"), json!(body.user()));
+
+        if !tools.is_empty() {
+            let tools: Vec<Tool> = tools
+                .into_iter()
+                .map(|tool: RmcpTool| Tool {
+                    r#type: ToolType::Function,
+                    function: Function {
+                        name: tool.name.into_owned(),
+                        description: tool.description.into_owned(),
+                        parameters: Arc::new(self.schema.sanitize(&tool.input_schema)),
+                    },
+                })
+                .collect();
+
+            request.insert(String::from("tools"), json!(tools));
+            request.insert(String::from("tool_choice"), json!("auto"));
+        }
+
+        request.insert(
+            String::from("messages"),
+            json!(self.messages(body.messages)),
+        );
+        request.retain(|_, value| !value.is_null());
+
+        let response = self
+            .client
+            .call(self.url.clone(), &Value::Object(request))
+            .await?;
+
+        let mut response = from_str::<ResponseBody>(&response).map_err(|error| {
+            event!(Level::ERROR, "Couldn't deserialize response: {error}");
+
+            ManagerError {
+                status: 502,
+                message: String::from("Invalid response from model"),
+            }
+        })?;
+
+        if response.choices.is_empty() {
+            event!(Level::ERROR, "Model gave no choices");
+
+            return Err(ManagerError {
+                status: 502,
+                message: String::from("Invalid response from model"),
+            });
+        }
+
+        if response.choices.len() > 1 {
+            event!(
+                Level::WARN,
+                "Model gave multiple choices, moving on with first one"
+            )
+        }
+
+        let message = response.choices.remove(0).message;
+
+        // Some gateways finish with "stop" even when calling tools, so their presence is what counts
+        let (decision, finish_reason) = match message.tool_calls {
+            Some(tool_calls) if !tool_calls.is_empty() => (
+                ModelDecision::ToolCalls(
+                    tool_calls
+                        .into_iter()
+                        .map(|call| {
+                            tool_call(
+                                call.id.unwrap_or_else(|| {
+                                    format!("call_{}", self.calls.fetch_add(1, Ordering::Relaxed))
+                                }),
+                                call.function.name,
+                                &call.function.arguments,
+                            )
+                        })
+                        .collect(),
+                ),
+                FinishReason::ToolCalls,
+            ),
+            _ => (
+                ModelDecision::TextMessage(message.content.unwrap_or_default()),
+                FinishReason::Stop,
+            ),
+        };
+
+        Ok(ModelTurn {
+            decisions: vec![decision],
+            usage: response.usage.map(Usage::from),
+            finish_reason: Some(finish_reason),
+            provider_id: response.id,
+            ..Default::default()
+        })
+    }
+}
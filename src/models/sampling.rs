@@ -0,0 +1,93 @@
+use tracing::{Level, event};
+
+use crate::ManagerBody;
+
+/// Sampling parameters a provider takes besides the temperature, `top_p` and `max_tokens`, which
+/// all of them take
+pub(crate) struct Support {
+    provider: &'static str,
+    top_k: bool,
+    /// Highest temperature accepted, when lower than the one of OpenAI
+    max_temperature: Option<f64>,
+}
+
+impl Support {
+    #[cfg(feature = "openai")]
+    pub(crate) const OPENAI: Support = Support {
+        provider: "OpenAI",
+        top_k: false,
+        max_temperature: None,
+    };
+    #[cfg(feature = "azure")]
+    pub(crate) const AZURE: Support = Support {
+        provider: "Azure OpenAI",
+        top_k: false,
+        max_temperature: None,
+    };
+    #[cfg(feature = "openai-compatible")]
+    pub(crate) const OPENAI_COMPATIBLE: Support = Support {
+        provider: "OpenAI compatible",
+        top_k: false,
+        max_temperature: None,
+    };
+    #[cfg(feature = "anthropic")]
+    pub(crate) const ANTHROPIC: Support = Support {
+        provider: "Anthropic",
+        top_k: true,
+        max_temperature: Some(1.0),
+    };
+    #[cfg(feature = "gemini")]
+    pub(crate) const GEMINI: Support = Support {
+        provider: "Gemini",
+        top_k: true,
+        max_temperature: None,
+    };
+    #[cfg(feature = "cohere")]
+    pub(crate) const COHERE: Support = Support {
+        provider: "Cohere",
+        top_k: true,
+        max_temperature: None,
+    };
+}
+
+/// Sampling parameters of a request, each provider giving them to its model in its own fields
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct Sampling {
+    pub(crate) temperature: Option<f64>,
+    pub(crate) top_p: Option<f64>,
+    pub(crate) top_k: Option<u64>,
+    pub(crate) max_tokens: Option<isize>,
+}
+
+impl Sampling {
+    /// Sampling parameters of the request the provider takes, the others being left out and too
+    /// high temperatures lowered, with a warning for the client to know its request changed
+    pub(crate) fn new(body: &ManagerBody, support: &Support) -> Sampling {
+        let provider = support.provider;
+        let mut top_k = body.top_k;
+        let mut temperature = body.temperature;
+
+        if !support.top_k && top_k.take().is_some() {
+            event!(Level::WARN, "{provider} doesn't take top_k, leaving it out");
+        }
+
+        if let Some(max_temperature) = support.max_temperature
+            && let Some(value) = temperature
+            && value > max_temperature
+        {
+            event!(
+                Level::WARN,
+                "{provider} takes temperatures up to {max_temperature}, lowering {value} to it"
+            );
+
+            temperature = Some(max_temperature);
+        }
+
+        Sampling {
+            temperature,
+            top_p: body.top_p,
+            top_k,
+            max_tokens: body.max_tokens,
+        }
+    }
+}
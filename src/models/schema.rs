@@ -0,0 +1,114 @@
+use rmcp::model::JsonObject;
+use serde_json::Value;
+
+/// Keywords holding a map of subschemas
+const SCHEMA_MAPS: [&str; 4] = ["properties", "patternProperties", "$defs", "definitions"];
+/// Keywords holding a single subschema
+const SCHEMA_VALUES: [&str; 3] = ["items", "additionalProperties", "not"];
+/// Keywords holding a list of subschemas
+const SCHEMA_LISTS: [&str; 3] = ["anyOf", "oneOf", "allOf"];
+
+/// Rules applied to the input schema of the tools before giving them to a model
+#[derive(Clone, Debug, Default)]
+pub struct SchemaRules {
+    /// Keywords removed from every (sub)schema
+    pub unsupported_keywords: Vec<String>,
+    /// Maximum nesting of objects and arrays, deeper levels lose their structure
+    pub max_depth: Option<usize>,
+    /// Convert enums with non-string values into string enums
+    pub string_enums: bool,
+}
+
+impl SchemaRules {
+    /// Rules for the Gemini API, which only supports an OpenAPI subset of JSON schema
+    pub fn gemini() -> SchemaRules {
+        SchemaRules {
+            unsupported_keywords: vec![
+                String::from("$schema"),
+                String::from("additionalProperties"),
+            ],
+            max_depth: None,
+            string_enums: true,
+        }
+    }
+
+    /// Add the rules of `other` to these ones
+    pub fn merge(mut self, other: SchemaRules) -> SchemaRules {
+        for keyword in other.unsupported_keywords {
+            if !self.unsupported_keywords.contains(&keyword) {
+                self.unsupported_keywords.push(keyword);
+            }
+        }
+
+        self.max_depth = match (self.max_depth, other.max_depth) {
+            (Some(depth), Some(other)) => Some(depth.min(other)),
+            (depth, other) => depth.or(other),
+        };
+        self.string_enums |= other.string_enums;
+
+        self
+    }
+
+    pub fn sanitize(&self, schema: &JsonObject) -> JsonObject {
+        let mut schema = schema.clone();
+
+        self.sanitize_level(&mut schema, 0);
+
+        schema
+    }
+
+    fn sanitize_level(&self, schema: &mut JsonObject, depth: usize) {
+        for keyword in self.unsupported_keywords.iter() {
+            schema.remove(keyword);
+        }
+
+        if self.string_enums
+            && let Some(Value::Array(values)) = schema.get_mut("enum")
+            && values.iter().any(|value| !value.is_string())
+        {
+            for value in values.iter_mut() {
+                if !value.is_string() {
+                    *value = Value::String(value.to_string());
+                }
+            }
+
+            schema.insert(String::from("type"), Value::String(String::from("string")));
+        }
+
+        if let Some(max_depth) = self.max_depth
+            && depth >= max_depth
+        {
+            for keyword in SCHEMA_MAPS.iter().chain(SCHEMA_VALUES.iter()) {
+                schema.remove(*keyword);
+            }
+
+            return;
+        }
+
+        for keyword in SCHEMA_MAPS {
+            if let Some(Value::Object(map)) = schema.get_mut(keyword) {
+                for value in map.values_mut() {
+                    if let Value::Object(subschema) = value {
+                        self.sanitize_level(subschema, depth + 1);
+                    }
+                }
+            }
+        }
+
+        for keyword in SCHEMA_VALUES {
+            if let Some(Value::Object(subschema)) = schema.get_mut(keyword) {
+                self.sanitize_level(subschema, depth + 1);
+            }
+        }
+
+        for keyword in SCHEMA_LISTS {
+            if let Some(Value::Array(values)) = schema.get_mut(keyword) {
+                for value in values.iter_mut() {
+                    if let Value::Object(subschema) = value {
+                        self.sanitize_level(subschema, depth);
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,128 @@
+use std::{collections::HashMap, io, path::Path};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::{io::AsyncWriteExt, sync::Mutex};
+use tracing::{Level, event};
+
+/// Identity a client credentials token was issued to
+#[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub(crate) struct TokenKey {
+    pub(crate) url: String,
+    pub(crate) client_id: String,
+    pub(crate) scope: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub(crate) struct TokenData {
+    pub(crate) token: String,
+    pub(crate) expiration: DateTime<Utc>,
+}
+
+/// Entry of the file the cache is persisted to
+#[derive(Deserialize, Serialize)]
+struct CachedToken {
+    #[serde(flatten)]
+    key: TokenKey,
+    #[serde(flatten)]
+    data: TokenData,
+}
+
+/// Client credentials tokens shared by the models using the same identity provider
+#[derive(Debug, Default)]
+pub struct TokenCache {
+    path: Option<String>,
+    tokens: Mutex<HashMap<TokenKey, TokenData>>,
+}
+
+impl TokenCache {
+    /// Cache persisted to `path`, starting with its tokens that are still valid
+    pub async fn new(path: Option<String>) -> TokenCache {
+        let mut tokens = HashMap::new();
+
+        if let Some(path) = &path {
+            match load(path).await {
+                Ok(cached) => {
+                    let now = Utc::now();
+
+                    tokens.extend(
+                        cached
+                            .into_iter()
+                            .filter(|token| token.data.expiration > now)
+                            .map(|token| (token.key, token.data)),
+                    );
+
+                    event!(Level::INFO, "Loaded {} token(s) from {path}", tokens.len());
+                }
+                Err(error) if error.kind() == io::ErrorKind::NotFound => (),
+                Err(error) => event!(Level::WARN, "Couldn't load tokens from {path}: {error}"),
+            }
+        }
+
+        TokenCache {
+            path,
+            tokens: Mutex::new(tokens),
+        }
+    }
+
+    /// Token of an identity, only fetched when there's no valid one in the cache
+    pub(crate) async fn get<F, Fut, E>(&self, key: &TokenKey, fetch: F) -> Result<String, E>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<TokenData, E>>,
+    {
+        let mut tokens = self.tokens.lock().await;
+
+        if let Some(data) = tokens.get(key)
+            && data.expiration > Utc::now()
+        {
+            return Ok(data.token.clone());
+        }
+
+        let data = fetch().await?;
+        let token = data.token.clone();
+
+        tokens.insert(key.clone(), data);
+
+        if let Some(path) = &self.path
+            && let Err(error) = save(path, &tokens).await
+        {
+            event!(Level::WARN, "Couldn't save tokens to {path}: {error}");
+        }
+
+        Ok(token)
+    }
+}
+
+async fn load(path: &str) -> io::Result<Vec<CachedToken>> {
+    let content = tokio::fs::read(path).await?;
+
+    serde_json::from_slice(&content).map_err(io::Error::other)
+}
+
+/// Write the tokens to a file only readable by the current user
+async fn save(path: &str, tokens: &HashMap<TokenKey, TokenData>) -> io::Result<()> {
+    let cached: Vec<CachedToken> = tokens
+        .iter()
+        .map(|(key, data)| CachedToken {
+            key: key.clone(),
+            data: data.clone(),
+        })
+        .collect();
+
+    let content = serde_json::to_vec(&cached).map_err(io::Error::other)?;
+
+    let mut options = tokio::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+
+    #[cfg(unix)]
+    options.mode(0o600);
+
+    let temporary = format!("{path}.tmp");
+
+    let mut file = options.open(&temporary).await?;
+    file.write_all(&content).await?;
+    file.flush().await?;
+
+    tokio::fs::rename(&temporary, Path::new(path)).await
+}
@@ -0,0 +1,213 @@
+use std::collections::HashMap;
+
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use serde_json::{from_str, json};
+use tracing::{Level, event, instrument};
+
+use crate::{
+    Error, ManagerBody,
+    models::{
+        Message, Role, TextMessage,
+        client::{ClientOptions, ModelClient},
+    },
+};
+
+/// Severity from which Azure Content Safety flags a category, out of 0, 2, 4 and 6
+const DEFAULT_AZURE_THRESHOLD: f64 = 4.0;
+
+/// API used to moderate the user messages
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ModerationProvider {
+    OpenAI,
+    Azure,
+}
+
+/// Check of the user messages by a moderation API before they enter the agent loop
+pub struct Moderation {
+    provider: ModerationProvider,
+    client: ModelClient,
+    url: Url,
+    model: Option<String>,
+    threshold: Option<f64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OpenAIResponse {
+    results: Vec<OpenAIResult>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OpenAIResult {
+    flagged: bool,
+    categories: HashMap<String, bool>,
+    category_scores: HashMap<String, f64>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AzureResponse {
+    categories_analysis: Vec<AzureCategory>,
+}
+
+#[derive(Debug, Deserialize)]
+struct AzureCategory {
+    category: String,
+    severity: f64,
+}
+
+impl Moderation {
+    pub async fn new(
+        provider: ModerationProvider,
+        url: String,
+        options: ClientOptions,
+        model: Option<String>,
+        threshold: Option<f64>,
+    ) -> Moderation {
+        let (client, url) = ModelClient::new(url, options, None, None).await;
+
+        Moderation {
+            provider,
+            client,
+            url,
+            model,
+            threshold,
+        }
+    }
+
+    /// Refuse requests with a user message flagged in any category
+    #[instrument(skip_all)]
+    pub(crate) async fn check(&self, body: &ManagerBody) -> Result<(), Error> {
+        let inputs: Vec<&str> = body
+            .messages
+            .iter()
+            .filter_map(|message| match message {
+                Message::TextMessage(TextMessage {
+                    role: Role::User,
+                    content,
+                }) => Some(content.as_str()),
+                _ => None,
+            })
+            .collect();
+
+        if inputs.is_empty() {
+            return Ok(());
+        }
+
+        let flagged = match self.provider {
+            ModerationProvider::OpenAI => self.openai(&inputs).await?,
+            ModerationProvider::Azure => self.azure(&inputs).await?,
+        };
+
+        if flagged.is_empty() {
+            return Ok(());
+        }
+
+        let categories: Vec<String> = flagged
+            .iter()
+            .map(|(category, score)| format!("{category} ({score})"))
+            .collect();
+
+        event!(
+            Level::WARN,
+            "Request flagged by moderation: {}",
+            categories.join(", ")
+        );
+
+        Err(Error {
+            status: 403,
+            message: format!("Request flagged by moderation: {}", categories.join(", ")),
+        })
+    }
+
+    async fn openai(&self, inputs: &[&str]) -> Result<Vec<(String, f64)>, Error> {
+        let mut request = json!({ "input": inputs });
+
+        if let Some(model) = &self.model {
+            request["model"] = json!(model);
+        }
+
+        let response: OpenAIResponse = parse(
+            &self
+                .client
+                .call(self.url.clone(), &request)
+                .await
+                .map_err(unavailable)?,
+        )?;
+
+        let mut flagged = HashMap::new();
+
+        for result in response.results {
+            for (category, score) in result.category_scores {
+                let is_flagged = match self.threshold {
+                    Some(threshold) => score >= threshold,
+                    None => {
+                        result.flagged && result.categories.get(&category).copied() == Some(true)
+                    }
+                };
+
+                if is_flagged {
+                    let entry = flagged.entry(category).or_insert(score);
+                    *entry = entry.max(score);
+                }
+            }
+        }
+
+        Ok(sorted(flagged))
+    }
+
+    async fn azure(&self, inputs: &[&str]) -> Result<Vec<(String, f64)>, Error> {
+        let threshold = self.threshold.unwrap_or(DEFAULT_AZURE_THRESHOLD);
+        let mut flagged = HashMap::new();
+
+        for input in inputs {
+            let response: AzureResponse = parse(
+                &self
+                    .client
+                    .call(self.url.clone(), &json!({ "text": input }))
+                    .await
+                    .map_err(unavailable)?,
+            )?;
+
+            for category in response.categories_analysis {
+                if category.severity >= threshold {
+                    let entry = flagged
+                        .entry(category.category)
+                        .or_insert(category.severity);
+                    *entry = entry.max(category.severity);
+                }
+            }
+        }
+
+        Ok(sorted(flagged))
+    }
+}
+
+fn parse<'a, T: Deserialize<'a>>(response: &'a str) -> Result<T, Error> {
+    from_str(response).map_err(|error| {
+        event!(Level::ERROR, "Invalid moderation response: {error}");
+
+        Error {
+            status: 502,
+            message: String::from("Moderation unavailable"),
+        }
+    })
+}
+
+/// Requests are refused when they can't be moderated
+fn unavailable(error: Error) -> Error {
+    event!(Level::ERROR, "Moderation failed: {}", error.message);
+
+    Error {
+        status: 502,
+        message: String::from("Moderation unavailable"),
+    }
+}
+
+fn sorted(flagged: HashMap<String, f64>) -> Vec<(String, f64)> {
+    let mut flagged: Vec<_> = flagged.into_iter().collect();
+    flagged.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+    flagged
+}
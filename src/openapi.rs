@@ -0,0 +1,1276 @@
+use axum::{Extension, Json};
+use serde_json::{Map, Value, json};
+use tracing::instrument;
+
+use crate::HandlerConfig;
+
+/// Version of the OpenAPI specification the documents follow
+const OPENAPI_VERSION: &str = "3.0.3";
+
+/// Document describing the workspaces served by a listener, with the endpoints common to all
+#[instrument(skip(config))]
+pub async fn openapi_handler(Extension(config): Extension<HandlerConfig>) -> Json<Value> {
+    let mut workspaces: Vec<(String, bool, bool)> = config
+        .read()
+        .await
+        .iter()
+        .map(|(path, workspace)| {
+            (
+                path.clone(),
+                workspace.direct_tool_calls,
+                workspace.embeddings.is_some(),
+            )
+        })
+        .collect();
+    workspaces.sort();
+
+    Json(workspaces_document(&workspaces))
+}
+
+/// Document describing the administration API
+#[instrument]
+pub async fn admin_openapi_handler() -> Json<Value> {
+    Json(admin_document())
+}
+
+/// Document of the workspaces, by path, whether their tools can be called directly and whether
+/// they have an embedding model
+fn workspaces_document(workspaces: &[(String, bool, bool)]) -> Value {
+    let mut operations = Map::new();
+
+    for (path, direct_tool_calls, embeddings) in workspaces {
+        operations.insert(path.clone(), workspace_operation(path));
+        operations.insert(format!("{path}/health"), health_operation(path));
+        operations.insert(format!("{path}/tools"), tools_operation(path));
+        operations.insert(format!("{path}/templates"), templates_operation(path));
+
+        if *direct_tool_calls {
+            operations.insert(
+                format!("{path}/tools/{{tool}}/call"),
+                tool_call_operation(path),
+            );
+        }
+
+        if *embeddings {
+            operations.insert(format!("{path}/embeddings"), embeddings_operation(path));
+        }
+    }
+
+    operations.insert(
+        String::from("/jobs/{id}"),
+        json!({
+            "get": {
+                "summary": "State of a request run in the background",
+                "operationId": "getJob",
+                "parameters": [{
+                    "name": "id",
+                    "in": "path",
+                    "required": true,
+                    "schema": {"type": "string"},
+                }],
+                "responses": {
+                    "200": json_response("Job, with its result once completed", "Job"),
+                    "404": error_response("Unknown or expired job"),
+                },
+            },
+        }),
+    );
+    operations.insert(
+        String::from("/v1/models"),
+        json!({
+            "get": {
+                "summary": "Workspaces of the listener, as OpenAI models",
+                "operationId": "listModels",
+                "responses": {
+                    "200": json_response("Workspaces of the listener", "ModelList"),
+                },
+            },
+        }),
+    );
+    operations.insert(
+        String::from("/openapi.json"),
+        json!({
+            "get": {
+                "summary": "This document",
+                "operationId": "getOpenApi",
+                "responses": {
+                    "200": {
+                        "description": "OpenAPI document",
+                        "content": {"application/json": {"schema": {"type": "object"}}},
+                    },
+                },
+            },
+        }),
+    );
+
+    json!({
+        "openapi": OPENAPI_VERSION,
+        "info": info("MCP Manager workspaces"),
+        "paths": operations,
+        "components": {"schemas": workspace_schemas()},
+    })
+}
+
+fn workspace_operation(path: &str) -> Value {
+    json!({
+        "post": {
+            "summary": format!("Run the agent loop of workspace {path}"),
+            "operationId": format!("run{}", operation_name(path)),
+            "parameters": [
+                {
+                    "name": "steps",
+                    "in": "query",
+                    "description": "Include the metadata of each step in the response",
+                    "schema": {"type": "boolean", "default": false},
+                },
+                {
+                    "name": "async",
+                    "in": "query",
+                    "description": "Run the request in the background, answering with the job to poll",
+                    "schema": {"type": "boolean", "default": false},
+                },
+                {
+                    "name": "response_mode",
+                    "in": "query",
+                    "description": "Part of the conversation answered, instead of the one of the workspace",
+                    "schema": {"$ref": "#/components/schemas/ResponseMode"},
+                },
+                {
+                    "name": "plan",
+                    "in": "query",
+                    "description": "Give back the tool calls of the model as a plan, without running them",
+                    "schema": {"type": "boolean", "default": false},
+                },
+                {
+                    "name": "approve",
+                    "in": "query",
+                    "description": "Run the planned tool calls the messages end with",
+                    "schema": {"type": "boolean", "default": false},
+                },
+                {
+                    "name": "Idempotency-Key",
+                    "in": "header",
+                    "description": "Key replaying the response of the first request sent with it",
+                    "schema": {"type": "string"},
+                },
+            ],
+            "requestBody": {
+                "required": true,
+                "content": {
+                    "application/json": {"schema": {"$ref": "#/components/schemas/ManagerRequest"}},
+                },
+            },
+            "responses": {
+                "200": {
+                    "description": "Answer of the workspace, in the response mode asked for, or chat completion chunks when streamed",
+                    "content": {
+                        "application/json": {
+                            "schema": {
+                                "oneOf": [
+                                    {"$ref": "#/components/schemas/Transcript"},
+                                    {"$ref": "#/components/schemas/FinalAnswer"},
+                                ],
+                            },
+                        },
+                        "text/event-stream": {
+                            "schema": {
+                                "type": "string",
+                                "description": "OpenAI chat.completion.chunk events ending with [DONE], or a StreamError event when the request fails",
+                            },
+                        },
+                    },
+                },
+                "202": {
+                    "description": "Request running in the background",
+                    "headers": {
+                        "Location": {
+                            "description": "Path of the job",
+                            "schema": {"type": "string"},
+                        },
+                    },
+                    "content": {
+                        "application/json": {"schema": {"$ref": "#/components/schemas/Job"}},
+                    },
+                },
+                "400": error_response("Invalid request or options"),
+                "401": error_response("Unknown API key"),
+                "403": error_response("Client not allowed"),
+                "404": error_response("Unknown workspace"),
+                "413": error_response("Body too large"),
+                "422": error_response(
+                    "Body not matching the schema, over the limits of the workspace or with tool calls not answered by their outputs",
+                ),
+                "424": error_response("MCP server of the workspace unavailable"),
+                "429": error_response("Budget of the client spent"),
+                "502": error_response("Model failed or answered unexpectedly"),
+                "503": error_response("MCP servers of the workspace not ready"),
+                "504": error_response("Model or MCP server timed out"),
+            },
+        },
+    })
+}
+
+fn health_operation(path: &str) -> Value {
+    json!({
+        "get": {
+            "summary": format!("Health of the model and MCP servers of workspace {path}"),
+            "operationId": format!("health{}", operation_name(path)),
+            "responses": {
+                "200": json_response("Workspace healthy", "WorkspaceHealth"),
+                "503": json_response("Workspace unhealthy", "WorkspaceHealth"),
+            },
+        },
+    })
+}
+
+fn tools_operation(path: &str) -> Value {
+    json!({
+        "get": {
+            "summary": format!("Tools of workspace {path}, as given to the model"),
+            "operationId": format!("listTools{}", operation_name(path)),
+            "responses": {
+                "200": json_array_response("Tools of the workspace", "ListedTool"),
+                "401": error_response("Unknown API key"),
+                "403": error_response("Client not allowed in the workspace"),
+                "424": error_response("MCP server unavailable"),
+                "503": error_response("MCP servers of the workspace not ready"),
+                "504": error_response("MCP server timed out"),
+            },
+        },
+    })
+}
+
+fn templates_operation(path: &str) -> Value {
+    json!({
+        "get": {
+            "summary": format!("Prompt templates of workspace {path}, with their variables"),
+            "operationId": format!("listTemplates{}", operation_name(path)),
+            "responses": {
+                "200": json_array_response("Templates of the workspace", "ListedTemplate"),
+                "401": error_response("Unknown API key"),
+                "403": error_response("Client not allowed in the workspace"),
+            },
+        },
+    })
+}
+
+fn tool_call_operation(path: &str) -> Value {
+    json!({
+        "post": {
+            "summary": format!("Call a tool of workspace {path} without the model"),
+            "operationId": format!("callTool{}", operation_name(path)),
+            "parameters": [{
+                "name": "tool",
+                "in": "path",
+                "required": true,
+                "schema": {"type": "string"},
+            }],
+            "requestBody": {
+                "required": true,
+                "content": {
+                    "application/json": {
+                        "schema": {
+                            "type": "object",
+                            "nullable": true,
+                            "description": "Arguments of the tool",
+                        },
+                    },
+                },
+            },
+            "responses": {
+                "200": json_response("Output of the tool", "DirectCall"),
+                "401": error_response("Unknown API key"),
+                "403": error_response("Client not allowed in the workspace"),
+                "404": error_response("Unknown tool"),
+                "413": error_response("Body too large"),
+                "422": error_response("Arguments not a JSON object"),
+                "424": error_response("MCP server unavailable"),
+                "503": error_response("MCP servers of the workspace not ready"),
+                "504": error_response("MCP server timed out"),
+            },
+        },
+    })
+}
+
+fn embeddings_operation(path: &str) -> Value {
+    json!({
+        "post": {
+            "summary": format!("Embeddings of texts by the embedding model of workspace {path}"),
+            "operationId": format!("embeddings{}", operation_name(path)),
+            "requestBody": {
+                "required": true,
+                "content": {
+                    "application/json": {
+                        "schema": {
+                            "type": "object",
+                            "description": "Request of the OpenAI embeddings API",
+                            "required": ["input"],
+                            "properties": {
+                                "input": {
+                                    "oneOf": [
+                                        {"type": "string"},
+                                        {"type": "array", "items": {"type": "string"}},
+                                    ],
+                                },
+                                "dimensions": {"type": "integer"},
+                            },
+                        },
+                    },
+                },
+            },
+            "responses": {
+                "200": {
+                    "description": "Response of the OpenAI embeddings API",
+                    "content": {"application/json": {"schema": {"type": "object"}}},
+                },
+                "401": error_response("Unknown API key"),
+                "403": error_response("Client not allowed in the workspace"),
+                "413": error_response("Body too large"),
+                "422": error_response("Body not a JSON object, or without texts to embed"),
+                "502": error_response("Embedding model failed or answered unexpectedly"),
+            },
+        },
+    })
+}
+
+fn admin_document() -> Value {
+    json!({
+        "openapi": OPENAPI_VERSION,
+        "info": info("MCP Manager administration"),
+        "paths": {
+            "/admin/config": {
+                "get": {
+                    "summary": "Configuration as loaded, with secrets redacted",
+                    "operationId": "getConfig",
+                    "responses": {
+                        "200": {
+                            "description": "Configuration",
+                            "content": {"application/json": {"schema": {"type": "object"}}},
+                        },
+                        "401": error_response("Unknown admin token"),
+                    },
+                },
+            },
+            "/admin/usage": {
+                "get": {
+                    "summary": "Requests, tokens and tool calls per workspace",
+                    "operationId": "getUsage",
+                    "parameters": [
+                        {"name": "workspace", "in": "query", "schema": {"type": "string"}},
+                        {
+                            "name": "since",
+                            "in": "query",
+                            "schema": {"type": "string", "format": "date-time"},
+                        },
+                        {
+                            "name": "by_model",
+                            "in": "query",
+                            "schema": {"type": "boolean", "default": false},
+                        },
+                    ],
+                    "responses": {
+                        "200": json_array_response("Usage", "UsageSummary"),
+                        "401": error_response("Unknown admin token"),
+                        "404": error_response("Storage not configured"),
+                        "500": error_response("Storage failed"),
+                    },
+                },
+            },
+            "/admin/conversations/{id}/export": {
+                "get": {
+                    "summary": "Transcript of a conversation",
+                    "operationId": "exportConversation",
+                    "parameters": [
+                        {
+                            "name": "id",
+                            "in": "path",
+                            "required": true,
+                            "schema": {"type": "string"},
+                        },
+                        {
+                            "name": "format",
+                            "in": "query",
+                            "schema": {
+                                "type": "string",
+                                "enum": ["openai", "anthropic", "markdown"],
+                                "default": "openai",
+                            },
+                        },
+                    ],
+                    "responses": {
+                        "200": {
+                            "description": "Messages of the conversation in the format of an API, or a Markdown document",
+                            "content": {
+                                "application/json": {"schema": {"type": "object"}},
+                                "text/markdown": {"schema": {"type": "string"}},
+                            },
+                        },
+                        "400": error_response("Invalid format"),
+                        "401": error_response("Unknown admin token"),
+                        "404": error_response("Storage not configured or unknown conversation"),
+                        "500": error_response("Storage failed"),
+                    },
+                },
+            },
+            "/admin/budgets": {
+                "get": {
+                    "summary": "Spending of each client in the current period",
+                    "operationId": "getBudgets",
+                    "responses": {
+                        "200": json_array_response("Budgets", "BudgetReport"),
+                        "401": error_response("Unknown admin token"),
+                        "404": error_response("Storage not configured"),
+                        "500": error_response("Storage failed"),
+                    },
+                },
+            },
+            "/admin/ready": {
+                "get": {
+                    "summary": "Readiness of each workspace",
+                    "operationId": "getReadiness",
+                    "responses": {
+                        "200": json_response("Every workspace ready", "ReadinessReport"),
+                        "401": error_response("Unknown admin token"),
+                        "503": json_response("Some workspace not ready", "ReadinessReport"),
+                    },
+                },
+            },
+            "/admin/metrics": {
+                "get": {
+                    "summary": "Metrics of the tool calls",
+                    "operationId": "getMetrics",
+                    "responses": {
+                        "200": {
+                            "description": "Metrics in the Prometheus text format",
+                            "content": {"text/plain": {"schema": {"type": "string"}}},
+                        },
+                        "401": error_response("Unknown admin token"),
+                    },
+                },
+            },
+            "/admin/log-level": {
+                "get": {
+                    "summary": "Filter of the logs",
+                    "operationId": "getLogLevel",
+                    "responses": {
+                        "200": json_response("Filter", "LogLevel"),
+                        "401": error_response("Unknown admin token"),
+                    },
+                },
+                "put": {
+                    "summary": "Change the filter of the logs",
+                    "operationId": "setLogLevel",
+                    "requestBody": {
+                        "required": true,
+                        "content": {
+                            "application/json": {"schema": {"$ref": "#/components/schemas/LogLevel"}},
+                        },
+                    },
+                    "responses": {
+                        "200": json_response("New filter", "LogLevel"),
+                        "400": error_response("Invalid filter"),
+                        "401": error_response("Unknown admin token"),
+                    },
+                },
+            },
+            "/openapi.json": {
+                "get": {
+                    "summary": "This document",
+                    "operationId": "getOpenApi",
+                    "responses": {
+                        "200": {
+                            "description": "OpenAPI document",
+                            "content": {"application/json": {"schema": {"type": "object"}}},
+                        },
+                        "401": error_response("Unknown admin token"),
+                    },
+                },
+            },
+        },
+        "components": {"schemas": admin_schemas()},
+    })
+}
+
+fn workspace_schemas() -> Value {
+    json!({
+        "Error": error_schema(),
+        "Role": {"type": "string", "enum": ["assistant", "system", "tool", "user"]},
+        "ResponseMode": {"type": "string", "enum": ["transcript", "final", "final_with_usage"]},
+        "TextMessage": {
+            "type": "object",
+            "required": ["role", "content"],
+            "properties": {
+                "role": {"$ref": "#/components/schemas/Role"},
+                "content": {"type": "string"},
+            },
+        },
+        "ToolCall": {
+            "type": "object",
+            "required": ["name", "id"],
+            "properties": {
+                "name": {"type": "string"},
+                "id": {"type": "string"},
+                "arguments": {"type": "object", "nullable": true},
+                "thought_signature": {"type": "string"},
+                "invalid_arguments": {
+                    "type": "string",
+                    "description": "Why the arguments given by the model couldn't be parsed",
+                },
+            },
+        },
+        "ToolCallsMessage": {
+            "type": "object",
+            "required": ["role", "tool_calls"],
+            "properties": {
+                "role": {"$ref": "#/components/schemas/Role"},
+                "tool_calls": {"type": "array", "items": {"$ref": "#/components/schemas/ToolCall"}},
+            },
+        },
+        "ToolOutputMessage": {
+            "type": "object",
+            "required": ["type", "call_id", "output"],
+            "properties": {
+                "type": {"type": "string", "enum": ["FunctionCallOutput"]},
+                "call_id": {"type": "string"},
+                "output": {"type": "string"},
+            },
+        },
+        "Message": {
+            "oneOf": [
+                {"$ref": "#/components/schemas/TextMessage"},
+                {"$ref": "#/components/schemas/ToolCallsMessage"},
+                {"$ref": "#/components/schemas/ToolOutputMessage"},
+            ],
+        },
+        "Tool": {
+            "type": "object",
+            "required": ["type", "function"],
+            "properties": {
+                "type": {"type": "string", "enum": ["function"]},
+                "function": {
+                    "type": "object",
+                    "required": ["name", "description", "parameters"],
+                    "properties": {
+                        "name": {"type": "string"},
+                        "description": {"type": "string"},
+                        "parameters": {"type": "object"},
+                    },
+                },
+            },
+        },
+        "ManagerRequest": {
+            "type": "object",
+            "properties": {
+                "messages": {"type": "array", "items": {"$ref": "#/components/schemas/Message"}},
+                "temperature": {"type": "number", "nullable": true},
+                "max_tokens": {"type": "integer", "nullable": true},
+                "top_p": {"type": "number", "nullable": true},
+                "top_k": {
+                    "type": "integer",
+                    "description": "Only given to Anthropic, Gemini and Cohere models",
+                },
+                "tools": {
+                    "type": "array",
+                    "nullable": true,
+                    "items": {"$ref": "#/components/schemas/Tool"},
+                },
+                "callback_url": {
+                    "type": "string",
+                    "description": "URL notified with the outcome, the request being run in the background",
+                },
+                "stream": {
+                    "type": "boolean",
+                    "default": false,
+                    "description": "Send the answer as chat completion chunks",
+                },
+                "metadata": {
+                    "type": "object",
+                    "description": "Data of the client about the request, given back in the response",
+                },
+                "template": {
+                    "type": "string",
+                    "description": "Template of the workspace added as the last user message",
+                },
+                "variables": {
+                    "type": "object",
+                    "additionalProperties": {"type": "string"},
+                    "description": "Values of the variables of the template",
+                },
+                "parent_message_id": {
+                    "type": "string",
+                    "description": "Stored message the request follows, the earlier messages being taken from the storage",
+                },
+            },
+        },
+        "Usage": {
+            "type": "object",
+            "properties": {
+                "prompt_tokens": {"type": "integer"},
+                "completion_tokens": {"type": "integer"},
+                "total_tokens": {"type": "integer"},
+            },
+        },
+        "Step": {
+            "type": "object",
+            "properties": {
+                "model_latency_ms": {"type": "integer"},
+                "usage": {"allOf": [{"$ref": "#/components/schemas/Usage"}], "nullable": true},
+                "finish_reason": {
+                    "type": "string",
+                    "enum": ["stop", "tool_calls", "length", "content_filter"],
+                },
+                "provider_id": {"type": "string"},
+                "tool_calls": {
+                    "type": "array",
+                    "items": {
+                        "type": "object",
+                        "properties": {
+                            "name": {"type": "string"},
+                            "id": {"type": "string"},
+                            "arguments": {"type": "object", "nullable": true},
+                            "duration_ms": {"type": "integer"},
+                            "annotations": {"type": "array", "items": {"type": "object"}},
+                        },
+                    },
+                },
+            },
+        },
+        "Transcript": {
+            "type": "object",
+            "description": "Every message exchanged, including the tool calls and their outputs",
+            "properties": {
+                "messages": {"type": "array", "items": {"$ref": "#/components/schemas/Message"}},
+                "temperature": {"type": "number", "nullable": true},
+                "max_tokens": {"type": "integer", "nullable": true},
+                "top_p": {"type": "number", "nullable": true},
+                "tools": {
+                    "type": "array",
+                    "nullable": true,
+                    "items": {"$ref": "#/components/schemas/Tool"},
+                },
+                "usage": {"$ref": "#/components/schemas/Usage"},
+                "steps": {"type": "array", "items": {"$ref": "#/components/schemas/Step"}},
+                "metadata": {"type": "object"},
+                "model": {
+                    "type": "string",
+                    "description": "Model that answered, when the requests are split between models",
+                },
+                "plan": {
+                    "type": "array",
+                    "items": {"$ref": "#/components/schemas/ToolCall"},
+                    "description": "Tool calls waiting for approval, which weren't run",
+                },
+                "finish_reason": {
+                    "type": "string",
+                    "enum": ["length", "content_filter"],
+                    "description": "Set when the last answer of the model was cut at the maximum number of tokens or by the content filter",
+                },
+                "message_ids": {
+                    "type": "array",
+                    "items": {"type": "string"},
+                    "description": "IDs the messages are stored under, when the transcripts are stored",
+                },
+            },
+        },
+        "FinalAnswer": {
+            "type": "object",
+            "description": "Last message of the model",
+            "properties": {
+                "message": {"allOf": [{"$ref": "#/components/schemas/TextMessage"}], "nullable": true},
+                "usage": {"$ref": "#/components/schemas/Usage"},
+                "metadata": {"type": "object"},
+                "model": {"type": "string"},
+                "plan": {
+                    "type": "array",
+                    "items": {"$ref": "#/components/schemas/ToolCall"},
+                    "description": "Tool calls waiting for approval, which weren't run",
+                },
+                "finish_reason": {
+                    "type": "string",
+                    "enum": ["length", "content_filter"],
+                    "description": "Set when the last answer of the model was cut at the maximum number of tokens or by the content filter",
+                },
+                "message_id": {
+                    "type": "string",
+                    "description": "ID the last message is stored under, when the transcripts are stored",
+                },
+            },
+        },
+        "StreamError": {
+            "type": "object",
+            "description": "Event sent when a streamed request fails",
+            "properties": {
+                "error": {
+                    "type": "object",
+                    "properties": {
+                        "message": {"type": "string"},
+                        "code": {"type": "integer"},
+                    },
+                },
+            },
+        },
+        "Job": {
+            "type": "object",
+            "required": ["id", "workspace", "status"],
+            "properties": {
+                "id": {"type": "string"},
+                "workspace": {"type": "string"},
+                "status": {"type": "string", "enum": ["running", "completed", "failed"]},
+                "result": {
+                    "oneOf": [
+                        {"$ref": "#/components/schemas/Transcript"},
+                        {"$ref": "#/components/schemas/FinalAnswer"},
+                    ],
+                },
+                "error": {
+                    "type": "object",
+                    "properties": {
+                        "status": {"type": "integer"},
+                        "message": {"type": "string"},
+                    },
+                },
+            },
+        },
+        "ModelList": {
+            "type": "object",
+            "properties": {
+                "object": {"type": "string", "enum": ["list"]},
+                "data": {
+                    "type": "array",
+                    "items": {
+                        "type": "object",
+                        "properties": {
+                            "id": {"type": "string"},
+                            "object": {"type": "string", "enum": ["model"]},
+                            "created": {"type": "integer"},
+                            "owned_by": {"type": "string"},
+                            "path": {"type": "string"},
+                            "provider": {"type": "string"},
+                            "tools": {"type": "integer"},
+                        },
+                    },
+                },
+            },
+        },
+        "ListedTool": {
+            "type": "object",
+            "properties": {
+                "mcp": {"type": "string", "description": "MCP server offering the tool"},
+                "name": {"type": "string"},
+                "description": {"type": "string"},
+                "inputSchema": {"type": "object", "description": "JSON schema of the arguments"},
+            },
+        },
+        "ListedTemplate": {
+            "type": "object",
+            "properties": {
+                "name": {"type": "string"},
+                "variables": {"type": "array", "items": {"type": "string"}},
+            },
+        },
+        "DirectCall": {
+            "type": "object",
+            "properties": {
+                "mcp": {"type": "string"},
+                "tool": {"type": "string"},
+                "duration_ms": {"type": "integer"},
+                "text": {"type": "string", "description": "Text of the contents intended for the model"},
+                "annotations": {"type": "array", "items": {"type": "object"}},
+                "error": {"type": "boolean", "description": "Whether the tool reported an error"},
+            },
+        },
+        "Status": status_schema(),
+        "WorkspaceHealth": {
+            "type": "object",
+            "properties": {
+                "healthy": {"type": "boolean"},
+                "model": {"$ref": "#/components/schemas/DependencyHealth"},
+                "split": {
+                    "type": "object",
+                    "additionalProperties": {"$ref": "#/components/schemas/DependencyHealth"},
+                },
+                "mcps": {
+                    "type": "object",
+                    "additionalProperties": {"$ref": "#/components/schemas/DependencyHealth"},
+                },
+            },
+        },
+        "DependencyHealth": {
+            "allOf": [
+                {"$ref": "#/components/schemas/Status"},
+                {
+                    "type": "object",
+                    "properties": {
+                        "name": {"type": "string"},
+                        "connected": {"type": "boolean"},
+                        "last_call": {"type": "object", "nullable": true},
+                        "last_success": {"type": "string", "format": "date-time", "nullable": true},
+                        "tools": {"type": "integer"},
+                    },
+                },
+            ],
+        },
+    })
+}
+
+fn admin_schemas() -> Value {
+    json!({
+        "Error": error_schema(),
+        "UsageSummary": {
+            "type": "object",
+            "properties": {
+                "workspace": {"type": "string"},
+                "model": {"type": "string"},
+                "requests": {"type": "integer"},
+                "failed_requests": {"type": "integer"},
+                "prompt_tokens": {"type": "integer"},
+                "completion_tokens": {"type": "integer"},
+                "total_tokens": {"type": "integer"},
+                "cost": {"type": "number"},
+                "tool_calls": {"type": "integer"},
+            },
+        },
+        "BudgetReport": {
+            "type": "object",
+            "properties": {
+                "name": {"type": "string"},
+                "period": {"type": "string", "enum": ["day", "month"]},
+                "since": {"type": "string", "format": "date-time"},
+                "tokens": {"type": "integer"},
+                "cost": {"type": "number"},
+                "max_tokens": {"type": "integer", "nullable": true},
+                "max_cost": {"type": "number", "nullable": true},
+                "exceeded": {"type": "boolean"},
+            },
+        },
+        "Status": status_schema(),
+        "ReadinessReport": {
+            "type": "object",
+            "properties": {
+                "ready": {"type": "boolean"},
+                "workspaces": {
+                    "type": "object",
+                    "additionalProperties": {
+                        "type": "object",
+                        "properties": {
+                            "ready": {"type": "boolean"},
+                            "model": {"$ref": "#/components/schemas/Status"},
+                            "split": {
+                                "type": "object",
+                                "additionalProperties": {"$ref": "#/components/schemas/Status"},
+                            },
+                            "mcps": {
+                                "type": "object",
+                                "additionalProperties": {"$ref": "#/components/schemas/Status"},
+                            },
+                        },
+                    },
+                },
+            },
+        },
+        "LogLevel": {
+            "type": "object",
+            "required": ["filter"],
+            "properties": {
+                "filter": {"type": "string", "description": "Filter with the syntax of RUST_LOG"},
+            },
+        },
+    })
+}
+
+fn info(title: &str) -> Value {
+    json!({
+        "title": title,
+        "version": env!("CARGO_PKG_VERSION"),
+    })
+}
+
+/// Errors are answered as plain text, with the status telling what failed
+fn error_schema() -> Value {
+    json!({
+        "type": "string",
+        "description": "Message of the error",
+    })
+}
+
+fn status_schema() -> Value {
+    json!({
+        "type": "object",
+        "required": ["status"],
+        "properties": {
+            "status": {"type": "string", "enum": ["starting", "ready", "failed"]},
+            "error": {"type": "string"},
+        },
+    })
+}
+
+fn json_response(description: &str, schema: &str) -> Value {
+    json!({
+        "description": description,
+        "content": {
+            "application/json": {"schema": {"$ref": format!("#/components/schemas/{schema}")}},
+        },
+    })
+}
+
+fn json_array_response(description: &str, schema: &str) -> Value {
+    json!({
+        "description": description,
+        "content": {
+            "application/json": {
+                "schema": {
+                    "type": "array",
+                    "items": {"$ref": format!("#/components/schemas/{schema}")},
+                },
+            },
+        },
+    })
+}
+
+fn error_response(description: &str) -> Value {
+    json!({
+        "description": description,
+        "content": {"text/plain": {"schema": {"$ref": "#/components/schemas/Error"}}},
+    })
+}
+
+/// Name of the operations of a workspace, from its path (e.g. `/team/search` gives `TeamSearch`)
+fn operation_name(path: &str) -> String {
+    path.split(|character: char| !character.is_ascii_alphanumeric())
+        .filter(|part| !part.is_empty())
+        .map(|part| {
+            let mut characters = part.chars();
+
+            characters
+                .next()
+                .map(|first| first.to_ascii_uppercase().to_string() + characters.as_str())
+                .unwrap_or_default()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::from_value;
+
+    use super::*;
+    use crate::{
+        Answer, Error, ManagerBody, ResponseMode, Step, ToolCallStep,
+        jobs::Jobs,
+        mcp::ToolCall,
+        models::{FinishReason, Message, Role, TextMessage, ToolOutputType, Usage},
+    };
+
+    /// Schema a reference points to in the components of a document
+    fn resolve<'a>(document: &'a Value, schema: &'a Value) -> &'a Value {
+        match schema["$ref"].as_str() {
+            Some(reference) => {
+                let name = reference.trim_start_matches("#/components/schemas/");
+
+                resolve(document, &document["components"]["schemas"][name])
+            }
+            None => schema,
+        }
+    }
+
+    /// Check a value against a schema of a document, refusing the properties it doesn't describe
+    /// so the structs and the document can't drift apart
+    fn check(document: &Value, schema: &Value, value: &Value, at: &str) -> Result<(), String> {
+        let schema = resolve(document, schema);
+
+        if value.is_null() && schema["nullable"] == true {
+            return Ok(());
+        }
+
+        if let Some(schemas) = schema["oneOf"].as_array() {
+            let errors: Vec<String> = schemas
+                .iter()
+                .map_while(|schema| check(document, schema, value, at).err())
+                .collect();
+
+            if errors.len() < schemas.len() {
+                return Ok(());
+            }
+
+            return Err(format!(
+                "{at} matches none of its schemas: {}",
+                errors.join(", ")
+            ));
+        }
+
+        if let Some(schemas) = schema["allOf"].as_array() {
+            for schema in schemas {
+                check_fields(document, schema, value, at)?;
+            }
+
+            return check_properties(document, schema, value, at);
+        }
+
+        check_fields(document, schema, value, at)?;
+        check_properties(document, schema, value, at)
+    }
+
+    /// Check the type of a value and its fields, but not which properties it has
+    fn check_fields(
+        document: &Value,
+        schema: &Value,
+        value: &Value,
+        at: &str,
+    ) -> Result<(), String> {
+        let schema = resolve(document, schema);
+
+        let matches = match schema["type"].as_str() {
+            None => true,
+            Some("string") => value.is_string(),
+            Some("integer") => value.is_i64() || value.is_u64(),
+            Some("number") => value.is_number(),
+            Some("boolean") => value.is_boolean(),
+            Some("array") => value.is_array(),
+            Some("object") => value.is_object(),
+            Some(other) => return Err(format!("{at}: unknown type {other}")),
+        };
+
+        if !matches {
+            return Err(format!("{at}: {value} isn't of type {}", schema["type"]));
+        }
+
+        if let Some(values) = schema["enum"].as_array()
+            && !values.contains(value)
+        {
+            return Err(format!("{at}: {value} isn't one of {values:?}"));
+        }
+
+        for required in schema["required"].as_array().into_iter().flatten() {
+            if value.get(required.as_str().unwrap()).is_none() {
+                return Err(format!("{at}: missing {required}"));
+            }
+        }
+
+        if let (Some(properties), Some(object)) =
+            (schema["properties"].as_object(), value.as_object())
+        {
+            for (name, value) in object {
+                if let Some(property) = properties.get(name) {
+                    check(document, property, value, &format!("{at}.{name}"))?;
+                }
+            }
+        }
+
+        if let (Some(additional), Some(object)) = (
+            schema
+                .get("additionalProperties")
+                .filter(|schema| schema.is_object()),
+            value.as_object(),
+        ) {
+            for (name, value) in object {
+                check(document, additional, value, &format!("{at}.{name}"))?;
+            }
+        }
+
+        if let (Some(items), Some(values)) = (schema.get("items"), value.as_array()) {
+            for (index, value) in values.iter().enumerate() {
+                check(document, items, value, &format!("{at}[{index}]"))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Refuse the properties of an object which the schema (or the ones it's made of) doesn't have
+    fn check_properties(
+        document: &Value,
+        schema: &Value,
+        value: &Value,
+        at: &str,
+    ) -> Result<(), String> {
+        let Some(object) = value.as_object() else {
+            return Ok(());
+        };
+
+        let mut described = Vec::new();
+        let mut schemas = vec![schema];
+
+        while let Some(schema) = schemas.pop() {
+            let schema = resolve(document, schema);
+
+            if schema.get("additionalProperties").is_some() {
+                return Ok(());
+            }
+
+            schemas.extend(schema["allOf"].as_array().into_iter().flatten());
+            described.extend(
+                schema["properties"]
+                    .as_object()
+                    .into_iter()
+                    .flat_map(|properties| properties.keys()),
+            );
+        }
+
+        // Objects without properties are free-form (e.g. metadata)
+        if described.is_empty() {
+            return Ok(());
+        }
+
+        match object.keys().find(|name| !described.contains(name)) {
+            Some(name) => Err(format!("{at}: property {name} not in the document")),
+            None => Ok(()),
+        }
+    }
+
+    fn check_schema(document: &Value, name: &str, value: &Value) {
+        let schema = json!({"$ref": format!("#/components/schemas/{name}")});
+
+        if let Err(error) = check(document, &schema, value, name) {
+            panic!("{error}");
+        }
+    }
+
+    /// Response with every field the manager can answer
+    fn answered() -> ManagerBody {
+        let call = ToolCall {
+            name: String::from("read"),
+            id: String::from("call_1"),
+            arguments: from_value(json!({"path": "/tmp"})).ok(),
+            thought_signature: Some(String::from("signature")),
+            invalid_arguments: None,
+            headers: Default::default(),
+        };
+        let usage = Usage {
+            prompt_tokens: 10,
+            completion_tokens: 5,
+            total_tokens: 15,
+        };
+
+        ManagerBody {
+            messages: vec![
+                Message::TextMessage(TextMessage {
+                    role: Role::User,
+                    content: String::from("Read /tmp"),
+                }),
+                Message::ToolCalls {
+                    role: Role::Assistant,
+                    tool_calls: vec![call.clone()],
+                },
+                Message::ToolOutput {
+                    r#type: ToolOutputType::FunctionCallOutput,
+                    call_id: String::from("call_1"),
+                    output: String::from("Empty"),
+                },
+                Message::TextMessage(TextMessage {
+                    role: Role::Assistant,
+                    content: String::from("/tmp is empty"),
+                }),
+            ],
+            temperature: Some(0.5),
+            usage: Some(usage),
+            steps: Some(vec![Step {
+                model_latency_ms: 100,
+                usage: Some(usage),
+                finish_reason: Some(FinishReason::ToolCalls),
+                provider_id: Some(String::from("response")),
+                tool_calls: vec![ToolCallStep {
+                    name: String::from("read"),
+                    id: String::from("call_1"),
+                    arguments: call.arguments.clone(),
+                    duration_ms: 10,
+                    annotations: Vec::new(),
+                }],
+            }]),
+            metadata: from_value(json!({"user_id": "alice"})).ok(),
+            model: Some(String::from("gemini")),
+            plan: Some(vec![call]),
+            finish_reason: Some(FinishReason::Length),
+            message_ids: Some(vec![String::from("message")]),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn describes_requests() {
+        let document = workspaces_document(&[]);
+        let request = json!({
+            "messages": [{"role": "user", "content": "Hello"}],
+            "temperature": 0.5,
+            "max_tokens": 100,
+            "top_p": 0.9,
+            "top_k": 40,
+            "tools": null,
+            "callback_url": "http://localhost/done",
+            "stream": true,
+            "metadata": {"user_id": "alice"},
+            "template": "greet",
+            "variables": {"name": "Alice"},
+            "parent_message_id": "message",
+        });
+
+        check_schema(&document, "ManagerRequest", &request);
+
+        // Every property of the document is read from the request
+        let body: ManagerBody = from_value(request).unwrap();
+
+        assert_eq!(body.messages.len(), 1);
+        assert_eq!(body.top_k, Some(40));
+        assert_eq!(body.callback_url.as_deref(), Some("http://localhost/done"));
+        assert!(body.stream);
+        assert!(body.metadata.is_some());
+        assert_eq!(body.template.as_deref(), Some("greet"));
+        assert!(body.variables.is_some());
+        assert_eq!(body.parent_message_id.as_deref(), Some("message"));
+    }
+
+    #[test]
+    fn describes_responses() {
+        let document = workspaces_document(&[]);
+
+        check_schema(&document, "Transcript", &json!(answered()));
+        check_schema(
+            &document,
+            "FinalAnswer",
+            &json!(ResponseMode::FinalWithUsage.answer(answered())),
+        );
+        check_schema(
+            &document,
+            "FinalAnswer",
+            &json!(ResponseMode::Final.answer(ManagerBody::default())),
+        );
+    }
+
+    #[tokio::test]
+    async fn describes_jobs() {
+        let document = workspaces_document(&[]);
+        let jobs = Jobs::default();
+
+        let running = jobs.start("test").await;
+        check_schema(&document, "Job", &json!(running));
+
+        let completed = jobs
+            .finish(running.id(), Ok(Answer::Transcript(answered())))
+            .await
+            .unwrap();
+        check_schema(&document, "Job", &json!(completed));
+
+        let failed = jobs.start("test").await;
+        let failed = jobs
+            .finish(
+                failed.id(),
+                Err(Error {
+                    status: 502,
+                    message: String::from("Model failed"),
+                }),
+            )
+            .await
+            .unwrap();
+        check_schema(&document, "Job", &json!(failed));
+    }
+
+    #[test]
+    fn refuses_undescribed_properties() {
+        let document = workspaces_document(&[]);
+        let mut answer = json!(answered());
+        answer["unknown"] = json!(true);
+
+        let schema = json!({"$ref": "#/components/schemas/Transcript"});
+
+        assert!(check(&document, &schema, &answer, "Transcript").is_err());
+    }
+}
@@ -0,0 +1,140 @@
+use std::collections::HashMap;
+
+use rmcp::model::{JsonObject, Tool};
+use serde_json::{Value, json};
+use tracing::{Level, event};
+
+use crate::mcp::ToolOutput;
+
+/// Builtin tool reading the rest of a tool output too long to be given at once
+pub(crate) const READ_MORE_TOOL: &str = "read_more";
+
+/// Tool outputs given to the model a page at a time when too long, the model reading the next
+/// pages with the `read_more` tool instead of the output being cut
+pub struct Paging {
+    /// Characters of an output given to the model at once
+    pub(crate) max_chars: usize,
+}
+
+/// Outputs too long for the model, kept for the length of a request by the ID of their call
+#[derive(Default)]
+pub(crate) struct Pages {
+    outputs: HashMap<String, String>,
+    /// Call of the last output cut, read when the model doesn't tell which one
+    last: Option<String>,
+}
+
+impl Paging {
+    /// Tool given to the model along with the tools of the MCP servers
+    pub(crate) fn tool(&self) -> Tool {
+        let input_schema = json!({
+            "type": "object",
+            "properties": {
+                "offset": {
+                    "type": "integer",
+                    "minimum": 0,
+                    "description": "Character of the output to read from",
+                },
+                "call_id": {
+                    "type": "string",
+                    "description": "ID of the tool call whose output to read, the last cut by default",
+                },
+            },
+            "required": ["offset"],
+        });
+
+        let Value::Object(input_schema) = input_schema else {
+            unreachable!("Schema of the tool is an object");
+        };
+
+        Tool::new(
+            READ_MORE_TOOL,
+            format!(
+                "Read more of a tool output cut for being longer than {} characters",
+                self.max_chars
+            ),
+            input_schema,
+        )
+    }
+
+    /// First page of an output, the whole output being kept for the model to read the rest
+    pub(crate) fn cut(&self, pages: &mut Pages, call_id: &str, text: String) -> String {
+        let length = text.chars().count();
+
+        if length <= self.max_chars {
+            return text;
+        }
+
+        event!(
+            Level::INFO,
+            "Output of tool call {call_id} has {length} characters, giving it in pages"
+        );
+
+        let page = self.page(call_id, &text, length, 0);
+
+        pages.outputs.insert(call_id.to_owned(), text);
+        pages.last = Some(call_id.to_owned());
+
+        page
+    }
+
+    /// Page of a kept output read by the model with the `read_more` tool
+    pub(crate) fn read_more(&self, pages: &Pages, arguments: Option<&JsonObject>) -> ToolOutput {
+        let offset = arguments
+            .and_then(|arguments| arguments.get("offset"))
+            .and_then(Value::as_u64);
+        let call_id = arguments
+            .and_then(|arguments| arguments.get("call_id"))
+            .and_then(Value::as_str)
+            .or(pages.last.as_deref());
+
+        let Some(offset) = offset else {
+            return error(String::from(
+                "Error: the offset to read from must be given, as a number of characters",
+            ));
+        };
+
+        let Some((call_id, text)) =
+            call_id.and_then(|call_id| pages.outputs.get_key_value(call_id))
+        else {
+            return error(format!(
+                "Error: no output was cut in this request{}, only those can be read",
+                call_id.map_or(String::new(), |call_id| format!(" for tool call {call_id}"))
+            ));
+        };
+
+        let length = text.chars().count();
+        let offset = usize::try_from(offset).unwrap_or(usize::MAX);
+
+        if offset >= length {
+            return error(format!(
+                "Error: the output of tool call {call_id} has only {length} characters"
+            ));
+        }
+
+        ToolOutput::from(self.page(call_id, text, length, offset))
+    }
+
+    /// Characters of an output from `offset`, with how to read the next ones if any
+    fn page(&self, call_id: &str, text: &str, length: usize, offset: usize) -> String {
+        let mut page: String = text.chars().skip(offset).take(self.max_chars).collect();
+        let end = (offset + self.max_chars).min(length);
+
+        if end < length {
+            page.push_str(&format!(
+                "\n\n[Characters {offset} to {end} of {length} of the output. Call {READ_MORE_TOOL} \
+                    with call_id \"{call_id}\" and offset {end} to read more]"
+            ));
+        }
+
+        page
+    }
+}
+
+fn error(text: String) -> ToolOutput {
+    ToolOutput {
+        text,
+        error: true,
+        ..Default::default()
+    }
+}
@@ -0,0 +1,303 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, RwLock},
+};
+
+use axum::{
+    Extension, Json,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use serde::Serialize;
+use tracing::{Level, event, instrument};
+
+use crate::{
+    Error, ManagerBody,
+    health::{Activity, ActivityReport},
+    mcp::McpServer,
+    models::{AIModel, Message, Role, TextMessage},
+};
+
+/// Message sent to the models to check they answer
+const PROBE: &str = "ping";
+
+/// State of a model or MCP server the workspaces depend on
+#[derive(Clone, Debug, Default, Serialize)]
+#[serde(rename_all = "lowercase", tag = "status")]
+pub enum Status {
+    #[default]
+    Starting,
+    Ready,
+    Failed {
+        error: String,
+    },
+}
+
+/// Dependencies of the workspaces, which are ready once their MCP servers listed their tools and
+/// their model passed its probe
+#[derive(Debug, Default)]
+pub struct Readiness {
+    /// Answer 503 on workspaces whose MCP servers aren't ready
+    gate: bool,
+    /// Models (the one getting the largest share first) and MCP servers of each workspace
+    workspaces: HashMap<String, (Vec<String>, Vec<String>)>,
+    models: RwLock<HashMap<String, Status>>,
+    mcps: RwLock<HashMap<String, Status>>,
+    /// Outcome of the calls made once started
+    model_activity: HashMap<String, Arc<Activity>>,
+    mcp_activity: HashMap<String, Arc<Activity>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReadinessReport {
+    ready: bool,
+    workspaces: BTreeMap<String, WorkspaceReport>,
+}
+
+#[derive(Debug, Serialize)]
+struct WorkspaceReport {
+    ready: bool,
+    model: Status,
+    /// Every model, when the requests are split between models
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    split: BTreeMap<String, Status>,
+    mcps: BTreeMap<String, Status>,
+}
+
+/// Status of the dependencies of a workspace along with the outcome of their last calls
+#[derive(Debug, Serialize)]
+pub struct WorkspaceHealth {
+    pub(crate) healthy: bool,
+    model: DependencyHealth,
+    /// Every model, when the requests are split between models
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    split: BTreeMap<String, DependencyHealth>,
+    mcps: BTreeMap<String, DependencyHealth>,
+}
+
+#[derive(Debug, Serialize)]
+struct DependencyHealth {
+    name: String,
+    #[serde(flatten)]
+    status: Status,
+    /// Started and answering its last call
+    connected: bool,
+    #[serde(flatten)]
+    activity: ActivityReport,
+}
+
+impl Readiness {
+    /// Track the models and MCP servers used by each workspace
+    pub(crate) fn new(
+        gate: bool,
+        workspaces: HashMap<String, (Vec<String>, Vec<String>)>,
+        model_activity: HashMap<String, Arc<Activity>>,
+        mcp_activity: HashMap<String, Arc<Activity>>,
+    ) -> Readiness {
+        Readiness {
+            gate,
+            workspaces,
+            model_activity,
+            mcp_activity,
+            ..Default::default()
+        }
+    }
+
+    pub(crate) fn set_model(&self, name: &str, status: Status) {
+        Self::set(&self.models, "Model", name, status);
+    }
+
+    pub(crate) fn set_mcp(&self, name: &str, status: Status) {
+        Self::set(&self.mcps, "MCP server", name, status);
+    }
+
+    /// Mark a MCP server as ready once it lists its tools
+    pub(crate) fn watch_mcp(self: &Arc<Self>, name: String, server: Arc<dyn McpServer + Send>) {
+        let readiness = Arc::clone(self);
+
+        tokio::spawn(async move {
+            let status = match server.list_tools().await {
+                Ok(_) => Status::Ready,
+                Err(error) => Status::Failed {
+                    error: error.to_string(),
+                },
+            };
+
+            readiness.set_mcp(&name, status);
+        });
+    }
+
+    /// Mark a model as ready once it answers a minimal request
+    pub(crate) fn probe_model(self: &Arc<Self>, name: String, model: Arc<dyn AIModel + Send>) {
+        let readiness = Arc::clone(self);
+
+        tokio::spawn(async move {
+            let body = ManagerBody {
+                messages: vec![Message::TextMessage(TextMessage {
+                    role: Role::User,
+                    content: String::from(PROBE),
+                })],
+                ..Default::default()
+            };
+
+            // Run apart, so a model panicking on an unexpected answer is reported as failed
+            let status = match tokio::spawn(async move { model.call(body, Vec::new()).await }).await
+            {
+                Ok(Ok(_)) => Status::Ready,
+                Ok(Err(error)) => Status::Failed {
+                    error: error.message,
+                },
+                Err(_) => Status::Failed {
+                    error: String::from("Invalid response from model"),
+                },
+            };
+
+            readiness.set_model(&name, status);
+        });
+    }
+
+    fn set(states: &RwLock<HashMap<String, Status>>, kind: &str, name: &str, status: Status) {
+        match &status {
+            Status::Starting => {}
+            Status::Ready => event!(Level::INFO, "{kind} {name} is ready"),
+            Status::Failed { error } => event!(Level::ERROR, "{kind} {name} isn't ready: {error}"),
+        }
+
+        states
+            .write()
+            .expect("Readiness lock poisoned")
+            .insert(name.to_owned(), status);
+    }
+
+    fn status(states: &RwLock<HashMap<String, Status>>, name: &str) -> Status {
+        states
+            .read()
+            .expect("Readiness lock poisoned")
+            .get(name)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    pub fn report(&self) -> ReadinessReport {
+        let workspaces: BTreeMap<String, WorkspaceReport> = self
+            .workspaces
+            .iter()
+            .map(|(name, (models, mcps))| {
+                let model = Self::status(&self.models, &models[0]);
+                let split: BTreeMap<String, Status> = split(models)
+                    .iter()
+                    .map(|model| (model.clone(), Self::status(&self.models, model)))
+                    .collect();
+                let mcps: BTreeMap<String, Status> = mcps
+                    .iter()
+                    .map(|mcp| (mcp.clone(), Self::status(&self.mcps, mcp)))
+                    .collect();
+
+                let ready = matches!(model, Status::Ready)
+                    && split.values().all(|status| matches!(status, Status::Ready))
+                    && mcps.values().all(|status| matches!(status, Status::Ready));
+
+                (
+                    name.clone(),
+                    WorkspaceReport {
+                        ready,
+                        model,
+                        split,
+                        mcps,
+                    },
+                )
+            })
+            .collect();
+
+        ReadinessReport {
+            ready: workspaces.values().all(|workspace| workspace.ready),
+            workspaces,
+        }
+    }
+
+    /// Status of the model and MCP servers of a workspace
+    pub(crate) fn health(&self, workspace: &str) -> Option<WorkspaceHealth> {
+        let (models, mcps) = self.workspaces.get(workspace)?;
+
+        let dependency = |states, activities: &HashMap<String, Arc<Activity>>, name: &String| {
+            let status = Self::status(states, name);
+            let activity = activities
+                .get(name)
+                .map(|activity| activity.report())
+                .unwrap_or_default();
+
+            DependencyHealth {
+                name: name.clone(),
+                connected: matches!(status, Status::Ready)
+                    && activity.last_call.as_ref().is_none_or(|call| call.ok),
+                status,
+                activity,
+            }
+        };
+
+        let model = dependency(&self.models, &self.model_activity, &models[0]);
+        let split: BTreeMap<String, DependencyHealth> = split(models)
+            .iter()
+            .map(|model| {
+                (
+                    model.clone(),
+                    dependency(&self.models, &self.model_activity, model),
+                )
+            })
+            .collect();
+        let mcps: BTreeMap<String, DependencyHealth> = mcps
+            .iter()
+            .map(|mcp| (mcp.clone(), dependency(&self.mcps, &self.mcp_activity, mcp)))
+            .collect();
+
+        Some(WorkspaceHealth {
+            healthy: model.connected
+                && split.values().all(|model| model.connected)
+                && mcps.values().all(|mcp| mcp.connected),
+            model,
+            split,
+            mcps,
+        })
+    }
+
+    /// Refuse requests to a workspace whose MCP servers aren't ready, when configured to
+    pub(crate) fn check(&self, workspace: &str) -> Result<(), Error> {
+        if !self.gate {
+            return Ok(());
+        }
+
+        let Some((_, mcps)) = self.workspaces.get(workspace) else {
+            return Ok(());
+        };
+
+        if mcps
+            .iter()
+            .all(|mcp| matches!(Self::status(&self.mcps, mcp), Status::Ready))
+        {
+            Ok(())
+        } else {
+            Err(Error {
+                status: 503,
+                message: String::from("Workspace not ready"),
+            })
+        }
+    }
+}
+
+/// Models of a workspace splitting its requests, none when a single model answers them
+fn split(models: &[String]) -> &[String] {
+    if models.len() > 1 { models } else { &[] }
+}
+
+#[instrument(skip(readiness))]
+pub async fn ready_handler(Extension(readiness): Extension<Arc<Readiness>>) -> Response {
+    let report = readiness.report();
+
+    let status = if report.ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+
+    (status, Json(report)).into_response()
+}
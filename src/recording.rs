@@ -0,0 +1,232 @@
+use std::{io, path::PathBuf, sync::Arc};
+
+use async_trait::async_trait;
+use futures::{StreamExt, stream};
+use rmcp::{ServiceError, model::Tool};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use serde_json::{Value, from_str, json, to_string_pretty};
+use sha2::{Digest, Sha256};
+use tokio::fs;
+use tracing::{Level, event, instrument};
+
+use crate::{
+    Error, ManagerBody,
+    mcp::{McpServer, ToolCall, ToolOutput},
+    models::{AIModel, DeltaStream, ModelDelta, ModelTurn},
+};
+
+/// Whether the exchanges with the models and MCP servers are saved or served back
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RecordingMode {
+    /// Call the models and MCP servers, saving each successful exchange
+    Record,
+    /// Answer with the saved exchanges, without calling any model nor MCP server
+    Replay,
+}
+
+/// Directory of the exchanges, one file per request named after its hash
+#[derive(Debug)]
+pub struct Recorder {
+    pub(crate) mode: RecordingMode,
+    pub(crate) path: PathBuf,
+}
+
+#[derive(Deserialize, Serialize)]
+struct Exchange<T> {
+    request: Value,
+    response: T,
+}
+
+impl Recorder {
+    pub(crate) fn replaying(&self) -> bool {
+        matches!(self.mode, RecordingMode::Replay)
+    }
+
+    fn file(&self, kind: &str, name: &str, request: &Value) -> PathBuf {
+        let hash = hex::encode(Sha256::digest(request.to_string()));
+
+        self.path.join(kind).join(name).join(format!("{hash}.json"))
+    }
+
+    async fn save<T: Serialize>(&self, kind: &str, name: &str, request: Value, response: &T) {
+        let file = self.file(kind, name, &request);
+        let exchange = Exchange { request, response };
+
+        let result = async {
+            if let Some(directory) = file.parent() {
+                fs::create_dir_all(directory).await?;
+            }
+
+            fs::write(&file, to_string_pretty(&exchange)?).await
+        }
+        .await;
+
+        if let Err(error) = result {
+            event!(
+                Level::ERROR,
+                "Couldn't record exchange in {}: {error}",
+                file.display()
+            );
+        }
+    }
+
+    async fn load<T: DeserializeOwned>(
+        &self,
+        kind: &str,
+        name: &str,
+        request: &Value,
+    ) -> Option<T> {
+        let file = self.file(kind, name, request);
+
+        let exchange = async {
+            let content = fs::read_to_string(&file).await?;
+
+            Ok::<Exchange<T>, io::Error>(from_str(&content)?)
+        }
+        .await;
+
+        match exchange {
+            Ok(exchange) => Some(exchange.response),
+            Err(error) => {
+                event!(
+                    Level::ERROR,
+                    "No recorded exchange with {name} in {}: {error}",
+                    file.display()
+                );
+
+                None
+            }
+        }
+    }
+}
+
+/// Model whose exchanges are recorded or replayed, `inner` being absent when replaying
+pub(crate) struct RecordedModel {
+    pub(crate) name: String,
+    pub(crate) inner: Option<Arc<dyn AIModel + Send>>,
+    pub(crate) recorder: Arc<Recorder>,
+}
+
+#[async_trait]
+impl AIModel for RecordedModel {
+    #[instrument(skip_all, fields(model = self.name))]
+    async fn call(&self, body: ManagerBody, tools: Vec<Tool>) -> Result<ModelTurn, Error> {
+        let request = json!({ "body": body, "tools": tools });
+
+        let Some(inner) = &self.inner else {
+            return self
+                .recorder
+                .load("models", &self.name, &request)
+                .await
+                .ok_or(Error {
+                    status: 502,
+                    message: String::from("No recorded response from model"),
+                });
+        };
+
+        let response = inner.call(body, tools).await?;
+
+        self.recorder
+            .save("models", &self.name, request, &response)
+            .await;
+
+        Ok(response)
+    }
+
+    /// Deltas of the inner model, the turn they make being recorded once it ends. A replayed
+    /// turn is given whole, as by a model that can't stream
+    #[instrument(skip_all, fields(model = self.name))]
+    async fn stream(&self, body: ManagerBody, tools: Vec<Tool>) -> Result<DeltaStream, Error> {
+        let Some(inner) = &self.inner else {
+            let turn = self.call(body, tools).await?;
+
+            return Ok(Box::pin(stream::iter(turn.into_deltas().map(Ok))));
+        };
+
+        let request = json!({ "body": body, "tools": tools });
+        let deltas = inner.stream(body, tools).await?;
+        let recorder = Arc::clone(&self.recorder);
+        let name = self.name.clone();
+
+        Ok(Box::pin(stream::unfold(
+            (deltas, ModelTurn::default(), Some(request)),
+            move |(mut deltas, mut turn, mut request)| {
+                let recorder = Arc::clone(&recorder);
+                let name = name.clone();
+
+                async move {
+                    let delta = deltas.next().await?;
+
+                    if let Ok(delta) = &delta {
+                        turn.push(delta);
+
+                        if let ModelDelta::Finish(_) = delta
+                            && let Some(request) = request.take()
+                        {
+                            recorder.save("models", &name, request, &turn).await;
+                        }
+                    }
+
+                    Some((delta, (deltas, turn, request)))
+                }
+            },
+        )))
+    }
+}
+
+/// MCP server whose exchanges are recorded or replayed, `inner` being absent when replaying
+pub(crate) struct RecordedMcp {
+    pub(crate) name: String,
+    pub(crate) inner: Option<Arc<dyn McpServer + Send>>,
+    pub(crate) recorder: Arc<Recorder>,
+}
+
+impl RecordedMcp {
+    async fn replay<T: DeserializeOwned>(&self, request: &Value) -> Result<T, ServiceError> {
+        self.recorder
+            .load("mcps", &self.name, request)
+            .await
+            .ok_or_else(|| {
+                ServiceError::Transport(io::Error::other("No recorded response from MCP server"))
+            })
+    }
+}
+
+#[async_trait]
+impl McpServer for RecordedMcp {
+    #[instrument(skip_all, fields(mcp = self.name, tool = call.name))]
+    async fn call(&self, call: ToolCall) -> Result<ToolOutput, ServiceError> {
+        // The ID changes with every model response, it doesn't make the call different
+        let request = json!({ "call": call.name, "arguments": call.arguments });
+
+        let Some(inner) = &self.inner else {
+            return self.replay(&request).await;
+        };
+
+        let output = inner.call(call).await?;
+
+        self.recorder
+            .save("mcps", &self.name, request, &output)
+            .await;
+
+        Ok(output)
+    }
+
+    #[instrument(skip_all, fields(mcp = self.name))]
+    async fn list_tools(&self) -> Result<Vec<Tool>, ServiceError> {
+        let request = json!({ "list_tools": true });
+
+        let Some(inner) = &self.inner else {
+            return self.replay(&request).await;
+        };
+
+        let tools = inner.list_tools().await?;
+
+        self.recorder
+            .save("mcps", &self.name, request, &tools)
+            .await;
+
+        Ok(tools)
+    }
+}
@@ -0,0 +1,139 @@
+use std::{collections::HashSet, sync::Arc};
+
+use rmcp::model::Tool;
+use tracing::{Level, event, instrument};
+
+use crate::{
+    ManagerBody,
+    models::{AIModel, Message, ModelDecision, Role, TextMessage, Usage},
+};
+
+/// Characters around tool names in the answer of the classifier (list markers, quotes, ...)
+const NAME_DELIMITERS: [char; 7] = ['-', '*', '"', '\'', '`', '.', ':'];
+
+/// Classifier selecting the tools relevant to a conversation before calling the main model
+pub struct ToolRouter {
+    pub(crate) model: Arc<dyn AIModel + Send>,
+    pub(crate) top_k: usize,
+}
+
+impl ToolRouter {
+    /// Keep at most `top_k` tools, falling back to all of them when the classifier fails
+    #[instrument(skip_all, fields(tools = tools.len(), top_k = self.top_k))]
+    pub(crate) async fn select(
+        &self,
+        body: &ManagerBody,
+        tools: Vec<Tool>,
+    ) -> (Vec<Tool>, Option<Usage>) {
+        if tools.len() <= self.top_k {
+            return (tools, None);
+        }
+
+        let request = ManagerBody {
+            messages: vec![Message::TextMessage(TextMessage {
+                role: Role::User,
+                content: self.prompt(body, &tools),
+            })],
+            temperature: Some(0.0),
+            ..Default::default()
+        };
+
+        let response = match self.model.call(request, Vec::new()).await {
+            Ok(response) => response,
+            Err(error) => {
+                event!(
+                    Level::WARN,
+                    "Tool router failed, using all tools: {}",
+                    error.message
+                );
+
+                return (tools, None);
+            }
+        };
+
+        let answer: String = response
+            .decisions
+            .into_iter()
+            .filter_map(|decision| match decision {
+                ModelDecision::TextMessage(text) => Some(text),
+                ModelDecision::ToolCalls(_) => None,
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        let selected = self.parse(&answer, &tools);
+
+        if selected.is_empty() {
+            event!(
+                Level::WARN,
+                "Tool router didn't select any known tool, using all tools"
+            );
+
+            return (tools, response.usage);
+        }
+
+        event!(Level::DEBUG, "Tool router selected {selected:?}");
+
+        let tools = selected
+            .into_iter()
+            .filter_map(|name| tools.iter().find(|tool| tool.name == name).cloned())
+            .collect();
+
+        (tools, response.usage)
+    }
+
+    fn prompt(&self, body: &ManagerBody, tools: &[Tool]) -> String {
+        let catalog: Vec<String> = tools
+            .iter()
+            .map(|tool| format!("- {}: {}", tool.name, tool.description))
+            .collect();
+
+        let conversation: Vec<String> = body
+            .messages
+            .iter()
+            .filter_map(|message| match message {
+                Message::TextMessage(TextMessage { role, content }) => {
+                    let role = match role {
+                        Role::Assistant => "assistant",
+                        Role::System => "system",
+                        Role::Tool => "tool",
+                        Role::User => "user",
+                    };
+
+                    Some(format!("{role}: {content}"))
+                }
+                Message::ToolCalls { tool_calls, .. } => Some(format!(
+                    "assistant called: {}",
+                    tool_calls
+                        .iter()
+                        .map(|call| call.name.as_str())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )),
+                Message::ToolOutput { .. } => None,
+            })
+            .collect();
+
+        format!(
+            "Select the tools that are the most relevant to continue the conversation below. \
+            Answer only with the names of at most {} tools, one per line, the most relevant first.\n\n\
+            Tools:\n{}\n\nConversation:\n{}",
+            self.top_k,
+            catalog.join("\n"),
+            conversation.join("\n")
+        )
+    }
+
+    /// Names of known tools in the answer, in order and without duplicates
+    fn parse<'a>(&self, answer: &'a str, tools: &[Tool]) -> Vec<&'a str> {
+        let mut seen = HashSet::new();
+
+        answer
+            .split(|c: char| c.is_whitespace() || c == ',')
+            .map(|word| word.trim_matches(&NAME_DELIMITERS[..]))
+            .filter(|word| tools.iter().any(|tool| tool.name == *word))
+            .filter(|word| seen.insert(*word))
+            .take(self.top_k)
+            .collect()
+    }
+}
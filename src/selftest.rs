@@ -0,0 +1,227 @@
+use std::{collections::BTreeMap, fmt, sync::Arc, time::Duration};
+
+use futures::future::join_all;
+use rmcp::model::JsonObject;
+use serde_json::Value;
+use tokio::time::timeout;
+
+use crate::{
+    ManagerConfig, Workspace,
+    mcp::{McpServer, ToolCall},
+};
+
+/// Time a tool is given to answer before its test fails
+const SELFTEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Call of a tool of a workspace, with what its output is expected to hold
+#[derive(Debug)]
+pub struct SelfTest {
+    pub(crate) tool: String,
+    pub(crate) arguments: Option<JsonObject>,
+    /// Texts the output must contain
+    pub(crate) contains: Vec<String>,
+    /// Values of the output, parsed as JSON
+    pub(crate) json: Vec<JsonAssertion>,
+    /// Whether the tool must report an error
+    pub(crate) error: bool,
+}
+
+/// Value expected at a JSONPath of the output
+#[derive(Debug)]
+pub(crate) struct JsonAssertion {
+    pub(crate) path: String,
+    pub(crate) segments: Vec<Segment>,
+    pub(crate) expected: Value,
+}
+
+/// Tool called by a test, with what it failed on
+type Outcome = (String, Result<(), String>);
+
+/// Outcome of the tests of every workspace, in the order they're configured
+pub struct SelfTestReport {
+    workspaces: BTreeMap<String, Vec<Outcome>>,
+}
+
+impl SelfTestReport {
+    /// Tests that failed
+    pub fn failures(&self) -> usize {
+        self.workspaces
+            .values()
+            .flatten()
+            .filter(|(_, outcome)| outcome.is_err())
+            .count()
+    }
+}
+
+impl fmt::Display for SelfTestReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (name, tests) in self.workspaces.iter() {
+            writeln!(f, "{name}:")?;
+
+            for (index, (tool, outcome)) in tests.iter().enumerate() {
+                match outcome {
+                    Ok(()) => writeln!(f, "  #{} {tool}: ok", index + 1)?,
+                    Err(error) => writeln!(f, "  #{} {tool}: FAILED: {error}", index + 1)?,
+                }
+            }
+        }
+
+        write!(f, "{} failure(s)", self.failures())
+    }
+}
+
+/// Run the tests of every workspace having some, the workspaces at the same time but the tests of
+/// each one after the other (a test can depend on what an earlier one did)
+pub async fn selftest(config: &ManagerConfig) -> SelfTestReport {
+    let workspaces = join_all(
+        config
+            .workspaces
+            .iter()
+            .filter(|(_, workspace)| !workspace.selftests.is_empty())
+            .map(|(name, workspace)| async move {
+                (name.clone(), run_workspace(Arc::clone(workspace)).await)
+            }),
+    )
+    .await;
+
+    SelfTestReport {
+        workspaces: workspaces.into_iter().collect(),
+    }
+}
+
+async fn run_workspace(workspace: Arc<Workspace>) -> Vec<Outcome> {
+    let tools = match workspace.list_tools().await {
+        Ok(tools) => tools,
+        Err(error) => {
+            return workspace
+                .selftests
+                .iter()
+                .map(|test| (test.tool.clone(), Err(error.message.clone())))
+                .collect();
+        }
+    };
+
+    let mut outcomes = Vec::new();
+
+    for test in workspace.selftests.iter() {
+        let mcp = workspace
+            .mcps
+            .iter()
+            .zip(tools.iter())
+            .find(|(_, tools)| tools.iter().any(|tool| tool.name == test.tool))
+            .map(|((_, mcp), _)| mcp);
+
+        let outcome = match mcp {
+            Some(mcp) => run(test, mcp.as_ref()).await,
+            None => Err(String::from("Tool doesn't exist in the workspace")),
+        };
+
+        outcomes.push((test.tool.clone(), outcome));
+    }
+
+    outcomes
+}
+
+fn call(test: &SelfTest) -> ToolCall {
+    ToolCall {
+        name: test.tool.clone(),
+        id: String::from("selftest"),
+        arguments: test.arguments.clone(),
+        thought_signature: None,
+        invalid_arguments: None,
+        headers: Default::default(),
+    }
+}
+
+/// Call the tool of a test and check its output against the assertions of its test
+async fn run(test: &SelfTest, mcp: &(dyn McpServer + Send)) -> Result<(), String> {
+    let output = match timeout(SELFTEST_TIMEOUT, mcp.call(call(test))).await {
+        Ok(Ok(output)) => output,
+        Ok(Err(error)) => return Err(error.to_string()),
+        Err(_) => return Err(String::from("Timed out calling the tool")),
+    };
+
+    if output.error != test.error {
+        return Err(if output.error {
+            format!("Tool reported an error: {}", output.text)
+        } else {
+            String::from("Tool didn't report an error")
+        });
+    }
+
+    if let Some(text) = test
+        .contains
+        .iter()
+        .find(|text| !output.text.contains(text.as_str()))
+    {
+        return Err(format!("Output doesn't contain '{text}'"));
+    }
+
+    if test.json.is_empty() {
+        return Ok(());
+    }
+
+    let value: Value = serde_json::from_str(&output.text)
+        .map_err(|error| format!("Output isn't JSON: {error}"))?;
+
+    for assertion in test.json.iter() {
+        let (path, expected) = (&assertion.path, &assertion.expected);
+
+        match select(&value, &assertion.segments) {
+            Some(found) if found == expected => {}
+            Some(found) => return Err(format!("{path} is {found}, expected {expected}")),
+            None => return Err(format!("{path} not found in the output")),
+        }
+    }
+
+    Ok(())
+}
+
+/// Step of a JSONPath
+#[derive(Debug)]
+pub(crate) enum Segment {
+    Key(String),
+    Index(usize),
+}
+
+/// Steps of a JSONPath made of keys and indexes, e.g. `$.items[0].name` or `$['a key']`
+pub(crate) fn parse_path(path: &str) -> Result<Vec<Segment>, String> {
+    let invalid = || format!("Invalid JSONPath '{path}'");
+    let mut rest = path.strip_prefix('$').ok_or_else(invalid)?;
+    let mut segments = Vec::new();
+
+    while !rest.is_empty() {
+        if let Some(after) = rest.strip_prefix('.') {
+            let end = after.find(['.', '[']).unwrap_or(after.len());
+
+            if end == 0 {
+                return Err(invalid());
+            }
+
+            segments.push(Segment::Key(after[..end].to_owned()));
+            rest = &after[end..];
+        } else if let Some(after) = rest.strip_prefix("['") {
+            let end = after.find("']").ok_or_else(invalid)?;
+
+            segments.push(Segment::Key(after[..end].to_owned()));
+            rest = &after[end + 2..];
+        } else if let Some(after) = rest.strip_prefix('[') {
+            let end = after.find(']').ok_or_else(invalid)?;
+            let index = after[..end].parse().map_err(|_| invalid())?;
+
+            segments.push(Segment::Index(index));
+            rest = &after[end + 1..];
+        } else {
+            return Err(invalid());
+        }
+    }
+
+    Ok(segments)
+}
+
+fn select<'a>(value: &'a Value, path: &[Segment]) -> Option<&'a Value> {
+    path.iter().try_fold(value, |value, segment| match segment {
+        Segment::Key(key) => value.get(key),
+        Segment::Index(index) => value.get(index),
+    })
+}
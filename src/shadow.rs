@@ -0,0 +1,80 @@
+use std::{sync::Arc, time::Instant};
+
+use rmcp::model::Tool;
+use serde_json::json;
+use tracing::{Instrument, Level, Span, event};
+
+use crate::{
+    ManagerBody,
+    budgets::Price,
+    logging::log_payloads,
+    models::{AIModel, ModelDecision},
+};
+
+/// Candidate model given a copy of the requests of a workspace, its answers being logged and
+/// discarded
+pub struct Shadow {
+    pub(crate) name: String,
+    pub(crate) model: Arc<dyn AIModel + Send>,
+    pub(crate) price: Option<Price>,
+}
+
+impl Shadow {
+    /// Call the model in the background with the request given to the main model, logging what
+    /// it would have answered. The tools it calls aren't run
+    pub(crate) fn mirror(&self, request: ManagerBody, tools: Vec<Tool>) {
+        let name = self.name.clone();
+        let model = Arc::clone(&self.model);
+        let price = self.price;
+
+        tokio::spawn(
+            async move {
+                let start = Instant::now();
+
+                let response = match model.call(request, tools).await {
+                    Ok(response) => response,
+                    Err(error) => {
+                        event!(Level::WARN, "Shadow model {name} failed: {}", error.message);
+
+                        return;
+                    }
+                };
+
+                let latency_ms = start.elapsed().as_millis();
+                let usage = response.usage.unwrap_or_default();
+
+                let answer: Vec<String> = response
+                    .decisions
+                    .iter()
+                    .map(|decision| match decision {
+                        ModelDecision::TextMessage(text) => format!("text ({} chars)", text.len()),
+                        ModelDecision::ToolCalls(calls) => format!(
+                            "tool calls ({})",
+                            calls
+                                .iter()
+                                .map(|call| call.name.as_str())
+                                .collect::<Vec<_>>()
+                                .join(", ")
+                        ),
+                    })
+                    .collect();
+
+                event!(
+                    Level::INFO,
+                    shadow = name,
+                    latency_ms,
+                    prompt_tokens = usage.prompt_tokens,
+                    completion_tokens = usage.completion_tokens,
+                    cost = price.map(|price| price.cost(&usage)),
+                    "Shadow model {name} answered: {}",
+                    answer.join(", ")
+                );
+
+                if log_payloads() {
+                    event!(Level::DEBUG, "Shadow answer: {}", json!(response.decisions));
+                }
+            }
+            .instrument(Span::current()),
+        );
+    }
+}
@@ -0,0 +1,202 @@
+use std::{
+    collections::VecDeque,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use futures::StreamExt;
+use rmcp::model::Tool;
+use tokio::{
+    sync::{Mutex, Semaphore},
+    time::sleep,
+};
+use tracing::{Level, event};
+
+use crate::{
+    Error, ManagerBody,
+    models::{AIModel, DeltaStream, ModelDelta, ModelTurn},
+};
+
+/// Span the requests and tokens per minute are counted over
+const WINDOW: Duration = Duration::from_secs(60);
+
+/// Ceilings of the calls made to a model, kept under the rate limits of its provider
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RateLimit {
+    pub requests_per_minute: Option<u32>,
+    /// Tokens used by the calls answered in the last minute, the tokens of a call only being
+    /// known once answered
+    pub tokens_per_minute: Option<u64>,
+    pub max_concurrent: Option<usize>,
+}
+
+/// Calls started and tokens used in the last minute
+#[derive(Debug, Default)]
+struct Window {
+    calls: VecDeque<Instant>,
+    tokens: VecDeque<(Instant, u64)>,
+}
+
+impl Window {
+    fn prune(&mut self, now: Instant) {
+        while self
+            .calls
+            .front()
+            .is_some_and(|at| now.duration_since(*at) >= WINDOW)
+        {
+            self.calls.pop_front();
+        }
+
+        while self
+            .tokens
+            .front()
+            .is_some_and(|(at, _)| now.duration_since(*at) >= WINDOW)
+        {
+            self.tokens.pop_front();
+        }
+    }
+
+    /// Time until a call can start, none when it can right away
+    fn wait(&self, limit: &RateLimit, now: Instant) -> Option<Duration> {
+        let mut until = None;
+
+        if let Some(requests) = limit.requests_per_minute
+            && self.calls.len() >= requests as usize
+        {
+            until = self.calls.front().copied();
+        }
+
+        if let Some(max_tokens) = limit.tokens_per_minute {
+            let mut used: u64 = self.tokens.iter().map(|(_, tokens)| tokens).sum();
+
+            // Wait for enough of the oldest calls to leave the window to be under the ceiling
+            for (at, tokens) in self.tokens.iter() {
+                if used < max_tokens {
+                    break;
+                }
+
+                used -= tokens;
+                until = until.max(Some(*at));
+            }
+        }
+
+        until.map(|at| (at + WINDOW).saturating_duration_since(now))
+    }
+}
+
+/// Model whose calls wait for the rate limit to have room for them
+pub(crate) struct ShapedModel {
+    pub(crate) name: String,
+    pub(crate) inner: Arc<dyn AIModel + Send>,
+    pub(crate) limit: RateLimit,
+    concurrency: Option<Arc<Semaphore>>,
+    window: Arc<Mutex<Window>>,
+}
+
+impl ShapedModel {
+    pub(crate) fn new(name: String, inner: Arc<dyn AIModel + Send>, limit: RateLimit) -> Self {
+        ShapedModel {
+            name,
+            inner,
+            limit,
+            concurrency: limit
+                .max_concurrent
+                .map(|permits| Arc::new(Semaphore::new(permits))),
+            window: Arc::new(Mutex::new(Window::default())),
+        }
+    }
+
+    /// Wait until a call can start under the requests and tokens per minute, counting it
+    async fn start(&self) {
+        loop {
+            let mut window = self.window.lock().await;
+            let now = Instant::now();
+
+            window.prune(now);
+
+            let Some(wait) = window.wait(&self.limit, now) else {
+                window.calls.push_back(now);
+
+                return;
+            };
+
+            drop(window);
+
+            event!(
+                Level::DEBUG,
+                "Rate limit of model {} reached, waiting {}ms",
+                self.name,
+                wait.as_millis()
+            );
+
+            sleep(wait).await;
+        }
+    }
+
+    /// Count the tokens of an answer in the window, when they're limited
+    async fn used(window: &Mutex<Window>, limit: &RateLimit, turn: &ModelTurn) {
+        if limit.tokens_per_minute.is_some()
+            && let Some(usage) = turn.usage
+        {
+            window
+                .lock()
+                .await
+                .tokens
+                .push_back((Instant::now(), usage.total_tokens as u64));
+        }
+    }
+}
+
+#[async_trait]
+impl AIModel for ShapedModel {
+    async fn call(&self, body: ManagerBody, tools: Vec<Tool>) -> Result<ModelTurn, Error> {
+        let _permit = match &self.concurrency {
+            Some(semaphore) => Some(semaphore.acquire().await.expect("Semaphore never closed")),
+            None => None,
+        };
+
+        self.start().await;
+
+        let response = self.inner.call(body, tools).await;
+
+        if let Ok(turn) = &response {
+            ShapedModel::used(&self.window, &self.limit, turn).await;
+        }
+
+        response
+    }
+
+    /// Deltas of the inner model, holding the concurrency permit until the stream is dropped and
+    /// counting the tokens of the turn once it ends
+    async fn stream(&self, body: ManagerBody, tools: Vec<Tool>) -> Result<DeltaStream, Error> {
+        let permit = match &self.concurrency {
+            Some(semaphore) => Some(
+                Arc::clone(semaphore)
+                    .acquire_owned()
+                    .await
+                    .expect("Semaphore never closed"),
+            ),
+            None => None,
+        };
+
+        self.start().await;
+
+        let deltas = self.inner.stream(body, tools).await?;
+        let window = Arc::clone(&self.window);
+        let limit = self.limit;
+
+        Ok(Box::pin(deltas.then(move |delta| {
+            let _permit = &permit;
+            let window = Arc::clone(&window);
+
+            async move {
+                if let Ok(ModelDelta::Finish(turn)) = &delta {
+                    ShapedModel::used(&window, &limit, turn).await;
+                }
+
+                delta
+            }
+        })))
+    }
+}
@@ -0,0 +1,115 @@
+use std::{future::Future, time::Duration};
+
+use reqwest::{Client as HttpClient, Url, header::HeaderValue};
+use rmcp::model::JsonObject;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use tokio::{pin, select, time::sleep};
+use tracing::{Level, event};
+
+use crate::metrics::ToolMetrics;
+
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+/// Hex characters of the hash of the arguments given in the alerts
+const HASH_LENGTH: usize = 16;
+
+/// Alerts on the tool calls of a workspace still running past a threshold, raised as soon as the
+/// threshold is crossed so calls to hung MCP servers are seen before they end (if they ever do)
+#[derive(Debug)]
+pub struct SlowCalls {
+    threshold: Duration,
+    webhook: Option<(Url, HttpClient)>,
+}
+
+/// Body POSTed to the webhook of a slow call
+#[derive(Serialize)]
+struct SlowCallAlert<'a> {
+    workspace: &'a str,
+    mcp: &'a str,
+    tool: &'a str,
+    arguments_hash: &'a str,
+    threshold_ms: u128,
+}
+
+impl SlowCalls {
+    pub fn new(threshold: Duration, webhook: Option<Url>, user_agent: HeaderValue) -> SlowCalls {
+        SlowCalls {
+            threshold,
+            webhook: webhook.map(|url| {
+                (
+                    url,
+                    HttpClient::builder()
+                        .timeout(WEBHOOK_TIMEOUT)
+                        .user_agent(user_agent)
+                        .build()
+                        .expect("Couldn't create slow call webhook client"),
+                )
+            }),
+        }
+    }
+
+    /// Wait for a tool call, raising an alert if it's still running once the threshold is crossed
+    pub(crate) async fn watch<T>(
+        &self,
+        metrics: &ToolMetrics,
+        (workspace, mcp, tool): (&str, &str, &str),
+        arguments: Option<&JsonObject>,
+        call: impl Future<Output = T>,
+    ) -> T {
+        pin!(call);
+
+        select! {
+            output = &mut call => return output,
+            _ = sleep(self.threshold) => {}
+        }
+
+        let arguments_hash = hash(arguments);
+
+        event!(
+            Level::WARN,
+            workspace,
+            mcp,
+            tool,
+            arguments_hash,
+            "Tool {tool} still running after {}ms",
+            self.threshold.as_millis()
+        );
+        metrics.slow_call(workspace, mcp, tool);
+
+        if let Some((url, client)) = &self.webhook {
+            let request = client.post(url.clone()).json(&SlowCallAlert {
+                workspace,
+                mcp,
+                tool,
+                arguments_hash: &arguments_hash,
+                threshold_ms: self.threshold.as_millis(),
+            });
+            let url = url.clone();
+
+            // The call goes on while the webhook is notified
+            tokio::spawn(async move {
+                if let Err(error) = request
+                    .send()
+                    .await
+                    .and_then(|response| response.error_for_status())
+                {
+                    event!(Level::WARN, "Slow call webhook {url} failed: {error}");
+                }
+            });
+        }
+
+        call.await
+    }
+}
+
+/// Short hash of the arguments of a call, to tell the calls apart without logging the arguments
+fn hash(arguments: Option<&JsonObject>) -> String {
+    let arguments = arguments
+        .map(|arguments| serde_json::to_vec(arguments).expect("Couldn't serialize arguments"))
+        .unwrap_or_default();
+
+    let mut hash = hex::encode(Sha256::digest(arguments));
+    hash.truncate(HASH_LENGTH);
+
+    hash
+}
@@ -0,0 +1,213 @@
+use std::{
+    collections::BTreeMap,
+    fs::{self, File, OpenOptions, TryLockError},
+    io::{self, ErrorKind, Read, Seek, Write},
+    sync::Mutex,
+};
+
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use serde_json::from_str;
+use sha2::{Digest, Sha256};
+use tracing::{Level, event};
+
+/// State file of the running manager, locked as long as it runs
+static STATE: Mutex<Option<StateFile>> = Mutex::new(None);
+
+/// What a running manager holds, for another one not to start over it, and for the next one to
+/// clean up after it when it didn't stop cleanly
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct State {
+    pid: u32,
+    started_at: String,
+    /// SHA-256 of the configuration file
+    config_hash: String,
+    listeners: Vec<String>,
+    /// Programs of the local MCP servers running, by process ID
+    processes: BTreeMap<u32, String>,
+}
+
+#[derive(Debug)]
+struct StateFile {
+    path: String,
+    file: File,
+    state: State,
+}
+
+impl StateFile {
+    fn write(&mut self) {
+        let result = (|| -> io::Result<()> {
+            self.file.set_len(0)?;
+            self.file.rewind()?;
+            serde_json::to_writer_pretty(&self.file, &self.state)?;
+            self.file.flush()
+        })();
+
+        if let Err(error) = result {
+            event!(
+                Level::ERROR,
+                "Couldn't write state file {}: {error}",
+                self.path
+            );
+        }
+    }
+}
+
+/// Lock the state file, refusing to start when another manager holds it. The local MCP servers
+/// a previous manager left running when it crashed are stopped, since they can't be talked to
+/// anymore without the pipes it held
+pub fn acquire(path: &str, config_file: &str) -> io::Result<()> {
+    let config_hash = hex::encode(Sha256::digest(fs::read(config_file)?));
+
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)?;
+
+    match file.try_lock() {
+        Ok(()) => (),
+        Err(TryLockError::WouldBlock) => {
+            let holder = read(&mut file)
+                .ok()
+                .and_then(|contents| from_str::<State>(&contents).ok())
+                .map_or(String::new(), |state| format!(" (process {})", state.pid));
+
+            return Err(io::Error::new(
+                ErrorKind::AddrInUse,
+                format!("Another manager{holder} is running with state file {path}"),
+            ));
+        }
+        Err(TryLockError::Error(error)) => return Err(error),
+    }
+
+    let previous = read(&mut file)?;
+
+    if !previous.trim().is_empty() {
+        match from_str::<State>(&previous) {
+            Ok(previous) => clean_up(previous, &config_hash),
+            Err(error) => event!(Level::WARN, "Ignoring invalid state file {path}: {error}"),
+        }
+    }
+
+    let mut state_file = StateFile {
+        path: path.to_owned(),
+        file,
+        state: State {
+            pid: std::process::id(),
+            started_at: Utc::now().to_rfc3339(),
+            config_hash,
+            ..Default::default()
+        },
+    };
+
+    state_file.write();
+
+    *STATE.lock().expect("State lock poisoned") = Some(state_file);
+
+    Ok(())
+}
+
+fn read(file: &mut File) -> io::Result<String> {
+    let mut contents = String::new();
+
+    file.rewind()?;
+    file.read_to_string(&mut contents)?;
+
+    Ok(contents)
+}
+
+/// Stop what the previous manager left running, its listeners having been closed along with it
+fn clean_up(previous: State, config_hash: &str) {
+    event!(
+        Level::WARN,
+        "Manager (process {}) started at {} didn't stop cleanly, cleaning up after it",
+        previous.pid,
+        previous.started_at
+    );
+
+    if previous.config_hash != config_hash {
+        event!(
+            Level::INFO,
+            "Previous manager was running another configuration"
+        );
+    }
+
+    for (pid, program) in previous.processes {
+        stop_orphan(pid, &program);
+    }
+}
+
+/// Stop a local MCP server left running, along with the processes it started, unless its process
+/// ID was since given to another program
+#[cfg(unix)]
+fn stop_orphan(pid: u32, program: &str) {
+    let Ok(group) = i32::try_from(pid) else {
+        return;
+    };
+
+    // SAFETY: only reads the process group of a process
+    if unsafe { libc::getpgid(group) } != group {
+        return;
+    }
+
+    let name = std::path::Path::new(program)
+        .file_name()
+        .map_or(program.into(), |name| name.to_string_lossy());
+
+    match fs::read(format!("/proc/{pid}/cmdline")) {
+        Ok(command) if String::from_utf8_lossy(&command).contains(name.as_ref()) => {
+            event!(Level::INFO, "Stopping orphan MCP server {program} ({pid})");
+
+            crate::mcp::process::kill_group(group);
+        }
+        Ok(_) => (),
+        Err(error) => event!(
+            Level::WARN,
+            "Couldn't check whether process {pid} is an orphan MCP server ({program}), \
+                leaving it running: {error}"
+        ),
+    }
+}
+
+/// The local MCP servers are killed by the system along with the job of the manager
+#[cfg(not(unix))]
+fn stop_orphan(_pid: u32, _program: &str) {}
+
+/// Record the addresses the manager listens on
+pub fn listening(listeners: &[String]) {
+    if let Some(state_file) = STATE.lock().expect("State lock poisoned").as_mut() {
+        state_file.state.listeners = listeners.to_vec();
+        state_file.write();
+    }
+}
+
+pub(crate) fn process_started(pid: u32, program: String) {
+    if let Some(state_file) = STATE.lock().expect("State lock poisoned").as_mut() {
+        state_file.state.processes.insert(pid, program);
+        state_file.write();
+    }
+}
+
+pub(crate) fn process_stopped(pid: u32) {
+    if let Some(state_file) = STATE.lock().expect("State lock poisoned").as_mut()
+        && state_file.state.processes.remove(&pid).is_some()
+    {
+        state_file.write();
+    }
+}
+
+/// Remove the state file once the manager stopped everything it started, for the next one not to
+/// clean up after it
+pub fn release() {
+    if let Some(state_file) = STATE.lock().expect("State lock poisoned").take()
+        && let Err(error) = fs::remove_file(&state_file.path)
+    {
+        event!(
+            Level::ERROR,
+            "Couldn't remove state file {}: {error}",
+            state_file.path
+        );
+    }
+}
@@ -0,0 +1,533 @@
+use std::str::FromStr;
+
+use chrono::{DateTime, Utc};
+use rmcp::model::JsonObject;
+use serde::Serialize;
+use serde_json::json;
+use sqlx::{
+    FromRow, QueryBuilder, Sqlite, SqlitePool,
+    sqlite::{SqliteConnectOptions, SqlitePoolOptions},
+};
+use tracing::{Level, event, instrument};
+
+use crate::{
+    Step,
+    anonymizing::Anonymizer,
+    mirroring::Outcome,
+    models::{Message, Usage},
+};
+
+/// Key of the metadata of a request naming the conversation it's part of
+pub(crate) const CONVERSATION_METADATA: &str = "conversation_id";
+
+/// Persistent store of requests, token usage and tool calls
+#[derive(Debug)]
+pub struct Storage {
+    pool: SqlitePool,
+    /// Whether the messages of the requests are recorded
+    transcripts: bool,
+    /// Hashes the contents of the messages and tool calls are stored as, instead of plaintext
+    anonymizer: Option<Anonymizer>,
+}
+
+/// Everything recorded about a single workspace request
+#[derive(Debug)]
+pub(crate) struct RequestRecord<'a> {
+    pub(crate) id: &'a str,
+    pub(crate) workspace: &'a str,
+    /// Model that answered the request
+    pub(crate) model: &'a str,
+    /// Name of the budget of the client sending the request
+    pub(crate) client: Option<&'a str>,
+    /// Metadata given by the client
+    pub(crate) metadata: Option<&'a JsonObject>,
+    pub(crate) started_at: DateTime<Utc>,
+    pub(crate) duration_ms: u128,
+    pub(crate) status: u16,
+    pub(crate) usage: Option<Usage>,
+    pub(crate) cost: Option<f64>,
+    pub(crate) steps: &'a [Step],
+    /// Messages of the request once answered
+    pub(crate) transcript: Option<&'a [Message]>,
+    /// Stored message the new messages of the transcript follow
+    pub(crate) parent_message_id: Option<&'a str>,
+    /// IDs the last messages of the transcript are stored under, the ones before having been
+    /// taken from the storage
+    pub(crate) message_ids: &'a [String],
+}
+
+/// Differences between the answer to a request and the one of the workspace mirroring it
+#[derive(Debug)]
+pub(crate) struct MirrorRecord<'a> {
+    /// ID the request is recorded under
+    pub(crate) request_id: &'a str,
+    /// Workspace mirroring the request
+    pub(crate) mirror: &'a str,
+    /// Model of the mirror workspace that answered
+    pub(crate) model: &'a str,
+    pub(crate) started_at: DateTime<Utc>,
+    pub(crate) outcome: &'a Outcome,
+    pub(crate) mirror_outcome: &'a Outcome,
+    /// Cost of the answer of the mirror workspace
+    pub(crate) cost: Option<f64>,
+}
+
+/// Last transcript recorded for a conversation
+#[derive(Debug, FromRow)]
+pub(crate) struct Conversation {
+    pub(crate) workspace: String,
+    pub(crate) started_at: DateTime<Utc>,
+    pub(crate) transcript: String,
+}
+
+/// Aggregated usage of a workspace, or of one of its models
+#[derive(Debug, FromRow, Serialize)]
+pub struct UsageSummary {
+    workspace: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    model: Option<String>,
+    requests: i64,
+    failed_requests: i64,
+    prompt_tokens: i64,
+    completion_tokens: i64,
+    total_tokens: i64,
+    cost: f64,
+    tool_calls: i64,
+}
+
+impl Storage {
+    pub async fn new(
+        path: &str,
+        transcripts: bool,
+        anonymizer: Option<Anonymizer>,
+    ) -> Result<Storage, sqlx::Error> {
+        let options = SqliteConnectOptions::from_str(path)?.create_if_missing(true);
+
+        let pool = SqlitePoolOptions::new().connect_with(options).await?;
+
+        sqlx::query(
+            "CREATE TABLE IF NOT EXISTS requests (
+                id TEXT PRIMARY KEY,
+                workspace TEXT NOT NULL,
+                started_at TEXT NOT NULL,
+                duration_ms INTEGER NOT NULL,
+                status INTEGER NOT NULL,
+                model_calls INTEGER NOT NULL,
+                prompt_tokens INTEGER NOT NULL,
+                completion_tokens INTEGER NOT NULL,
+                total_tokens INTEGER NOT NULL
+            )",
+        )
+        .execute(&pool)
+        .await?;
+
+        sqlx::query(
+            "CREATE TABLE IF NOT EXISTS tool_calls (
+                request_id TEXT NOT NULL REFERENCES requests(id),
+                call_id TEXT NOT NULL,
+                tool TEXT NOT NULL,
+                arguments TEXT,
+                duration_ms INTEGER NOT NULL
+            )",
+        )
+        .execute(&pool)
+        .await?;
+
+        sqlx::query(
+            "CREATE TABLE IF NOT EXISTS mirrored_requests (
+                request_id TEXT NOT NULL REFERENCES requests(id),
+                mirror TEXT NOT NULL,
+                model TEXT NOT NULL,
+                started_at TEXT NOT NULL,
+                status INTEGER NOT NULL,
+                mirror_status INTEGER NOT NULL,
+                duration_ms INTEGER NOT NULL,
+                mirror_duration_ms INTEGER NOT NULL,
+                total_tokens INTEGER NOT NULL,
+                mirror_total_tokens INTEGER NOT NULL,
+                mirror_cost REAL,
+                tools TEXT NOT NULL,
+                mirror_tools TEXT NOT NULL,
+                answer TEXT,
+                mirror_answer TEXT,
+                same_status INTEGER NOT NULL,
+                same_tools INTEGER NOT NULL,
+                same_answer INTEGER NOT NULL
+            )",
+        )
+        .execute(&pool)
+        .await?;
+
+        // Tree of the messages of the conversations, each following its parent
+        sqlx::query(
+            "CREATE TABLE IF NOT EXISTS messages (
+                id TEXT PRIMARY KEY,
+                parent_id TEXT REFERENCES messages(id),
+                request_id TEXT NOT NULL REFERENCES requests(id),
+                message TEXT NOT NULL
+            )",
+        )
+        .execute(&pool)
+        .await?;
+
+        sqlx::query(
+            "CREATE INDEX IF NOT EXISTS requests_workspace ON requests(workspace, started_at)",
+        )
+        .execute(&pool)
+        .await?;
+
+        // Databases created by older versions lack the newer columns
+        for (column, kind) in [
+            ("client", "TEXT"),
+            ("cost", "REAL"),
+            ("metadata", "TEXT"),
+            ("model", "TEXT"),
+            ("transcript", "TEXT"),
+        ] {
+            let (exists,): (bool,) = sqlx::query_as(
+                "SELECT COUNT(*) > 0 FROM pragma_table_info('requests') WHERE name = ?",
+            )
+            .bind(column)
+            .fetch_one(&pool)
+            .await?;
+
+            if !exists {
+                sqlx::query(&format!("ALTER TABLE requests ADD COLUMN {column} {kind}"))
+                    .execute(&pool)
+                    .await?;
+            }
+        }
+
+        sqlx::query("CREATE INDEX IF NOT EXISTS requests_client ON requests(client, started_at)")
+            .execute(&pool)
+            .await?;
+
+        Ok(Storage {
+            pool,
+            transcripts,
+            anonymizer,
+        })
+    }
+
+    /// Persist a request and its tool calls, logging failures instead of returning them
+    #[instrument(skip_all, fields(id = record.id))]
+    pub(crate) async fn record(&self, record: RequestRecord<'_>) {
+        if let Err(error) = self.insert(record).await {
+            event!(Level::ERROR, "Couldn't store request: {error}");
+        }
+    }
+
+    async fn insert(&self, record: RequestRecord<'_>) -> Result<(), sqlx::Error> {
+        let usage = record.usage.unwrap_or_default();
+
+        let mut transaction = self.pool.begin().await?;
+
+        sqlx::query(
+            "INSERT INTO requests (id, workspace, model, client, started_at, duration_ms, status,
+                model_calls, prompt_tokens, completion_tokens, total_tokens, cost, metadata,
+                transcript)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(record.id)
+        .bind(record.workspace)
+        .bind(record.model)
+        .bind(record.client)
+        .bind(record.started_at)
+        .bind(record.duration_ms as i64)
+        .bind(record.status)
+        .bind(record.steps.len() as i64)
+        .bind(usage.prompt_tokens as i64)
+        .bind(usage.completion_tokens as i64)
+        .bind(usage.total_tokens as i64)
+        .bind(record.cost)
+        .bind(record.metadata.map(|metadata| {
+            match &self.anonymizer {
+                Some(anonymizer) => json!(anonymizer.arguments(metadata)),
+                None => json!(metadata),
+            }
+            .to_string()
+        }))
+        .bind(
+            record
+                .transcript
+                .filter(|_| self.transcripts)
+                .map(|transcript| match &self.anonymizer {
+                    Some(anonymizer) => json!(
+                        transcript
+                            .iter()
+                            .map(|message| anonymizer.message(message))
+                            .collect::<Vec<_>>()
+                    )
+                    .to_string(),
+                    None => json!(transcript).to_string(),
+                }),
+        )
+        .execute(&mut *transaction)
+        .await?;
+
+        for call in record.steps.iter().flat_map(|step| step.tool_calls.iter()) {
+            sqlx::query(
+                "INSERT INTO tool_calls (request_id, call_id, tool, arguments, duration_ms)
+                VALUES (?, ?, ?, ?, ?)",
+            )
+            .bind(record.id)
+            .bind(&call.id)
+            .bind(&call.name)
+            .bind(call.arguments.as_ref().map(|arguments| {
+                match &self.anonymizer {
+                    Some(anonymizer) => json!(anonymizer.arguments(arguments)),
+                    None => json!(arguments),
+                }
+                .to_string()
+            }))
+            .bind(call.duration_ms as i64)
+            .execute(&mut *transaction)
+            .await?;
+        }
+
+        if let Some(transcript) = record.transcript.filter(|_| self.transcripts) {
+            let new = &transcript[transcript.len().saturating_sub(record.message_ids.len())..];
+            let mut parent = record.parent_message_id;
+
+            for (id, message) in record.message_ids.iter().zip(new) {
+                sqlx::query(
+                    "INSERT INTO messages (id, parent_id, request_id, message)
+                    VALUES (?, ?, ?, ?)",
+                )
+                .bind(id)
+                .bind(parent)
+                .bind(record.id)
+                .bind(
+                    match &self.anonymizer {
+                        Some(anonymizer) => json!(anonymizer.message(message)),
+                        None => json!(message),
+                    }
+                    .to_string(),
+                )
+                .execute(&mut *transaction)
+                .await?;
+
+                parent = Some(id);
+            }
+        }
+
+        transaction.commit().await
+    }
+
+    /// Persist how the answer of a mirror workspace differs, logging failures instead of
+    /// returning them
+    #[instrument(skip_all, fields(id = record.request_id))]
+    pub(crate) async fn record_mirror(&self, record: MirrorRecord<'_>) {
+        let (outcome, mirror) = (record.outcome, record.mirror_outcome);
+
+        let result = sqlx::query(
+            "INSERT INTO mirrored_requests (request_id, mirror, model, started_at, status,
+                mirror_status, duration_ms, mirror_duration_ms, total_tokens, mirror_total_tokens,
+                mirror_cost, tools, mirror_tools, answer, mirror_answer, same_status, same_tools,
+                same_answer)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(record.request_id)
+        .bind(record.mirror)
+        .bind(record.model)
+        .bind(record.started_at)
+        .bind(outcome.status)
+        .bind(mirror.status)
+        .bind(outcome.duration_ms as i64)
+        .bind(mirror.duration_ms as i64)
+        .bind(outcome.usage.unwrap_or_default().total_tokens as i64)
+        .bind(mirror.usage.unwrap_or_default().total_tokens as i64)
+        .bind(record.cost)
+        .bind(json!(outcome.tools).to_string())
+        .bind(json!(mirror.tools).to_string())
+        .bind(self.anonymize(outcome.answer.as_deref()))
+        .bind(self.anonymize(mirror.answer.as_deref()))
+        .bind(outcome.status == mirror.status)
+        .bind(outcome.tools == mirror.tools)
+        .bind(outcome.answer == mirror.answer)
+        .execute(&self.pool)
+        .await;
+
+        if let Err(error) = result {
+            event!(Level::ERROR, "Couldn't store mirrored request: {error}");
+        }
+    }
+
+    /// Usage per workspace (and per model when `by_model`), optionally restricted to a workspace
+    /// and to requests after a date
+    pub async fn usage(
+        &self,
+        workspace: Option<&str>,
+        since: Option<DateTime<Utc>>,
+        by_model: bool,
+    ) -> Result<Vec<UsageSummary>, sqlx::Error> {
+        let mut query = QueryBuilder::<Sqlite>::new(if by_model {
+            "SELECT workspace, model,"
+        } else {
+            "SELECT workspace, NULL AS model,"
+        });
+
+        query.push(
+            " COUNT(*) AS requests,
+                SUM(status >= 400) AS failed_requests,
+                SUM(prompt_tokens) AS prompt_tokens,
+                SUM(completion_tokens) AS completion_tokens,
+                SUM(total_tokens) AS total_tokens,
+                TOTAL(cost) AS cost,
+                SUM((SELECT COUNT(*) FROM tool_calls WHERE request_id = requests.id)) AS tool_calls
+            FROM requests WHERE 1 = 1",
+        );
+
+        if let Some(workspace) = workspace {
+            query.push(" AND workspace = ").push_bind(workspace);
+        }
+
+        if let Some(since) = since {
+            query.push(" AND started_at >= ").push_bind(since);
+        }
+
+        if by_model {
+            query.push(" GROUP BY workspace, model ORDER BY workspace, model");
+        } else {
+            query.push(" GROUP BY workspace ORDER BY workspace");
+        }
+
+        query.build_query_as().fetch_all(&self.pool).await
+    }
+
+    /// Transcript of the last answered request of a conversation, which holds the messages of the
+    /// previous ones. Its ID is hashed as in the metadata when anonymized
+    pub(crate) async fn conversation(&self, id: &str) -> Result<Option<Conversation>, sqlx::Error> {
+        sqlx::query_as(
+            "SELECT workspace, started_at, transcript FROM requests
+            WHERE transcript IS NOT NULL AND json_extract(metadata, ?) = ?
+            ORDER BY started_at DESC LIMIT 1",
+        )
+        .bind(format!("$.{CONVERSATION_METADATA}"))
+        .bind(self.anonymize(Some(id)))
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    /// Messages from the start of a conversation to a message of a workspace, with their IDs,
+    /// following the branch it's on. Empty when the message isn't stored
+    pub(crate) async fn branch(
+        &self,
+        workspace: &str,
+        id: &str,
+    ) -> Result<Vec<(String, Message)>, sqlx::Error> {
+        let rows: Vec<(String, String)> = sqlx::query_as(
+            "WITH RECURSIVE branch(id, parent_id, message, depth) AS (
+                SELECT messages.id, parent_id, message, 0 FROM messages
+                JOIN requests ON requests.id = messages.request_id
+                WHERE messages.id = ? AND requests.workspace = ?
+                UNION ALL
+                SELECT messages.id, messages.parent_id, messages.message, depth + 1
+                FROM messages JOIN branch ON messages.id = branch.parent_id
+            )
+            SELECT id, message FROM branch ORDER BY depth DESC",
+        )
+        .bind(id)
+        .bind(workspace)
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.into_iter()
+            .map(|(id, message)| {
+                serde_json::from_str(&message)
+                    .map(|message| (id, message))
+                    .map_err(|error| sqlx::Error::Decode(Box::new(error)))
+            })
+            .collect()
+    }
+
+    /// Whether the messages of the requests are recorded
+    pub(crate) fn transcripts(&self) -> bool {
+        self.transcripts
+    }
+
+    /// Whether the contents are stored as hashes, the conversations then not being followed
+    pub(crate) fn anonymized(&self) -> bool {
+        self.anonymizer.is_some()
+    }
+
+    /// Text as stored, hashed when anonymized
+    fn anonymize(&self, text: Option<&str>) -> Option<String> {
+        text.map(|text| match &self.anonymizer {
+            Some(anonymizer) => anonymizer.hash(text),
+            None => text.to_owned(),
+        })
+    }
+
+    /// Tokens and cost spent by a client since a date
+    pub(crate) async fn spent(
+        &self,
+        client: &str,
+        since: DateTime<Utc>,
+    ) -> Result<(i64, f64), sqlx::Error> {
+        sqlx::query_as(
+            "SELECT COALESCE(SUM(total_tokens), 0), TOTAL(cost)
+            FROM requests WHERE client = ? AND started_at >= ?",
+        )
+        .bind(client)
+        .bind(since)
+        .fetch_one(&self.pool)
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::from_value;
+
+    use super::*;
+    use crate::models::{Role, TextMessage};
+
+    #[tokio::test]
+    async fn anonymizes_metadata() {
+        let storage = Storage::new(
+            "sqlite::memory:",
+            true,
+            Some(Anonymizer::new(String::from("salt"))),
+        )
+        .await
+        .unwrap();
+        let metadata: JsonObject =
+            from_value(json!({"conversation_id": "first", "user_id": "alice"})).unwrap();
+        let transcript = [Message::TextMessage(TextMessage {
+            role: Role::User,
+            content: String::from("Hello"),
+        })];
+
+        storage
+            .record(RequestRecord {
+                id: "request",
+                workspace: "test",
+                model: "stub",
+                client: None,
+                metadata: Some(&metadata),
+                started_at: Utc::now(),
+                duration_ms: 0,
+                status: 200,
+                usage: None,
+                cost: None,
+                steps: &[],
+                transcript: Some(&transcript),
+                parent_message_id: None,
+                message_ids: &[],
+            })
+            .await;
+
+        let (stored,): (String,) = sqlx::query_as("SELECT metadata FROM requests")
+            .fetch_one(&storage.pool)
+            .await
+            .unwrap();
+
+        assert!(!stored.contains("alice") && !stored.contains("first"));
+        assert!(stored.contains("user_id"));
+
+        // The conversations are still found by their ID
+        assert!(storage.conversation("first").await.unwrap().is_some());
+        assert!(storage.conversation("other").await.unwrap().is_none());
+    }
+}
@@ -0,0 +1,306 @@
+use std::{
+    convert::Infallible,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
+use axum::{
+    Json,
+    body::{Body, Bytes},
+    http::header::CONTENT_TYPE,
+    response::{
+        IntoResponse, Response,
+        sse::{Event, KeepAlive, Sse},
+    },
+};
+use chrono::Utc;
+use futures::{Stream, stream};
+use rand::distr::{Alphanumeric, SampleString};
+use serde::Serialize;
+use serde_json::json;
+use tokio::{
+    select,
+    sync::{
+        mpsc::{UnboundedReceiver, UnboundedSender, unbounded_channel},
+        oneshot,
+    },
+    time::{sleep, timeout},
+};
+use tracing::{Level, event};
+
+use crate::{
+    Error, ManagerBody,
+    mcp::ToolCall,
+    models::{Message, Role, TextMessage, Usage},
+};
+
+const ID_LEN: usize = 24;
+/// Last event of a stream, as sent by OpenAI
+const DONE: &str = "[DONE]";
+
+#[derive(Debug, Serialize)]
+struct Chunk<'a> {
+    id: &'a str,
+    object: &'static str,
+    created: i64,
+    model: &'a str,
+    choices: Vec<Choice>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    usage: Option<Usage>,
+}
+
+#[derive(Debug, Serialize)]
+struct Choice {
+    index: usize,
+    delta: Delta,
+    finish_reason: Option<&'static str>,
+}
+
+#[derive(Debug, Default, Serialize)]
+struct Delta {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    role: Option<Role>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    content: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tool_calls: Option<Vec<ToolCallDelta>>,
+}
+
+#[derive(Debug, Serialize)]
+struct ToolCallDelta {
+    index: usize,
+    id: String,
+    r#type: &'static str,
+    function: FunctionDelta,
+}
+
+#[derive(Debug, Serialize)]
+struct FunctionDelta {
+    name: String,
+    /// Arguments encoded as JSON, as in the OpenAI API
+    arguments: String,
+}
+
+/// Answer of a workspace sent to the client as OpenAI `chat.completion.chunk` events while the
+/// agent loop runs, one delta per message of the model
+pub(crate) struct ChunkStream {
+    id: String,
+    created: i64,
+    /// Name of the workspace, given as the model of the chunks
+    model: String,
+    /// Whether the tool calls of the model are sent, instead of only its text
+    tool_calls: bool,
+    /// Whether any message of the model was sent
+    sent: AtomicBool,
+    sender: UnboundedSender<Event>,
+}
+
+impl ChunkStream {
+    pub(crate) fn new(model: String, tool_calls: bool) -> (ChunkStream, UnboundedReceiver<Event>) {
+        let (sender, receiver) = unbounded_channel();
+
+        let stream = ChunkStream {
+            id: format!(
+                "chatcmpl-{}",
+                Alphanumeric.sample_string(&mut rand::rng(), ID_LEN)
+            ),
+            created: Utc::now().timestamp(),
+            model,
+            tool_calls,
+            sent: AtomicBool::new(false),
+            sender,
+        };
+
+        stream.send(
+            Delta {
+                role: Some(Role::Assistant),
+                ..Default::default()
+            },
+            None,
+            None,
+        );
+
+        (stream, receiver)
+    }
+
+    fn send(&self, delta: Delta, finish_reason: Option<&'static str>, usage: Option<Usage>) {
+        let chunk = Chunk {
+            id: &self.id,
+            object: "chat.completion.chunk",
+            created: self.created,
+            model: &self.model,
+            choices: vec![Choice {
+                index: 0,
+                delta,
+                finish_reason,
+            }],
+            usage,
+        };
+
+        // The client is gone when nothing receives the events anymore, the request still runs
+        let _ = self
+            .sender
+            .send(Event::default().data(json!(chunk).to_string()));
+    }
+
+    /// Text message of the model
+    pub(crate) fn text(&self, content: &str) {
+        self.sent.store(true, Ordering::Relaxed);
+
+        self.send(
+            Delta {
+                content: Some(content.to_owned()),
+                ..Default::default()
+            },
+            None,
+            None,
+        );
+    }
+
+    /// Tool calls requested by the model, only sent when configured
+    pub(crate) fn tool_calls(&self, calls: &[ToolCall]) {
+        self.sent.store(true, Ordering::Relaxed);
+
+        if !self.tool_calls {
+            return;
+        }
+
+        self.send(
+            Delta {
+                tool_calls: Some(
+                    calls
+                        .iter()
+                        .enumerate()
+                        .map(|(index, call)| ToolCallDelta {
+                            index,
+                            id: call.id.clone(),
+                            r#type: "function",
+                            function: FunctionDelta {
+                                name: call.name.clone(),
+                                arguments: json!(call.arguments).to_string(),
+                            },
+                        })
+                        .collect(),
+                ),
+                ..Default::default()
+            },
+            None,
+            None,
+        );
+    }
+
+    /// End of the answer, the messages after the first `from` being sent first when the request
+    /// wasn't run but replayed (idempotency)
+    pub(crate) fn finish(&self, body: &ManagerBody, from: usize) {
+        if !self.sent.load(Ordering::Relaxed) {
+            for message in body.messages.iter().skip(from) {
+                match message {
+                    Message::TextMessage(TextMessage {
+                        role: Role::Assistant,
+                        content,
+                    }) => self.text(content),
+                    Message::ToolCalls { tool_calls, .. } => self.tool_calls(tool_calls),
+                    _ => (),
+                }
+            }
+        }
+
+        self.send(
+            Delta::default(),
+            Some(body.finish_reason.map_or("stop", |reason| reason.as_str())),
+            body.usage,
+        );
+        let _ = self.sender.send(Event::default().data(DONE));
+    }
+
+    /// Failure of the request after the answer started
+    pub(crate) fn fail(&self, error: Error) {
+        let _ = self.sender.send(
+            Event::default().data(
+                json!({
+                    "error": {
+                        "message": error.message,
+                        "code": error.status,
+                    }
+                })
+                .to_string(),
+            ),
+        );
+        let _ = self.sender.send(Event::default().data(DONE));
+    }
+}
+
+/// Response sending the events of a stream as they come, with a comment sent after each
+/// `keep_alive` without events (15 seconds by default)
+pub(crate) fn response(
+    receiver: UnboundedReceiver<Event>,
+    keep_alive: Option<Duration>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let events = stream::unfold(receiver, |mut receiver| async move {
+        let event = receiver.recv().await?;
+
+        Some((Ok(event), receiver))
+    });
+
+    Sse::new(events).keep_alive(match keep_alive {
+        Some(interval) => KeepAlive::new().interval(interval),
+        None => KeepAlive::default(),
+    })
+}
+
+/// Answer of a request that isn't streamed, given as usual when ready within `interval`. Past it,
+/// the response starts with a `200` and a space is sent after each `interval` until the answer
+/// is ready, for the proxies not to close the idle connection. Leading whitespace is ignored by
+/// JSON parsers, a failure being given as an `{"error": ...}` body since the status was sent
+pub(crate) async fn keep_alive<T: Serialize + Send + 'static>(
+    mut result: oneshot::Receiver<Result<T, Error>>,
+    interval: Duration,
+) -> Response {
+    if let Ok(result) = timeout(interval, &mut result).await {
+        return match result.unwrap_or_else(|_| Err(dropped())) {
+            Ok(answer) => Json(answer).into_response(),
+            Err(error) => error.into_response(),
+        };
+    }
+
+    event!(
+        Level::DEBUG,
+        "Answer not ready after {}s, keeping the connection alive",
+        interval.as_secs()
+    );
+
+    let body = stream::unfold(Some(result), move |result| async move {
+        let mut result = result?;
+
+        select! {
+            result = &mut result => {
+                let body = match result.unwrap_or_else(|_| Err(dropped())) {
+                    Ok(answer) => json!(answer),
+                    Err(error) => json!({
+                        "error": {
+                            "message": error.message,
+                            "code": error.status,
+                        }
+                    }),
+                };
+
+                Some((Ok::<_, Infallible>(Bytes::from(body.to_string())), None))
+            }
+            _ = sleep(interval) => Some((Ok(Bytes::from_static(b" ")), Some(result))),
+        }
+    });
+
+    (
+        [(CONTENT_TYPE, "application/json")],
+        Body::from_stream(body),
+    )
+        .into_response()
+}
+
+/// Failure of a request whose task ended without answering
+fn dropped() -> Error {
+    Error {
+        status: 500,
+        message: String::from("Request ended without an answer"),
+    }
+}
@@ -0,0 +1,174 @@
+use std::sync::Arc;
+
+use serde_json::json;
+use tracing::{Level, event, instrument};
+
+use crate::{
+    ManagerBody,
+    models::{AIModel, Message, Role, TextMessage, Usage, complete},
+};
+
+pub(crate) const DEFAULT_KEEP_LAST: usize = 4;
+
+/// Rough number of characters per token, enough to decide when to summarize
+const CHARS_PER_TOKEN: usize = 4;
+
+/// Condensation of the older history into a summary once the conversation gets too long
+pub struct Summarizer {
+    pub(crate) model: Arc<dyn AIModel + Send>,
+    /// Estimated tokens above which the older history is summarized
+    pub(crate) max_tokens: usize,
+    /// Last messages always sent as they are
+    pub(crate) keep_last: usize,
+}
+
+/// Summary of the history of a request, kept across the iterations of the agent loop
+#[derive(Default)]
+pub(crate) struct Summary {
+    /// Messages of the body replaced by the summary
+    end: usize,
+    text: Option<String>,
+}
+
+impl Summarizer {
+    /// Transcript to send to the model, with the older history summarized when it's too long
+    #[instrument(skip_all, fields(max_tokens = self.max_tokens, keep_last = self.keep_last))]
+    pub(crate) async fn condense(
+        &self,
+        body: ManagerBody,
+        summary: &mut Summary,
+    ) -> (ManagerBody, Option<Usage>) {
+        // The instructions at the start are never summarized
+        let start = body
+            .messages
+            .iter()
+            .take_while(|message| {
+                matches!(
+                    message,
+                    Message::TextMessage(TextMessage {
+                        role: Role::System,
+                        ..
+                    })
+                )
+            })
+            .count();
+
+        let current = Self::apply(body.clone(), start, summary);
+
+        if estimate(&current) <= self.max_tokens {
+            return (current, None);
+        }
+
+        // A tool output can't be sent without the call it answers
+        let mut end = body.messages.len().saturating_sub(self.keep_last);
+
+        while end > start && matches!(body.messages.get(end), Some(Message::ToolOutput { .. })) {
+            end -= 1;
+        }
+
+        let from = summary.end.max(start);
+
+        if end <= from {
+            return (current, None);
+        }
+
+        let prompt = self.prompt(&body.messages[from..end], summary.text.as_deref());
+
+        match complete(self.model.as_ref(), prompt).await {
+            Ok((text, _)) if text.trim().is_empty() => {
+                event!(Level::WARN, "Empty summary of the conversation, keeping it");
+
+                (current, None)
+            }
+            Ok((text, usage)) => {
+                event!(Level::INFO, "Summarized {} messages", end - start);
+
+                summary.end = end;
+                summary.text = Some(text);
+
+                (Self::apply(body, start, summary), usage)
+            }
+            Err(error) => {
+                event!(
+                    Level::WARN,
+                    "Couldn't summarize the conversation, keeping it: {}",
+                    error.message
+                );
+
+                (current, None)
+            }
+        }
+    }
+
+    /// Replace the summarized messages by the summary, given as a user message since not every
+    /// provider accepts system messages past the start nor a conversation starting with the assistant
+    fn apply(mut body: ManagerBody, start: usize, summary: &Summary) -> ManagerBody {
+        if let Some(text) = &summary.text {
+            body.messages.splice(
+                start..summary.end,
+                [Message::TextMessage(TextMessage {
+                    role: Role::User,
+                    content: format!("[Summary of the earlier conversation] {text}"),
+                })],
+            );
+        }
+
+        body
+    }
+
+    fn prompt(&self, messages: &[Message], previous: Option<&str>) -> String {
+        let conversation: Vec<String> = messages
+            .iter()
+            .map(|message| match message {
+                Message::TextMessage(TextMessage { role, content }) => {
+                    let role = match role {
+                        Role::Assistant => "assistant",
+                        Role::System => "system",
+                        Role::Tool => "tool",
+                        Role::User => "user",
+                    };
+
+                    format!("{role}: {content}")
+                }
+                Message::ToolCalls { tool_calls, .. } => format!(
+                    "assistant called: {}",
+                    tool_calls
+                        .iter()
+                        .map(|call| format!("{}({})", call.name, json!(call.arguments)))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
+                Message::ToolOutput { output, .. } => format!("tool output: {output}"),
+            })
+            .collect();
+
+        let previous = previous
+            .map(|previous| format!("Summary of what happened before:\n{previous}\n\n"))
+            .unwrap_or_default();
+
+        format!(
+            "Summarize the conversation below between a user and an assistant using tools, keeping \
+            the requests, decisions and facts needed to carry on with the task. Answer only with \
+            the summary.\n\n{previous}Conversation:\n{}",
+            conversation.join("\n")
+        )
+    }
+}
+
+/// Estimated number of tokens of the messages sent to the model
+fn estimate(body: &ManagerBody) -> usize {
+    let chars: usize = body
+        .messages
+        .iter()
+        .map(|message| match message {
+            Message::TextMessage(TextMessage { content, .. }) => content.len(),
+            Message::ToolCalls { tool_calls, .. } => tool_calls
+                .iter()
+                .map(|call| call.name.len() + json!(call.arguments).to_string().len())
+                .sum(),
+            Message::ToolOutput { output, .. } => output.len(),
+        })
+        .sum();
+
+    chars / CHARS_PER_TOKEN
+}
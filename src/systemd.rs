@@ -0,0 +1,98 @@
+use std::{collections::HashMap, env, net::TcpListener};
+
+use tracing::{Level, event};
+
+/// First file descriptor passed by systemd
+#[cfg(unix)]
+const LISTEN_FDS_START: i32 = 3;
+
+/// Sockets passed by systemd with socket activation, by their address
+#[cfg(unix)]
+pub fn listen_fds() -> HashMap<String, TcpListener> {
+    use std::os::fd::FromRawFd;
+
+    let mut sockets = HashMap::new();
+
+    // The sockets are meant for the process systemd started, not for its children
+    if env::var("LISTEN_PID").ok() != Some(std::process::id().to_string()) {
+        return sockets;
+    }
+
+    let count: i32 = env::var("LISTEN_FDS")
+        .ok()
+        .and_then(|count| count.parse().ok())
+        .unwrap_or_default();
+
+    for fd in LISTEN_FDS_START..LISTEN_FDS_START + count {
+        // SAFETY: systemd passes the sockets as open file descriptors starting at 3
+        let socket = unsafe { TcpListener::from_raw_fd(fd) };
+
+        // The duplicate isn't inherited by the MCP servers, unlike the descriptor passed
+        let duplicate = socket.try_clone();
+        drop(socket);
+
+        let socket = match duplicate {
+            Ok(socket) => socket,
+            Err(error) => {
+                event!(Level::ERROR, "Couldn't use socket {fd} of systemd: {error}");
+                continue;
+            }
+        };
+
+        match socket.local_addr() {
+            Ok(address) => {
+                event!(Level::INFO, "Using socket {address} of systemd");
+
+                sockets.insert(address.to_string(), socket);
+            }
+            Err(error) => {
+                event!(
+                    Level::ERROR,
+                    "Socket {fd} of systemd isn't a TCP socket: {error}"
+                );
+            }
+        }
+    }
+
+    sockets
+}
+
+#[cfg(not(unix))]
+pub fn listen_fds() -> HashMap<String, TcpListener> {
+    HashMap::new()
+}
+
+/// Tell systemd the manager is ready, when started as a `Type=notify` service
+#[cfg(unix)]
+pub fn notify_ready(status: &str) {
+    use std::os::unix::net::{SocketAddr, UnixDatagram};
+
+    let Some(path) = env::var_os("NOTIFY_SOCKET") else {
+        return;
+    };
+
+    let notify = || -> std::io::Result<()> {
+        let socket = UnixDatagram::unbound()?;
+
+        let address = match path.to_str().and_then(|path| path.strip_prefix('@')) {
+            #[cfg(target_os = "linux")]
+            Some(name) => {
+                use std::os::linux::net::SocketAddrExt;
+
+                SocketAddr::from_abstract_name(name)?
+            }
+            _ => SocketAddr::from_pathname(&path)?,
+        };
+
+        socket.send_to_addr(format!("READY=1\nSTATUS={status}").as_bytes(), &address)?;
+
+        Ok(())
+    };
+
+    if let Err(error) = notify() {
+        event!(Level::ERROR, "Couldn't notify systemd: {error}");
+    }
+}
+
+#[cfg(not(unix))]
+pub fn notify_ready(_status: &str) {}
@@ -0,0 +1,123 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    sync::LazyLock,
+};
+
+use axum::{
+    Json,
+    response::{IntoResponse, Response},
+};
+use regex::Regex;
+use serde::Serialize;
+
+use crate::{
+    Error, ManagerBody, Workspace,
+    models::{Message, Role, TextMessage},
+};
+
+/// Suffix of the path listing the templates of a workspace
+const TEMPLATES_SUFFIX: &str = "/templates";
+
+/// Variable of a template, e.g. `{{text}}`
+static VARIABLE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\{\{\s*([A-Za-z0-9_]+)\s*\}\}").unwrap());
+
+/// Prompts of a workspace by name, expanded into the user message of the requests naming them
+#[derive(Debug, Default)]
+pub struct Templates {
+    pub(crate) templates: HashMap<String, String>,
+}
+
+/// Template of a workspace, with the variables the requests give it
+#[derive(Debug, Serialize)]
+struct ListedTemplate<'a> {
+    name: &'a str,
+    variables: Vec<&'a str>,
+}
+
+/// Names of the variables of a template, in order and without repetitions
+pub(crate) fn variables(template: &str) -> Vec<&str> {
+    let mut seen = BTreeSet::new();
+
+    VARIABLE
+        .captures_iter(template)
+        .filter_map(|captures| captures.get(1))
+        .map(|name| name.as_str())
+        .filter(|name| seen.insert(*name))
+        .collect()
+}
+
+impl Templates {
+    /// Add the user message of the template named by the request, with its variables filled in
+    pub(crate) fn expand(&self, body: &mut ManagerBody) -> Result<(), Error> {
+        let given = body.variables.take();
+
+        let Some(name) = body.template.take() else {
+            if given.is_some() {
+                return Err(Error {
+                    status: 400,
+                    message: String::from("Variables given without a template"),
+                });
+            }
+
+            return Ok(());
+        };
+
+        let Some(template) = self.templates.get(&name) else {
+            return Err(Error {
+                status: 400,
+                message: format!("Unknown template '{name}'"),
+            });
+        };
+
+        let given = given.unwrap_or_default();
+
+        let missing: Vec<&str> = variables(template)
+            .into_iter()
+            .filter(|variable| !given.contains_key(*variable))
+            .collect();
+
+        if !missing.is_empty() {
+            return Err(Error {
+                status: 400,
+                message: format!(
+                    "Missing variables of template '{name}': {}",
+                    missing.join(", ")
+                ),
+            });
+        }
+
+        let content = VARIABLE.replace_all(template, |captures: &regex::Captures| {
+            given[&captures[1]].clone()
+        });
+
+        body.messages.push(Message::TextMessage(TextMessage {
+            role: Role::User,
+            content: content.into_owned(),
+        }));
+
+        Ok(())
+    }
+}
+
+/// Path of the workspace whose templates are listed in `<path>/templates`
+pub(crate) fn list_path(path: &str) -> Option<&str> {
+    path.strip_suffix(TEMPLATES_SUFFIX)
+        .filter(|workspace| !workspace.is_empty())
+}
+
+/// Templates of the workspace with their variables, for the clients to know what to send
+pub(crate) fn list(workspace: &Workspace) -> Response {
+    let mut templates: Vec<ListedTemplate> = workspace
+        .templates
+        .templates
+        .iter()
+        .map(|(name, template)| ListedTemplate {
+            name,
+            variables: variables(template),
+        })
+        .collect();
+    templates.sort_by_key(|template| template.name);
+
+    Json(templates).into_response()
+}
@@ -0,0 +1,244 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+
+use async_trait::async_trait;
+use axum::Router;
+use rmcp::model::{JsonObject, Tool};
+use serde_json::{Value, json};
+use tokio::net::TcpListener;
+
+use crate::{
+    DEFAULT_ARGUMENT_RETRIES, Error, ManagerBody, ManagerConfig, UnknownToolPolicy, Workspace,
+    WorkspaceModel, listener_router,
+    mcp::{
+        McpServer, ToolCall,
+        in_process::{InProcessMcp, ToolHandler},
+    },
+    models::{AIModel, ModelDecision, ModelTurn},
+    templates::Templates,
+};
+
+/// MCP server run in the process of the tests, speaking the protocol through an in-memory pipe
+pub struct McpServerBuilder {
+    name: String,
+    tools: Vec<(Tool, ToolHandler)>,
+}
+
+impl McpServerBuilder {
+    pub fn new(name: impl Into<String>) -> McpServerBuilder {
+        McpServerBuilder {
+            name: name.into(),
+            tools: Vec::new(),
+        }
+    }
+
+    /// Add a tool, whose handler answers the text of its result or of the error it reports
+    pub fn tool<F>(mut self, name: &str, description: &str, input_schema: Value, handler: F) -> Self
+    where
+        F: Fn(Option<JsonObject>) -> Result<String, String> + Send + Sync + 'static,
+    {
+        let input_schema = match input_schema {
+            Value::Object(schema) => schema,
+            _ => panic!("Schema of tool {name} isn't an object"),
+        };
+
+        self.tools.push((
+            Tool::new(name.to_owned(), description.to_owned(), input_schema),
+            Arc::new(handler),
+        ));
+        self
+    }
+
+    /// Start the server and connect to it
+    pub async fn start(self) -> TestMcp {
+        let server = InProcessMcp::start(self.name.clone(), self.tools)
+            .await
+            .unwrap_or_else(|error| panic!("{error}"));
+
+        TestMcp {
+            name: self.name,
+            server: Arc::new(server),
+        }
+    }
+}
+
+/// Started MCP server, to give to a workspace
+pub struct TestMcp {
+    name: String,
+    server: Arc<dyn McpServer + Send>,
+}
+
+/// Model answering with the decisions it's given, in order, and keeping the requests it gets
+#[derive(Default)]
+pub struct StubModel {
+    answers: Mutex<VecDeque<ModelDecision>>,
+    requests: Mutex<Vec<ManagerBody>>,
+}
+
+impl StubModel {
+    pub fn new() -> StubModel {
+        StubModel::default()
+    }
+
+    /// Answer with a text message, ending the agent loop
+    pub fn text(self, text: impl Into<String>) -> Self {
+        self.answer(ModelDecision::TextMessage(text.into()))
+    }
+
+    /// Answer by calling a tool with these arguments (a JSON object)
+    pub fn tool_call(self, name: impl Into<String>, arguments: Value) -> Self {
+        let id = format!("call_{}", self.answers.lock().unwrap().len());
+
+        self.answer(ModelDecision::ToolCalls(vec![ToolCall {
+            name: name.into(),
+            id,
+            arguments: match arguments {
+                Value::Object(arguments) => Some(arguments),
+                _ => None,
+            },
+            thought_signature: None,
+            invalid_arguments: None,
+            headers: Default::default(),
+        }]))
+    }
+
+    fn answer(self, decision: ModelDecision) -> Self {
+        self.answers.lock().unwrap().push_back(decision);
+        self
+    }
+
+    /// Requests the model got, as sent to it (e.g. with the preamble)
+    pub fn requests(&self) -> Vec<Value> {
+        self.requests
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|request| json!(request))
+            .collect()
+    }
+}
+
+#[async_trait]
+impl AIModel for StubModel {
+    async fn call(&self, body: ManagerBody, _tools: Vec<Tool>) -> Result<ModelTurn, Error> {
+        self.requests.lock().unwrap().push(body);
+
+        let Some(decision) = self.answers.lock().unwrap().pop_front() else {
+            return Err(Error {
+                status: 502,
+                message: String::from("Stub model has no answer left"),
+            });
+        };
+
+        Ok(ModelTurn {
+            decisions: vec![decision],
+            ..Default::default()
+        })
+    }
+}
+
+/// Workspace of the tests, with the default configuration
+pub struct TestWorkspace {
+    path: String,
+    model: Arc<dyn AIModel + Send>,
+    mcps: Vec<TestMcp>,
+}
+
+impl TestWorkspace {
+    pub fn new(path: impl Into<String>, model: Arc<dyn AIModel + Send>) -> TestWorkspace {
+        TestWorkspace {
+            path: path.into(),
+            model,
+            mcps: Vec::new(),
+        }
+    }
+
+    pub fn mcp(mut self, mcp: TestMcp) -> Self {
+        self.mcps.push(mcp);
+        self
+    }
+}
+
+/// Router of a listener serving the workspaces of the tests
+pub fn router(workspaces: Vec<TestWorkspace>) -> Router {
+    let config = ManagerConfig::default();
+
+    let workspaces: HashMap<String, Arc<Workspace>> = workspaces
+        .into_iter()
+        .map(|workspace| {
+            let name = workspace.path.trim_start_matches('/').to_owned();
+
+            (
+                workspace.path,
+                Arc::new(Workspace {
+                    models: vec![WorkspaceModel {
+                        name: String::from("stub"),
+                        provider: "stub",
+                        model: workspace.model,
+                        price: None,
+                        weight: 1,
+                    }],
+                    mcps: workspace
+                        .mcps
+                        .into_iter()
+                        .map(|mcp| (mcp.name, mcp.server))
+                        .collect(),
+                    limits: Default::default(),
+                    guardrails: Default::default(),
+                    moderation: None,
+                    router: None,
+                    shadow: None,
+                    mirror: None,
+                    preamble: Vec::new(),
+                    context: None,
+                    constraints: None,
+                    templates: Templates::default(),
+                    embeddings: None,
+                    planning: false,
+                    forward_metadata: Vec::new(),
+                    unknown_tools: UnknownToolPolicy::default(),
+                    response_mode: Default::default(),
+                    stream_tool_calls: false,
+                    keep_alive: None,
+                    direct_tool_calls: false,
+                    allow_client_system: true,
+                    argument_retries: DEFAULT_ARGUMENT_RETRIES,
+                    max_tool_rounds: None,
+                    final_answer: false,
+                    metrics: Arc::clone(&config.metrics),
+                    trimming: None,
+                    paging: None,
+                    slow_calls: None,
+                    summarization: None,
+                    selftests: Vec::new(),
+                    name,
+                }),
+            )
+        })
+        .collect();
+
+    listener_router(workspaces, &config)
+}
+
+/// Serve the workspaces of the tests on a free port of the loopback address, in the background
+pub async fn serve(workspaces: Vec<TestWorkspace>) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("Couldn't bind a free port");
+    let address = listener.local_addr().expect("Listener without address");
+
+    let router = router(workspaces);
+
+    tokio::spawn(async move {
+        axum::serve(
+            listener,
+            router.into_make_service_with_connect_info::<SocketAddr>(),
+        )
+        .await
+    });
+
+    address
+}
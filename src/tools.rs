@@ -0,0 +1,170 @@
+use std::{sync::Arc, time::Instant};
+
+use axum::{
+    Json,
+    body::Body,
+    http::HeaderMap,
+    response::{IntoResponse, Response},
+};
+use rmcp::model::{JsonObject, Tool};
+use serde::Serialize;
+use tracing::{Level, event};
+
+use crate::{
+    Error, Workspace,
+    clients::Client,
+    error_path,
+    mcp::{ToolCall, ToolOutput},
+    mcp_error,
+};
+
+/// Suffix of the path listing the tools of a workspace
+const TOOLS_SUFFIX: &str = "/tools";
+/// Segment of the path of a workspace followed by the name of a tool
+const TOOLS_SEGMENT: &str = "/tools/";
+/// Suffix of the path calling a tool of a workspace directly
+const CALL_SUFFIX: &str = "/call";
+
+/// Result of a tool called without the model
+#[derive(Debug, Serialize)]
+struct DirectCall {
+    mcp: String,
+    tool: String,
+    duration_ms: u128,
+    #[serde(flatten)]
+    output: ToolOutput,
+}
+
+/// Tool of a workspace, as given to the model, with the MCP server offering it
+#[derive(Debug, Serialize)]
+struct ListedTool {
+    mcp: String,
+    #[serde(flatten)]
+    tool: Tool,
+}
+
+/// Path of the workspace whose tools are listed in `<path>/tools`
+pub(crate) fn list_path(path: &str) -> Option<&str> {
+    path.strip_suffix(TOOLS_SUFFIX)
+        .filter(|workspace| !workspace.is_empty())
+}
+
+/// Tools of the workspace, in the order they're given to the model, to check what it would be
+/// offered without calling it
+pub(crate) async fn list(
+    workspace: &Workspace,
+    client: Option<&Client>,
+) -> Result<Response, Error> {
+    let mut tools = workspace.list_tools().await?;
+
+    if let Some(client) = client {
+        client.filter_tools(&mut tools);
+    }
+
+    let tools: Vec<ListedTool> = workspace
+        .mcps
+        .iter()
+        .zip(tools)
+        .flat_map(|((name, _), tools)| {
+            tools.into_iter().map(|tool| ListedTool {
+                mcp: name.clone(),
+                tool,
+            })
+        })
+        .collect();
+
+    Ok(Json(tools).into_response())
+}
+
+/// Path of a workspace and name of the tool called in `<path>/tools/<tool>/call`
+pub(crate) fn call_path(path: &str) -> Option<(&str, &str)> {
+    let (workspace, tool) = path.strip_suffix(CALL_SUFFIX)?.rsplit_once(TOOLS_SEGMENT)?;
+
+    (!workspace.is_empty() && !tool.is_empty()).then_some((workspace, tool))
+}
+
+/// Call a tool of the workspace with the arguments in the body, bypassing the model, to tell
+/// whether a failure comes from the model or from the MCP server
+pub(crate) async fn call(
+    workspace: &Workspace,
+    client: Option<&Client>,
+    tool: &str,
+    headers: HeaderMap,
+    body: Body,
+) -> Result<Response, Error> {
+    if !workspace.direct_tool_calls {
+        return Err(error_path().await);
+    }
+
+    let arguments: Option<JsonObject> = workspace.limits.read_json(body).await?;
+
+    let mut tools = workspace.list_tools().await?;
+
+    if let Some(client) = client {
+        client.filter_tools(&mut tools);
+    }
+
+    let Some((name, mcp)) = workspace
+        .mcps
+        .iter()
+        .zip(tools.iter())
+        .find(|(_, tools)| tools.iter().any(|candidate| candidate.name == tool))
+        .map(|((name, mcp), _)| (name, Arc::clone(mcp)))
+    else {
+        return Err(Error {
+            status: 404,
+            message: format!("Tool '{tool}' doesn't exist in the workspace"),
+        });
+    };
+
+    event!(Level::INFO, "Calling tool {tool} of {name} directly");
+
+    let start = Instant::now();
+
+    let call = ToolCall {
+        name: tool.to_owned(),
+        id: String::from("direct"),
+        arguments,
+        thought_signature: None,
+        invalid_arguments: None,
+        headers: Arc::new(headers),
+    };
+
+    let in_progress = workspace.metrics.start(&workspace.name, name, tool);
+
+    let result = match &workspace.slow_calls {
+        Some(slow_calls) => {
+            let arguments = call.arguments.clone();
+
+            slow_calls
+                .watch(
+                    &workspace.metrics,
+                    (&workspace.name, name, tool),
+                    arguments.as_ref(),
+                    mcp.call(call),
+                )
+                .await
+        }
+        None => mcp.call(call).await,
+    };
+
+    workspace.metrics.observe(
+        &workspace.name,
+        name,
+        tool,
+        start.elapsed(),
+        result.as_ref().ok().map(|output| output.text.len()),
+        !result.as_ref().is_ok_and(|output| !output.error),
+    );
+    drop(in_progress);
+
+    let output = result.map_err(|error| mcp_error(name, error))?;
+
+    Ok(Json(DirectCall {
+        mcp: name.clone(),
+        tool: tool.to_owned(),
+        duration_ms: start.elapsed().as_millis(),
+        output,
+    })
+    .into_response())
+}
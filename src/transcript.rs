@@ -0,0 +1,261 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Deserializer, de::Error as _};
+use serde_json::Value;
+use tracing::{Level, event};
+
+use crate::{
+    Error, ManagerBody,
+    models::{Message, Role, TextMessage},
+};
+
+/// Roles of the messages the models are given, the others being left out of the requests
+const ROLES: [&str; 4] = ["assistant", "system", "tool", "user"];
+
+/// Messages of a request, leaving out the ones with a role no model takes (e.g. `function` of
+/// older clients)
+pub(crate) fn deserialize_messages<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<Message>, D::Error> {
+    let messages = Vec::<Value>::deserialize(deserializer)?;
+    let count = messages.len();
+
+    let messages = messages
+        .into_iter()
+        .filter(|message| match message.get("role") {
+            Some(Value::String(role)) => ROLES.contains(&role.as_str()),
+            _ => true,
+        })
+        .map(|message| Message::deserialize(message).map_err(D::Error::custom))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if messages.len() < count {
+        event!(
+            Level::WARN,
+            "Leaving out {} message(s) with an unknown role",
+            count - messages.len()
+        );
+    }
+
+    Ok(messages)
+}
+
+/// Check the history given by the client holds together before giving it to a model, every tool
+/// call being answered by a single output. The outputs are moved right after their calls, in the
+/// order of the calls, and empty tool calls are left out. Only the planned tool calls of an
+/// approved request can go without outputs, at its end
+pub(crate) fn sanitize(body: &mut ManagerBody) -> Result<(), Error> {
+    let mut outputs = HashMap::new();
+    let mut rest = Vec::new();
+
+    for (index, message) in body.messages.drain(..).enumerate() {
+        match message {
+            Message::ToolOutput { ref call_id, .. } => {
+                if outputs.insert(call_id.clone(), (index, message)).is_some() {
+                    return Err(invalid(index, "answers a tool call already answered"));
+                }
+            }
+            Message::ToolCalls { tool_calls, .. } if tool_calls.is_empty() => {}
+            Message::ToolCalls {
+                role: Role::Assistant,
+                ..
+            } => rest.push((index, message)),
+            Message::ToolCalls { .. } => {
+                return Err(invalid(
+                    index,
+                    "has tool calls, which only the assistant makes",
+                ));
+            }
+            Message::TextMessage(TextMessage {
+                role: Role::Tool, ..
+            }) => {
+                return Err(invalid(
+                    index,
+                    "is a tool output without the call it answers",
+                ));
+            }
+            message => rest.push((index, message)),
+        }
+    }
+
+    let last = rest.len().saturating_sub(1);
+    let mut messages = Vec::with_capacity(rest.len() + outputs.len());
+    let mut reordered = false;
+
+    for (position, (index, message)) in rest.into_iter().enumerate() {
+        let calls = match &message {
+            Message::ToolCalls { tool_calls, .. } => tool_calls
+                .iter()
+                .map(|call| call.id.clone())
+                .collect::<Vec<_>>(),
+            _ => Vec::new(),
+        };
+
+        messages.push(message);
+
+        if position == last && body.approved && !calls.iter().any(|id| outputs.contains_key(id)) {
+            break;
+        }
+
+        let mut previous = index;
+
+        for id in calls {
+            let Some((output_index, output)) = outputs.remove(&id) else {
+                return Err(invalid(index, "has tool calls without outputs"));
+            };
+
+            reordered |= output_index != previous + 1;
+            previous = output_index;
+            messages.push(output);
+        }
+    }
+
+    if let Some((index, _)) = outputs.values().min_by_key(|(index, _)| *index) {
+        return Err(invalid(*index, "answers a tool call that wasn't made"));
+    }
+
+    if reordered {
+        event!(Level::DEBUG, "Moved the tool outputs after their calls");
+    }
+
+    body.messages = messages;
+
+    Ok(())
+}
+
+fn invalid(index: usize, problem: &str) -> Error {
+    Error {
+        status: 422,
+        message: format!("Message {index} {problem}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{Value, from_value, json};
+
+    use super::*;
+
+    fn body(messages: Value) -> ManagerBody {
+        from_value(json!({ "messages": messages })).expect("Valid body")
+    }
+
+    fn calls(ids: &[&str]) -> Value {
+        json!({
+            "role": "assistant",
+            "tool_calls": ids
+                .iter()
+                .map(|id| json!({"name": "read", "id": id, "arguments": {}}))
+                .collect::<Vec<_>>(),
+        })
+    }
+
+    fn output(id: &str) -> Value {
+        json!({"type": "FunctionCallOutput", "call_id": id, "output": id})
+    }
+
+    fn shape(body: &ManagerBody) -> Vec<String> {
+        body.messages
+            .iter()
+            .map(|message| match message {
+                Message::TextMessage(TextMessage { content, .. }) => content.clone(),
+                Message::ToolCalls { tool_calls, .. } => tool_calls
+                    .iter()
+                    .map(|call| call.id.as_str())
+                    .collect::<Vec<_>>()
+                    .join(","),
+                Message::ToolOutput { call_id, .. } => format!("-> {call_id}"),
+            })
+            .collect()
+    }
+
+    fn error(mut body: ManagerBody) -> String {
+        let error = sanitize(&mut body).expect_err("Invalid transcript");
+
+        assert_eq!(error.status, 422);
+
+        error.message
+    }
+
+    #[test]
+    fn leaves_out_unknown_roles() {
+        let body = body(json!([
+            {"role": "user", "content": "hi"},
+            {"role": "function", "name": "read", "content": "old"},
+        ]));
+
+        assert_eq!(shape(&body), ["hi"]);
+    }
+
+    #[test]
+    fn moves_outputs_after_their_calls() {
+        let mut body = body(json!([
+            {"role": "user", "content": "hi"},
+            calls(&["a", "b"]),
+            {"role": "user", "content": "go on"},
+            output("b"),
+            output("a"),
+        ]));
+
+        sanitize(&mut body).unwrap();
+
+        assert_eq!(shape(&body), ["hi", "a,b", "-> a", "-> b", "go on"]);
+    }
+
+    #[test]
+    fn leaves_out_empty_tool_calls() {
+        let mut body = body(json!([
+            {"role": "user", "content": "hi"},
+            calls(&[]),
+            {"role": "assistant", "content": "hello"},
+        ]));
+
+        sanitize(&mut body).unwrap();
+
+        assert_eq!(shape(&body), ["hi", "hello"]);
+    }
+
+    #[test]
+    fn refuses_broken_transcripts() {
+        assert_eq!(
+            error(body(json!([calls(&["a"]), output("a"), output("a")]))),
+            "Message 2 answers a tool call already answered"
+        );
+        assert_eq!(
+            error(body(json!([calls(&["a", "b"]), output("a")]))),
+            "Message 0 has tool calls without outputs"
+        );
+        assert_eq!(
+            error(body(
+                json!([{"role": "user", "content": "hi"}, output("a")])
+            )),
+            "Message 1 answers a tool call that wasn't made"
+        );
+        assert_eq!(
+            error(body(json!([{"role": "tool", "content": "out"}]))),
+            "Message 0 is a tool output without the call it answers"
+        );
+        assert_eq!(
+            error(body(json!([{
+                "role": "user",
+                "tool_calls": [{"name": "read", "id": "a", "arguments": {}}],
+            }]))),
+            "Message 0 has tool calls, which only the assistant makes"
+        );
+    }
+
+    #[test]
+    fn keeps_planned_calls_of_approved_requests() {
+        let mut planned = body(json!([{"role": "user", "content": "hi"}, calls(&["a"])]));
+
+        assert_eq!(
+            error(planned.clone()),
+            "Message 1 has tool calls without outputs"
+        );
+
+        planned.approved = true;
+        sanitize(&mut planned).unwrap();
+
+        assert_eq!(shape(&planned), ["hi", "a"]);
+    }
+}
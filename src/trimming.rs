@@ -0,0 +1,123 @@
+use std::{collections::HashMap, sync::Arc};
+
+use tracing::{Level, event, instrument};
+
+use crate::{
+    ManagerBody,
+    models::{AIModel, Message, Usage, complete},
+};
+
+/// Reduction of the old tool outputs sent back to the model on each iteration of the agent loop
+pub enum Trimming {
+    /// Remove the outputs of the tool calls made at least `turns` iterations ago
+    Drop { turns: usize },
+    /// Replace the outputs of the tool calls made at least `turns` iterations ago by a summary
+    Summarize {
+        turns: usize,
+        model: Arc<dyn AIModel + Send>,
+    },
+    /// Remove all the outputs but the last `count` ones
+    KeepLast { count: usize },
+}
+
+impl Trimming {
+    /// Transcript to send to the model, the summaries being kept across iterations
+    #[instrument(skip_all)]
+    pub(crate) async fn trim(
+        &self,
+        mut body: ManagerBody,
+        summaries: &mut HashMap<String, String>,
+    ) -> (ManagerBody, Option<Usage>) {
+        let mut names = HashMap::new();
+        // Index of each tool output with the iteration in which its call was made
+        let mut outputs = Vec::new();
+        let mut turns = 0;
+
+        for (index, message) in body.messages.iter().enumerate() {
+            match message {
+                Message::ToolCalls { tool_calls, .. } => {
+                    turns += 1;
+
+                    for call in tool_calls {
+                        names.insert(call.id.clone(), call.name.clone());
+                    }
+                }
+                Message::ToolOutput { .. } => outputs.push((index, turns)),
+                Message::TextMessage(_) => {}
+            }
+        }
+
+        let trimmed: Vec<usize> = match self {
+            Trimming::Drop { turns: age } | Trimming::Summarize { turns: age, .. } => outputs
+                .iter()
+                .filter(|(_, turn)| turns - turn >= *age)
+                .map(|(index, _)| *index)
+                .collect(),
+            Trimming::KeepLast { count } => outputs
+                .iter()
+                .take(outputs.len().saturating_sub(*count))
+                .map(|(index, _)| *index)
+                .collect(),
+        };
+
+        let mut usage: Option<Usage> = None;
+
+        for index in trimmed {
+            let Message::ToolOutput {
+                call_id, output, ..
+            } = &mut body.messages[index]
+            else {
+                continue;
+            };
+
+            let name = names.get(call_id).map_or("tool", String::as_str);
+
+            let Trimming::Summarize { model, .. } = self else {
+                *output = format!("[Output of {name} removed to keep the conversation short]");
+                continue;
+            };
+
+            if !summaries.contains_key(call_id) {
+                match summarize(model.as_ref(), output).await {
+                    Some((summary, summary_usage)) => {
+                        summaries.insert(call_id.clone(), summary);
+
+                        if let Some(summary_usage) = summary_usage {
+                            *usage.get_or_insert_default() += summary_usage;
+                        }
+                    }
+                    None => continue,
+                }
+            }
+
+            *output = format!("[Summary of the output of {name}] {}", summaries[call_id]);
+        }
+
+        (body, usage)
+    }
+}
+
+async fn summarize(model: &(dyn AIModel + Send), output: &str) -> Option<(String, Option<Usage>)> {
+    let prompt = format!(
+        "Summarize the following tool output in a few sentences, keeping the facts needed \
+        to carry on with the task. Answer only with the summary.\n\n{output}"
+    );
+
+    match complete(model, prompt).await {
+        Ok((summary, _)) if summary.trim().is_empty() => {
+            event!(Level::WARN, "Empty summary of tool output, keeping it");
+
+            None
+        }
+        Ok(summary) => Some(summary),
+        Err(error) => {
+            event!(
+                Level::WARN,
+                "Couldn't summarize tool output, keeping it: {}",
+                error.message
+            );
+
+            None
+        }
+    }
+}
@@ -0,0 +1,113 @@
+use std::sync::Arc;
+
+use mcp_manager::testing::{McpServerBuilder, StubModel, TestMcp, TestWorkspace, serve};
+use serde_json::{Value, json};
+
+/// MCP server adding two integers, refusing anything else
+async fn math() -> TestMcp {
+    McpServerBuilder::new("math")
+        .tool(
+            "add",
+            "Add two numbers",
+            json!({
+                "type": "object",
+                "properties": {"a": {"type": "integer"}, "b": {"type": "integer"}}
+            }),
+            |arguments| {
+                let arguments = arguments.unwrap_or_default();
+                let number = |name: &str| {
+                    arguments
+                        .get(name)
+                        .and_then(Value::as_i64)
+                        .ok_or_else(|| format!("'{name}' must be an integer"))
+                };
+
+                Ok((number("a")? + number("b")?).to_string())
+            },
+        )
+        .start()
+        .await
+}
+
+/// Answer of the workspace to a question
+async fn ask(model: Arc<StubModel>, question: &str) -> Value {
+    let address = serve(vec![TestWorkspace::new("/calc", model).mcp(math().await)]).await;
+
+    let response = reqwest::Client::new()
+        .post(format!("http://{address}/calc"))
+        .json(&json!({"messages": [{"role": "user", "content": question}]}))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), 200);
+
+    response.json().await.unwrap()
+}
+
+/// Outputs of the tool calls of a transcript
+fn outputs(response: &Value) -> Vec<&str> {
+    response["messages"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter_map(|message| message["output"].as_str())
+        .collect()
+}
+
+#[tokio::test]
+async fn calls_tools_of_workspace() {
+    let model = Arc::new(
+        StubModel::new()
+            .tool_call("add", json!({"a": 1, "b": 2}))
+            .text("1 + 2 = 3"),
+    );
+
+    let response = ask(Arc::clone(&model), "What's 1 + 2?").await;
+
+    assert_eq!(outputs(&response), ["3"]);
+    assert_eq!(
+        response["messages"].as_array().unwrap().last().unwrap()["content"],
+        "1 + 2 = 3"
+    );
+
+    // The model got the output of its call before answering
+    let requests = model.requests();
+
+    assert_eq!(requests.len(), 2);
+    assert!(requests[1].to_string().contains(r#""output":"3""#));
+}
+
+#[tokio::test]
+async fn gives_tool_errors_to_model() {
+    let model = Arc::new(
+        StubModel::new()
+            .tool_call("add", json!({"a": "one", "b": 2}))
+            .text("I can only add integers"),
+    );
+
+    let response = ask(Arc::clone(&model), "What's one + 2?").await;
+    let outputs = outputs(&response);
+
+    assert_eq!(outputs.len(), 1);
+    assert!(outputs[0].contains("'a' must be an integer"), "{outputs:?}");
+    assert_eq!(model.requests().len(), 2);
+}
+
+#[tokio::test]
+async fn fails_without_model_answer() {
+    let address = serve(vec![TestWorkspace::new(
+        "/calc",
+        Arc::new(StubModel::new()),
+    )])
+    .await;
+
+    let response = reqwest::Client::new()
+        .post(format!("http://{address}/calc"))
+        .json(&json!({"messages": [{"role": "user", "content": "Hello"}]}))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), 502);
+}